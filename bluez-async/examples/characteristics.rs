@@ -32,17 +32,37 @@ async fn main() -> Result<(), eyre::Report> {
                 );
                 let characteristics = session.get_characteristics(&service.id).await?;
                 for characteristic in characteristics {
+                    let metadata = session
+                        .get_characteristic_metadata(&characteristic.id)
+                        .await?;
                     println!(
                         "  Characteristic {} ({:?}): {}",
                         characteristic.uuid.succinctly(),
                         characteristic.flags,
                         characteristic.id
                     );
+                    if let Some(user_description) = &metadata.user_description {
+                        println!("    Description: {:?}", user_description);
+                    }
+                    if let Some(presentation_format) = &metadata.presentation_format {
+                        println!(
+                            "    Format: {:?}, exponent {}, unit {:#06x}",
+                            presentation_format.format,
+                            presentation_format.exponent,
+                            presentation_format.unit
+                        );
+                    }
                     if characteristic.flags.contains(CharacteristicFlags::READ) {
                         let value = session
                             .read_characteristic_value(&characteristic.id)
                             .await?;
-                        println!("    {}", debug_format_maybe_string(&value));
+                        match metadata
+                            .presentation_format
+                            .and_then(|format| format.decode(&value))
+                        {
+                            Some(scaled) => println!("    {} {:?}", scaled, value),
+                            None => println!("    {}", debug_format_maybe_string(&value)),
+                        }
                     }
                     let descriptors = session.get_descriptors(&characteristic.id).await?;
                     for descriptor in descriptors {
@@ -8,9 +8,10 @@
 //!
 //! [RuuviTag]: https://ruuvi.com/ruuvitag-specs/
 
-use std::collections::HashMap;
-
-use bluez_async::{BluetoothEvent, BluetoothSession, DeviceEvent, DiscoveryFilter};
+use bluez_async::{
+    AdvertisementDecoder, AdvertisementDecoders, AdvertisementRecord, AdvertisementStreamOptions,
+    BluetoothSession, DiscoveryFilter,
+};
 use futures::stream::StreamExt;
 
 /// The [Bluetooth company identifier](https://www.bluetooth.com/specifications/assigned-numbers/company-identifiers/)
@@ -21,17 +22,37 @@ const RUUVI_ID: u16 = 0x0499;
 /// [RAWv2](https://github.com/ruuvi/ruuvi-sensor-protocols/blob/master/dataformat_05.md)
 const PROTOCOL_VERSION: u8 = 0x05;
 
-/// Search for manufacturer data from a Ruuvi device with protocol version 5.
-fn get_ruuvi_data(mdata: &HashMap<u16, Vec<u8>>) -> Option<&Vec<u8>> {
-    match mdata.get(&RUUVI_ID) {
-        Some(data) if !data.is_empty() && data[0] == PROTOCOL_VERSION => Some(data),
-        _ => None,
+/// A measurement from a RuuviTag.
+#[derive(Clone, Debug)]
+struct Measurement {
+    /// Temperature in `°C`.
+    temperature: f64,
+    /// Humidity in `%`.
+    humidity: f64,
+    /// Pressure in `Pa`.
+    pressure: f64,
+}
+
+/// Decodes manufacturer data from a Ruuvi device with protocol version 5.
+struct RuuviDecoder;
+
+impl AdvertisementDecoder for RuuviDecoder {
+    type Output = Measurement;
+
+    fn decode(&self, record: &AdvertisementRecord) -> Option<Measurement> {
+        match record.manufacturer_data.get(&RUUVI_ID) {
+            Some(data) if data.len() >= 7 && data[0] == PROTOCOL_VERSION => Some(Measurement {
+                temperature: temperature(data),
+                humidity: humidity(data),
+                pressure: pressure(data),
+            }),
+            _ => None,
+        }
     }
 }
 
 /// Temperature in `°C`.
 fn temperature(data: &[u8]) -> f64 {
-    assert!(data.len() >= 3);
     let value = [data[1], data[2]];
     let value = u16::from_be_bytes(value);
     (value as f64) * 0.005
@@ -39,7 +60,6 @@ fn temperature(data: &[u8]) -> f64 {
 
 /// Humidity in `%`.
 fn humidity(data: &[u8]) -> f64 {
-    assert!(data.len() >= 5);
     let value = [data[3], data[4]];
     let value = u16::from_be_bytes(value);
     (value as f64) * 0.0025
@@ -47,7 +67,6 @@ fn humidity(data: &[u8]) -> f64 {
 
 /// Pressure in `Pa`.
 fn pressure(data: &[u8]) -> f64 {
-    assert!(data.len() >= 7);
     let value = [data[5], data[6]];
     let value = u16::from_be_bytes(value);
     (value as f64) + 50_000_f64
@@ -58,7 +77,13 @@ async fn main() -> Result<(), eyre::Report> {
     pretty_env_logger::init();
 
     let (_, session) = BluetoothSession::new().await?;
-    let mut events = session.event_stream().await?;
+    let mut decoders = AdvertisementDecoders::new();
+    decoders.register(RuuviDecoder);
+    let mut measurements = session
+        .advertisement_stream(AdvertisementStreamOptions::default())
+        .await?
+        .decode(decoders)
+        .boxed();
     // Requires duplicate data else new sensor measurements wouldn't be recognized.
     session
         .start_discovery_with_filter(&DiscoveryFilter {
@@ -67,24 +92,11 @@ async fn main() -> Result<(), eyre::Report> {
         })
         .await?;
 
-    while let Some(event) = events.next().await {
-        match event {
-            BluetoothEvent::Device {
-                id,
-                event: DeviceEvent::ManufacturerData { manufacturer_data },
-            } => {
-                if let Some(data) = get_ruuvi_data(&manufacturer_data) {
-                    let t = temperature(data);
-                    let h = humidity(data);
-                    let p = pressure(data);
-                    println!(
-                        "RuuviTag {} measured: t = {:6.2} °C, h = {:6.2} %, p = {:6} Pa",
-                        id, t, h, p
-                    );
-                }
-            }
-            _ => {}
-        }
+    while let Some((record, measurement)) = measurements.next().await {
+        println!(
+            "RuuviTag {} measured: t = {:6.2} °C, h = {:6.2} %, p = {:6} Pa",
+            record.mac_address, measurement.temperature, measurement.humidity, measurement.pressure
+        );
     }
 
     Ok(())
@@ -0,0 +1,184 @@
+//! Support for notifications and writes without response over file descriptors acquired from
+//! BlueZ with `AcquireNotify` and `AcquireWrite`, which avoids a D-Bus round trip for each value.
+
+use dbus::arg::OwnedFd;
+use futures::{ready, Sink, Stream};
+use std::fmt::{self, Debug, Formatter};
+use std::io::{self, Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::UnixStream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::unix::AsyncFd;
+
+use crate::BluetoothError;
+
+/// The size of the ATT header which is sent along with each value, and so must be subtracted from
+/// the MTU to get the maximum size of a value.
+const ATT_HEADER_LENGTH: usize = 3;
+
+/// Wrap a file descriptor acquired from BlueZ so that it can be used asynchronously.
+fn async_socket(fd: OwnedFd) -> io::Result<AsyncFd<UnixStream>> {
+    // Safe because `into_fd` gives us ownership of the file descriptor, which BlueZ sent us as a
+    // socket.
+    let socket = unsafe { UnixStream::from_raw_fd(fd.into_fd()) };
+    socket.set_nonblocking(true)?;
+    AsyncFd::new(socket)
+}
+
+/// A stream of the values notified by a GATT characteristic, as returned by
+/// [`BluetoothSession::acquire_notify`](struct.BluetoothSession.html#method.acquire_notify).
+///
+/// Notifications will stop when this is dropped. The stream will end if the device disconnects.
+pub struct NotificationStream {
+    socket: AsyncFd<UnixStream>,
+    mtu: u16,
+}
+
+impl Debug for NotificationStream {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "NotificationStream {{ mtu: {} }}", self.mtu)
+    }
+}
+
+impl NotificationStream {
+    pub(crate) fn new(fd: OwnedFd, mtu: u16) -> io::Result<Self> {
+        Ok(Self {
+            socket: async_socket(fd)?,
+            mtu,
+        })
+    }
+
+    /// Get the ATT MTU of the connection, as reported by BlueZ when the notifications were
+    /// acquired.
+    pub fn mtu(&self) -> u16 {
+        self.mtu
+    }
+}
+
+impl Stream for NotificationStream {
+    type Item = Vec<u8>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Vec<u8>>> {
+        let this = self.get_mut();
+        loop {
+            let mut guard = match ready!(this.socket.poll_read_ready(cx)) {
+                Ok(guard) => guard,
+                Err(e) => {
+                    log::error!("Error waiting for notification: {}", e);
+                    return Poll::Ready(None);
+                }
+            };
+            let mut buffer = vec![0; this.mtu.into()];
+            match guard.try_io(|socket| socket.get_ref().read(&mut buffer)) {
+                // BlueZ closes the socket when the device disconnects.
+                Ok(Ok(0)) => return Poll::Ready(None),
+                Ok(Ok(length)) => {
+                    buffer.truncate(length);
+                    return Poll::Ready(Some(buffer));
+                }
+                Ok(Err(e)) => {
+                    log::error!("Error reading notification: {}", e);
+                    return Poll::Ready(None);
+                }
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+/// A writer for sending values to a GATT characteristic without response, as returned by
+/// [`BluetoothSession::acquire_write`](struct.BluetoothSession.html#method.acquire_write).
+///
+/// Values may be sent either with [`write`](#method.write) or via the `Sink` implementation.
+pub struct CharacteristicWriter {
+    socket: AsyncFd<UnixStream>,
+    mtu: u16,
+    /// A value passed to `Sink::start_send` which hasn't yet been written.
+    pending: Option<Vec<u8>>,
+}
+
+impl Debug for CharacteristicWriter {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "CharacteristicWriter {{ mtu: {} }}", self.mtu)
+    }
+}
+
+impl CharacteristicWriter {
+    pub(crate) fn new(fd: OwnedFd, mtu: u16) -> io::Result<Self> {
+        Ok(Self {
+            socket: async_socket(fd)?,
+            mtu,
+            pending: None,
+        })
+    }
+
+    /// Get the ATT MTU of the connection, as reported by BlueZ when the writer was acquired.
+    pub fn mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    /// Get the maximum length of a single value which may be written, i.e. the MTU less the ATT
+    /// header.
+    pub fn max_value_length(&self) -> usize {
+        usize::from(self.mtu).saturating_sub(ATT_HEADER_LENGTH)
+    }
+
+    /// Write the given value to the characteristic, without waiting for a response.
+    pub async fn write(&mut self, value: &[u8]) -> Result<(), BluetoothError> {
+        self.check_length(value)?;
+        futures::future::poll_fn(|cx| self.poll_write_value(cx, value)).await
+    }
+
+    fn check_length(&self, value: &[u8]) -> Result<(), BluetoothError> {
+        let max_length = self.max_value_length();
+        if value.len() > max_length {
+            Err(BluetoothError::ValueTooLong {
+                length: value.len(),
+                max_length,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn poll_write_value(&self, cx: &mut Context, value: &[u8]) -> Poll<Result<(), BluetoothError>> {
+        loop {
+            let mut guard = ready!(self.socket.poll_write_ready(cx))?;
+            // Each write on the socket is sent as a single value, so it is either written in full
+            // or not at all.
+            match guard.try_io(|socket| socket.get_ref().write(value)) {
+                Ok(result) => return Poll::Ready(result.map(|_| ()).map_err(Into::into)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl Sink<Vec<u8>> for CharacteristicWriter {
+    type Error = BluetoothError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), BluetoothError>> {
+        self.poll_flush(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, value: Vec<u8>) -> Result<(), BluetoothError> {
+        let this = self.get_mut();
+        this.check_length(&value)?;
+        this.pending = Some(value);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), BluetoothError>> {
+        let this = self.get_mut();
+        if let Some(value) = &this.pending {
+            ready!(this.poll_write_value(cx, value))?;
+            this.pending = None;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), BluetoothError>> {
+        self.poll_flush(cx)
+    }
+}
@@ -1,8 +1,10 @@
 use bluez_generated::OrgBluezAdapter1Properties;
 use dbus::Path;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
 
-use crate::{AddressType, BluetoothError, MacAddress};
+use crate::{deserialize_object_path, AddressType, BluetoothError, MacAddress};
 
 /// Opaque identifier for a Bluetooth adapter on the system.
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
@@ -31,6 +33,20 @@ impl Display for AdapterId {
     }
 }
 
+impl Serialize for AdapterId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.object_path)
+    }
+}
+
+impl<'de> Deserialize<'de> for AdapterId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self {
+            object_path: deserialize_object_path(deserializer)?,
+        })
+    }
+}
+
 /// Information about a Bluetooth adapter on the system.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct AdapterInfo {
@@ -46,6 +62,13 @@ pub struct AdapterInfo {
     pub alias: String,
     /// Whether the adapter is currently turned on.
     pub powered: bool,
+    /// Whether the adapter is currently discoverable by other devices.
+    pub discoverable: bool,
+    /// How long the adapter will remain discoverable for after being made discoverable, or zero if
+    /// it will remain discoverable indefinitely.
+    pub discoverable_timeout: Duration,
+    /// Whether the adapter currently allows incoming pairing requests.
+    pub pairable: bool,
     /// Whether the adapter is currently discovering devices.
     pub discovering: bool,
 }
@@ -78,6 +101,20 @@ impl AdapterInfo {
             powered: adapter_properties
                 .powered()
                 .ok_or(BluetoothError::RequiredPropertyMissing("Powered"))?,
+            discoverable: adapter_properties
+                .discoverable()
+                .ok_or(BluetoothError::RequiredPropertyMissing("Discoverable"))?,
+            discoverable_timeout: Duration::from_secs(
+                adapter_properties
+                    .discoverable_timeout()
+                    .ok_or(BluetoothError::RequiredPropertyMissing(
+                        "DiscoverableTimeout",
+                    ))?
+                    .into(),
+            ),
+            pairable: adapter_properties
+                .pairable()
+                .ok_or(BluetoothError::RequiredPropertyMissing("Pairable"))?,
             discovering: adapter_properties
                 .discovering()
                 .ok_or(BluetoothError::RequiredPropertyMissing("Discovering"))?,
@@ -107,6 +144,9 @@ mod tests {
         adapter_properties.insert("Name".to_string(), Variant(Box::new("name".to_string())));
         adapter_properties.insert("Alias".to_string(), Variant(Box::new("alias".to_string())));
         adapter_properties.insert("Powered".to_string(), Variant(Box::new(false)));
+        adapter_properties.insert("Discoverable".to_string(), Variant(Box::new(false)));
+        adapter_properties.insert("DiscoverableTimeout".to_string(), Variant(Box::new(180u32)));
+        adapter_properties.insert("Pairable".to_string(), Variant(Box::new(true)));
         adapter_properties.insert("Discovering".to_string(), Variant(Box::new(false)));
 
         let adapter = AdapterInfo::from_properties(
@@ -123,6 +163,9 @@ mod tests {
                 name: "name".to_string(),
                 alias: "alias".to_string(),
                 powered: false,
+                discoverable: false,
+                discoverable_timeout: Duration::from_secs(180),
+                pairable: true,
                 discovering: false
             }
         )
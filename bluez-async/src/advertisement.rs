@@ -0,0 +1,283 @@
+//! Support for broadcasting BLE advertisements via the BlueZ LEAdvertisingManager1 interface.
+
+use bluez_generated::OrgBluezLEAdvertisingManager1;
+use dbus::arg::Variant;
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus::{MethodErr, Path};
+use dbus_crossroads::{Crossroads, IfaceBuilder, IfaceToken};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::{AdapterId, BluetoothError};
+
+/// The name of the D-Bus interface which advertisement objects implement.
+const ORG_BLUEZ_LEADVERTISEMENT1_NAME: &str = "org.bluez.LEAdvertisement1";
+
+/// Prefix for the object paths of advertisements.
+const ADVERTISEMENT_PATH_PREFIX: &str = "/bluez_async/advertisement";
+
+/// Counter used to give each advertisement a unique object path.
+static NEXT_ADVERTISEMENT_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+/// The type of an advertisement, which determines whether remote devices may connect.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum AdvertisementType {
+    /// A non-connectable advertisement, e.g. for a beacon.
+    Broadcast,
+    /// A connectable advertisement.
+    #[default]
+    Peripheral,
+}
+
+impl AdvertisementType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Broadcast => "broadcast",
+            Self::Peripheral => "peripheral",
+        }
+    }
+}
+
+impl Display for AdvertisementType {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A BLE advertisement to broadcast from a local adapter. Optional fields may be left as `None` to
+/// omit them from the advertisement.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Advertisement {
+    /// The type of advertisement.
+    pub advertisement_type: AdvertisementType,
+    /// Service UUIDs to include in the advertisement.
+    pub service_uuids: Vec<Uuid>,
+    /// Service UUIDs to include in the 'Service Solicitation' field of the advertisement.
+    pub solicit_uuids: Vec<Uuid>,
+    /// Manufacturer-specific data to include in the advertisement, keyed by company ID.
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
+    /// Service data to include in the advertisement, keyed by service UUID.
+    pub service_data: HashMap<Uuid, Vec<u8>>,
+    /// The local name to include in the advertisement.
+    pub local_name: Option<String>,
+    /// The appearance to include in the advertisement, as defined by GAP.
+    pub appearance: Option<u16>,
+    /// Whether to include the TX power in the advertisement.
+    pub include_tx_power: bool,
+    /// The TX power to use for the advertisement, in dBm. BlueZ will pick the closest supported
+    /// value.
+    pub tx_power: Option<i16>,
+    /// Whether to set the General Discoverable flag in the advertisement.
+    pub discoverable: Option<bool>,
+    /// How long the advertisement should remain discoverable for. Only used if `discoverable` is
+    /// set. BlueZ only supports whole seconds up to `u16::MAX`; registering an advertisement with
+    /// a longer timeout will fail.
+    pub discoverable_timeout: Option<Duration>,
+}
+
+impl Advertisement {
+    /// Get the discoverable timeout in whole seconds, as BlueZ expects it.
+    pub(crate) fn discoverable_timeout_secs(&self) -> Result<Option<u16>, BluetoothError> {
+        self.discoverable_timeout
+            .map(|timeout| {
+                u16::try_from(timeout.as_secs())
+                    .map_err(|_| BluetoothError::DiscoverableTimeoutTooLong(timeout))
+            })
+            .transpose()
+    }
+}
+
+/// A handle to an advertisement which has been registered with BlueZ. The advertisement will be
+/// unregistered when this is dropped, or it may be explicitly unregistered with
+/// [`BluetoothSession::unregister_advertisement`](struct.BluetoothSession.html#method.unregister_advertisement)
+/// to find out whether that succeeded.
+pub struct AdvertisementHandle {
+    pub(crate) connection: Arc<SyncConnection>,
+    pub(crate) crossroads: Arc<Mutex<Crossroads>>,
+    /// The timeout for D-Bus method calls, from the session which registered this.
+    pub(crate) method_call_timeout: Duration,
+    pub(crate) adapter: AdapterId,
+    pub(crate) object_path: Path<'static>,
+    /// Whether the advertisement still needs to be unregistered when the handle is dropped.
+    pub(crate) registered: bool,
+}
+
+impl Debug for AdvertisementHandle {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "AdvertisementHandle {{ adapter: {}, object_path: {} }}",
+            self.adapter, self.object_path
+        )
+    }
+}
+
+impl AdvertisementHandle {
+    /// Get the D-Bus object path of the advertisement.
+    pub fn object_path(&self) -> &Path<'static> {
+        &self.object_path
+    }
+
+    /// Get a proxy for the advertising manager of the adapter on which the advertisement is
+    /// registered.
+    pub(crate) fn advertising_manager(&self) -> impl OrgBluezLEAdvertisingManager1 {
+        Proxy::new(
+            "org.bluez",
+            self.adapter.object_path.clone(),
+            self.method_call_timeout,
+            self.connection.clone(),
+        )
+    }
+
+    /// Stop exporting the advertisement object, and mark it as no longer needing to be
+    /// unregistered.
+    pub(crate) fn remove(&mut self) {
+        self.crossroads
+            .lock()
+            .unwrap()
+            .remove::<Advertisement>(&self.object_path);
+        self.registered = false;
+    }
+}
+
+impl Drop for AdvertisementHandle {
+    fn drop(&mut self) {
+        if !self.registered {
+            return;
+        }
+        self.remove();
+        let advertising_manager = self.advertising_manager();
+        let object_path = self.object_path.clone();
+        tokio::spawn(async move {
+            if let Err(e) = advertising_manager
+                .unregister_advertisement(object_path)
+                .await
+            {
+                log::error!("Error unregistering advertisement: {}", e);
+            }
+        });
+    }
+}
+
+/// Register the `LEAdvertisement1` interface with the given `Crossroads` instance.
+pub(crate) fn register_advertisement(crossroads: &mut Crossroads) -> IfaceToken<Advertisement> {
+    crossroads.register(
+        ORG_BLUEZ_LEADVERTISEMENT1_NAME,
+        |b: &mut IfaceBuilder<Advertisement>| {
+            b.property("Type")
+                .get(|_, advertisement| Ok(advertisement.advertisement_type.to_string()));
+            b.property("ServiceUUIDs")
+                .get(|_, advertisement| Ok(uuids_to_strings(&advertisement.service_uuids)));
+            b.property("SolicitUUIDs")
+                .get(|_, advertisement| Ok(uuids_to_strings(&advertisement.solicit_uuids)));
+            b.property("ManufacturerData").get(|_, advertisement| {
+                Ok(advertisement
+                    .manufacturer_data
+                    .iter()
+                    .map(|(&company_id, data)| (company_id, Variant(data.clone())))
+                    .collect::<HashMap<_, _>>())
+            });
+            b.property("ServiceData").get(|_, advertisement| {
+                Ok(advertisement
+                    .service_data
+                    .iter()
+                    .map(|(uuid, data)| (uuid.to_string(), Variant(data.clone())))
+                    .collect::<HashMap<_, _>>())
+            });
+            b.property("Includes").get(|_, advertisement| {
+                let mut includes: Vec<String> = vec![];
+                if advertisement.include_tx_power {
+                    includes.push("tx-power".to_owned());
+                }
+                Ok(includes)
+            });
+            b.property("LocalName").get(|ctx, advertisement| {
+                advertisement
+                    .local_name
+                    .clone()
+                    .ok_or_else(|| MethodErr::no_property(ctx.name()))
+            });
+            b.property("Appearance").get(|ctx, advertisement| {
+                advertisement
+                    .appearance
+                    .ok_or_else(|| MethodErr::no_property(ctx.name()))
+            });
+            b.property("TxPower").get(|ctx, advertisement| {
+                advertisement
+                    .tx_power
+                    .ok_or_else(|| MethodErr::no_property(ctx.name()))
+            });
+            b.property("Discoverable").get(|ctx, advertisement| {
+                advertisement
+                    .discoverable
+                    .ok_or_else(|| MethodErr::no_property(ctx.name()))
+            });
+            b.property("DiscoverableTimeout").get(|ctx, advertisement| {
+                advertisement
+                    .discoverable_timeout_secs()
+                    .map_err(|e| MethodErr::failed(&e))?
+                    .ok_or_else(|| MethodErr::no_property(ctx.name()))
+            });
+            b.method("Release", (), (), |ctx, _, ()| {
+                log::info!("Advertisement {} released by BlueZ", ctx.path());
+                Ok(())
+            });
+        },
+    )
+}
+
+/// Insert an object for the given advertisement into the given `Crossroads` instance, and return
+/// its path.
+pub(crate) fn insert_advertisement(
+    crossroads: &mut Crossroads,
+    interface: IfaceToken<Advertisement>,
+    advertisement: Advertisement,
+) -> Path<'static> {
+    let object_path: Path<'static> = format!(
+        "{}{}",
+        ADVERTISEMENT_PATH_PREFIX,
+        NEXT_ADVERTISEMENT_INDEX.fetch_add(1, Ordering::Relaxed)
+    )
+    .into();
+    crossroads.insert(object_path.clone(), &[interface], advertisement);
+    object_path
+}
+
+fn uuids_to_strings(uuids: &[Uuid]) -> Vec<String> {
+    uuids.iter().map(Uuid::to_string).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advertisement_type_to_string() {
+        assert_eq!(AdvertisementType::Broadcast.to_string(), "broadcast");
+        assert_eq!(AdvertisementType::Peripheral.to_string(), "peripheral");
+        assert_eq!(AdvertisementType::default(), AdvertisementType::Peripheral);
+    }
+
+    #[test]
+    fn discoverable_timeout() {
+        let mut advertisement = Advertisement::default();
+        assert_eq!(advertisement.discoverable_timeout_secs().unwrap(), None);
+
+        advertisement.discoverable_timeout = Some(Duration::from_secs(180));
+        assert_eq!(
+            advertisement.discoverable_timeout_secs().unwrap(),
+            Some(180)
+        );
+
+        advertisement.discoverable_timeout = Some(Duration::from_secs(65536));
+        assert!(matches!(
+            advertisement.discoverable_timeout_secs(),
+            Err(BluetoothError::DiscoverableTimeoutTooLong(_))
+        ));
+    }
+}
@@ -0,0 +1,321 @@
+//! A stream of advertisements received while scanning, with the data for each device merged into a
+//! single record, for reading sensors which broadcast their readings without needing to connect to
+//! them.
+
+use futures::stream::{self, BoxStream};
+use futures::{Stream, StreamExt};
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+use uuid::Uuid;
+
+use crate::{BluetoothEvent, BluetoothSession, DeviceEvent, DeviceId, DeviceInfo, MacAddress};
+
+/// The most recent advertisement data received from a device, as returned by an
+/// [`AdvertisementStream`](struct.AdvertisementStream.html).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AdvertisementRecord {
+    /// The ID of the device on the adapter which received the advertisement.
+    pub id: DeviceId,
+    /// The MAC address of the device.
+    pub mac_address: MacAddress,
+    /// The human-readable name of the device, if available.
+    pub name: Option<String>,
+    /// The Received Signal Strength Indicator of the device's advertisement, if known.
+    pub rssi: Option<i16>,
+    /// The transmission power level advertised by the device, if any.
+    pub tx_power: Option<i16>,
+    /// The GATT service UUIDs (if any) from the device's advertisement.
+    pub services: Vec<Uuid>,
+    /// Manufacturer-specific advertisement data, keyed by company ID.
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
+    /// Service advertisement data, keyed by service UUID.
+    pub service_data: HashMap<Uuid, Vec<u8>>,
+    /// When the last update included in this record was received.
+    pub timestamp: SystemTime,
+}
+
+impl AdvertisementRecord {
+    fn from_device_info(device: DeviceInfo) -> Self {
+        Self {
+            id: device.id,
+            mac_address: device.mac_address,
+            name: device.name,
+            rssi: device.rssi,
+            tx_power: device.tx_power,
+            services: device.services,
+            manufacturer_data: device.manufacturer_data,
+            service_data: device.service_data,
+            timestamp: SystemTime::now(),
+        }
+    }
+
+    /// Update the record with the given event. Returns false if the event isn't relevant to
+    /// advertisements.
+    fn update(&mut self, event: DeviceEvent) -> bool {
+        match event {
+            DeviceEvent::Discovered => {}
+            DeviceEvent::RSSI { rssi } => self.rssi = Some(rssi),
+            DeviceEvent::TxPower { tx_power } => self.tx_power = Some(tx_power),
+            DeviceEvent::Name { name } => self.name = Some(name),
+            DeviceEvent::Services { services } => self.services = services,
+            DeviceEvent::ManufacturerData { manufacturer_data } => {
+                self.manufacturer_data = manufacturer_data
+            }
+            DeviceEvent::ServiceData { service_data } => self.service_data = service_data,
+            _ => return false,
+        }
+        self.timestamp = SystemTime::now();
+        true
+    }
+}
+
+/// Options for an [`AdvertisementStream`](struct.AdvertisementStream.html).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AdvertisementStreamOptions {
+    /// If this is set, at most one record will be returned for each device in each period of this
+    /// length. Updates received in between will still be merged into the next record returned for
+    /// the device.
+    pub min_interval: Option<Duration>,
+}
+
+/// A decoder for the data in advertisements from some type of device, e.g. a particular brand of
+/// sensor.
+pub trait AdvertisementDecoder: Send + Sync {
+    /// The type of values which this decoder produces.
+    type Output;
+
+    /// Try to decode the given advertisement, returning `None` if it isn't from a device which
+    /// this decoder understands.
+    fn decode(&self, record: &AdvertisementRecord) -> Option<Self::Output>;
+}
+
+/// A set of decoders for advertisements from different types of device, all producing the same
+/// output type.
+pub struct AdvertisementDecoders<T> {
+    decoders: Vec<Box<dyn AdvertisementDecoder<Output = T>>>,
+}
+
+impl<T> Debug for AdvertisementDecoders<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "AdvertisementDecoders {{ {} decoders }}",
+            self.decoders.len()
+        )
+    }
+}
+
+impl<T> Default for AdvertisementDecoders<T> {
+    fn default() -> Self {
+        Self { decoders: vec![] }
+    }
+}
+
+impl<T> AdvertisementDecoders<T> {
+    /// Create an empty set of decoders.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the given decoder to the set. Decoders are tried in the order in which they were
+    /// added.
+    pub fn register(&mut self, decoder: impl AdvertisementDecoder<Output = T> + 'static) {
+        self.decoders.push(Box::new(decoder));
+    }
+
+    /// Decode the given advertisement with the first decoder which understands it, if any.
+    pub fn decode(&self, record: &AdvertisementRecord) -> Option<T> {
+        self.decoders
+            .iter()
+            .find_map(|decoder| decoder.decode(record))
+    }
+}
+
+/// The state needed to produce the next advertisement record.
+struct StreamState {
+    session: BluetoothSession,
+    events: BoxStream<'static, BluetoothEvent>,
+    options: AdvertisementStreamOptions,
+    records: HashMap<DeviceId, AdvertisementRecord>,
+    last_returned: HashMap<DeviceId, Instant>,
+}
+
+impl StreamState {
+    async fn next_record(&mut self) -> Option<AdvertisementRecord> {
+        while let Some(event) = self.events.next().await {
+            let (id, event) = match event {
+                BluetoothEvent::Device {
+                    id,
+                    event: DeviceEvent::Removed,
+                } => {
+                    self.records.remove(&id);
+                    self.last_returned.remove(&id);
+                    continue;
+                }
+                BluetoothEvent::Device { id, event } => (id, event),
+                _ => continue,
+            };
+            if !self.records.contains_key(&id) {
+                match self.session.get_device_info(&id).await {
+                    Ok(device) => {
+                        self.records
+                            .insert(id.clone(), AdvertisementRecord::from_device_info(device));
+                    }
+                    Err(e) => {
+                        log::warn!("Error getting info for device {}: {}", id, e);
+                        continue;
+                    }
+                }
+            }
+            let record = self.records.get_mut(&id).unwrap();
+            if !record.update(event) {
+                continue;
+            }
+            let now = Instant::now();
+            if let (Some(min_interval), Some(last_returned)) =
+                (self.options.min_interval, self.last_returned.get(&id))
+            {
+                if now.duration_since(*last_returned) < min_interval {
+                    continue;
+                }
+            }
+            self.last_returned.insert(id, now);
+            return Some(record.clone());
+        }
+        None
+    }
+}
+
+/// A stream of advertisements received from devices while discovering, as returned by
+/// [`BluetoothSession::advertisement_stream`](struct.BluetoothSession.html#method.advertisement_stream).
+///
+/// Each item is a record of all the advertisement data most recently received from a device,
+/// returned whenever any of it changes.
+pub struct AdvertisementStream {
+    records: BoxStream<'static, AdvertisementRecord>,
+}
+
+impl Debug for AdvertisementStream {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "AdvertisementStream")
+    }
+}
+
+impl AdvertisementStream {
+    pub(crate) async fn new(
+        session: BluetoothSession,
+        options: AdvertisementStreamOptions,
+    ) -> Result<Self, crate::BluetoothError> {
+        // Subscribe to events before getting the current state of devices, so nothing is missed.
+        let events = session.event_stream().await?.boxed();
+        let records = session
+            .get_devices()
+            .await?
+            .into_iter()
+            .map(|device| {
+                (
+                    device.id.clone(),
+                    AdvertisementRecord::from_device_info(device),
+                )
+            })
+            .collect();
+        let state = StreamState {
+            session,
+            events,
+            options,
+            records,
+            last_returned: HashMap::new(),
+        };
+        let records = stream::unfold(state, |mut state| async move {
+            let record = state.next_record().await?;
+            Some((record, state))
+        })
+        .boxed();
+        Ok(Self { records })
+    }
+
+    /// Decode each advertisement with the given decoders, returning only those which one of them
+    /// understands, along with the decoded value.
+    pub fn decode<T: Send + 'static>(
+        self,
+        decoders: AdvertisementDecoders<T>,
+    ) -> impl Stream<Item = (AdvertisementRecord, T)> {
+        self.filter_map(move |record| {
+            let decoded = decoders.decode(&record).map(|value| (record, value));
+            async move { decoded }
+        })
+    }
+}
+
+impl Stream for AdvertisementStream {
+    type Item = AdvertisementRecord;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<AdvertisementRecord>> {
+        self.records.as_mut().poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct CompanyDecoder(u16);
+
+    impl AdvertisementDecoder for CompanyDecoder {
+        type Output = (u16, u8);
+
+        fn decode(&self, record: &AdvertisementRecord) -> Option<(u16, u8)> {
+            let data = record.manufacturer_data.get(&self.0)?;
+            Some((self.0, *data.first()?))
+        }
+    }
+
+    fn record(manufacturer_data: HashMap<u16, Vec<u8>>) -> AdvertisementRecord {
+        AdvertisementRecord {
+            id: DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66"),
+            mac_address: "11:22:33:44:55:66".parse().unwrap(),
+            name: None,
+            rssi: None,
+            tx_power: None,
+            services: vec![],
+            manufacturer_data,
+            service_data: HashMap::new(),
+            timestamp: SystemTime::now(),
+        }
+    }
+
+    #[test]
+    fn decoders_tried_in_order() {
+        let mut decoders = AdvertisementDecoders::new();
+        decoders.register(CompanyDecoder(0x1234));
+        decoders.register(CompanyDecoder(0x5678));
+
+        let mut manufacturer_data = HashMap::new();
+        assert_eq!(decoders.decode(&record(manufacturer_data.clone())), None);
+        manufacturer_data.insert(0x5678, vec![2]);
+        assert_eq!(
+            decoders.decode(&record(manufacturer_data.clone())),
+            Some((0x5678, 2))
+        );
+        manufacturer_data.insert(0x1234, vec![1]);
+        assert_eq!(
+            decoders.decode(&record(manufacturer_data)),
+            Some((0x1234, 1))
+        );
+    }
+
+    #[test]
+    fn update_record() {
+        let mut record = record(HashMap::new());
+        assert!(record.update(DeviceEvent::RSSI { rssi: -42 }));
+        assert!(record.update(DeviceEvent::Name {
+            name: "Sensor".to_string()
+        }));
+        assert!(!record.update(DeviceEvent::Connected { connected: true }));
+        assert_eq!(record.rssi, Some(-42));
+        assert_eq!(record.name.as_deref(), Some("Sensor"));
+    }
+}
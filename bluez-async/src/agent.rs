@@ -0,0 +1,454 @@
+//! Support for handling pairing and authorization requests from BlueZ, by exporting an agent via
+//! the BlueZ AgentManager1 interface.
+
+use async_trait::async_trait;
+use bluez_generated::OrgBluezAgentManager1;
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus::{MethodErr, Path};
+use dbus_crossroads::{Crossroads, IfaceBuilder, IfaceToken};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::DeviceId;
+
+/// The name of the D-Bus interface which agent objects implement.
+const ORG_BLUEZ_AGENT1_NAME: &str = "org.bluez.Agent1";
+
+/// Prefix for the object paths of agents.
+const AGENT_PATH_PREFIX: &str = "/bluez_async/agent";
+
+/// Counter used to give each agent a unique object path.
+static NEXT_AGENT_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+/// The input and output capabilities of an agent, which determine which pairing methods BlueZ will
+/// use.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum AgentCapability {
+    /// The agent can display a passkey or PIN code, but not accept any input.
+    DisplayOnly,
+    /// The agent can display a passkey and ask the user to confirm it.
+    DisplayYesNo,
+    /// The agent can ask the user to enter a passkey or PIN code, but not display anything.
+    KeyboardOnly,
+    /// The agent has no way to interact with the user, so can only use 'Just Works' pairing.
+    NoInputNoOutput,
+    /// The agent can both display and ask the user to enter a passkey or PIN code.
+    #[default]
+    KeyboardDisplay,
+}
+
+impl AgentCapability {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::DisplayOnly => "DisplayOnly",
+            Self::DisplayYesNo => "DisplayYesNo",
+            Self::KeyboardOnly => "KeyboardOnly",
+            Self::NoInputNoOutput => "NoInputNoOutput",
+            Self::KeyboardDisplay => "KeyboardDisplay",
+        }
+    }
+}
+
+impl Display for AgentCapability {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An error which an [`Agent`](trait.Agent.html) can return to BlueZ to refuse a request.
+#[derive(Clone, Copy, Debug, Eq, Error, PartialEq)]
+pub enum AgentError {
+    /// The request was rejected, e.g. because the user declined it.
+    #[error("Rejected")]
+    Rejected,
+    /// The request was canceled, e.g. because the user gave up.
+    #[error("Canceled")]
+    Canceled,
+}
+
+impl AgentError {
+    fn error_name(&self) -> &'static str {
+        match self {
+            Self::Rejected => "org.bluez.Error.Rejected",
+            Self::Canceled => "org.bluez.Error.Canceled",
+        }
+    }
+}
+
+impl From<AgentError> for MethodErr {
+    fn from(error: AgentError) -> Self {
+        (error.error_name(), error.to_string()).into()
+    }
+}
+
+/// Handler for pairing and authorization requests from BlueZ.
+///
+/// All methods which may refuse a request have default implementations which reject it, so you only
+/// need to implement those which are relevant to the capability with which the agent is
+/// registered.
+#[async_trait]
+pub trait Agent: Send + Sync {
+    /// Called when BlueZ unregisters the agent, e.g. because it is shutting down. The agent won't
+    /// receive any more requests after this.
+    async fn release(&self) {}
+
+    /// Ask for the PIN code to use for legacy pairing with the given device. This must be a string
+    /// of 1 to 16 alphanumeric characters.
+    async fn request_pin_code(&self, _device: DeviceId) -> Result<String, AgentError> {
+        Err(AgentError::Rejected)
+    }
+
+    /// Display the given PIN code, which the user should enter on the given device.
+    async fn display_pin_code(
+        &self,
+        _device: DeviceId,
+        _pin_code: String,
+    ) -> Result<(), AgentError> {
+        Err(AgentError::Rejected)
+    }
+
+    /// Ask for the passkey to use for pairing with the given device. This must be between 0 and
+    /// 999999.
+    async fn request_passkey(&self, _device: DeviceId) -> Result<u32, AgentError> {
+        Err(AgentError::Rejected)
+    }
+
+    /// Display the given passkey, which the user should enter on the given device. `entered` is the
+    /// number of digits which have been typed on the remote side so far.
+    async fn display_passkey(&self, _device: DeviceId, _passkey: u32, _entered: u16) {}
+
+    /// Ask the user to confirm that the given passkey matches that shown on the given device.
+    async fn request_confirmation(
+        &self,
+        _device: DeviceId,
+        _passkey: u32,
+    ) -> Result<(), AgentError> {
+        Err(AgentError::Rejected)
+    }
+
+    /// Ask whether to allow the given device to pair without any passkey, e.g. for 'Just Works'
+    /// pairing initiated by the remote device.
+    async fn request_authorization(&self, _device: DeviceId) -> Result<(), AgentError> {
+        Err(AgentError::Rejected)
+    }
+
+    /// Ask whether to allow the given device to connect to the service with the given UUID.
+    async fn authorize_service(&self, _device: DeviceId, _uuid: Uuid) -> Result<(), AgentError> {
+        Err(AgentError::Rejected)
+    }
+
+    /// Called when BlueZ cancels an outstanding request, e.g. because the pairing timed out.
+    async fn cancel(&self) {}
+}
+
+/// A handle to an agent which has been registered with BlueZ. The agent will be unregistered when
+/// this is dropped, or it may be explicitly unregistered with
+/// [`BluetoothSession::unregister_agent`](struct.BluetoothSession.html#method.unregister_agent)
+/// to find out whether that succeeded.
+pub struct AgentHandle {
+    pub(crate) connection: Arc<SyncConnection>,
+    pub(crate) crossroads: Arc<Mutex<Crossroads>>,
+    /// The timeout for D-Bus method calls, from the session which registered this.
+    pub(crate) method_call_timeout: Duration,
+    pub(crate) object_path: Path<'static>,
+    /// Whether the agent still needs to be unregistered when the handle is dropped.
+    pub(crate) registered: bool,
+}
+
+impl Debug for AgentHandle {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "AgentHandle {{ object_path: {} }}", self.object_path)
+    }
+}
+
+impl AgentHandle {
+    /// Get the D-Bus object path of the agent.
+    pub fn object_path(&self) -> &Path<'static> {
+        &self.object_path
+    }
+
+    /// Get a proxy for the BlueZ agent manager.
+    pub(crate) fn agent_manager(&self) -> impl OrgBluezAgentManager1 {
+        Proxy::new(
+            "org.bluez",
+            "/org/bluez",
+            self.method_call_timeout,
+            self.connection.clone(),
+        )
+    }
+
+    /// Stop exporting the agent object, and mark it as no longer needing to be unregistered.
+    pub(crate) fn remove(&mut self) {
+        self.crossroads
+            .lock()
+            .unwrap()
+            .remove::<LocalAgent>(&self.object_path);
+        self.registered = false;
+    }
+}
+
+impl Drop for AgentHandle {
+    fn drop(&mut self) {
+        if !self.registered {
+            return;
+        }
+        self.remove();
+        let agent_manager = self.agent_manager();
+        let object_path = self.object_path.clone();
+        tokio::spawn(async move {
+            if let Err(e) = agent_manager.unregister_agent(object_path).await {
+                log::error!("Error unregistering agent: {}", e);
+            }
+        });
+    }
+}
+
+pub(crate) struct LocalAgent {
+    agent: Arc<dyn Agent>,
+}
+
+/// Register the `Agent1` interface with the given `Crossroads` instance.
+pub(crate) fn register_agent(crossroads: &mut Crossroads) -> IfaceToken<LocalAgent> {
+    crossroads.register(ORG_BLUEZ_AGENT1_NAME, |b: &mut IfaceBuilder<LocalAgent>| {
+        b.method_with_cr_async("Release", (), (), |mut ctx, crossroads, ()| {
+            let agent = agent(crossroads, ctx.path());
+            async move {
+                let result = match agent {
+                    Ok(agent) => {
+                        agent.release().await;
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                };
+                ctx.reply(result)
+            }
+        });
+        b.method_with_cr_async(
+            "RequestPinCode",
+            ("device",),
+            ("pincode",),
+            |mut ctx, crossroads, (device,): (Path<'static>,)| {
+                let agent = agent(crossroads, ctx.path());
+                async move {
+                    let result = match agent {
+                        Ok(agent) => agent
+                            .request_pin_code(DeviceId {
+                                object_path: device,
+                            })
+                            .await
+                            .map(|pin_code| (pin_code,))
+                            .map_err(Into::into),
+                        Err(e) => Err(e),
+                    };
+                    ctx.reply(result)
+                }
+            },
+        );
+        b.method_with_cr_async(
+            "DisplayPinCode",
+            ("device", "pincode"),
+            (),
+            |mut ctx, crossroads, (device, pin_code): (Path<'static>, String)| {
+                let agent = agent(crossroads, ctx.path());
+                async move {
+                    let result = match agent {
+                        Ok(agent) => agent
+                            .display_pin_code(
+                                DeviceId {
+                                    object_path: device,
+                                },
+                                pin_code,
+                            )
+                            .await
+                            .map_err(Into::into),
+                        Err(e) => Err(e),
+                    };
+                    ctx.reply(result)
+                }
+            },
+        );
+        b.method_with_cr_async(
+            "RequestPasskey",
+            ("device",),
+            ("passkey",),
+            |mut ctx, crossroads, (device,): (Path<'static>,)| {
+                let agent = agent(crossroads, ctx.path());
+                async move {
+                    let result = match agent {
+                        Ok(agent) => agent
+                            .request_passkey(DeviceId {
+                                object_path: device,
+                            })
+                            .await
+                            .map(|passkey| (passkey,))
+                            .map_err(Into::into),
+                        Err(e) => Err(e),
+                    };
+                    ctx.reply(result)
+                }
+            },
+        );
+        b.method_with_cr_async(
+            "DisplayPasskey",
+            ("device", "passkey", "entered"),
+            (),
+            |mut ctx, crossroads, (device, passkey, entered): (Path<'static>, u32, u16)| {
+                let agent = agent(crossroads, ctx.path());
+                async move {
+                    let result = match agent {
+                        Ok(agent) => {
+                            agent
+                                .display_passkey(
+                                    DeviceId {
+                                        object_path: device,
+                                    },
+                                    passkey,
+                                    entered,
+                                )
+                                .await;
+                            Ok(())
+                        }
+                        Err(e) => Err(e),
+                    };
+                    ctx.reply(result)
+                }
+            },
+        );
+        b.method_with_cr_async(
+            "RequestConfirmation",
+            ("device", "passkey"),
+            (),
+            |mut ctx, crossroads, (device, passkey): (Path<'static>, u32)| {
+                let agent = agent(crossroads, ctx.path());
+                async move {
+                    let result = match agent {
+                        Ok(agent) => agent
+                            .request_confirmation(
+                                DeviceId {
+                                    object_path: device,
+                                },
+                                passkey,
+                            )
+                            .await
+                            .map_err(Into::into),
+                        Err(e) => Err(e),
+                    };
+                    ctx.reply(result)
+                }
+            },
+        );
+        b.method_with_cr_async(
+            "RequestAuthorization",
+            ("device",),
+            (),
+            |mut ctx, crossroads, (device,): (Path<'static>,)| {
+                let agent = agent(crossroads, ctx.path());
+                async move {
+                    let result = match agent {
+                        Ok(agent) => agent
+                            .request_authorization(DeviceId {
+                                object_path: device,
+                            })
+                            .await
+                            .map_err(Into::into),
+                        Err(e) => Err(e),
+                    };
+                    ctx.reply(result)
+                }
+            },
+        );
+        b.method_with_cr_async(
+            "AuthorizeService",
+            ("device", "uuid"),
+            (),
+            |mut ctx, crossroads, (device, uuid): (Path<'static>, String)| {
+                let agent = agent(crossroads, ctx.path());
+                async move {
+                    let result = match (agent, Uuid::parse_str(&uuid)) {
+                        (Ok(agent), Ok(uuid)) => agent
+                            .authorize_service(
+                                DeviceId {
+                                    object_path: device,
+                                },
+                                uuid,
+                            )
+                            .await
+                            .map_err(Into::into),
+                        (Err(e), _) => Err(e),
+                        (_, Err(_)) => Err(MethodErr::invalid_arg(&uuid)),
+                    };
+                    ctx.reply(result)
+                }
+            },
+        );
+        b.method_with_cr_async("Cancel", (), (), |mut ctx, crossroads, ()| {
+            let agent = agent(crossroads, ctx.path());
+            async move {
+                let result = match agent {
+                    Ok(agent) => {
+                        agent.cancel().await;
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                };
+                ctx.reply(result)
+            }
+        });
+    })
+}
+
+/// Insert an object for the given agent into the given `Crossroads` instance, and return its path.
+pub(crate) fn insert_agent(
+    crossroads: &mut Crossroads,
+    interface: IfaceToken<LocalAgent>,
+    agent: Arc<dyn Agent>,
+) -> Path<'static> {
+    let object_path: Path<'static> = format!(
+        "{}{}",
+        AGENT_PATH_PREFIX,
+        NEXT_AGENT_INDEX.fetch_add(1, Ordering::Relaxed)
+    )
+    .into();
+    crossroads.insert(object_path.clone(), &[interface], LocalAgent { agent });
+    object_path
+}
+
+/// Get the agent at the given path.
+fn agent(crossroads: &mut Crossroads, path: &Path<'static>) -> Result<Arc<dyn Agent>, MethodErr> {
+    let local_agent = crossroads
+        .data_mut::<LocalAgent>(path)
+        .ok_or_else(|| MethodErr::no_path(path))?;
+    Ok(local_agent.agent.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn agent_capability_to_string() {
+        assert_eq!(AgentCapability::DisplayOnly.to_string(), "DisplayOnly");
+        assert_eq!(AgentCapability::DisplayYesNo.to_string(), "DisplayYesNo");
+        assert_eq!(AgentCapability::KeyboardOnly.to_string(), "KeyboardOnly");
+        assert_eq!(
+            AgentCapability::NoInputNoOutput.to_string(),
+            "NoInputNoOutput"
+        );
+        assert_eq!(
+            AgentCapability::KeyboardDisplay.to_string(),
+            "KeyboardDisplay"
+        );
+    }
+
+    #[test]
+    fn agent_error_names() {
+        let error: MethodErr = AgentError::Rejected.into();
+        assert_eq!(error.errorname(), "org.bluez.Error.Rejected");
+        let error: MethodErr = AgentError::Canceled.into();
+        assert_eq!(error.errorname(), "org.bluez.Error.Canceled");
+    }
+}
@@ -0,0 +1,139 @@
+//! Names of well-known 16-bit UUIDs from the Bluetooth SIG
+//! [assigned numbers](https://www.bluetooth.com/specifications/assigned-numbers/).
+
+use uuid::Uuid;
+
+use crate::BleUuid;
+
+/// Names of standard GATT services.
+const SERVICE_NAMES: &[(u16, &str)] = &[
+    (0x1800, "Generic Access"),
+    (0x1801, "Generic Attribute"),
+    (0x1802, "Immediate Alert"),
+    (0x1803, "Link Loss"),
+    (0x1804, "Tx Power"),
+    (0x1805, "Current Time"),
+    (0x1809, "Health Thermometer"),
+    (0x180a, "Device Information"),
+    (0x180d, "Heart Rate"),
+    (0x180f, "Battery"),
+    (0x1810, "Blood Pressure"),
+    (0x1812, "Human Interface Device"),
+    (0x1816, "Cycling Speed and Cadence"),
+    (0x1818, "Cycling Power"),
+    (0x1819, "Location and Navigation"),
+    (0x181a, "Environmental Sensing"),
+    (0x181c, "User Data"),
+    (0x181d, "Weight Scale"),
+    (0x1826, "Fitness Machine"),
+];
+
+/// Names of standard GATT characteristics.
+const CHARACTERISTIC_NAMES: &[(u16, &str)] = &[
+    (0x2a00, "Device Name"),
+    (0x2a01, "Appearance"),
+    (0x2a04, "Peripheral Preferred Connection Parameters"),
+    (0x2a05, "Service Changed"),
+    (0x2a06, "Alert Level"),
+    (0x2a07, "Tx Power Level"),
+    (0x2a19, "Battery Level"),
+    (0x2a1c, "Temperature Measurement"),
+    (0x2a23, "System ID"),
+    (0x2a24, "Model Number String"),
+    (0x2a25, "Serial Number String"),
+    (0x2a26, "Firmware Revision String"),
+    (0x2a27, "Hardware Revision String"),
+    (0x2a28, "Software Revision String"),
+    (0x2a29, "Manufacturer Name String"),
+    (
+        0x2a2a,
+        "IEEE 11073-20601 Regulatory Certification Data List",
+    ),
+    (0x2a2b, "Current Time"),
+    (0x2a37, "Heart Rate Measurement"),
+    (0x2a50, "PnP ID"),
+    (0x2a6c, "Elevation"),
+    (0x2a6d, "Pressure"),
+    (0x2a6e, "Temperature"),
+    (0x2a6f, "Humidity"),
+    (0x2a76, "UV Index"),
+    (0x2a7b, "Dew Point"),
+    (0x2aa6, "Central Address Resolution"),
+];
+
+/// Names of standard GATT descriptors.
+const DESCRIPTOR_NAMES: &[(u16, &str)] = &[
+    (0x2900, "Characteristic Extended Properties"),
+    (0x2901, "Characteristic User Description"),
+    (0x2902, "Client Characteristic Configuration"),
+    (0x2903, "Server Characteristic Configuration"),
+    (0x2904, "Characteristic Presentation Format"),
+    (0x2905, "Characteristic Aggregate Format"),
+    (0x2906, "Valid Range"),
+    (0x290b, "Environmental Sensing Configuration"),
+    (0x290c, "Environmental Sensing Measurement"),
+    (0x290d, "Environmental Sensing Trigger Setting"),
+];
+
+fn lookup(names: &[(u16, &'static str)], uuid: Uuid) -> Option<&'static str> {
+    let uuid16 = uuid.to_ble_u16()?;
+    names
+        .iter()
+        .find(|(number, _)| *number == uuid16)
+        .map(|(_, name)| *name)
+}
+
+/// Get the name of the standard GATT service with the given UUID, if it is a well-known one.
+pub fn service_name(uuid: Uuid) -> Option<&'static str> {
+    lookup(SERVICE_NAMES, uuid)
+}
+
+/// Get the name of the standard GATT characteristic with the given UUID, if it is a well-known
+/// one.
+pub fn characteristic_name(uuid: Uuid) -> Option<&'static str> {
+    lookup(CHARACTERISTIC_NAMES, uuid)
+}
+
+/// Get the name of the standard GATT descriptor with the given UUID, if it is a well-known one.
+pub fn descriptor_name(uuid: Uuid) -> Option<&'static str> {
+    lookup(DESCRIPTOR_NAMES, uuid)
+}
+
+/// Get the name of the given UUID if it is a well-known service, characteristic or descriptor.
+/// These are allocated from separate ranges, so there is no ambiguity.
+pub(crate) fn assigned_name(uuid: Uuid) -> Option<&'static str> {
+    service_name(uuid)
+        .or_else(|| characteristic_name(uuid))
+        .or_else(|| descriptor_name(uuid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{uuid_from_u16, uuid_from_u32};
+
+    #[test]
+    fn known_names() {
+        assert_eq!(
+            service_name(uuid_from_u16(0x181a)),
+            Some("Environmental Sensing")
+        );
+        assert_eq!(
+            characteristic_name(uuid_from_u16(0x2a6e)),
+            Some("Temperature")
+        );
+        assert_eq!(
+            descriptor_name(uuid_from_u16(0x2902)),
+            Some("Client Characteristic Configuration")
+        );
+    }
+
+    #[test]
+    fn unknown_names() {
+        // Names are only looked up in the table for the right kind of attribute.
+        assert_eq!(service_name(uuid_from_u16(0x2a6e)), None);
+        assert_eq!(assigned_name(uuid_from_u16(0x1122)), None);
+        assert_eq!(assigned_name(uuid_from_u32(0x2a6e0000)), None);
+        assert_eq!(assigned_name(Uuid::nil()), None);
+    }
+}
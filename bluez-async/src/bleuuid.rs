@@ -1,5 +1,7 @@
 use uuid::Uuid;
 
+use crate::assigned_numbers::assigned_name;
+
 const BLUETOOTH_BASE_UUID: u128 = 0x00000000_0000_1000_8000_00805f9b34fb;
 const BLUETOOTH_BASE_MASK: u128 = 0x00000000_ffff_ffff_ffff_ffffffffffff;
 const BLUETOOTH_BASE_MASK_16: u128 = 0xffff0000_ffff_ffff_ffff_ffffffffffff;
@@ -25,7 +27,9 @@ pub trait BleUuid {
     /// `None`.
     fn to_ble_u16(&self) -> Option<u16>;
 
-    /// Convert the UUID to a string, using short format if applicable.
+    /// Convert the UUID to a string, using short format if applicable. Well-known services,
+    /// characteristics and descriptors are also given their assigned names, e.g.
+    /// "Temperature (0x2A6E)".
     fn succinctly(&self) -> String;
 }
 
@@ -50,9 +54,13 @@ impl BleUuid for Uuid {
 
     fn succinctly(&self) -> String {
         if let Some(uuid16) = self.to_ble_u16() {
-            format!("{:#04x}", uuid16)
+            if let Some(name) = assigned_name(*self) {
+                format!("{} ({:#06X})", name, uuid16)
+            } else {
+                format!("{:#06X}", uuid16)
+            }
         } else if let Some(uuid32) = self.to_ble_u32() {
-            format!("{:#06x}", uuid32)
+            format!("{:#010X}", uuid32)
         } else {
             self.to_string()
         }
@@ -125,6 +133,18 @@ mod tests {
         assert_eq!(uuid.succinctly(), "0x1122");
     }
 
+    #[test]
+    fn succinctly_u16_padded() {
+        let uuid = uuid_from_u16(0x00ab);
+        assert_eq!(uuid.succinctly(), "0x00AB");
+    }
+
+    #[test]
+    fn succinctly_named() {
+        let uuid = uuid_from_u16(0x2a6e);
+        assert_eq!(uuid.succinctly(), "Temperature (0x2A6E)");
+    }
+
     #[test]
     fn succinctly_u32() {
         let uuid = uuid_from_u32(0x11223344);
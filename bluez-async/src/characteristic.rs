@@ -1,10 +1,15 @@
 use bitflags::bitflags;
+use bluez_generated::OrgBluezGattCharacteristic1Properties;
 use dbus::Path;
-use std::convert::TryFrom;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::{TryFrom, TryInto};
 use std::fmt::{self, Display, Formatter};
 use uuid::Uuid;
 
-use crate::{BluetoothError, ServiceId};
+use crate::{
+    deserialize_object_path, BluetoothError, ClientCharacteristicConfiguration, PresentationFormat,
+    ServiceId,
+};
 
 /// Opaque identifier for a GATT characteristic on a Bluetooth device.
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
@@ -48,8 +53,22 @@ impl Display for CharacteristicId {
     }
 }
 
+impl Serialize for CharacteristicId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.object_path)
+    }
+}
+
+impl<'de> Deserialize<'de> for CharacteristicId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self {
+            object_path: deserialize_object_path(deserializer)?,
+        })
+    }
+}
+
 /// Information about a GATT characteristic on a Bluetooth device.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
 pub struct CharacteristicInfo {
     /// An opaque identifier for the characteristic on the device, including a reference to which
     /// adapter it was discovered on.
@@ -59,6 +78,51 @@ pub struct CharacteristicInfo {
     /// The set of flags (a.k.a. properties) of the characteristic, defining how the characteristic
     /// can be used.
     pub flags: CharacteristicFlags,
+    /// The ATT MTU negotiated for the connection to the device, if it is connected and BlueZ
+    /// reports it (as BlueZ 5.62 and later do).
+    pub mtu: Option<u16>,
+}
+
+impl CharacteristicInfo {
+    pub(crate) fn from_properties(
+        id: CharacteristicId,
+        characteristic_properties: OrgBluezGattCharacteristic1Properties,
+    ) -> Result<CharacteristicInfo, BluetoothError> {
+        let uuid = Uuid::parse_str(
+            characteristic_properties
+                .uuid()
+                .ok_or(BluetoothError::RequiredPropertyMissing("UUID"))?,
+        )?;
+        let flags = characteristic_properties
+            .flags()
+            .ok_or(BluetoothError::RequiredPropertyMissing("Flags"))?
+            .to_owned()
+            .try_into()?;
+        Ok(CharacteristicInfo {
+            id,
+            uuid,
+            flags,
+            mtu: characteristic_properties.mtu(),
+        })
+    }
+}
+
+/// Information about a GATT characteristic along with the values of its standard descriptors, as
+/// returned by
+/// [`BluetoothSession::get_characteristic_metadata`](struct.BluetoothSession.html#method.get_characteristic_metadata).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CharacteristicMetadata {
+    /// Basic information about the characteristic.
+    pub info: CharacteristicInfo,
+    /// The human-readable description of the characteristic from its Characteristic User
+    /// Description descriptor, if it has one.
+    pub user_description: Option<String>,
+    /// Whether notifications or indications are enabled, from its Client Characteristic
+    /// Configuration descriptor, if it has one.
+    pub client_configuration: Option<ClientCharacteristicConfiguration>,
+    /// How to interpret the value of the characteristic, from its Characteristic Presentation
+    /// Format descriptor, if it has one. If there are several then this is the first.
+    pub presentation_format: Option<PresentationFormat>,
 }
 
 bitflags! {
@@ -83,31 +147,71 @@ bitflags! {
     }
 }
 
+/// The BlueZ string representation of each characteristic flag.
+const FLAG_STRINGS: [(CharacteristicFlags, &str); 15] = [
+    (CharacteristicFlags::BROADCAST, "broadcast"),
+    (CharacteristicFlags::READ, "read"),
+    (
+        CharacteristicFlags::WRITE_WITHOUT_RESPONSE,
+        "write-without-response",
+    ),
+    (CharacteristicFlags::WRITE, "write"),
+    (CharacteristicFlags::NOTIFY, "notify"),
+    (CharacteristicFlags::INDICATE, "indicate"),
+    (
+        CharacteristicFlags::SIGNED_WRITE,
+        "authenticated-signed-write",
+    ),
+    (
+        CharacteristicFlags::EXTENDED_PROPERTIES,
+        "extended-properties",
+    ),
+    (CharacteristicFlags::RELIABLE_WRITE, "reliable-write"),
+    (
+        CharacteristicFlags::WRITABLE_AUXILIARIES,
+        "writable-auxiliaries",
+    ),
+    (CharacteristicFlags::ENCRYPT_READ, "encrypt-read"),
+    (CharacteristicFlags::ENCRYPT_WRITE, "encrypt-write"),
+    (
+        CharacteristicFlags::ENCRYPT_AUTHENTICATED_READ,
+        "encrypt-authenticated-read",
+    ),
+    (
+        CharacteristicFlags::ENCRYPT_AUTHENTICATED_WRITE,
+        "encrypt-authenticated-write",
+    ),
+    (CharacteristicFlags::AUTHORIZE, "authorize"),
+];
+
+impl CharacteristicFlags {
+    /// Convert the flags to the list of strings which BlueZ uses to represent them.
+    pub(crate) fn to_strings(self) -> Vec<String> {
+        FLAG_STRINGS
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, flag_string)| (*flag_string).to_owned())
+            .collect()
+    }
+}
+
+impl Serialize for CharacteristicFlags {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.to_strings())
+    }
+}
+
 impl TryFrom<Vec<String>> for CharacteristicFlags {
     type Error = BluetoothError;
 
     fn try_from(value: Vec<String>) -> Result<Self, BluetoothError> {
         let mut flags = Self::empty();
         for flag_string in value {
-            let flag = match flag_string.as_ref() {
-                "broadcast" => Self::BROADCAST,
-                "read" => Self::READ,
-                "write-without-response" => Self::WRITE_WITHOUT_RESPONSE,
-                "write" => Self::WRITE,
-                "notify" => Self::NOTIFY,
-                "indicate" => Self::INDICATE,
-                "authenticated-signed-write" => Self::SIGNED_WRITE,
-                "extended-properties" => Self::EXTENDED_PROPERTIES,
-                "reliable-write" => Self::RELIABLE_WRITE,
-                "writable-auxiliaries" => Self::WRITABLE_AUXILIARIES,
-                "encrypt-read" => Self::ENCRYPT_READ,
-                "encrypt-write" => Self::ENCRYPT_WRITE,
-                "encrypt-authenticated-read" => Self::ENCRYPT_AUTHENTICATED_READ,
-                "encrypt-authenticated-write" => Self::ENCRYPT_AUTHENTICATED_WRITE,
-                "authorize" => Self::AUTHORIZE,
-                _ => return Err(BluetoothError::FlagParseError(flag_string)),
-            };
-            flags.insert(flag);
+            let (flag, _) = FLAG_STRINGS
+                .iter()
+                .find(|(_, s)| *s == flag_string)
+                .ok_or(BluetoothError::FlagParseError(flag_string))?;
+            flags.insert(*flag);
         }
         Ok(flags)
     }
@@ -138,6 +242,17 @@ mod tests {
         )
     }
 
+    #[test]
+    fn flags_to_strings() {
+        let flags = CharacteristicFlags::READ | CharacteristicFlags::ENCRYPT_WRITE;
+        assert_eq!(
+            flags.to_strings(),
+            vec!["read".to_string(), "encrypt-write".to_string()]
+        );
+        let parsed: CharacteristicFlags = flags.to_strings().try_into().unwrap();
+        assert_eq!(parsed, flags);
+    }
+
     #[test]
     fn parse_flags_fail() {
         let flags: Result<CharacteristicFlags, BluetoothError> =
@@ -0,0 +1,520 @@
+//! A manager to keep a set of devices connected, reconnecting with backoff when they disconnect or
+//! a connection attempt fails.
+
+use async_trait::async_trait;
+use futures::channel::mpsc::{self, UnboundedSender};
+use futures::stream::FuturesUnordered;
+use futures::{select, FutureExt, Stream, StreamExt};
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::task::{JoinError, JoinHandle};
+use tokio::time;
+
+use crate::{
+    BluetoothError, BluetoothEvent, BluetoothSession, CharacteristicEvent, DeviceEvent, DeviceId,
+    MacAddress,
+};
+
+/// A handler which a [`ConnectionManager`](struct.ConnectionManager.html) calls each time it
+/// connects to a device.
+#[async_trait]
+pub trait ConnectionHandler: Send + Sync {
+    /// Called after connecting to the given device, to do whatever needs doing again after each
+    /// reconnection, such as starting notifications.
+    ///
+    /// If this returns an error then the device will be disconnected, and the connection retried
+    /// after a backoff.
+    async fn on_connected(
+        &self,
+        session: &BluetoothSession,
+        id: &DeviceId,
+    ) -> Result<(), BluetoothError>;
+}
+
+/// The state of a device managed by a [`ConnectionManager`](struct.ConnectionManager.html).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum ConnectionState {
+    /// Not connected. A connection will be attempted once the device has been found on some
+    /// adapter and any backoff since the last failed attempt has elapsed.
+    Disconnected,
+    /// A connection attempt is in progress.
+    Connecting,
+    /// Connected via the given ID, and the `on_connected` handler has succeeded.
+    Connected { id: DeviceId },
+}
+
+/// A change in the state of a device managed by a
+/// [`ConnectionManager`](struct.ConnectionManager.html).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConnectionStateChange {
+    /// The MAC address of the device.
+    pub mac_address: MacAddress,
+    /// The new state of the device.
+    pub state: ConnectionState,
+}
+
+/// Options for a [`ConnectionManager`](struct.ConnectionManager.html).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConnectionManagerOptions {
+    /// The maximum number of connection attempts to have in progress at once. Many Bluetooth
+    /// adapters misbehave if asked to connect to several devices at the same time.
+    pub max_concurrent_connects: NonZeroUsize,
+    /// How long to wait before retrying after the first failed connection attempt to a device.
+    /// This doubles with each consecutive failure, up to `max_backoff`.
+    pub initial_backoff: Duration,
+    /// The maximum time to wait before retrying after a failed connection attempt.
+    pub max_backoff: Duration,
+    /// If this is set, a connected device which hasn't had any activity for this long will be
+    /// disconnected, so that it will be reconnected. Characteristic value events count as activity,
+    /// as do calls to [`ConnectionManager::record_activity`](struct.ConnectionManager.html#method.record_activity).
+    pub stale_timeout: Option<Duration>,
+    /// How often to look for newly discovered devices and start connection attempts.
+    pub poll_interval: Duration,
+}
+
+impl Default for ConnectionManagerOptions {
+    fn default() -> Self {
+        Self {
+            max_concurrent_connects: NonZeroUsize::new(1).unwrap(),
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5 * 60),
+            stale_timeout: None,
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// The manager's state for a single device.
+#[derive(Debug)]
+struct ManagedDevice {
+    /// The IDs of the device on each adapter on which it has been discovered.
+    ids: Vec<DeviceId>,
+    state: ConnectionState,
+    /// The number of consecutive failed connection attempts.
+    failures: u32,
+    /// The time before which no connection attempt should be made.
+    retry_at: Instant,
+    /// The last time there was some activity from the device while it was connected.
+    last_activity: Instant,
+    /// IDs via which the device disconnected or was removed while a connection attempt was in
+    /// progress, so the attempt may already have lost its connection by the time it finishes.
+    disconnected_while_connecting: Vec<DeviceId>,
+}
+
+#[derive(Debug, Default)]
+struct ManagerState {
+    devices: HashMap<MacAddress, ManagedDevice>,
+    subscribers: Vec<UnboundedSender<ConnectionStateChange>>,
+}
+
+impl ManagerState {
+    /// Set the state of the given device, and notify subscribers if it has changed.
+    fn set_state(&mut self, mac_address: &MacAddress, state: ConnectionState) {
+        let device = match self.devices.get_mut(mac_address) {
+            Some(device) => device,
+            None => return,
+        };
+        if device.state == state {
+            return;
+        }
+        log::info!("{} is now {:?}", mac_address, state);
+        device.state = state.clone();
+        let change = ConnectionStateChange {
+            mac_address: mac_address.to_owned(),
+            state,
+        };
+        self.subscribers
+            .retain(|subscriber| subscriber.unbounded_send(change.clone()).is_ok());
+    }
+
+    /// Find the MAC address of the device which is currently connected via the given ID, if any.
+    fn connected_via(&self, id: &DeviceId) -> Option<MacAddress> {
+        self.devices
+            .iter()
+            .find(|(_, device)| matches!(&device.state, ConnectionState::Connected { id: connected_id } if connected_id == id))
+            .map(|(mac_address, _)| mac_address.to_owned())
+    }
+
+    /// Update the state of whichever device was connected or connecting via the given ID, after
+    /// it has disconnected or been removed.
+    fn disconnected(&mut self, id: &DeviceId) {
+        if let Some(mac_address) = self.connected_via(id) {
+            self.set_state(&mac_address, ConnectionState::Disconnected);
+        }
+        for device in self.devices.values_mut() {
+            if device.state == ConnectionState::Connecting && device.ids.contains(id) {
+                device.disconnected_while_connecting.push(id.to_owned());
+            }
+        }
+    }
+}
+
+/// Keeps a set of Bluetooth devices connected, identified by their MAC addresses.
+///
+/// The manager will connect to each device via whichever adapter it has been discovered on,
+/// calling the given [`ConnectionHandler`](trait.ConnectionHandler.html) each time it connects. If a
+/// connection attempt fails it will retry with exponential backoff, and if a device disconnects
+/// or stops responding it will reconnect.
+///
+/// The manager doesn't start discovery itself, so devices which BlueZ doesn't already know about
+/// won't be found unless discovery is started separately with
+/// [`BluetoothSession::start_discovery`](struct.BluetoothSession.html#method.start_discovery).
+///
+/// Nothing happens until [`run`](#method.run) is called.
+#[derive(Clone)]
+pub struct ConnectionManager {
+    session: BluetoothSession,
+    handler: Arc<dyn ConnectionHandler>,
+    options: ConnectionManagerOptions,
+    state: Arc<Mutex<ManagerState>>,
+}
+
+impl Debug for ConnectionManager {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("ConnectionManager")
+            .field("options", &self.options)
+            .field("states", &self.states())
+            .finish()
+    }
+}
+
+impl ConnectionManager {
+    /// Create a new connection manager for the devices with the given MAC addresses.
+    pub fn new(
+        session: BluetoothSession,
+        mac_addresses: impl IntoIterator<Item = MacAddress>,
+        handler: Arc<dyn ConnectionHandler>,
+        options: ConnectionManagerOptions,
+    ) -> Self {
+        let now = Instant::now();
+        let devices = mac_addresses
+            .into_iter()
+            .map(|mac_address| {
+                let device = ManagedDevice {
+                    ids: vec![],
+                    state: ConnectionState::Disconnected,
+                    failures: 0,
+                    retry_at: now,
+                    last_activity: now,
+                    disconnected_while_connecting: vec![],
+                };
+                (mac_address, device)
+            })
+            .collect();
+        Self {
+            session,
+            handler,
+            options,
+            state: Arc::new(Mutex::new(ManagerState {
+                devices,
+                subscribers: vec![],
+            })),
+        }
+    }
+
+    /// Get the current state of each managed device.
+    pub fn states(&self) -> HashMap<MacAddress, ConnectionState> {
+        self.state
+            .lock()
+            .unwrap()
+            .devices
+            .iter()
+            .map(|(mac_address, device)| (mac_address.to_owned(), device.state.clone()))
+            .collect()
+    }
+
+    /// Get a stream of changes to the state of managed devices, from now on.
+    pub fn state_changes(&self) -> impl Stream<Item = ConnectionStateChange> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.state.lock().unwrap().subscribers.push(sender);
+        receiver
+    }
+
+    /// Record that there has been some activity from the given device, so that it shouldn't be
+    /// considered stale. This is only needed for activity which the manager can't see for itself,
+    /// such as notifications received via
+    /// [`BluetoothSession::acquire_notify`](struct.BluetoothSession.html#method.acquire_notify).
+    pub fn record_activity(&self, id: &DeviceId) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(mac_address) = state.connected_via(id) {
+            if let Some(device) = state.devices.get_mut(&mac_address) {
+                device.last_activity = Instant::now();
+            }
+        }
+    }
+
+    /// Keep the managed devices connected.
+    ///
+    /// This only returns if there is an error subscribing to events from BlueZ, or the event stream
+    /// ends because the D-Bus connection was lost. Any connection attempts still in progress are
+    /// aborted when it returns or is dropped.
+    pub async fn run(&self) -> Result<(), BluetoothError> {
+        let mut events = Box::pin(self.session.event_stream().await?).fuse();
+        let mut attempts = FuturesUnordered::new();
+        let mut interval = time::interval(self.options.poll_interval);
+        loop {
+            select! {
+                _ = interval.tick().fuse() => {
+                    // Keep using the IDs from last time rather than giving up, as the error may
+                    // well be transient.
+                    if let Err(e) = self.update_ids().await {
+                        log::warn!("Error updating device IDs: {}", e);
+                    }
+                    self.disconnect_stale().await;
+                    // Handle any events which have already arrived, such as from disconnecting
+                    // stale devices, so they aren't mistaken for disconnections during the new
+                    // attempts.
+                    while let Some(Some(event)) = events.next().now_or_never() {
+                        self.handle_event(event);
+                    }
+                    for (mac_address, ids) in self.start_attempts() {
+                        let session = self.session.clone();
+                        let handler = self.handler.clone();
+                        attempts.push(Attempt {
+                            mac_address,
+                            handle: tokio::spawn(connect(session, handler, ids)),
+                        });
+                    }
+                }
+                event = events.next() => match event {
+                    Some(event) => self.handle_event(event),
+                    None => return Ok(()),
+                },
+                (mac_address, result) = attempts.select_next_some() => {
+                    let result = result.unwrap_or_else(|e| {
+                        log::error!("Connection attempt to {} failed: {}", mac_address, e);
+                        None
+                    });
+                    self.finish_attempt(&mac_address, result);
+                }
+            }
+        }
+    }
+
+    /// Update the list of IDs for each managed device from the devices BlueZ knows about.
+    async fn update_ids(&self) -> Result<(), BluetoothError> {
+        let devices = self.session.get_devices().await?;
+        let mut state = self.state.lock().unwrap();
+        for (mac_address, managed) in &mut state.devices {
+            managed.ids = devices
+                .iter()
+                .filter(|device| &device.mac_address == mac_address)
+                .map(|device| device.id.to_owned())
+                .collect();
+        }
+        Ok(())
+    }
+
+    /// Disconnect any connected devices which haven't had any activity for longer than the stale
+    /// timeout.
+    async fn disconnect_stale(&self) {
+        let stale_timeout = match self.options.stale_timeout {
+            Some(stale_timeout) => stale_timeout,
+            None => return,
+        };
+        let now = Instant::now();
+        let stale_ids: Vec<DeviceId> = {
+            let mut state = self.state.lock().unwrap();
+            let stale: Vec<(MacAddress, DeviceId)> = state
+                .devices
+                .iter()
+                .filter_map(|(mac_address, device)| match &device.state {
+                    ConnectionState::Connected { id }
+                        if now.duration_since(device.last_activity) > stale_timeout =>
+                    {
+                        Some((mac_address.to_owned(), id.to_owned()))
+                    }
+                    _ => None,
+                })
+                .collect();
+            for (mac_address, _) in &stale {
+                log::info!("No activity from {} for {:?}", mac_address, stale_timeout);
+                state.set_state(mac_address, ConnectionState::Disconnected);
+            }
+            stale.into_iter().map(|(_, id)| id).collect()
+        };
+        for id in stale_ids {
+            if let Err(e) = self.session.disconnect(&id).await {
+                log::warn!("Error disconnecting stale device {}: {}", id, e);
+            }
+        }
+    }
+
+    /// Mark as connecting as many disconnected devices as are ready for a connection attempt,
+    /// within the concurrency limit, and return their IDs.
+    fn start_attempts(&self) -> Vec<(MacAddress, Vec<DeviceId>)> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let connecting = state
+            .devices
+            .values()
+            .filter(|device| device.state == ConnectionState::Connecting)
+            .count();
+        let available = self
+            .options
+            .max_concurrent_connects
+            .get()
+            .saturating_sub(connecting);
+        let ready: Vec<(MacAddress, Vec<DeviceId>)> = state
+            .devices
+            .iter()
+            .filter(|(_, device)| {
+                device.state == ConnectionState::Disconnected
+                    && !device.ids.is_empty()
+                    && device.retry_at <= now
+            })
+            .take(available)
+            .map(|(mac_address, device)| (mac_address.to_owned(), device.ids.clone()))
+            .collect();
+        for (mac_address, _) in &ready {
+            if let Some(device) = state.devices.get_mut(mac_address) {
+                device.disconnected_while_connecting.clear();
+            }
+            state.set_state(mac_address, ConnectionState::Connecting);
+        }
+        ready
+    }
+
+    /// Update the state of a device after a connection attempt finishes, either successfully with
+    /// the ID via which it connected or unsuccessfully.
+    fn finish_attempt(&self, mac_address: &MacAddress, result: Option<DeviceId>) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let device = match state.devices.get_mut(mac_address) {
+            Some(device) => device,
+            None => return,
+        };
+        let new_state = match result {
+            Some(id) if !device.disconnected_while_connecting.contains(&id) => {
+                device.failures = 0;
+                device.last_activity = now;
+                ConnectionState::Connected { id }
+            }
+            result => {
+                if let Some(id) = result {
+                    log::info!(
+                        "{} disconnected from {} before the connection attempt finished",
+                        mac_address,
+                        id
+                    );
+                }
+                device.failures += 1;
+                device.retry_at = now + backoff(&self.options, device.failures);
+                ConnectionState::Disconnected
+            }
+        };
+        state.set_state(mac_address, new_state);
+    }
+
+    fn handle_event(&self, event: BluetoothEvent) {
+        let mut state = self.state.lock().unwrap();
+        match event {
+            BluetoothEvent::Device {
+                id,
+                event: DeviceEvent::Connected { connected: false },
+            } => state.disconnected(&id),
+            BluetoothEvent::Device {
+                id,
+                event: DeviceEvent::Removed,
+            } => {
+                state.disconnected(&id);
+                for device in state.devices.values_mut() {
+                    device.ids.retain(|device_id| device_id != &id);
+                }
+            }
+            BluetoothEvent::Characteristic {
+                id,
+                event: CharacteristicEvent::Value { .. },
+            } => {
+                let device_id = id.service().device();
+                if let Some(mac_address) = state.connected_via(&device_id) {
+                    if let Some(device) = state.devices.get_mut(&mac_address) {
+                        device.last_activity = Instant::now();
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Get the time to wait before retrying after the given number of consecutive failures.
+fn backoff(options: &ConnectionManagerOptions, failures: u32) -> Duration {
+    let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+    options
+        .initial_backoff
+        .checked_mul(factor)
+        .map_or(options.max_backoff, |backoff| {
+            backoff.min(options.max_backoff)
+        })
+}
+
+/// A spawned connection attempt to a device, which is aborted if it is dropped before it finishes.
+struct Attempt {
+    mac_address: MacAddress,
+    handle: JoinHandle<Option<DeviceId>>,
+}
+
+impl Future for Attempt {
+    type Output = (MacAddress, Result<Option<DeviceId>, JoinError>);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let result = futures::ready!(Pin::new(&mut self.handle).poll(cx));
+        Poll::Ready((self.mac_address.to_owned(), result))
+    }
+}
+
+impl Drop for Attempt {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Try to connect to the given IDs of a device in turn, and run the handler on the first which
+/// succeeds. Returns the ID which connected, or `None` if they all failed.
+async fn connect(
+    session: BluetoothSession,
+    handler: Arc<dyn ConnectionHandler>,
+    ids: Vec<DeviceId>,
+) -> Option<DeviceId> {
+    for id in ids {
+        if let Err(e) = session.connect(&id).await {
+            log::info!("Failed to connect to {}: {}", id, e);
+            continue;
+        }
+        if let Err(e) = handler.on_connected(&session, &id).await {
+            log::warn!("Connection handler for {} failed: {}", id, e);
+            if let Err(e) = session.disconnect(&id).await {
+                log::warn!("Error disconnecting from {}: {}", id, e);
+            }
+            return None;
+        }
+        return Some(id);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let options = ConnectionManagerOptions {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(10),
+            ..Default::default()
+        };
+        assert_eq!(backoff(&options, 1), Duration::from_secs(1));
+        assert_eq!(backoff(&options, 2), Duration::from_secs(2));
+        assert_eq!(backoff(&options, 4), Duration::from_secs(8));
+        assert_eq!(backoff(&options, 5), Duration::from_secs(10));
+        assert_eq!(backoff(&options, 100), Duration::from_secs(10));
+    }
+}
@@ -1,8 +1,19 @@
+use bitflags::bitflags;
 use dbus::Path;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryInto;
 use std::fmt::{self, Display, Formatter};
+use thiserror::Error;
 use uuid::Uuid;
 
-use crate::CharacteristicId;
+use crate::{deserialize_object_path, uuid_from_u16, BleUuid, CharacteristicId};
+
+/// UUID of the Characteristic User Description descriptor.
+const USER_DESCRIPTION_UUID: Uuid = uuid_from_u16(0x2901);
+/// UUID of the Client Characteristic Configuration descriptor.
+const CLIENT_CONFIGURATION_UUID: Uuid = uuid_from_u16(0x2902);
+/// UUID of the Characteristic Presentation Format descriptor.
+const PRESENTATION_FORMAT_UUID: Uuid = uuid_from_u16(0x2904);
 
 /// Opaque identifier for a GATT characteristic descriptor on a Bluetooth device.
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
@@ -11,7 +22,7 @@ pub struct DescriptorId {
 }
 
 impl DescriptorId {
-    #[cfg(test)]
+    #[cfg(any(test, feature = "fake"))]
     pub(crate) fn new(object_path: &str) -> Self {
         Self {
             object_path: object_path.to_owned().into(),
@@ -47,8 +58,22 @@ impl Display for DescriptorId {
     }
 }
 
+impl Serialize for DescriptorId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.object_path)
+    }
+}
+
+impl<'de> Deserialize<'de> for DescriptorId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self {
+            object_path: deserialize_object_path(deserializer)?,
+        })
+    }
+}
+
 /// Information about a GATT descriptor on a Bluetooth device.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
 pub struct DescriptorInfo {
     /// An opaque identifier for the descriptor on the device, including a reference to which
     /// adapter it was discovered on.
@@ -57,6 +82,336 @@ pub struct DescriptorInfo {
     pub uuid: Uuid,
 }
 
+bitflags! {
+    /// The set of flags of a descriptor exported by a local GATT application, defining how remote
+    /// devices may use it.
+    pub struct DescriptorFlags: u16 {
+        const READ = 0x01;
+        const WRITE = 0x02;
+        const ENCRYPT_READ = 0x04;
+        const ENCRYPT_WRITE = 0x08;
+        const ENCRYPT_AUTHENTICATED_READ = 0x10;
+        const ENCRYPT_AUTHENTICATED_WRITE = 0x20;
+        const SECURE_READ = 0x40;
+        const SECURE_WRITE = 0x80;
+        const AUTHORIZE = 0x100;
+    }
+}
+
+/// The BlueZ string representation of each descriptor flag.
+const FLAG_STRINGS: [(DescriptorFlags, &str); 9] = [
+    (DescriptorFlags::READ, "read"),
+    (DescriptorFlags::WRITE, "write"),
+    (DescriptorFlags::ENCRYPT_READ, "encrypt-read"),
+    (DescriptorFlags::ENCRYPT_WRITE, "encrypt-write"),
+    (
+        DescriptorFlags::ENCRYPT_AUTHENTICATED_READ,
+        "encrypt-authenticated-read",
+    ),
+    (
+        DescriptorFlags::ENCRYPT_AUTHENTICATED_WRITE,
+        "encrypt-authenticated-write",
+    ),
+    (DescriptorFlags::SECURE_READ, "secure-read"),
+    (DescriptorFlags::SECURE_WRITE, "secure-write"),
+    (DescriptorFlags::AUTHORIZE, "authorize"),
+];
+
+impl DescriptorFlags {
+    /// Convert the flags to the list of strings which BlueZ uses to represent them.
+    pub(crate) fn to_strings(self) -> Vec<String> {
+        FLAG_STRINGS
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, flag_string)| (*flag_string).to_owned())
+            .collect()
+    }
+}
+
+/// An error parsing the value of a standard GATT descriptor.
+#[derive(Clone, Debug, Error, Eq, PartialEq)]
+#[error("Invalid value {value:?} for descriptor {}", .uuid.succinctly())]
+pub struct ParseDescriptorError {
+    /// The UUID of the descriptor whose value couldn't be parsed.
+    pub uuid: Uuid,
+    /// The invalid value.
+    pub value: Vec<u8>,
+}
+
+impl ParseDescriptorError {
+    fn new(uuid: Uuid, value: &[u8]) -> Self {
+        Self {
+            uuid,
+            value: value.to_owned(),
+        }
+    }
+}
+
+/// Parse the value of a Characteristic User Description descriptor (0x2901), which is a UTF-8
+/// string. Some devices include a trailing NUL, which is removed.
+pub fn parse_user_description(value: &[u8]) -> Result<String, ParseDescriptorError> {
+    let description = std::str::from_utf8(value)
+        .map_err(|_| ParseDescriptorError::new(USER_DESCRIPTION_UUID, value))?;
+    Ok(description.trim_end_matches('\0').to_owned())
+}
+
+bitflags! {
+    /// The value of a Client Characteristic Configuration descriptor (0x2902), which says whether
+    /// notifications or indications are enabled for a characteristic.
+    pub struct ClientCharacteristicConfiguration: u16 {
+        /// Notifications are enabled.
+        const NOTIFY = 0x01;
+        /// Indications are enabled.
+        const INDICATE = 0x02;
+    }
+}
+
+impl ClientCharacteristicConfiguration {
+    /// Parse the value of a Client Characteristic Configuration descriptor. Reserved bits are
+    /// ignored.
+    pub fn from_bytes(value: &[u8]) -> Result<Self, ParseDescriptorError> {
+        let bytes = value
+            .try_into()
+            .map_err(|_| ParseDescriptorError::new(CLIENT_CONFIGURATION_UUID, value))?;
+        Ok(Self::from_bits_truncate(u16::from_le_bytes(bytes)))
+    }
+
+    /// Convert the flags to the value to write to a Client Characteristic Configuration
+    /// descriptor.
+    pub fn to_bytes(self) -> Vec<u8> {
+        self.bits().to_le_bytes().to_vec()
+    }
+}
+
+/// The format of a characteristic value, as given by its Characteristic Presentation Format
+/// descriptor.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ValueFormat {
+    /// A boolean, in the least-significant bit of a byte.
+    Boolean,
+    /// An unsigned 2-bit integer.
+    Uint2,
+    /// An unsigned 4-bit integer.
+    Uint4,
+    /// An unsigned 8-bit integer.
+    Uint8,
+    /// An unsigned 12-bit integer.
+    Uint12,
+    /// An unsigned 16-bit integer.
+    Uint16,
+    /// An unsigned 24-bit integer.
+    Uint24,
+    /// An unsigned 32-bit integer.
+    Uint32,
+    /// An unsigned 48-bit integer.
+    Uint48,
+    /// An unsigned 64-bit integer.
+    Uint64,
+    /// An unsigned 128-bit integer.
+    Uint128,
+    /// A signed 8-bit integer.
+    Sint8,
+    /// A signed 12-bit integer.
+    Sint12,
+    /// A signed 16-bit integer.
+    Sint16,
+    /// A signed 24-bit integer.
+    Sint24,
+    /// A signed 32-bit integer.
+    Sint32,
+    /// A signed 48-bit integer.
+    Sint48,
+    /// A signed 64-bit integer.
+    Sint64,
+    /// A signed 128-bit integer.
+    Sint128,
+    /// IEEE-754 32-bit floating point.
+    Float32,
+    /// IEEE-754 64-bit floating point.
+    Float64,
+    /// IEEE-11073 16-bit SFLOAT.
+    Sfloat,
+    /// IEEE-11073 32-bit FLOAT.
+    Float,
+    /// IEEE-20601 format, two 16-bit unsigned integers.
+    Duint16,
+    /// A UTF-8 string.
+    Utf8s,
+    /// A UTF-16 string.
+    Utf16s,
+    /// Opaque structure.
+    Struct,
+    /// A reserved format type.
+    Reserved(u8),
+}
+
+impl From<u8> for ValueFormat {
+    fn from(format: u8) -> Self {
+        match format {
+            0x01 => Self::Boolean,
+            0x02 => Self::Uint2,
+            0x03 => Self::Uint4,
+            0x04 => Self::Uint8,
+            0x05 => Self::Uint12,
+            0x06 => Self::Uint16,
+            0x07 => Self::Uint24,
+            0x08 => Self::Uint32,
+            0x09 => Self::Uint48,
+            0x0a => Self::Uint64,
+            0x0b => Self::Uint128,
+            0x0c => Self::Sint8,
+            0x0d => Self::Sint12,
+            0x0e => Self::Sint16,
+            0x0f => Self::Sint24,
+            0x10 => Self::Sint32,
+            0x11 => Self::Sint48,
+            0x12 => Self::Sint64,
+            0x13 => Self::Sint128,
+            0x14 => Self::Float32,
+            0x15 => Self::Float64,
+            0x16 => Self::Sfloat,
+            0x17 => Self::Float,
+            0x18 => Self::Duint16,
+            0x19 => Self::Utf8s,
+            0x1a => Self::Utf16s,
+            0x1b => Self::Struct,
+            _ => Self::Reserved(format),
+        }
+    }
+}
+
+/// The value of a Characteristic Presentation Format descriptor (0x2904), which describes how to
+/// interpret the value of a characteristic.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct PresentationFormat {
+    /// The format of the characteristic value.
+    pub format: ValueFormat,
+    /// The base 10 exponent by which the characteristic value should be scaled. The actual value
+    /// is the characteristic value multiplied by 10 to the power of this exponent.
+    pub exponent: i8,
+    /// The unit of the characteristic value, as a 16-bit Bluetooth SIG assigned number, e.g.
+    /// 0x272f for degrees Celsius.
+    pub unit: u16,
+    /// The organisation which defines the description, 0x01 for the Bluetooth SIG.
+    pub namespace: u8,
+    /// A description of the characteristic, defined by the namespace. This is used to distinguish
+    /// between several characteristics of the same type, e.g. "inside" and "outside".
+    pub description: u16,
+}
+
+impl PresentationFormat {
+    /// Parse the value of a Characteristic Presentation Format descriptor.
+    pub fn from_bytes(value: &[u8]) -> Result<Self, ParseDescriptorError> {
+        if value.len() != 7 {
+            return Err(ParseDescriptorError::new(PRESENTATION_FORMAT_UUID, value));
+        }
+        Ok(Self {
+            format: value[0].into(),
+            exponent: value[1] as i8,
+            unit: u16::from_le_bytes([value[2], value[3]]),
+            namespace: value[4],
+            description: u16::from_le_bytes([value[5], value[6]]),
+        })
+    }
+
+    /// Decode the given characteristic value according to this format, and scale it by the
+    /// exponent.
+    ///
+    /// Returns `None` if the format is not numeric, or if the value has the wrong length for the
+    /// format. 128-bit integers and `Duint16` are not supported.
+    pub fn decode(&self, value: &[u8]) -> Option<f64> {
+        let raw = match self.format {
+            ValueFormat::Boolean => (read_unsigned(value, 1)? & 0x01) as f64,
+            ValueFormat::Uint2 => (read_unsigned(value, 1)? & 0x03) as f64,
+            ValueFormat::Uint4 => (read_unsigned(value, 1)? & 0x0f) as f64,
+            ValueFormat::Uint8 => read_unsigned(value, 1)? as f64,
+            ValueFormat::Uint12 => (read_unsigned(value, 2)? & 0x0fff) as f64,
+            ValueFormat::Uint16 => read_unsigned(value, 2)? as f64,
+            ValueFormat::Uint24 => read_unsigned(value, 3)? as f64,
+            ValueFormat::Uint32 => read_unsigned(value, 4)? as f64,
+            ValueFormat::Uint48 => read_unsigned(value, 6)? as f64,
+            ValueFormat::Uint64 => read_unsigned(value, 8)? as f64,
+            ValueFormat::Sint8 => read_signed(value, 1, 8)? as f64,
+            ValueFormat::Sint12 => read_signed(value, 2, 12)? as f64,
+            ValueFormat::Sint16 => read_signed(value, 2, 16)? as f64,
+            ValueFormat::Sint24 => read_signed(value, 3, 24)? as f64,
+            ValueFormat::Sint32 => read_signed(value, 4, 32)? as f64,
+            ValueFormat::Sint48 => read_signed(value, 6, 48)? as f64,
+            ValueFormat::Sint64 => read_signed(value, 8, 64)? as f64,
+            ValueFormat::Float32 => f32::from_le_bytes(value.try_into().ok()?) as f64,
+            ValueFormat::Float64 => f64::from_le_bytes(value.try_into().ok()?),
+            ValueFormat::Sfloat => {
+                let raw = read_unsigned(value, 2)?;
+                ieee_11073_to_f64(
+                    sign_extend(raw & 0x0fff, 12),
+                    sign_extend(raw >> 12, 4),
+                    0x7ff,
+                )
+            }
+            ValueFormat::Float => {
+                let raw = read_unsigned(value, 4)?;
+                ieee_11073_to_f64(
+                    sign_extend(raw & 0xffffff, 24),
+                    sign_extend(raw >> 24, 8),
+                    0x7fffff,
+                )
+            }
+            _ => return None,
+        };
+        Some(scale(raw, self.exponent.into()))
+    }
+}
+
+/// Read an unsigned little-endian integer of exactly the given number of bytes.
+fn read_unsigned(value: &[u8], bytes: usize) -> Option<u64> {
+    if value.len() != bytes {
+        return None;
+    }
+    Some(
+        value
+            .iter()
+            .rev()
+            .fold(0, |acc, &byte| (acc << 8) | u64::from(byte)),
+    )
+}
+
+/// Read a signed little-endian integer of the given number of bits, stored in exactly the given
+/// number of bytes.
+fn read_signed(value: &[u8], bytes: usize, bits: u32) -> Option<i64> {
+    Some(sign_extend(read_unsigned(value, bytes)?, bits))
+}
+
+/// Sign-extend the lowest `bits` bits of the given value.
+fn sign_extend(value: u64, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    ((value << shift) as i64) >> shift
+}
+
+/// Convert an IEEE-11073 mantissa and exponent to a float, handling the special values. `nan` is
+/// the maximum positive mantissa, which is used with an exponent of 0 to represent NaN.
+fn ieee_11073_to_f64(mantissa: i64, exponent: i64, nan: i64) -> f64 {
+    if exponent != 0 {
+        return scale(mantissa as f64, exponent as i32);
+    }
+    match mantissa {
+        m if m == nan - 1 => f64::INFINITY,
+        m if m == -(nan - 1) => f64::NEG_INFINITY,
+        // NaN, NRes (not at this resolution) and the reserved value.
+        m if m == nan || m == -(nan + 1) || m == -nan => f64::NAN,
+        _ => mantissa as f64,
+    }
+}
+
+/// Multiply the given value by 10 to the power of the given exponent. Negative exponents divide,
+/// so that e.g. 2314 with an exponent of -2 gives exactly 23.14.
+fn scale(value: f64, exponent: i32) -> f64 {
+    if exponent < 0 {
+        value / 10f64.powi(-exponent)
+    } else {
+        value * 10f64.powi(exponent)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -70,4 +425,118 @@ mod tests {
         );
         assert_eq!(descriptor_id.characteristic(), characteristic_id);
     }
+
+    #[test]
+    fn descriptor_flags_to_strings() {
+        let flags = DescriptorFlags::READ | DescriptorFlags::SECURE_WRITE;
+        assert_eq!(
+            flags.to_strings(),
+            vec!["read".to_string(), "secure-write".to_string()]
+        );
+    }
+
+    #[test]
+    fn user_description() {
+        assert_eq!(
+            parse_user_description(b"Temperature").unwrap(),
+            "Temperature"
+        );
+        assert_eq!(parse_user_description(b"Humidity\0").unwrap(), "Humidity");
+        assert_eq!(
+            parse_user_description(&[0xff, 0xfe]),
+            Err(ParseDescriptorError {
+                uuid: USER_DESCRIPTION_UUID,
+                value: vec![0xff, 0xfe]
+            })
+        );
+    }
+
+    #[test]
+    fn client_configuration() {
+        assert_eq!(
+            ClientCharacteristicConfiguration::from_bytes(&[0x01, 0x00]).unwrap(),
+            ClientCharacteristicConfiguration::NOTIFY
+        );
+        assert_eq!(
+            ClientCharacteristicConfiguration::from_bytes(&[0x03, 0x80]).unwrap(),
+            ClientCharacteristicConfiguration::NOTIFY | ClientCharacteristicConfiguration::INDICATE
+        );
+        assert!(ClientCharacteristicConfiguration::from_bytes(&[0x01]).is_err());
+        assert_eq!(
+            ClientCharacteristicConfiguration::INDICATE.to_bytes(),
+            vec![0x02, 0x00]
+        );
+    }
+
+    #[test]
+    fn presentation_format() {
+        // Temperature in degrees Celsius with a resolution of 0.01.
+        let format =
+            PresentationFormat::from_bytes(&[0x0e, 0xfe, 0x2f, 0x27, 0x01, 0x00, 0x01]).unwrap();
+        assert_eq!(
+            format,
+            PresentationFormat {
+                format: ValueFormat::Sint16,
+                exponent: -2,
+                unit: 0x272f,
+                namespace: 0x01,
+                description: 0x0100,
+            }
+        );
+        assert_eq!(format.decode(&[0x0a, 0x09]), Some(23.14));
+        assert_eq!(format.decode(&[0xf6, 0xff]), Some(-0.1));
+        // Wrong length.
+        assert_eq!(format.decode(&[0x0a]), None);
+
+        assert!(PresentationFormat::from_bytes(&[0x0e, 0xfe]).is_err());
+    }
+
+    #[test]
+    fn decode_formats() {
+        let format = |format, exponent| PresentationFormat {
+            format,
+            exponent,
+            unit: 0x2700,
+            namespace: 0x01,
+            description: 0,
+        };
+        assert_eq!(format(ValueFormat::Boolean, 0).decode(&[0x01]), Some(1.0));
+        assert_eq!(format(ValueFormat::Uint8, 1).decode(&[0x2a]), Some(420.0));
+        assert_eq!(
+            format(ValueFormat::Uint24, 0).decode(&[0x01, 0x02, 0x03]),
+            Some(197121.0)
+        );
+        assert_eq!(
+            format(ValueFormat::Sint12, 0).decode(&[0xff, 0x0f]),
+            Some(-1.0)
+        );
+        assert_eq!(
+            format(ValueFormat::Sint24, 0).decode(&[0xfe, 0xff, 0xff]),
+            Some(-2.0)
+        );
+        assert_eq!(
+            format(ValueFormat::Float32, 0).decode(&1.5f32.to_le_bytes()),
+            Some(1.5)
+        );
+        // 36.4 as an SFLOAT: mantissa 364, exponent -1.
+        assert_eq!(
+            format(ValueFormat::Sfloat, 0).decode(&[0x6c, 0xf1]),
+            Some(36.4)
+        );
+        // 36.4 as a FLOAT.
+        assert_eq!(
+            format(ValueFormat::Float, 0).decode(&[0x6c, 0x01, 0x00, 0xff]),
+            Some(36.4)
+        );
+        assert!(format(ValueFormat::Sfloat, 0)
+            .decode(&[0xff, 0x07])
+            .unwrap()
+            .is_nan());
+        assert_eq!(
+            format(ValueFormat::Sfloat, 0).decode(&[0xfe, 0x07]),
+            Some(f64::INFINITY)
+        );
+        assert_eq!(format(ValueFormat::Utf8s, 0).decode(b"abc"), None);
+        assert_eq!(ValueFormat::from(0x42), ValueFormat::Reserved(0x42));
+    }
 }
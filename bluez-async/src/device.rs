@@ -1,12 +1,13 @@
-use bluez_generated::OrgBluezDevice1Properties;
+use bluez_generated::{OrgBluezBattery1Properties, OrgBluezDevice1Properties};
 use dbus::arg::{cast, RefArg, Variant};
 use dbus::Path;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
 use std::fmt::{self, Display, Formatter};
 use std::str::FromStr;
 use uuid::Uuid;
 
-use crate::{AdapterId, BluetoothError, MacAddress};
+use crate::{deserialize_object_path, AdapterId, BluetoothError, MacAddress};
 
 /// Opaque identifier for a Bluetooth device which the system knows about. This includes a reference
 /// to which Bluetooth adapter it was discovered on, which means that any attempt to connect to it
@@ -31,6 +32,13 @@ impl DeviceId {
             .expect("DeviceId object_path must contain a slash.");
         AdapterId::new(&self.object_path[0..index])
     }
+
+    /// Get the MAC address of the device from its ID, without asking BlueZ. This relies on the form
+    /// of object path which BlueZ uses for devices, so returns `None` if the ID doesn't match it.
+    pub fn mac_address(&self) -> Option<MacAddress> {
+        let (_, name) = self.object_path.rsplit_once('/')?;
+        name.strip_prefix("dev_")?.replace('_', ":").parse().ok()
+    }
 }
 
 impl From<DeviceId> for Path<'static> {
@@ -52,8 +60,22 @@ impl Display for DeviceId {
     }
 }
 
+impl Serialize for DeviceId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.object_path)
+    }
+}
+
+impl<'de> Deserialize<'de> for DeviceId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self {
+            object_path: deserialize_object_path(deserializer)?,
+        })
+    }
+}
+
 /// Information about a Bluetooth device which was discovered.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct DeviceInfo {
     /// An opaque identifier for the device, including a reference to which adapter it was
     /// discovered on. This can be used to connect to it.
@@ -73,6 +95,10 @@ pub struct DeviceInfo {
     pub services: Vec<Uuid>,
     /// Whether the device is currently paired with the adapter.
     pub paired: bool,
+    /// Whether the device is trusted, i.e. allowed to connect without authorization.
+    pub trusted: bool,
+    /// Whether connections from the device are blocked.
+    pub blocked: bool,
     /// Whether the device is currently connected to the adapter.
     pub connected: bool,
     /// The Received Signal Strength Indicator of the device advertisement or inquiry.
@@ -86,12 +112,39 @@ pub struct DeviceInfo {
     pub service_data: HashMap<Uuid, Vec<u8>>,
     /// Whether service discovery has finished for the device.
     pub services_resolved: bool,
+    /// The battery level of the device as a percentage, if BlueZ reports it. This is only
+    /// available while the device is connected, and only for devices which BlueZ's battery plugin
+    /// supports; see
+    /// [`BluetoothSession::get_battery_percentage`](struct.BluetoothSession.html#method.get_battery_percentage)
+    /// for a fallback.
+    pub battery_percentage: Option<u8>,
+}
+
+/// Information about the connection to a Bluetooth device, as returned by
+/// [`BluetoothSession::get_connection_info`](struct.BluetoothSession.html#method.get_connection_info).
+///
+/// BlueZ doesn't currently expose the connection interval, latency or PHY of a connection over
+/// D-Bus, so they can't be reported here.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConnectionInfo {
+    /// The ID of the device.
+    pub id: DeviceId,
+    /// Whether the device is currently connected to the adapter.
+    pub connected: bool,
+    /// The ATT MTU negotiated for the connection, if the device is connected, its services have
+    /// been resolved and BlueZ reports it (as BlueZ 5.62 and later do). The largest value which can
+    /// be written or notified in a single packet is 3 bytes less than this.
+    pub mtu: Option<u16>,
+    /// The Received Signal Strength Indicator of the device, if known. BlueZ only updates this
+    /// while discovering, so it may be stale.
+    pub rssi: Option<i16>,
 }
 
 impl DeviceInfo {
     pub(crate) fn from_properties(
         id: DeviceId,
         device_properties: OrgBluezDevice1Properties,
+        battery_properties: Option<OrgBluezBattery1Properties>,
     ) -> Result<DeviceInfo, BluetoothError> {
         let mac_address = device_properties
             .address()
@@ -114,6 +167,12 @@ impl DeviceInfo {
             paired: device_properties
                 .paired()
                 .ok_or(BluetoothError::RequiredPropertyMissing("Paired"))?,
+            trusted: device_properties
+                .trusted()
+                .ok_or(BluetoothError::RequiredPropertyMissing("Trusted"))?,
+            blocked: device_properties
+                .blocked()
+                .ok_or(BluetoothError::RequiredPropertyMissing("Blocked"))?,
             connected: device_properties
                 .connected()
                 .ok_or(BluetoothError::RequiredPropertyMissing("Connected"))?,
@@ -124,12 +183,14 @@ impl DeviceInfo {
             services_resolved: device_properties
                 .services_resolved()
                 .ok_or(BluetoothError::RequiredPropertyMissing("ServicesResolved"))?,
+            battery_percentage: battery_properties.and_then(|battery| battery.percentage()),
         })
     }
 }
 
 /// MAC address type of a Bluetooth device.
-#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum AddressType {
     /// Public address.
     Public,
@@ -195,46 +256,52 @@ fn get_service_data(
     //         ("0000fe95-0000-1000-8000-00805f9b34fb", Variant([48, 88, 91, 5, 1, 23, 33, 215, 56, 193, 164, 40, 1, 0])
     //     )], outer_sig: Signature("a{sv}") })
     // instead.
-    Some(
-        device_properties
-            .service_data()?
-            .iter()
-            .filter_map(|(k, v)| match Uuid::parse_str(k) {
-                Ok(uuid) => {
-                    if let Some(v) = cast::<Vec<u8>>(&v.0) {
-                        Some((uuid, v.to_owned()))
-                    } else {
-                        log::warn!("Service data had wrong type: {:?}", &v.0);
-                        None
-                    }
-                }
-                Err(err) => {
-                    log::warn!("Error parsing service data UUID: {}", err);
+    Some(convert_service_data(device_properties.service_data()?))
+}
+
+pub(crate) fn convert_service_data(
+    data: &HashMap<String, Variant<Box<dyn RefArg>>>,
+) -> HashMap<Uuid, Vec<u8>> {
+    data.iter()
+        .filter_map(|(k, v)| match Uuid::parse_str(k) {
+            Ok(uuid) => {
+                if let Some(v) = cast::<Vec<u8>>(&v.0) {
+                    Some((uuid, v.to_owned()))
+                } else {
+                    log::warn!("Service data had wrong type: {:?}", &v.0);
                     None
                 }
-            })
-            .collect(),
-    )
+            }
+            Err(err) => {
+                log::warn!("Error parsing service data UUID: {}", err);
+                None
+            }
+        })
+        .collect()
 }
 
 fn get_services(device_properties: OrgBluezDevice1Properties) -> Vec<Uuid> {
     if let Some(uuids) = device_properties.uuids() {
-        uuids
-            .iter()
-            .filter_map(|uuid| {
-                Uuid::parse_str(uuid)
-                    .map_err(|err| {
-                        log::warn!("Error parsing service data UUID: {}", err);
-                        err
-                    })
-                    .ok()
-            })
-            .collect()
+        convert_services(uuids)
     } else {
         vec![]
     }
 }
 
+pub(crate) fn convert_services(uuids: &[String]) -> Vec<Uuid> {
+    uuids
+        .iter()
+        .filter_map(|uuid| {
+            Uuid::parse_str(uuid)
+                .map_err(|err| {
+                    log::warn!("Error parsing service data UUID: {}", err);
+                    err
+                })
+                .ok()
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use crate::uuid_from_u32;
@@ -248,6 +315,20 @@ mod tests {
         assert_eq!(device_id.adapter(), adapter_id);
     }
 
+    #[test]
+    fn device_mac_address() {
+        let device_id = DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66");
+        assert_eq!(
+            device_id.mac_address(),
+            Some("11:22:33:44:55:66".parse().unwrap())
+        );
+        assert_eq!(DeviceId::new("/org/bluez/hci0/foo").mac_address(), None);
+        assert_eq!(
+            DeviceId::new("/org/bluez/hci0/dev_11_22").mac_address(),
+            None
+        );
+    }
+
     #[test]
     fn service_data() {
         let uuid = uuid_from_u32(0x11223344);
@@ -298,12 +379,17 @@ mod tests {
             Variant(Box::new("public".to_string())),
         );
         device_properties.insert("Paired".to_string(), Variant(Box::new(false)));
+        device_properties.insert("Trusted".to_string(), Variant(Box::new(false)));
+        device_properties.insert("Blocked".to_string(), Variant(Box::new(false)));
         device_properties.insert("Connected".to_string(), Variant(Box::new(false)));
         device_properties.insert("ServicesResolved".to_string(), Variant(Box::new(false)));
 
-        let device =
-            DeviceInfo::from_properties(id.clone(), OrgBluezDevice1Properties(&device_properties))
-                .unwrap();
+        let device = DeviceInfo::from_properties(
+            id.clone(),
+            OrgBluezDevice1Properties(&device_properties),
+            None,
+        )
+        .unwrap();
         assert_eq!(
             device,
             DeviceInfo {
@@ -314,12 +400,15 @@ mod tests {
                 appearance: None,
                 services: vec![],
                 paired: false,
+                trusted: false,
+                blocked: false,
                 connected: false,
                 rssi: None,
                 tx_power: None,
                 manufacturer_data: HashMap::new(),
                 service_data: HashMap::new(),
                 services_resolved: false,
+                battery_percentage: None,
             }
         )
     }
@@ -1,19 +1,22 @@
 use bluez_generated::{
-    OrgBluezAdapter1Properties, OrgBluezDevice1Properties, OrgBluezGattCharacteristic1Properties,
-    ORG_BLUEZ_ADAPTER1_NAME, ORG_BLUEZ_DEVICE1_NAME, ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+    OrgBluezAdapter1Properties, OrgBluezBattery1Properties, OrgBluezDevice1Properties,
+    OrgBluezGattCharacteristic1Properties, ORG_BLUEZ_ADAPTER1_NAME, ORG_BLUEZ_BATTERY1_NAME,
+    ORG_BLUEZ_DEVICE1_NAME, ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
 };
 use dbus::message::{MatchRule, SignalArgs};
 use dbus::nonblock::stdintf::org_freedesktop_dbus::{
-    ObjectManagerInterfacesAdded, PropertiesPropertiesChanged,
+    ObjectManagerInterfacesAdded, ObjectManagerInterfacesRemoved, PropertiesPropertiesChanged,
 };
 use dbus::{Message, Path};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use uuid::Uuid;
 
-use super::device::convert_manufacturer_data;
+use super::device::{convert_manufacturer_data, convert_service_data, convert_services};
 use super::{AdapterId, CharacteristicId, DeviceId};
 
 /// An event relating to a Bluetooth device or adapter.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum BluetoothEvent {
     /// An event related to a Bluetooth adapter.
     Adapter {
@@ -39,21 +42,34 @@ pub enum BluetoothEvent {
 }
 
 /// Details of an event related to a Bluetooth adapter.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 #[non_exhaustive]
 pub enum AdapterEvent {
+    /// A new adapter has been added to the system, e.g. because a USB dongle was plugged in.
+    Added,
+    /// The adapter has been removed from the system, e.g. because a USB dongle was unplugged.
+    Removed,
     /// The adapter has been powered on or off.
     Powered { powered: bool },
+    /// The adapter's friendly name has changed.
+    Alias { alias: String },
+    /// The adapter has become discoverable or stopped being discoverable.
+    Discoverable { discoverable: bool },
+    /// The adapter has started or stopped allowing incoming pairing requests.
+    Pairable { pairable: bool },
     /// The adapter has started or stopped scanning for devices.
     Discovering { discovering: bool },
 }
 
 /// Details of an event related to a Bluetooth device.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 #[non_exhaustive]
 pub enum DeviceEvent {
     /// A new device has been discovered.
     Discovered,
+    /// BlueZ has removed the device, e.g. because it hasn't been seen for a while or because it was
+    /// explicitly removed. Its `DeviceId` is no longer valid.
+    Removed,
     /// The device has connected or disconnected.
     Connected { connected: bool },
     /// A new value is available for the RSSI of the device.
@@ -62,12 +78,30 @@ pub enum DeviceEvent {
     ManufacturerData {
         manufacturer_data: HashMap<u16, Vec<u8>>,
     },
+    /// A new value is available for the service advertisement data of the device.
+    ServiceData {
+        service_data: HashMap<Uuid, Vec<u8>>,
+    },
+    /// The set of service UUIDs advertised or offered by the device has changed.
+    Services { services: Vec<Uuid> },
+    /// The device's remote name has changed.
+    Name { name: String },
+    /// The device's alias has changed.
+    Alias { alias: String },
+    /// A new value is available for the advertised transmit power of the device.
+    TxPower { tx_power: i16 },
+    /// The device has been paired or unpaired.
+    Paired { paired: bool },
+    /// The device has been trusted or untrusted.
+    Trusted { trusted: bool },
     /// Service discovery has completed.
     ServicesResolved,
+    /// A new value is available for the battery level of the device, as reported by BlueZ.
+    BatteryPercentage { battery_percentage: u8 },
 }
 
 /// Details of an event related to a GATT characteristic.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 #[non_exhaustive]
 pub enum CharacteristicEvent {
     /// A new value of the characteristic has been received. This may be from a notification.
@@ -80,7 +114,8 @@ impl BluetoothEvent {
     /// characteristic).
     ///
     /// Note that the match rules for a device will not match the device discovered event for that
-    /// device, as it is considered an event for the system rather than the device itself.
+    /// device, as it is considered an event for the system rather than the device itself. Likewise
+    /// for devices being removed and adapters being added or removed.
     pub(crate) fn match_rules(object: Option<impl Into<Path<'static>>>) -> Vec<MatchRule<'static>> {
         // BusName validation just checks that the length and format is valid, so it should never
         // fail for a constant that we know is valid.
@@ -89,11 +124,15 @@ impl BluetoothEvent {
         let mut match_rules = vec![];
 
         // If we aren't filtering to a single device or characteristic, then match ObjectManager
-        // signals so we can get events for new devices being discovered.
+        // signals so we can get events for devices being discovered or removed and adapters being
+        // added or removed.
         if object.is_none() {
             let match_rule =
                 ObjectManagerInterfacesAdded::match_rule(Some(&bus_name), None).static_clone();
             match_rules.push(match_rule);
+            let match_rule =
+                ObjectManagerInterfacesRemoved::match_rule(Some(&bus_name), None).static_clone();
+            match_rules.push(match_rule);
         }
 
         // Match PropertiesChanged signals for the given device or characteristic and all objects
@@ -117,6 +156,10 @@ impl BluetoothEvent {
         } else if let Some(interfaces_added) = ObjectManagerInterfacesAdded::from_message(&message)
         {
             Self::interfaces_added_to_events(interfaces_added)
+        } else if let Some(interfaces_removed) =
+            ObjectManagerInterfacesRemoved::from_message(&message)
+        {
+            Self::interfaces_removed_to_events(interfaces_removed)
         } else {
             log::info!("Unexpected message: {:?}", message);
             vec![]
@@ -130,15 +173,66 @@ impl BluetoothEvent {
         log::trace!("InterfacesAdded: {:?}", interfaces_added);
         let mut events = vec![];
         let object_path = interfaces_added.object;
+        if let Some(_adapter) =
+            OrgBluezAdapter1Properties::from_interfaces(&interfaces_added.interfaces)
+        {
+            let id = AdapterId {
+                object_path: object_path.clone(),
+            };
+            events.push(BluetoothEvent::Adapter {
+                id,
+                event: AdapterEvent::Added,
+            })
+        }
         if let Some(_device) =
             OrgBluezDevice1Properties::from_interfaces(&interfaces_added.interfaces)
         {
-            let id = DeviceId { object_path };
+            let id = DeviceId {
+                object_path: object_path.clone(),
+            };
             events.push(BluetoothEvent::Device {
                 id,
                 event: DeviceEvent::Discovered,
             })
         }
+        // BlueZ adds the battery interface to a device some time after it connects.
+        if let Some(battery_percentage) =
+            OrgBluezBattery1Properties::from_interfaces(&interfaces_added.interfaces)
+                .and_then(|battery| battery.percentage())
+        {
+            let id = DeviceId { object_path };
+            events.push(BluetoothEvent::Device {
+                id,
+                event: DeviceEvent::BatteryPercentage { battery_percentage },
+            })
+        }
+        events
+    }
+
+    /// Return a list of Bluetooth events parsed from an InterfacesRemoved signal.
+    fn interfaces_removed_to_events(
+        interfaces_removed: ObjectManagerInterfacesRemoved,
+    ) -> Vec<BluetoothEvent> {
+        log::trace!("InterfacesRemoved: {:?}", interfaces_removed);
+        let mut events = vec![];
+        let object_path = interfaces_removed.object;
+        for interface in &interfaces_removed.interfaces {
+            match interface.as_ref() {
+                ORG_BLUEZ_ADAPTER1_NAME => events.push(BluetoothEvent::Adapter {
+                    id: AdapterId {
+                        object_path: object_path.clone(),
+                    },
+                    event: AdapterEvent::Removed,
+                }),
+                ORG_BLUEZ_DEVICE1_NAME => events.push(BluetoothEvent::Device {
+                    id: DeviceId {
+                        object_path: object_path.clone(),
+                    },
+                    event: DeviceEvent::Removed,
+                }),
+                _ => {}
+            }
+        }
         events
     }
 
@@ -164,6 +258,26 @@ impl BluetoothEvent {
                         event: AdapterEvent::Powered { powered },
                     })
                 }
+                if let Some(alias) = adapter.alias() {
+                    events.push(BluetoothEvent::Adapter {
+                        id: id.clone(),
+                        event: AdapterEvent::Alias {
+                            alias: alias.to_owned(),
+                        },
+                    })
+                }
+                if let Some(discoverable) = adapter.discoverable() {
+                    events.push(BluetoothEvent::Adapter {
+                        id: id.clone(),
+                        event: AdapterEvent::Discoverable { discoverable },
+                    })
+                }
+                if let Some(pairable) = adapter.pairable() {
+                    events.push(BluetoothEvent::Adapter {
+                        id: id.clone(),
+                        event: AdapterEvent::Pairable { pairable },
+                    })
+                }
                 if let Some(discovering) = adapter.discovering() {
                     events.push(BluetoothEvent::Adapter {
                         id,
@@ -194,6 +308,56 @@ impl BluetoothEvent {
                         },
                     })
                 }
+                if let Some(service_data) = device.service_data() {
+                    events.push(BluetoothEvent::Device {
+                        id: id.clone(),
+                        event: DeviceEvent::ServiceData {
+                            service_data: convert_service_data(service_data),
+                        },
+                    })
+                }
+                if let Some(services) = device.uuids() {
+                    events.push(BluetoothEvent::Device {
+                        id: id.clone(),
+                        event: DeviceEvent::Services {
+                            services: convert_services(services),
+                        },
+                    })
+                }
+                if let Some(name) = device.name() {
+                    events.push(BluetoothEvent::Device {
+                        id: id.clone(),
+                        event: DeviceEvent::Name {
+                            name: name.to_owned(),
+                        },
+                    })
+                }
+                if let Some(alias) = device.alias() {
+                    events.push(BluetoothEvent::Device {
+                        id: id.clone(),
+                        event: DeviceEvent::Alias {
+                            alias: alias.to_owned(),
+                        },
+                    })
+                }
+                if let Some(tx_power) = device.tx_power() {
+                    events.push(BluetoothEvent::Device {
+                        id: id.clone(),
+                        event: DeviceEvent::TxPower { tx_power },
+                    })
+                }
+                if let Some(paired) = device.paired() {
+                    events.push(BluetoothEvent::Device {
+                        id: id.clone(),
+                        event: DeviceEvent::Paired { paired },
+                    })
+                }
+                if let Some(trusted) = device.trusted() {
+                    events.push(BluetoothEvent::Device {
+                        id: id.clone(),
+                        event: DeviceEvent::Trusted { trusted },
+                    })
+                }
                 if device.services_resolved() == Some(true) {
                     events.push(BluetoothEvent::Device {
                         id,
@@ -201,6 +365,16 @@ impl BluetoothEvent {
                     });
                 }
             }
+            ORG_BLUEZ_BATTERY1_NAME => {
+                let id = DeviceId { object_path };
+                let battery = OrgBluezBattery1Properties(changed_properties);
+                if let Some(battery_percentage) = battery.percentage() {
+                    events.push(BluetoothEvent::Device {
+                        id,
+                        event: DeviceEvent::BatteryPercentage { battery_percentage },
+                    })
+                }
+            }
             ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME => {
                 let id = CharacteristicId { object_path };
                 let characteristic = OrgBluezGattCharacteristic1Properties(changed_properties);
@@ -239,6 +413,66 @@ mod tests {
         )
     }
 
+    #[test]
+    fn adapter_alias_and_discoverable() {
+        let mut changed_properties: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        changed_properties.insert("Alias".to_string(), Variant(Box::new("name".to_string())));
+        changed_properties.insert("Discoverable".to_string(), Variant(Box::new(true)));
+        changed_properties.insert("Pairable".to_string(), Variant(Box::new(false)));
+        let message = PropertiesPropertiesChanged {
+            interface_name: "org.bluez.Adapter1".to_string(),
+            changed_properties,
+            invalidated_properties: vec![],
+        }
+        .to_emit_message(&"/org/bluez/hci0".into());
+        let id = AdapterId::new("/org/bluez/hci0");
+        assert_eq!(
+            BluetoothEvent::message_to_events(message),
+            vec![
+                BluetoothEvent::Adapter {
+                    id: id.clone(),
+                    event: AdapterEvent::Alias {
+                        alias: "name".to_string()
+                    }
+                },
+                BluetoothEvent::Adapter {
+                    id: id.clone(),
+                    event: AdapterEvent::Discoverable { discoverable: true }
+                },
+                BluetoothEvent::Adapter {
+                    id,
+                    event: AdapterEvent::Pairable { pairable: false }
+                },
+            ]
+        )
+    }
+
+    #[test]
+    fn adapter_added() {
+        let message = new_adapter_message("/org/bluez/hci1");
+        let id = AdapterId::new("/org/bluez/hci1");
+        assert_eq!(
+            BluetoothEvent::message_to_events(message),
+            vec![BluetoothEvent::Adapter {
+                id,
+                event: AdapterEvent::Added
+            }]
+        )
+    }
+
+    #[test]
+    fn adapter_removed() {
+        let message = removed_adapter_message("/org/bluez/hci1");
+        let id = AdapterId::new("/org/bluez/hci1");
+        assert_eq!(
+            BluetoothEvent::message_to_events(message),
+            vec![BluetoothEvent::Adapter {
+                id,
+                event: AdapterEvent::Removed
+            }]
+        )
+    }
+
     #[test]
     fn device_rssi() {
         let rssi = 42;
@@ -271,6 +505,169 @@ mod tests {
         )
     }
 
+    #[test]
+    fn device_service_data() {
+        let uuid = Uuid::parse_str("0000fe95-0000-1000-8000-00805f9b34fb").unwrap();
+        let mut service_data: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        service_data.insert(uuid.to_string(), Variant(Box::new(vec![1u8, 2, 3])));
+        // Entries with an invalid UUID should be skipped.
+        service_data.insert("invalid".to_string(), Variant(Box::new(vec![4u8])));
+        let mut changed_properties: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        changed_properties.insert("ServiceData".to_string(), Variant(Box::new(service_data)));
+        let message = device_properties_changed_message(
+            "/org/bluez/hci0/dev_11_22_33_44_55_66",
+            changed_properties,
+        );
+
+        let mut expected_service_data = HashMap::new();
+        expected_service_data.insert(uuid, vec![1u8, 2, 3]);
+        let id = DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66");
+        assert_eq!(
+            BluetoothEvent::message_to_events(message),
+            vec![BluetoothEvent::Device {
+                id,
+                event: DeviceEvent::ServiceData {
+                    service_data: expected_service_data
+                }
+            }]
+        )
+    }
+
+    #[test]
+    fn device_services() {
+        let uuid = Uuid::parse_str("0000181a-0000-1000-8000-00805f9b34fb").unwrap();
+        let mut changed_properties: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        changed_properties.insert(
+            "UUIDs".to_string(),
+            Variant(Box::new(vec![uuid.to_string(), "invalid".to_string()])),
+        );
+        let message = device_properties_changed_message(
+            "/org/bluez/hci0/dev_11_22_33_44_55_66",
+            changed_properties,
+        );
+        let id = DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66");
+        assert_eq!(
+            BluetoothEvent::message_to_events(message),
+            vec![BluetoothEvent::Device {
+                id,
+                event: DeviceEvent::Services {
+                    services: vec![uuid]
+                }
+            }]
+        )
+    }
+
+    #[test]
+    fn device_name_alias_and_tx_power() {
+        let mut changed_properties: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        changed_properties.insert(
+            "Name".to_string(),
+            Variant(Box::new("LYWSD03MMC".to_string())),
+        );
+        changed_properties.insert(
+            "Alias".to_string(),
+            Variant(Box::new("Kitchen".to_string())),
+        );
+        changed_properties.insert("TxPower".to_string(), Variant(Box::new(-4i16)));
+        let message = device_properties_changed_message(
+            "/org/bluez/hci0/dev_11_22_33_44_55_66",
+            changed_properties,
+        );
+        let id = DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66");
+        assert_eq!(
+            BluetoothEvent::message_to_events(message),
+            vec![
+                BluetoothEvent::Device {
+                    id: id.clone(),
+                    event: DeviceEvent::Name {
+                        name: "LYWSD03MMC".to_string()
+                    }
+                },
+                BluetoothEvent::Device {
+                    id: id.clone(),
+                    event: DeviceEvent::Alias {
+                        alias: "Kitchen".to_string()
+                    }
+                },
+                BluetoothEvent::Device {
+                    id,
+                    event: DeviceEvent::TxPower { tx_power: -4 }
+                },
+            ]
+        )
+    }
+
+    #[test]
+    fn device_paired_and_trusted() {
+        let mut changed_properties: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        changed_properties.insert("Paired".to_string(), Variant(Box::new(true)));
+        changed_properties.insert("Trusted".to_string(), Variant(Box::new(false)));
+        let message = device_properties_changed_message(
+            "/org/bluez/hci0/dev_11_22_33_44_55_66",
+            changed_properties,
+        );
+        let id = DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66");
+        assert_eq!(
+            BluetoothEvent::message_to_events(message),
+            vec![
+                BluetoothEvent::Device {
+                    id: id.clone(),
+                    event: DeviceEvent::Paired { paired: true }
+                },
+                BluetoothEvent::Device {
+                    id,
+                    event: DeviceEvent::Trusted { trusted: false }
+                },
+            ]
+        )
+    }
+
+    #[test]
+    fn device_battery_percentage() {
+        let mut changed_properties: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        changed_properties.insert("Percentage".to_string(), Variant(Box::new(87u8)));
+        let properties_changed = PropertiesPropertiesChanged {
+            interface_name: "org.bluez.Battery1".to_string(),
+            changed_properties,
+            invalidated_properties: vec![],
+        };
+        let message =
+            properties_changed.to_emit_message(&"/org/bluez/hci0/dev_11_22_33_44_55_66".into());
+        let id = DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66");
+        assert_eq!(
+            BluetoothEvent::message_to_events(message),
+            vec![BluetoothEvent::Device {
+                id,
+                event: DeviceEvent::BatteryPercentage {
+                    battery_percentage: 87
+                }
+            }]
+        )
+    }
+
+    #[test]
+    fn device_battery_added() {
+        let mut properties: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        properties.insert("Percentage".to_string(), Variant(Box::new(42u8)));
+        let mut interfaces = HashMap::new();
+        interfaces.insert("org.bluez.Battery1".to_string(), properties);
+        let message = ObjectManagerInterfacesAdded {
+            object: "/org/bluez/hci0/dev_11_22_33_44_55_66".into(),
+            interfaces,
+        }
+        .to_emit_message(&"/".into());
+        let id = DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66");
+        assert_eq!(
+            BluetoothEvent::message_to_events(message),
+            vec![BluetoothEvent::Device {
+                id,
+                event: DeviceEvent::BatteryPercentage {
+                    battery_percentage: 42
+                }
+            }]
+        )
+    }
+
     #[test]
     fn characteristic_value() {
         let value: Vec<u8> = vec![1, 2, 3];
@@ -302,6 +699,26 @@ mod tests {
         )
     }
 
+    #[test]
+    fn device_removed() {
+        let message = ObjectManagerInterfacesRemoved {
+            object: "/org/bluez/hci0/dev_11_22_33_44_55_66".into(),
+            interfaces: vec![
+                "org.freedesktop.DBus.Properties".to_string(),
+                "org.bluez.Device1".to_string(),
+            ],
+        }
+        .to_emit_message(&"/".into());
+        let id = DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66");
+        assert_eq!(
+            BluetoothEvent::message_to_events(message),
+            vec![BluetoothEvent::Device {
+                id,
+                event: DeviceEvent::Removed
+            }]
+        )
+    }
+
     #[test]
     fn match_rules_all() {
         let match_rules = BluetoothEvent::match_rules(None::<DeviceId>);
@@ -312,6 +729,9 @@ mod tests {
         let message = adapter_powered_message("/org/bluez/hci0", true);
         assert_eq!(match_rules.iter().any(|rule| rule.matches(&message)), true);
 
+        let message = removed_adapter_message("/org/bluez/hci1");
+        assert_eq!(match_rules.iter().any(|rule| rule.matches(&message)), true);
+
         let message = device_rssi_message("/org/bluez/hci0/dev_11_22_33_44_55_66", 42);
         assert_eq!(match_rules.iter().any(|rule| rule.matches(&message)), true);
 
@@ -333,6 +753,9 @@ mod tests {
         let message = adapter_powered_message("/org/bluez/hci0", true);
         assert_eq!(match_rules.iter().any(|rule| rule.matches(&message)), false);
 
+        let message = removed_adapter_message("/org/bluez/hci1");
+        assert_eq!(match_rules.iter().any(|rule| rule.matches(&message)), false);
+
         let message = device_rssi_message("/org/bluez/hci0/dev_11_22_33_44_55_66", 42);
         assert_eq!(match_rules.iter().any(|rule| rule.matches(&message)), true);
 
@@ -397,6 +820,28 @@ mod tests {
         interfaces_added.to_emit_message(&"/".into())
     }
 
+    fn new_adapter_message(adapter_path: &'static str) -> Message {
+        let properties = HashMap::new();
+        let mut interfaces = HashMap::new();
+        interfaces.insert("org.bluez.Adapter1".to_string(), properties);
+        let interfaces_added = ObjectManagerInterfacesAdded {
+            object: adapter_path.into(),
+            interfaces,
+        };
+        interfaces_added.to_emit_message(&"/".into())
+    }
+
+    fn removed_adapter_message(adapter_path: &'static str) -> Message {
+        let interfaces_removed = ObjectManagerInterfacesRemoved {
+            object: adapter_path.into(),
+            interfaces: vec![
+                "org.bluez.Adapter1".to_string(),
+                "org.bluez.GattManager1".to_string(),
+            ],
+        };
+        interfaces_removed.to_emit_message(&"/".into())
+    }
+
     fn adapter_powered_message(adapter_path: &'static str, powered: bool) -> Message {
         let mut changed_properties: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
         changed_properties.insert("Powered".to_string(), Variant(Box::new(powered)));
@@ -440,6 +885,18 @@ mod tests {
         properties_changed.to_emit_message(&device_path.into())
     }
 
+    fn device_properties_changed_message(
+        device_path: &'static str,
+        changed_properties: HashMap<String, Variant<Box<dyn RefArg>>>,
+    ) -> Message {
+        let properties_changed = PropertiesPropertiesChanged {
+            interface_name: "org.bluez.Device1".to_string(),
+            changed_properties,
+            invalidated_properties: vec![],
+        };
+        properties_changed.to_emit_message(&device_path.into())
+    }
+
     fn characteristic_value_message(characteristic_path: &'static str, value: &[u8]) -> Message {
         let mut changed_properties: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
         changed_properties.insert("Value".to_string(), Variant(Box::new(value.to_owned())));
@@ -0,0 +1,1606 @@
+//! A fake implementation of the BlueZ D-Bus API, for testing code which uses a
+//! [`BluetoothSession`](../struct.BluetoothSession.html) without a real Bluetooth adapter.
+//!
+//! [`FakeBluez`](struct.FakeBluez.html) starts a private D-Bus daemon, claims the `org.bluez` name
+//! on it, and publishes whatever adapters, devices, services, characteristics and descriptors you
+//! add to it. A `BluetoothSession` can then be connected to the same bus with
+//! [`BluetoothSession::new_with_address`](../struct.BluetoothSession.html#method.new_with_address).
+//!
+//! This is only available with the `fake` feature, and requires the `dbus-daemon` binary to be
+//! available on the `PATH`.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use bluez_async::fake::{FakeAdapter, FakeBluez, FakeDevice};
+//! use bluez_async::BluetoothSession;
+//!
+//! let fake = FakeBluez::start().await?;
+//! let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse()?));
+//! fake.add_device(&adapter, FakeDevice::new("11:22:33:44:55:66".parse()?));
+//!
+//! let (_, session) = BluetoothSession::new_with_address(fake.address()).await?;
+//! let devices = session.get_devices().await?;
+//! assert_eq!(devices.len(), 1);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    AdapterId, AddressType, CharacteristicFlags, CharacteristicId, DescriptorId, DeviceId,
+    MacAddress, ServiceId,
+};
+use bluez_generated::{
+    ORG_BLUEZ_ADAPTER1_NAME, ORG_BLUEZ_AGENT_MANAGER1_NAME, ORG_BLUEZ_BATTERY1_NAME,
+    ORG_BLUEZ_DEVICE1_NAME, ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME, ORG_BLUEZ_GATT_DESCRIPTOR1_NAME,
+    ORG_BLUEZ_GATT_MANAGER1_NAME, ORG_BLUEZ_GATT_SERVICE1_NAME,
+    ORG_BLUEZ_LEADVERTISING_MANAGER1_NAME, ORG_BLUEZ_PROFILE_MANAGER1_NAME,
+};
+use dbus::arg::{prop_cast, OwnedFd, PropMap, RefArg, Variant};
+use dbus::channel::{Channel, MatchingReceiver, Sender};
+use dbus::message::{MatchRule, SignalArgs};
+use dbus::nonblock::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus::{MethodErr, Path};
+use dbus_crossroads::{Context, Crossroads, IfaceBuilder, IfaceToken};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Debug, Formatter};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixStream;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// An error starting a fake BlueZ daemon.
+#[derive(Debug, Error)]
+pub enum FakeBluezError {
+    /// There was an error starting or talking to the private D-Bus daemon.
+    #[error("Error starting D-Bus daemon: {0}")]
+    DaemonStart(#[from] io::Error),
+    /// There was an error connecting to the private bus or claiming the BlueZ bus name on it.
+    #[error(transparent)]
+    Dbus(#[from] dbus::Error),
+}
+
+/// The initial state of a fake Bluetooth adapter.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FakeAdapter {
+    /// The MAC address of the adapter.
+    pub mac_address: MacAddress,
+    /// The type of MAC address the adapter uses.
+    pub address_type: AddressType,
+    /// The Bluetooth system name, e.g. the hostname.
+    pub name: String,
+    /// The Bluetooth friendly name.
+    pub alias: String,
+    /// Whether the adapter is currently turned on.
+    pub powered: bool,
+    /// Whether the adapter is currently discoverable by other devices.
+    pub discoverable: bool,
+    /// How long the adapter remains discoverable for, or zero for indefinitely.
+    pub discoverable_timeout: Duration,
+    /// Whether the adapter currently allows incoming pairing requests.
+    pub pairable: bool,
+    /// Whether the adapter is currently discovering devices.
+    pub discovering: bool,
+}
+
+impl FakeAdapter {
+    /// Create a powered-on, pairable adapter with the given MAC address, which is not currently
+    /// discoverable or discovering.
+    pub fn new(mac_address: MacAddress) -> Self {
+        FakeAdapter {
+            mac_address,
+            address_type: AddressType::Public,
+            name: "fake".to_owned(),
+            alias: "fake".to_owned(),
+            powered: true,
+            discoverable: false,
+            discoverable_timeout: Duration::from_secs(180),
+            pairable: true,
+            discovering: false,
+        }
+    }
+}
+
+/// The initial state of a fake Bluetooth device.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FakeDevice {
+    /// The MAC address of the device.
+    pub mac_address: MacAddress,
+    /// The type of MAC address the device uses.
+    pub address_type: AddressType,
+    /// The human-readable name of the device, if available.
+    pub name: Option<String>,
+    /// The alias of the device. BlueZ defaults this to the name, or the address if there is no name.
+    pub alias: String,
+    /// The appearance of the device, as defined by GAP.
+    pub appearance: Option<u16>,
+    /// The GATT service UUIDs (if any) from the device's advertisement or service discovery.
+    pub services: Vec<Uuid>,
+    /// Whether the device is currently paired with the adapter.
+    pub paired: bool,
+    /// Whether the device is trusted.
+    pub trusted: bool,
+    /// Whether connections from the device are blocked.
+    pub blocked: bool,
+    /// Whether the device is currently connected to the adapter.
+    pub connected: bool,
+    /// The Received Signal Strength Indicator of the device advertisement or inquiry.
+    pub rssi: Option<i16>,
+    /// The transmission power level advertised by the device.
+    pub tx_power: Option<i16>,
+    /// Manufacturer-specific advertisement data, keyed by company ID.
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
+    /// Service advertisement data, keyed by service UUID.
+    pub service_data: HashMap<Uuid, Vec<u8>>,
+    /// Whether service discovery has finished.
+    pub services_resolved: bool,
+    /// The battery level reported by BlueZ's battery plugin. The `org.bluez.Battery1` interface is
+    /// only added to the device if this is set when the device is added. As with BlueZ, `services`
+    /// should include the Battery service (0x180F) for it to be used.
+    pub battery_percentage: Option<u8>,
+    /// Whether attempts to connect to the device succeed. This isn't a BlueZ property, but lets
+    /// tests simulate a device which is out of range.
+    pub connectable: bool,
+    /// Whether calls to connect to the device get any reply. If this is false they will never be
+    /// answered, to let tests simulate BlueZ taking too long.
+    pub responsive: bool,
+}
+
+impl FakeDevice {
+    /// Create a disconnected device with the given MAC address and no other properties.
+    pub fn new(mac_address: MacAddress) -> Self {
+        FakeDevice {
+            alias: mac_address.to_string().replace(":", "-"),
+            mac_address,
+            address_type: AddressType::Public,
+            name: None,
+            appearance: None,
+            services: vec![],
+            paired: false,
+            trusted: false,
+            blocked: false,
+            connected: false,
+            rssi: None,
+            tx_power: None,
+            manufacturer_data: HashMap::new(),
+            service_data: HashMap::new(),
+            services_resolved: false,
+            battery_percentage: None,
+            connectable: true,
+            responsive: true,
+        }
+    }
+}
+
+/// A fake GATT service.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FakeService {
+    /// The 128-bit UUID of the service.
+    pub uuid: Uuid,
+    /// Whether this GATT service is a primary service.
+    pub primary: bool,
+}
+
+/// A fake GATT characteristic.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FakeCharacteristic {
+    /// The 128-bit UUID of the characteristic.
+    pub uuid: Uuid,
+    /// The set of flags (a.k.a. properties) of the characteristic, which determine which operations
+    /// the fake will allow on it.
+    pub flags: CharacteristicFlags,
+    /// The current value of the characteristic.
+    pub value: Vec<u8>,
+}
+
+/// A fake GATT descriptor.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FakeDescriptor {
+    /// The 128-bit UUID of the descriptor.
+    pub uuid: Uuid,
+    /// The current value of the descriptor.
+    pub value: Vec<u8>,
+}
+
+/// An object such as a GATT application or advertisement which a client has registered with one of
+/// the fake's manager interfaces.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FakeRegistration {
+    /// The adapter on which the object was registered.
+    pub adapter: AdapterId,
+    /// The unique bus name of the client which registered the object.
+    pub owner: String,
+    /// The path of the object which the client exports.
+    pub object_path: Path<'static>,
+}
+
+/// An agent which a client has registered with the fake's agent manager.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FakeAgent {
+    /// The unique bus name of the client which registered the agent.
+    pub owner: String,
+    /// The path of the agent object which the client exports.
+    pub object_path: Path<'static>,
+    /// The input and output capability with which the agent was registered.
+    pub capability: String,
+    /// Whether the client has requested that this be the default agent.
+    pub default: bool,
+}
+
+/// A Bluetooth Classic profile which a client has registered with the fake's profile manager.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FakeProfile {
+    /// The unique bus name of the client which registered the profile.
+    pub owner: String,
+    /// The path of the profile object which the client exports.
+    pub object_path: Path<'static>,
+    /// The UUID of the profile.
+    pub uuid: Uuid,
+    /// The name option with which the profile was registered, if any.
+    pub name: Option<String>,
+    /// The role option with which the profile was registered, if any.
+    pub role: Option<String>,
+    /// The RFCOMM channel option with which the profile was registered, if any.
+    pub channel: Option<u16>,
+}
+
+/// The name of the D-Bus interface which client profile objects implement.
+const ORG_BLUEZ_PROFILE1_NAME: &str = "org.bluez.Profile1";
+
+/// The ATT MTU which the fake reports for acquired notify and write file descriptors.
+const FAKE_MTU: u16 = 23;
+
+/// The state of a characteristic published by the fake.
+#[derive(Debug)]
+struct CharacteristicState {
+    characteristic: FakeCharacteristic,
+    notifying: bool,
+    /// The fake's end of the socket for notifications, if a client has acquired it.
+    notify_socket: Option<UnixStream>,
+    /// The fake's end of the socket for writes, if a client has acquired it.
+    write_socket: Option<UnixStream>,
+}
+
+impl CharacteristicState {
+    /// Apply any values which the client has written to the acquired write socket, and forget
+    /// about sockets which the client has closed.
+    fn receive_acquired(&mut self) {
+        if let Some(socket) = &self.write_socket {
+            let mut buffer = [0; FAKE_MTU as usize];
+            loop {
+                match (&*socket).read(&mut buffer) {
+                    Ok(0) => {
+                        self.write_socket = None;
+                        break;
+                    }
+                    Ok(length) => self.characteristic.value = buffer[..length].to_vec(),
+                    Err(_) => break,
+                }
+            }
+        }
+        if matches!(&self.notify_socket, Some(socket) if peer_closed(socket)) {
+            self.notify_socket = None;
+        }
+    }
+}
+
+/// Tokens for the BlueZ interfaces registered with the `Crossroads` instance.
+#[derive(Clone, Copy)]
+struct Interfaces {
+    adapter: IfaceToken<FakeAdapter>,
+    gatt_manager: IfaceToken<FakeAdapter>,
+    advertising_manager: IfaceToken<FakeAdapter>,
+    device: IfaceToken<FakeDevice>,
+    battery: IfaceToken<FakeDevice>,
+    service: IfaceToken<FakeService>,
+    characteristic: IfaceToken<CharacteristicState>,
+    descriptor: IfaceToken<FakeDescriptor>,
+}
+
+/// A fake BlueZ daemon running on a private D-Bus bus.
+///
+/// The bus and the fake are shut down when this is dropped.
+pub struct FakeBluez {
+    daemon: Child,
+    address: String,
+    connection: Arc<SyncConnection>,
+    crossroads: Arc<Mutex<Crossroads>>,
+    interfaces: Interfaces,
+    next_adapter_index: AtomicUsize,
+    next_handle: AtomicU16,
+    /// The paths of all adapters, devices and GATT objects which have been added.
+    object_paths: Arc<Mutex<BTreeSet<Path<'static>>>>,
+    dbus_task: JoinHandle<()>,
+    gatt_applications: Arc<Mutex<Vec<FakeRegistration>>>,
+    advertisements: Arc<Mutex<Vec<FakeRegistration>>>,
+    agents: Arc<Mutex<Vec<FakeAgent>>>,
+    profiles: Arc<Mutex<Vec<FakeProfile>>>,
+}
+
+impl Debug for FakeBluez {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "FakeBluez {{ address: {:?} }}", self.address)
+    }
+}
+
+impl FakeBluez {
+    /// Start a new private D-Bus daemon, and claim the `org.bluez` name on it.
+    ///
+    /// This must be called from within a Tokio runtime.
+    pub async fn start() -> Result<Self, FakeBluezError> {
+        // Start a private bus, and find out its address. This is blocking, but it only needs to
+        // wait for the daemon to start up.
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut address = String::new();
+        let stdout = daemon.stdout.take().unwrap();
+        if let Err(e) = BufReader::new(stdout).read_line(&mut address) {
+            daemon.kill().ok();
+            return Err(e.into());
+        }
+        let address = address.trim().to_owned();
+
+        let (dbus_resource, connection) = match connect(&address) {
+            Ok(resource_and_connection) => resource_and_connection,
+            Err(e) => {
+                daemon.kill().ok();
+                return Err(e.into());
+            }
+        };
+        let dbus_task = tokio::spawn(async {
+            let err = dbus_resource.await;
+            log::error!("Lost connection to fake BlueZ bus: {}", err);
+        });
+
+        let mut crossroads = Crossroads::new();
+        crossroads
+            .set_object_manager_support(Some(connection.clone() as Arc<dyn Sender + Send + Sync>));
+        let object_manager = crossroads.object_manager();
+        crossroads.insert("/", &[object_manager], ());
+        let gatt_applications = Arc::new(Mutex::new(vec![]));
+        let advertisements = Arc::new(Mutex::new(vec![]));
+        let agents = Arc::new(Mutex::new(vec![]));
+        let profiles = Arc::new(Mutex::new(vec![]));
+        let object_paths = Arc::new(Mutex::new(BTreeSet::new()));
+        let agent_manager = register_agent_manager(&mut crossroads, agents.clone());
+        let profile_manager = register_profile_manager(&mut crossroads, profiles.clone());
+        crossroads.insert("/org/bluez", &[agent_manager, profile_manager], ());
+        let interfaces = Interfaces {
+            adapter: register_adapter(&mut crossroads, object_paths.clone()),
+            gatt_manager: register_manager(
+                &mut crossroads,
+                ORG_BLUEZ_GATT_MANAGER1_NAME,
+                "RegisterApplication",
+                "UnregisterApplication",
+                gatt_applications.clone(),
+            ),
+            advertising_manager: register_manager(
+                &mut crossroads,
+                ORG_BLUEZ_LEADVERTISING_MANAGER1_NAME,
+                "RegisterAdvertisement",
+                "UnregisterAdvertisement",
+                advertisements.clone(),
+            ),
+            device: register_device(&mut crossroads),
+            battery: register_battery(&mut crossroads),
+            service: register_service(&mut crossroads),
+            characteristic: register_characteristic(&mut crossroads),
+            descriptor: register_descriptor(&mut crossroads),
+        };
+        let crossroads = Arc::new(Mutex::new(crossroads));
+
+        let receiver = crossroads.clone();
+        connection.start_receive(
+            MatchRule::new_method_call(),
+            Box::new(move |message, connection| {
+                receiver
+                    .lock()
+                    .unwrap()
+                    .handle_message(message, connection)
+                    .unwrap();
+                true
+            }),
+        );
+
+        // From here on the daemon will be stopped by `Drop` if anything goes wrong.
+        let fake = FakeBluez {
+            daemon,
+            address,
+            connection,
+            crossroads,
+            interfaces,
+            next_adapter_index: AtomicUsize::new(0),
+            next_handle: AtomicU16::new(1),
+            object_paths,
+            dbus_task,
+            gatt_applications,
+            advertisements,
+            agents,
+            profiles,
+        };
+        fake.connection
+            .request_name("org.bluez", false, true, false)
+            .await?;
+        Ok(fake)
+    }
+
+    /// Get the address of the private bus, to pass to
+    /// [`BluetoothSession::new_with_address`](../struct.BluetoothSession.html#method.new_with_address).
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Get the connection which the fake uses, e.g. to call methods on objects which a client has
+    /// exported, as BlueZ would.
+    pub fn connection(&self) -> Arc<SyncConnection> {
+        self.connection.clone()
+    }
+
+    /// Get the GATT applications which are currently registered with the fake.
+    pub fn gatt_applications(&self) -> Vec<FakeRegistration> {
+        self.gatt_applications.lock().unwrap().clone()
+    }
+
+    /// Get the LE advertisements which are currently registered with the fake.
+    pub fn advertisements(&self) -> Vec<FakeRegistration> {
+        self.advertisements.lock().unwrap().clone()
+    }
+
+    /// Get the agents which are currently registered with the fake.
+    pub fn agents(&self) -> Vec<FakeAgent> {
+        self.agents.lock().unwrap().clone()
+    }
+
+    /// Get the Bluetooth Classic profiles which are currently registered with the fake.
+    pub fn profiles(&self) -> Vec<FakeProfile> {
+        self.profiles.lock().unwrap().clone()
+    }
+
+    /// Connect the given device to the given registered profile, as BlueZ would when a remote
+    /// device connects. Returns the fake's end of the connection, which stands in for the remote
+    /// device.
+    pub async fn connect_profile(
+        &self,
+        profile: &FakeProfile,
+        device: &DeviceId,
+    ) -> Result<tokio::net::UnixStream, FakeBluezError> {
+        let (socket, fd) = socket_pair(libc::SOCK_STREAM)?;
+        let proxy = Proxy::new(
+            profile.owner.clone(),
+            profile.object_path.clone(),
+            Duration::from_secs(5),
+            self.connection.clone(),
+        );
+        proxy
+            .method_call::<(), _, _, _>(
+                ORG_BLUEZ_PROFILE1_NAME,
+                "NewConnection",
+                (device.object_path.clone(), fd, PropMap::new()),
+            )
+            .await?;
+        Ok(tokio::net::UnixStream::from_std(socket)?)
+    }
+
+    /// Add a new Bluetooth adapter.
+    pub fn add_adapter(&self, adapter: FakeAdapter) -> AdapterId {
+        let index = self.next_adapter_index.fetch_add(1, Ordering::Relaxed);
+        let id = AdapterId::new(&format!("/org/bluez/hci{}", index));
+        self.insert(
+            &id.object_path,
+            &[
+                self.interfaces.adapter,
+                self.interfaces.gatt_manager,
+                self.interfaces.advertising_manager,
+            ],
+            adapter,
+        );
+        id
+    }
+
+    /// Remove the given adapter and all its devices, as if it had been unplugged.
+    ///
+    /// Returns false if the adapter doesn't exist.
+    pub fn remove_adapter(&self, id: &AdapterId) -> bool {
+        self.remove_tree(&id.object_path)
+    }
+
+    /// Add a new Bluetooth device on the given adapter, as if it had just been discovered.
+    pub fn add_device(&self, adapter: &AdapterId, device: FakeDevice) -> DeviceId {
+        let id = DeviceId::new(&format!(
+            "{}/dev_{}",
+            adapter.object_path,
+            device.mac_address.to_string().replace(":", "_")
+        ));
+        if device.battery_percentage.is_some() {
+            self.insert(
+                &id.object_path,
+                &[self.interfaces.device, self.interfaces.battery],
+                device,
+            );
+        } else {
+            self.insert(&id.object_path, &[self.interfaces.device], device);
+        }
+        id
+    }
+
+    /// Remove the given device and all its services, as BlueZ does when a device which isn't paired
+    /// hasn't been seen for a while.
+    ///
+    /// Returns false if the device doesn't exist.
+    pub fn remove_device(&self, id: &DeviceId) -> bool {
+        self.remove_tree(&id.object_path)
+    }
+
+    /// Add a new GATT service to the given device.
+    pub fn add_service(&self, device: &DeviceId, service: FakeService) -> ServiceId {
+        let id = ServiceId::new(&format!(
+            "{}/service{:04x}",
+            device.object_path,
+            self.next_handle()
+        ));
+        self.insert(&id.object_path, &[self.interfaces.service], service);
+        id
+    }
+
+    /// Add a new GATT characteristic to the given service.
+    pub fn add_characteristic(
+        &self,
+        service: &ServiceId,
+        characteristic: FakeCharacteristic,
+    ) -> CharacteristicId {
+        let id = CharacteristicId::new(&format!(
+            "{}/char{:04x}",
+            service.object_path,
+            self.next_handle()
+        ));
+        let state = CharacteristicState {
+            characteristic,
+            notifying: false,
+            notify_socket: None,
+            write_socket: None,
+        };
+        self.insert(&id.object_path, &[self.interfaces.characteristic], state);
+        id
+    }
+
+    /// Add a new GATT descriptor to the given characteristic.
+    pub fn add_descriptor(
+        &self,
+        characteristic: &CharacteristicId,
+        descriptor: FakeDescriptor,
+    ) -> DescriptorId {
+        let id = DescriptorId::new(&format!(
+            "{}/desc{:04x}",
+            characteristic.object_path,
+            self.next_handle()
+        ));
+        self.insert(&id.object_path, &[self.interfaces.descriptor], descriptor);
+        id
+    }
+
+    /// Get the current state of the given device, or `None` if it doesn't exist.
+    pub fn device(&self, id: &DeviceId) -> Option<FakeDevice> {
+        self.crossroads
+            .lock()
+            .unwrap()
+            .data_mut::<FakeDevice>(&id.object_path)
+            .cloned()
+    }
+
+    /// Modify the state of the given device, emitting a `PropertiesChanged` signal for whichever
+    /// properties have changed.
+    ///
+    /// Returns false if the device doesn't exist.
+    pub fn update_device(&self, id: &DeviceId, update: impl FnOnce(&mut FakeDevice)) -> bool {
+        let messages = {
+            let mut crossroads = self.crossroads.lock().unwrap();
+            let device = match crossroads.data_mut::<FakeDevice>(&id.object_path) {
+                Some(device) => device,
+                None => return false,
+            };
+            let old = device.clone();
+            update(device);
+            vec![
+                device_properties_changed(&id.object_path, &old, device),
+                battery_properties_changed(&id.object_path, &old, device),
+            ]
+        };
+        for message in messages.into_iter().flatten() {
+            self.send(message);
+        }
+        true
+    }
+
+    /// Get the current value of the given characteristic, or `None` if it doesn't exist.
+    pub fn characteristic_value(&self, id: &CharacteristicId) -> Option<Vec<u8>> {
+        self.crossroads
+            .lock()
+            .unwrap()
+            .data_mut::<CharacteristicState>(&id.object_path)
+            .map(|state| {
+                state.receive_acquired();
+                state.characteristic.value.clone()
+            })
+    }
+
+    /// Set the value of the given characteristic. If notifications have been started on it then a
+    /// `PropertiesChanged` signal will be emitted, as BlueZ does when it receives a notification.
+    /// If a client has acquired notifications then the value will be sent on its socket instead.
+    ///
+    /// Returns false if the characteristic doesn't exist.
+    pub fn set_characteristic_value(
+        &self,
+        id: &CharacteristicId,
+        value: impl Into<Vec<u8>>,
+    ) -> bool {
+        let value = value.into();
+        let notifying = {
+            let mut crossroads = self.crossroads.lock().unwrap();
+            let state = match crossroads.data_mut::<CharacteristicState>(&id.object_path) {
+                Some(state) => state,
+                None => return false,
+            };
+            state.characteristic.value = value.clone();
+            state.receive_acquired();
+            if let Some(socket) = &state.notify_socket {
+                if let Err(e) = (&*socket).write(&value) {
+                    log::warn!("Error sending acquired notification: {}", e);
+                }
+            }
+            state.notifying
+        };
+        if notifying {
+            self.send(properties_changed(
+                &id.object_path,
+                ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+                "Value",
+                value,
+            ));
+        }
+        true
+    }
+
+    /// Insert an object with the given interfaces, and keep track of its path so that it can be
+    /// removed along with its parent later.
+    fn insert<D: Send + 'static>(
+        &self,
+        object_path: &Path<'static>,
+        interfaces: &[IfaceToken<D>],
+        data: D,
+    ) {
+        self.object_paths
+            .lock()
+            .unwrap()
+            .insert(object_path.clone());
+        self.crossroads
+            .lock()
+            .unwrap()
+            .insert(object_path.clone(), interfaces, data);
+    }
+
+    /// Remove the object at the given path and all objects under it.
+    ///
+    /// Returns false if there is no object at the given path.
+    fn remove_tree(&self, object_path: &Path<'static>) -> bool {
+        remove_tree(
+            &mut self.crossroads.lock().unwrap(),
+            &self.object_paths,
+            object_path,
+        )
+    }
+
+    fn next_handle(&self) -> u16 {
+        self.next_handle.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn send(&self, message: dbus::Message) {
+        if self.connection.send(message).is_err() {
+            log::error!("Failed to send signal from fake BlueZ.");
+        }
+    }
+}
+
+impl Drop for FakeBluez {
+    fn drop(&mut self) {
+        self.dbus_task.abort();
+        if let Err(e) = self.daemon.kill().and_then(|()| self.daemon.wait()) {
+            log::error!("Failed to stop fake BlueZ D-Bus daemon: {}", e);
+        }
+    }
+}
+
+/// Remove the object at the given path and all objects under it from the given `Crossroads`
+/// instance and set of known object paths.
+///
+/// The `Crossroads` instance must be locked before the set of object paths, as it is while handling
+/// method calls.
+///
+/// Returns false if there is no object at the given path.
+fn remove_tree(
+    crossroads: &mut Crossroads,
+    object_paths: &Mutex<BTreeSet<Path<'static>>>,
+    object_path: &Path<'static>,
+) -> bool {
+    let mut object_paths = object_paths.lock().unwrap();
+    if !object_paths.remove(object_path) {
+        return false;
+    }
+    let prefix = format!("{}/", object_path);
+    let children: Vec<Path<'static>> = object_paths
+        .iter()
+        .filter(|path| path.starts_with(&prefix))
+        .cloned()
+        .collect();
+    // Remove children before their parents, as BlueZ does.
+    for child in children.iter().rev() {
+        object_paths.remove(child);
+        crossroads.remove::<()>(child);
+    }
+    crossroads.remove::<()>(object_path);
+    true
+}
+
+/// Connect to the private bus at the given address.
+fn connect(
+    address: &str,
+) -> Result<
+    (
+        dbus_tokio::connection::IOResource<SyncConnection>,
+        Arc<SyncConnection>,
+    ),
+    dbus::Error,
+> {
+    let mut channel = Channel::open_private(address)?;
+    channel.register()?;
+    dbus_tokio::connection::from_channel(channel)
+}
+
+fn register_adapter(
+    crossroads: &mut Crossroads,
+    object_paths: Arc<Mutex<BTreeSet<Path<'static>>>>,
+) -> IfaceToken<FakeAdapter> {
+    crossroads.register(
+        ORG_BLUEZ_ADAPTER1_NAME,
+        move |b: &mut IfaceBuilder<FakeAdapter>| {
+            b.property("Address")
+                .get(|_, adapter| Ok(adapter.mac_address.to_string()));
+            b.property("AddressType")
+                .get(|_, adapter| Ok(adapter.address_type.to_string()));
+            b.property("Name")
+                .get(|_, adapter| Ok(adapter.name.clone()));
+            b.property("Alias")
+                .get(|_, adapter| Ok(adapter.alias.clone()))
+                .set(|_, adapter, alias: String| {
+                    adapter.alias = alias.clone();
+                    Ok(Some(alias))
+                });
+            b.property("Powered")
+                .get(|_, adapter| Ok(adapter.powered))
+                .set(|_, adapter, powered| {
+                    adapter.powered = powered;
+                    Ok(Some(powered))
+                });
+            b.property("Discoverable")
+                .get(|_, adapter| Ok(adapter.discoverable))
+                .set(|_, adapter, discoverable| {
+                    adapter.discoverable = discoverable;
+                    Ok(Some(discoverable))
+                });
+            b.property("DiscoverableTimeout")
+                .get(|_, adapter| Ok(adapter.discoverable_timeout.as_secs() as u32))
+                .set(|_, adapter, timeout: u32| {
+                    adapter.discoverable_timeout = Duration::from_secs(timeout.into());
+                    Ok(Some(timeout))
+                });
+            b.property("Pairable")
+                .get(|_, adapter| Ok(adapter.pairable))
+                .set(|_, adapter, pairable| {
+                    adapter.pairable = pairable;
+                    Ok(Some(pairable))
+                });
+            b.property("Discovering")
+                .get(|_, adapter| Ok(adapter.discovering));
+            b.method("StartDiscovery", (), (), |ctx, adapter, ()| {
+                if !adapter.powered {
+                    return Err(not_ready());
+                }
+                if !adapter.discovering {
+                    adapter.discovering = true;
+                    ctx.push_msg(properties_changed(
+                        ctx.path(),
+                        ORG_BLUEZ_ADAPTER1_NAME,
+                        "Discovering",
+                        true,
+                    ));
+                }
+                Ok(())
+            });
+            b.method("StopDiscovery", (), (), |ctx, adapter, ()| {
+                if !adapter.powered {
+                    return Err(not_ready());
+                }
+                if adapter.discovering {
+                    adapter.discovering = false;
+                    ctx.push_msg(properties_changed(
+                        ctx.path(),
+                        ORG_BLUEZ_ADAPTER1_NAME,
+                        "Discovering",
+                        false,
+                    ));
+                }
+                Ok(())
+            });
+            b.method(
+                "SetDiscoveryFilter",
+                ("properties",),
+                (),
+                |_, _, (_filter,): (PropMap,)| Ok(()),
+            );
+            let object_paths = object_paths.clone();
+            b.method_with_cr(
+                "RemoveDevice",
+                ("device",),
+                (),
+                move |ctx, crossroads, (device,): (Path<'static>,)| {
+                    let is_child = DeviceId {
+                        object_path: device.clone(),
+                    }
+                    .adapter()
+                    .object_path
+                        == *ctx.path();
+                    if !is_child
+                        || crossroads.data_mut::<FakeDevice>(&device).is_none()
+                        || !remove_tree(crossroads, &object_paths, &device)
+                    {
+                        return Err(("org.bluez.Error.DoesNotExist", "Does Not Exist").into());
+                    }
+                    Ok(())
+                },
+            );
+        },
+    )
+}
+
+/// Register an interface for a BlueZ manager object which clients use to register and unregister
+/// objects which they export, such as GATT applications or advertisements.
+fn register_manager(
+    crossroads: &mut Crossroads,
+    interface_name: &'static str,
+    register_method: &'static str,
+    unregister_method: &'static str,
+    registrations: Arc<Mutex<Vec<FakeRegistration>>>,
+) -> IfaceToken<FakeAdapter> {
+    crossroads.register(interface_name, |b: &mut IfaceBuilder<FakeAdapter>| {
+        let registered = registrations.clone();
+        b.method(
+            register_method,
+            ("object", "options"),
+            (),
+            move |ctx, _, (object_path, _options): (Path<'static>, PropMap)| {
+                let registration = FakeRegistration {
+                    adapter: AdapterId {
+                        object_path: ctx.path().clone(),
+                    },
+                    owner: message_sender(ctx)?,
+                    object_path,
+                };
+                let mut registrations = registered.lock().unwrap();
+                if registrations.contains(&registration) {
+                    return Err(("org.bluez.Error.AlreadyExists", "Already Exists").into());
+                }
+                registrations.push(registration);
+                Ok(())
+            },
+        );
+        b.method(
+            unregister_method,
+            ("object",),
+            (),
+            move |ctx, _, (object_path,): (Path<'static>,)| {
+                let sender = ctx.message().sender().map(|sender| sender.to_string());
+                let mut registrations = registrations.lock().unwrap();
+                let index = registrations
+                    .iter()
+                    .position(|registration| {
+                        &registration.adapter.object_path == ctx.path()
+                            && Some(&registration.owner) == sender.as_ref()
+                            && registration.object_path == object_path
+                    })
+                    .ok_or(("org.bluez.Error.DoesNotExist", "Does Not Exist"))?;
+                registrations.remove(index);
+                Ok(())
+            },
+        );
+    })
+}
+
+fn register_agent_manager(
+    crossroads: &mut Crossroads,
+    agents: Arc<Mutex<Vec<FakeAgent>>>,
+) -> IfaceToken<()> {
+    crossroads.register(ORG_BLUEZ_AGENT_MANAGER1_NAME, |b: &mut IfaceBuilder<()>| {
+        let registered = agents.clone();
+        b.method(
+            "RegisterAgent",
+            ("agent", "capability"),
+            (),
+            move |ctx, _, (object_path, capability): (Path<'static>, String)| {
+                let owner = message_sender(ctx)?;
+                let mut agents = registered.lock().unwrap();
+                if agents.iter().any(|agent| agent.owner == owner) {
+                    return Err(("org.bluez.Error.AlreadyExists", "Already Exists").into());
+                }
+                agents.push(FakeAgent {
+                    owner,
+                    object_path,
+                    capability,
+                    default: false,
+                });
+                Ok(())
+            },
+        );
+        let registered = agents.clone();
+        b.method(
+            "UnregisterAgent",
+            ("agent",),
+            (),
+            move |ctx, _, (object_path,): (Path<'static>,)| {
+                let mut agents = registered.lock().unwrap();
+                let index = find_agent(ctx, &agents, &object_path)?;
+                agents.remove(index);
+                Ok(())
+            },
+        );
+        b.method(
+            "RequestDefaultAgent",
+            ("agent",),
+            (),
+            move |ctx, _, (object_path,): (Path<'static>,)| {
+                let mut agents = agents.lock().unwrap();
+                let index = find_agent(ctx, &agents, &object_path)?;
+                for (i, agent) in agents.iter_mut().enumerate() {
+                    agent.default = i == index;
+                }
+                Ok(())
+            },
+        );
+    })
+}
+
+fn register_profile_manager(
+    crossroads: &mut Crossroads,
+    profiles: Arc<Mutex<Vec<FakeProfile>>>,
+) -> IfaceToken<()> {
+    crossroads.register(
+        ORG_BLUEZ_PROFILE_MANAGER1_NAME,
+        |b: &mut IfaceBuilder<()>| {
+            let registered = profiles.clone();
+            b.method(
+                "RegisterProfile",
+                ("profile", "UUID", "options"),
+                (),
+                move |ctx, _, (object_path, uuid, options): (Path<'static>, String, PropMap)| {
+                    let uuid = Uuid::parse_str(&uuid).map_err(|_| MethodErr::invalid_arg(&uuid))?;
+                    let mut profiles = registered.lock().unwrap();
+                    if profiles.iter().any(|profile| profile.uuid == uuid) {
+                        return Err(("org.bluez.Error.AlreadyExists", "Already Exists").into());
+                    }
+                    profiles.push(FakeProfile {
+                        owner: message_sender(ctx)?,
+                        object_path,
+                        uuid,
+                        name: prop_cast(&options, "Name").cloned(),
+                        role: prop_cast(&options, "Role").cloned(),
+                        channel: prop_cast(&options, "Channel").copied(),
+                    });
+                    Ok(())
+                },
+            );
+            b.method(
+                "UnregisterProfile",
+                ("profile",),
+                (),
+                move |ctx, _, (object_path,): (Path<'static>,)| {
+                    let owner = message_sender(ctx)?;
+                    let mut profiles = profiles.lock().unwrap();
+                    let index = profiles
+                        .iter()
+                        .position(|profile| {
+                            profile.owner == owner && profile.object_path == object_path
+                        })
+                        .ok_or(("org.bluez.Error.DoesNotExist", "Does Not Exist"))?;
+                    profiles.remove(index);
+                    Ok(())
+                },
+            );
+        },
+    )
+}
+
+/// Find the index of the agent with the given path which was registered by the sender of the
+/// current message.
+fn find_agent(
+    ctx: &Context,
+    agents: &[FakeAgent],
+    object_path: &Path<'static>,
+) -> Result<usize, MethodErr> {
+    let owner = message_sender(ctx)?;
+    agents
+        .iter()
+        .position(|agent| agent.owner == owner && &agent.object_path == object_path)
+        .ok_or_else(|| ("org.bluez.Error.DoesNotExist", "Does Not Exist").into())
+}
+
+fn message_sender(ctx: &Context) -> Result<String, MethodErr> {
+    Ok(ctx
+        .message()
+        .sender()
+        .ok_or_else(|| MethodErr::failed("Message has no sender"))?
+        .to_string())
+}
+
+fn register_device(crossroads: &mut Crossroads) -> IfaceToken<FakeDevice> {
+    crossroads.register(
+        ORG_BLUEZ_DEVICE1_NAME,
+        |b: &mut IfaceBuilder<FakeDevice>| {
+            b.property("Address")
+                .get(|_, device| Ok(device.mac_address.to_string()));
+            b.property("AddressType")
+                .get(|_, device| Ok(device.address_type.to_string()));
+            b.property("Name")
+                .get(|ctx, device| device.name.clone().ok_or_else(|| no_property(ctx.name())));
+            b.property("Alias")
+                .get(|_, device| Ok(device.alias.clone()))
+                .set(|_, device, alias: String| {
+                    device.alias = alias.clone();
+                    Ok(Some(alias))
+                });
+            b.property("Appearance")
+                .get(|ctx, device| device.appearance.ok_or_else(|| no_property(ctx.name())));
+            b.property("UUIDs")
+                .get(|_, device| Ok(uuids_to_strings(&device.services)));
+            b.property("Paired").get(|_, device| Ok(device.paired));
+            b.property("Trusted")
+                .get(|_, device| Ok(device.trusted))
+                .set(|_, device, trusted| {
+                    device.trusted = trusted;
+                    Ok(Some(trusted))
+                });
+            b.property("Blocked")
+                .get(|_, device| Ok(device.blocked))
+                .set(|_, device, blocked| {
+                    device.blocked = blocked;
+                    Ok(Some(blocked))
+                });
+            b.property("Connected")
+                .get(|_, device| Ok(device.connected));
+            b.property("RSSI")
+                .get(|ctx, device| device.rssi.ok_or_else(|| no_property(ctx.name())));
+            b.property("TxPower")
+                .get(|ctx, device| device.tx_power.ok_or_else(|| no_property(ctx.name())));
+            b.property("ManufacturerData")
+                .get(|_, device| Ok(manufacturer_data_to_dbus(&device.manufacturer_data)));
+            b.property("ServiceData")
+                .get(|_, device| Ok(service_data_to_dbus(&device.service_data)));
+            b.property("ServicesResolved")
+                .get(|_, device| Ok(device.services_resolved));
+            b.property("Adapter").get(|ctx, _| {
+                Ok(DeviceId {
+                    object_path: ctx.path().clone(),
+                }
+                .adapter()
+                .object_path)
+            });
+            b.method_with_cr_custom::<(), (), _, _>(
+                "Connect",
+                (),
+                (),
+                |mut ctx, crossroads, ()| {
+                    let device = match crossroads.data_mut::<FakeDevice>(ctx.path()) {
+                        Some(device) => device,
+                        None => {
+                            ctx.reply::<()>(Err(MethodErr::no_path(ctx.path())));
+                            return Some(ctx);
+                        }
+                    };
+                    if !device.responsive {
+                        // Never reply, so the caller will time out.
+                        return None;
+                    }
+                    if !device.connectable {
+                        ctx.reply::<()>(Err((
+                            "org.bluez.Error.Failed",
+                            "le-connection-abort-by-local",
+                        )
+                            .into()));
+                        return Some(ctx);
+                    }
+                    let old = device.clone();
+                    device.connected = true;
+                    device.services_resolved = true;
+                    if let Some(message) = device_properties_changed(ctx.path(), &old, device) {
+                        ctx.push_msg(message);
+                    }
+                    ctx.reply(Ok(()));
+                    Some(ctx)
+                },
+            );
+            b.method("Pair", (), (), |ctx, device, ()| {
+                if device.paired {
+                    return Err(("org.bluez.Error.AlreadyExists", "Already Paired").into());
+                }
+                device.paired = true;
+                ctx.push_msg(properties_changed(
+                    ctx.path(),
+                    ORG_BLUEZ_DEVICE1_NAME,
+                    "Paired",
+                    true,
+                ));
+                Ok(())
+            });
+            b.method("CancelPairing", (), (), |_, _, ()| {
+                // Pairing with the fake completes immediately, so there is never anything to cancel.
+                Err::<(), _>(("org.bluez.Error.DoesNotExist", "No pairing in progress").into())
+            });
+            b.method("Disconnect", (), (), |ctx, device, ()| {
+                let old = device.clone();
+                device.connected = false;
+                device.services_resolved = false;
+                if let Some(message) = device_properties_changed(ctx.path(), &old, device) {
+                    ctx.push_msg(message);
+                }
+                Ok(())
+            });
+        },
+    )
+}
+
+fn register_battery(crossroads: &mut Crossroads) -> IfaceToken<FakeDevice> {
+    crossroads.register(
+        ORG_BLUEZ_BATTERY1_NAME,
+        |b: &mut IfaceBuilder<FakeDevice>| {
+            b.property("Percentage").get(|ctx, device| {
+                device
+                    .battery_percentage
+                    .ok_or_else(|| no_property(ctx.name()))
+            });
+        },
+    )
+}
+
+fn register_service(crossroads: &mut Crossroads) -> IfaceToken<FakeService> {
+    crossroads.register(
+        ORG_BLUEZ_GATT_SERVICE1_NAME,
+        |b: &mut IfaceBuilder<FakeService>| {
+            b.property("UUID")
+                .get(|_, service| Ok(service.uuid.to_string()));
+            b.property("Primary").get(|_, service| Ok(service.primary));
+            b.property("Device").get(|ctx, _| {
+                Ok(ServiceId {
+                    object_path: ctx.path().clone(),
+                }
+                .device()
+                .object_path)
+            });
+        },
+    )
+}
+
+fn register_characteristic(crossroads: &mut Crossroads) -> IfaceToken<CharacteristicState> {
+    crossroads.register(
+        ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+        |b: &mut IfaceBuilder<CharacteristicState>| {
+            b.property("UUID")
+                .get(|_, state| Ok(state.characteristic.uuid.to_string()));
+            b.property("Flags")
+                .get(|_, state| Ok(state.characteristic.flags.to_strings()));
+            b.property("Value")
+                .get(|_, state| Ok(state.characteristic.value.clone()));
+            b.property("Notifying").get(|_, state| Ok(state.notifying));
+            b.property("MTU").get(|_, _| Ok(FAKE_MTU));
+            b.property("NotifyAcquired").get(|_, state| {
+                state.receive_acquired();
+                Ok(state.notify_socket.is_some())
+            });
+            b.property("WriteAcquired").get(|_, state| {
+                state.receive_acquired();
+                Ok(state.write_socket.is_some())
+            });
+            b.property("Service").get(|ctx, _| {
+                Ok(CharacteristicId {
+                    object_path: ctx.path().clone(),
+                }
+                .service()
+                .object_path)
+            });
+            b.method(
+                "ReadValue",
+                ("options",),
+                ("value",),
+                |_, state, (options,): (PropMap,)| {
+                    if !state
+                        .characteristic
+                        .flags
+                        .contains(CharacteristicFlags::READ)
+                    {
+                        return Err(not_permitted("Read not permitted"));
+                    }
+                    Ok((read_from_offset(&state.characteristic.value, &options)?,))
+                },
+            );
+            b.method(
+                "WriteValue",
+                ("value", "options"),
+                (),
+                |_, state, (value, options): (Vec<u8>, PropMap)| {
+                    if !state.characteristic.flags.intersects(
+                        CharacteristicFlags::WRITE | CharacteristicFlags::WRITE_WITHOUT_RESPONSE,
+                    ) {
+                        return Err(not_permitted("Write not permitted"));
+                    }
+                    write_at_offset(&mut state.characteristic.value, value, &options)
+                },
+            );
+            b.method(
+                "AcquireNotify",
+                ("options",),
+                ("fd", "mtu"),
+                |_, state, (_options,): (PropMap,)| {
+                    if !state
+                        .characteristic
+                        .flags
+                        .contains(CharacteristicFlags::NOTIFY)
+                    {
+                        return Err(not_supported("Notify not supported"));
+                    }
+                    state.receive_acquired();
+                    if state.notifying || state.notify_socket.is_some() {
+                        return Err(not_permitted("Notify already started"));
+                    }
+                    let (socket, fd) =
+                        socket_pair(libc::SOCK_SEQPACKET).map_err(|e| MethodErr::failed(&e))?;
+                    state.notify_socket = Some(socket);
+                    Ok((fd, FAKE_MTU))
+                },
+            );
+            b.method(
+                "AcquireWrite",
+                ("options",),
+                ("fd", "mtu"),
+                |_, state, (_options,): (PropMap,)| {
+                    if !state
+                        .characteristic
+                        .flags
+                        .contains(CharacteristicFlags::WRITE_WITHOUT_RESPONSE)
+                    {
+                        return Err(not_supported("Write without response not supported"));
+                    }
+                    state.receive_acquired();
+                    if state.write_socket.is_some() {
+                        return Err(not_permitted("Write already acquired"));
+                    }
+                    let (socket, fd) =
+                        socket_pair(libc::SOCK_SEQPACKET).map_err(|e| MethodErr::failed(&e))?;
+                    state.write_socket = Some(socket);
+                    Ok((fd, FAKE_MTU))
+                },
+            );
+            b.method("StartNotify", (), (), |ctx, state, ()| {
+                if !state
+                    .characteristic
+                    .flags
+                    .intersects(CharacteristicFlags::NOTIFY | CharacteristicFlags::INDICATE)
+                {
+                    return Err(not_supported("Notify not supported"));
+                }
+                if !state.notifying {
+                    state.notifying = true;
+                    ctx.push_msg(properties_changed(
+                        ctx.path(),
+                        ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+                        "Notifying",
+                        true,
+                    ));
+                }
+                Ok(())
+            });
+            b.method("StopNotify", (), (), |ctx, state, ()| {
+                if state.notifying {
+                    state.notifying = false;
+                    ctx.push_msg(properties_changed(
+                        ctx.path(),
+                        ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+                        "Notifying",
+                        false,
+                    ));
+                }
+                Ok(())
+            });
+        },
+    )
+}
+
+fn register_descriptor(crossroads: &mut Crossroads) -> IfaceToken<FakeDescriptor> {
+    crossroads.register(
+        ORG_BLUEZ_GATT_DESCRIPTOR1_NAME,
+        |b: &mut IfaceBuilder<FakeDescriptor>| {
+            b.property("UUID")
+                .get(|_, descriptor| Ok(descriptor.uuid.to_string()));
+            b.property("Value")
+                .get(|_, descriptor| Ok(descriptor.value.clone()));
+            b.property("Characteristic").get(|ctx, _| {
+                Ok(DescriptorId {
+                    object_path: ctx.path().clone(),
+                }
+                .characteristic()
+                .object_path)
+            });
+            b.method(
+                "ReadValue",
+                ("options",),
+                ("value",),
+                |_, descriptor, (options,): (PropMap,)| {
+                    Ok((read_from_offset(&descriptor.value, &options)?,))
+                },
+            );
+            b.method(
+                "WriteValue",
+                ("value", "options"),
+                (),
+                |_, descriptor, (value, options): (Vec<u8>, PropMap)| {
+                    write_at_offset(&mut descriptor.value, value, &options)
+                },
+            );
+        },
+    )
+}
+
+fn not_ready() -> MethodErr {
+    ("org.bluez.Error.NotReady", "Resource Not Ready").into()
+}
+
+/// Create a connected pair of sockets of the given type, e.g. `SOCK_SEQPACKET` as BlueZ uses for
+/// acquired notify and write file descriptors. The first is non-blocking, for the fake to use, and
+/// the second is to send to the client.
+fn socket_pair(socket_type: libc::c_int) -> io::Result<(UnixStream, OwnedFd)> {
+    let mut fds = [0; 2];
+    // Safe because we pass a valid pointer to an array of two file descriptors.
+    if unsafe {
+        libc::socketpair(
+            libc::AF_UNIX,
+            socket_type | libc::SOCK_CLOEXEC,
+            0,
+            fds.as_mut_ptr(),
+        )
+    } != 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    // Safe because socketpair just gave us ownership of both file descriptors.
+    let (socket, fd) = unsafe { (UnixStream::from_raw_fd(fds[0]), OwnedFd::new(fds[1])) };
+    socket.set_nonblocking(true)?;
+    Ok((socket, fd))
+}
+
+/// Whether the client has closed its end of the given non-blocking socket.
+fn peer_closed(socket: &UnixStream) -> bool {
+    let mut buffer = [0u8; 1];
+    // Safe because we pass a valid buffer along with its length.
+    let result = unsafe {
+        libc::recv(
+            socket.as_raw_fd(),
+            buffer.as_mut_ptr() as *mut libc::c_void,
+            buffer.len(),
+            libc::MSG_PEEK | libc::MSG_DONTWAIT,
+        )
+    };
+    result == 0
+}
+
+fn not_permitted(message: &str) -> MethodErr {
+    ("org.bluez.Error.NotPermitted", message).into()
+}
+
+fn not_supported(message: &str) -> MethodErr {
+    ("org.bluez.Error.NotSupported", message).into()
+}
+
+fn no_property(name: &str) -> MethodErr {
+    MethodErr::no_property(name)
+}
+
+fn get_offset(options: &PropMap) -> usize {
+    options
+        .get("offset")
+        .and_then(|offset| offset.0.as_u64())
+        .unwrap_or(0) as usize
+}
+
+fn read_from_offset(value: &[u8], options: &PropMap) -> Result<Vec<u8>, MethodErr> {
+    let offset = get_offset(options);
+    value
+        .get(offset..)
+        .map(|value| value.to_owned())
+        .ok_or_else(|| ("org.bluez.Error.InvalidOffset", "Invalid offset").into())
+}
+
+fn write_at_offset(
+    value: &mut Vec<u8>,
+    new_value: Vec<u8>,
+    options: &PropMap,
+) -> Result<(), MethodErr> {
+    let offset = get_offset(options);
+    if offset > value.len() {
+        return Err(("org.bluez.Error.InvalidOffset", "Invalid offset").into());
+    }
+    value.truncate(offset);
+    value.extend(new_value);
+    Ok(())
+}
+
+fn uuids_to_strings(uuids: &[Uuid]) -> Vec<String> {
+    uuids.iter().map(Uuid::to_string).collect()
+}
+
+fn manufacturer_data_to_dbus(data: &HashMap<u16, Vec<u8>>) -> HashMap<u16, Variant<Vec<u8>>> {
+    data.iter()
+        .map(|(&company_id, value)| (company_id, Variant(value.clone())))
+        .collect()
+}
+
+fn service_data_to_dbus(data: &HashMap<Uuid, Vec<u8>>) -> HashMap<String, Variant<Vec<u8>>> {
+    data.iter()
+        .map(|(uuid, value)| (uuid.to_string(), Variant(value.clone())))
+        .collect()
+}
+
+/// Construct a `PropertiesChanged` signal for a single property of the given object.
+fn properties_changed(
+    path: &Path<'static>,
+    interface_name: &str,
+    property_name: &str,
+    value: impl RefArg + 'static,
+) -> dbus::Message {
+    let mut changed_properties: PropMap = HashMap::new();
+    changed_properties.insert(property_name.to_owned(), Variant(Box::new(value)));
+    PropertiesPropertiesChanged {
+        interface_name: interface_name.to_owned(),
+        changed_properties,
+        invalidated_properties: vec![],
+    }
+    .to_emit_message(path)
+}
+
+/// A set of changed properties of some object, to be sent in a `PropertiesChanged` signal.
+#[derive(Debug, Default)]
+struct PropertyChanges {
+    changed_properties: PropMap,
+    invalidated_properties: Vec<String>,
+}
+
+impl PropertyChanges {
+    /// Record the given property as changed if its old and new values differ. If the new value
+    /// converts to `None` then the property is recorded as invalidated.
+    fn compare<T: PartialEq, V: RefArg + 'static>(
+        &mut self,
+        name: &str,
+        old: &T,
+        new: &T,
+        to_dbus: impl FnOnce(&T) -> Option<V>,
+    ) {
+        if old != new {
+            match to_dbus(new) {
+                Some(value) => {
+                    self.changed_properties
+                        .insert(name.to_owned(), Variant(Box::new(value)));
+                }
+                None => self.invalidated_properties.push(name.to_owned()),
+            }
+        }
+    }
+
+    fn into_message(self, path: &Path<'static>, interface_name: &str) -> Option<dbus::Message> {
+        if self.changed_properties.is_empty() && self.invalidated_properties.is_empty() {
+            return None;
+        }
+        Some(
+            PropertiesPropertiesChanged {
+                interface_name: interface_name.to_owned(),
+                changed_properties: self.changed_properties,
+                invalidated_properties: self.invalidated_properties,
+            }
+            .to_emit_message(path),
+        )
+    }
+}
+
+/// Construct a `PropertiesChanged` signal for whichever properties differ between the old and new
+/// states of the given device, or `None` if nothing has changed.
+fn device_properties_changed(
+    path: &Path<'static>,
+    old: &FakeDevice,
+    new: &FakeDevice,
+) -> Option<dbus::Message> {
+    let mut changes = PropertyChanges::default();
+    changes.compare(
+        "Address",
+        &old.mac_address,
+        &new.mac_address,
+        |mac_address| Some(mac_address.to_string()),
+    );
+    changes.compare(
+        "AddressType",
+        &old.address_type,
+        &new.address_type,
+        |address_type| Some(address_type.to_string()),
+    );
+    changes.compare("Name", &old.name, &new.name, |name| name.clone());
+    changes.compare("Alias", &old.alias, &new.alias, |alias| Some(alias.clone()));
+    changes.compare(
+        "Appearance",
+        &old.appearance,
+        &new.appearance,
+        |appearance| *appearance,
+    );
+    changes.compare("UUIDs", &old.services, &new.services, |services| {
+        Some(uuids_to_strings(services))
+    });
+    changes.compare("Paired", &old.paired, &new.paired, |paired| Some(*paired));
+    changes.compare("Trusted", &old.trusted, &new.trusted, |trusted| {
+        Some(*trusted)
+    });
+    changes.compare("Blocked", &old.blocked, &new.blocked, |blocked| {
+        Some(*blocked)
+    });
+    changes.compare("Connected", &old.connected, &new.connected, |connected| {
+        Some(*connected)
+    });
+    changes.compare("RSSI", &old.rssi, &new.rssi, |rssi| *rssi);
+    changes.compare("TxPower", &old.tx_power, &new.tx_power, |tx_power| {
+        *tx_power
+    });
+    changes.compare(
+        "ManufacturerData",
+        &old.manufacturer_data,
+        &new.manufacturer_data,
+        |manufacturer_data| Some(manufacturer_data_to_dbus(manufacturer_data)),
+    );
+    changes.compare(
+        "ServiceData",
+        &old.service_data,
+        &new.service_data,
+        |service_data| Some(service_data_to_dbus(service_data)),
+    );
+    changes.compare(
+        "ServicesResolved",
+        &old.services_resolved,
+        &new.services_resolved,
+        |services_resolved| Some(*services_resolved),
+    );
+    changes.into_message(path, ORG_BLUEZ_DEVICE1_NAME)
+}
+
+/// Construct a `PropertiesChanged` signal for the battery interface of the given device if its
+/// battery level has changed, or `None` if it hasn't.
+fn battery_properties_changed(
+    path: &Path<'static>,
+    old: &FakeDevice,
+    new: &FakeDevice,
+) -> Option<dbus::Message> {
+    let mut changes = PropertyChanges::default();
+    changes.compare(
+        "Percentage",
+        &old.battery_percentage,
+        &new.battery_percentage,
+        |battery_percentage| *battery_percentage,
+    );
+    changes.into_message(path, ORG_BLUEZ_BATTERY1_NAME)
+}
@@ -0,0 +1,417 @@
+//! A cache of the GATT services, characteristics and descriptors of each device, so that looking
+//! them up doesn't need several D-Bus round trips every time.
+
+use bluez_generated::{
+    OrgBluezDevice1Properties, OrgBluezGattCharacteristic1Properties,
+    OrgBluezGattDescriptor1Properties, OrgBluezGattService1Properties, ORG_BLUEZ_DEVICE1_NAME,
+    ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+};
+use dbus::arg::PropMap;
+use dbus::message::SignalArgs;
+use dbus::nonblock::stdintf::org_freedesktop_dbus::{
+    ObjectManagerInterfacesAdded, ObjectManagerInterfacesRemoved, PropertiesPropertiesChanged,
+};
+use dbus::nonblock::{MsgMatch, SyncConnection};
+use dbus::{Message, Path};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Mutex as AsyncMutex;
+use uuid::Uuid;
+
+use crate::{
+    BluetoothError, BluetoothEvent, CharacteristicId, CharacteristicInfo, DescriptorId,
+    DescriptorInfo, DeviceId, ServiceId, ServiceInfo,
+};
+
+/// A snapshot of all the GATT services, characteristics and descriptors of a device, as returned
+/// by [`BluetoothSession::get_gatt_database`](struct.BluetoothSession.html#method.get_gatt_database).
+///
+/// This can be serialized, e.g. with [`to_json`](#method.to_json), to record the structure of a
+/// device for debugging.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct GattDatabase {
+    /// The device which the services belong to.
+    pub device: DeviceId,
+    /// The GATT services of the device, in the order of their object paths.
+    pub services: Vec<GattDatabaseService>,
+}
+
+/// A GATT service along with its characteristics, as part of a [`GattDatabase`].
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct GattDatabaseService {
+    /// Information about the service itself.
+    #[serde(flatten)]
+    pub info: ServiceInfo,
+    /// The characteristics of the service.
+    pub characteristics: Vec<GattDatabaseCharacteristic>,
+}
+
+/// A GATT characteristic along with its descriptors, as part of a [`GattDatabase`].
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct GattDatabaseCharacteristic {
+    /// Information about the characteristic itself.
+    #[serde(flatten)]
+    pub info: CharacteristicInfo,
+    /// The descriptors of the characteristic.
+    pub descriptors: Vec<DescriptorInfo>,
+}
+
+impl GattDatabase {
+    /// Build the database for the given device from the objects returned by BlueZ's
+    /// `GetManagedObjects`.
+    pub(crate) fn from_managed_objects(
+        device: &DeviceId,
+        objects: &HashMap<Path<'static>, HashMap<String, PropMap>>,
+    ) -> Result<Self, BluetoothError> {
+        let prefix = format!("{}/", device.object_path);
+        let mut paths: Vec<_> = objects
+            .keys()
+            .filter(|path| path.starts_with(&prefix))
+            .collect();
+        // Sorting the paths puts each object after its parent, and keeps the order stable.
+        paths.sort();
+
+        let mut services: Vec<GattDatabaseService> = vec![];
+        for path in paths {
+            let interfaces = &objects[path];
+            if let Some(service) = OrgBluezGattService1Properties::from_interfaces(interfaces) {
+                let uuid = Uuid::parse_str(
+                    service
+                        .uuid()
+                        .ok_or(BluetoothError::RequiredPropertyMissing("UUID"))?,
+                )?;
+                services.push(GattDatabaseService {
+                    info: ServiceInfo {
+                        id: ServiceId {
+                            object_path: path.clone(),
+                        },
+                        uuid,
+                        primary: service
+                            .primary()
+                            .ok_or(BluetoothError::RequiredPropertyMissing("Primary"))?,
+                    },
+                    characteristics: vec![],
+                });
+            } else if let Some(characteristic) =
+                OrgBluezGattCharacteristic1Properties::from_interfaces(interfaces)
+            {
+                let id = CharacteristicId {
+                    object_path: path.clone(),
+                };
+                let service_id = id.service();
+                let info = CharacteristicInfo::from_properties(id, characteristic)?;
+                if let Some(service) = services
+                    .iter_mut()
+                    .find(|service| service.info.id == service_id)
+                {
+                    service.characteristics.push(GattDatabaseCharacteristic {
+                        info,
+                        descriptors: vec![],
+                    });
+                }
+            } else if let Some(descriptor) =
+                OrgBluezGattDescriptor1Properties::from_interfaces(interfaces)
+            {
+                let id = DescriptorId {
+                    object_path: path.clone(),
+                };
+                let characteristic_id = id.characteristic();
+                let uuid = Uuid::parse_str(
+                    descriptor
+                        .uuid()
+                        .ok_or(BluetoothError::RequiredPropertyMissing("UUID"))?,
+                )?;
+                if let Some(characteristic) = services
+                    .iter_mut()
+                    .flat_map(|service| service.characteristics.iter_mut())
+                    .find(|characteristic| characteristic.info.id == characteristic_id)
+                {
+                    characteristic.descriptors.push(DescriptorInfo { id, uuid });
+                }
+            }
+        }
+
+        Ok(Self {
+            device: device.to_owned(),
+            services,
+        })
+    }
+
+    /// Find the given service in the database.
+    pub fn service(&self, id: &ServiceId) -> Option<&GattDatabaseService> {
+        self.services.iter().find(|service| &service.info.id == id)
+    }
+
+    /// Find the given characteristic in the database.
+    pub fn characteristic(&self, id: &CharacteristicId) -> Option<&GattDatabaseCharacteristic> {
+        self.service(&id.service())?
+            .characteristics
+            .iter()
+            .find(|characteristic| &characteristic.info.id == id)
+    }
+
+    /// Serialize the database as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Failed to serialize GATT database")
+    }
+}
+
+/// Whether BlueZ has finished resolving the services of the given device, so that the set of GATT
+/// objects under it is complete.
+pub(crate) fn services_resolved(
+    device: &DeviceId,
+    objects: &HashMap<Path<'static>, HashMap<String, PropMap>>,
+) -> bool {
+    objects
+        .get(&device.object_path)
+        .and_then(OrgBluezDevice1Properties::from_interfaces)
+        .and_then(|device| device.services_resolved())
+        .unwrap_or(false)
+}
+
+/// The cached GATT databases of all devices, which are invalidated whenever a device connects or
+/// disconnects, its services are resolved, the MTU of one of its characteristics changes, or GATT
+/// objects are added to or removed from it.
+#[derive(Default)]
+pub(crate) struct GattCache {
+    databases: Arc<Mutex<HashMap<DeviceId, Arc<GattDatabase>>>>,
+    /// Incremented every time any device is invalidated, so that a database fetched while an
+    /// invalidation happened isn't cached.
+    generation: Arc<AtomicU64>,
+    /// The matches for the signals which invalidate the cache, once they have been added. These
+    /// must be kept alive for their callbacks to be called.
+    matches: AsyncMutex<Option<Vec<MsgMatch>>>,
+}
+
+impl Debug for GattCache {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("GattCache")
+            .field("databases", &self.databases)
+            .field("generation", &self.generation)
+            .finish()
+    }
+}
+
+impl GattCache {
+    /// Get the cached database for the given device, if there is one.
+    pub fn get(&self, device: &DeviceId) -> Option<Arc<GattDatabase>> {
+        self.databases.lock().unwrap().get(device).cloned()
+    }
+
+    /// Get the current generation, to pass to `insert` after fetching a database.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Cache the given database, unless anything has been invalidated since `generation` was
+    /// read.
+    pub fn insert(&self, generation: u64, database: Arc<GattDatabase>) {
+        let mut databases = self.databases.lock().unwrap();
+        // Check the generation while holding the lock, as invalidation also takes it.
+        if self.generation() == generation {
+            databases.insert(database.device.clone(), database);
+        }
+    }
+
+    /// Make sure that the signals which invalidate the cache are being watched. This must be
+    /// called before reading anything to insert into the cache.
+    pub async fn watch(&self, connection: &SyncConnection) -> Result<(), BluetoothError> {
+        let mut matches = self.matches.lock().await;
+        if matches.is_none() {
+            let mut new_matches = vec![];
+            for match_rule in BluetoothEvent::match_rules(None::<DeviceId>) {
+                let databases = self.databases.clone();
+                let generation = self.generation.clone();
+                let msg_match = connection
+                    .add_match(match_rule)
+                    .await?
+                    .msg_cb(move |message| {
+                        if let Some(device) = invalidated_device(&message) {
+                            let mut databases = databases.lock().unwrap();
+                            generation.fetch_add(1, Ordering::SeqCst);
+                            databases.remove(&device);
+                        }
+                        true
+                    });
+                new_matches.push(msg_match);
+            }
+            *matches = Some(new_matches);
+        }
+        Ok(())
+    }
+}
+
+/// Return the device whose GATT database the given signal invalidates, if any.
+fn invalidated_device(message: &Message) -> Option<DeviceId> {
+    let object_path = if let Some(properties_changed) =
+        PropertiesPropertiesChanged::from_message(message)
+    {
+        let changed_properties = &properties_changed.changed_properties;
+        match properties_changed.interface_name.as_str() {
+            ORG_BLUEZ_DEVICE1_NAME => {
+                let device = OrgBluezDevice1Properties(changed_properties);
+                if device.connected().is_none() && device.services_resolved().is_none() {
+                    return None;
+                }
+            }
+            // The MTU is negotiated on each connection, and may be different after reconnecting.
+            ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME => {
+                OrgBluezGattCharacteristic1Properties(changed_properties).mtu()?;
+            }
+            _ => return None,
+        }
+        message.path()?.into_static()
+    } else if let Some(interfaces_added) = ObjectManagerInterfacesAdded::from_message(message) {
+        interfaces_added.object
+    } else if let Some(interfaces_removed) = ObjectManagerInterfacesRemoved::from_message(message) {
+        interfaces_removed.object
+    } else {
+        return None;
+    };
+    device_for_path(&object_path)
+}
+
+/// Get the device which the object at the given path belongs to, if any.
+fn device_for_path(object_path: &str) -> Option<DeviceId> {
+    // Device paths are always of the form /org/bluez/{hci0,hci1,...}/dev_XX_XX_XX_XX_XX_XX, and
+    // GATT objects are under them.
+    let mut end = 0;
+    for component in object_path.split('/').skip(1) {
+        end += component.len() + 1;
+        if component.starts_with("dev_") {
+            return Some(DeviceId::new(&object_path[..end]));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CharacteristicFlags;
+    use dbus::arg::Variant;
+
+    #[test]
+    fn device_paths() {
+        let device = DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66");
+        assert_eq!(
+            device_for_path("/org/bluez/hci0/dev_11_22_33_44_55_66"),
+            Some(device.clone())
+        );
+        assert_eq!(
+            device_for_path("/org/bluez/hci0/dev_11_22_33_44_55_66/service0012/char0034"),
+            Some(device)
+        );
+        assert_eq!(device_for_path("/org/bluez/hci0"), None);
+    }
+
+    #[test]
+    fn invalidated_by_connection_changes() {
+        let path = "/org/bluez/hci0/dev_11_22_33_44_55_66";
+        let message = |property: &str| {
+            let mut changed_properties: PropMap = HashMap::new();
+            changed_properties.insert(property.to_string(), Variant(Box::new(false)));
+            PropertiesPropertiesChanged {
+                interface_name: ORG_BLUEZ_DEVICE1_NAME.to_string(),
+                changed_properties,
+                invalidated_properties: vec![],
+            }
+            .to_emit_message(&path.into())
+        };
+        assert_eq!(invalidated_device(&message("Paired")), None);
+        assert_eq!(
+            invalidated_device(&message("Connected")),
+            Some(DeviceId::new(path))
+        );
+        assert_eq!(
+            invalidated_device(&message("ServicesResolved")),
+            Some(DeviceId::new(path))
+        );
+    }
+
+    #[test]
+    fn invalidated_by_mtu_change() {
+        let path = "/org/bluez/hci0/dev_11_22_33_44_55_66/service0012/char0034";
+        let message = |property: &str| {
+            let mut changed_properties: PropMap = HashMap::new();
+            changed_properties.insert(property.to_string(), Variant(Box::new(247u16)));
+            PropertiesPropertiesChanged {
+                interface_name: ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME.to_string(),
+                changed_properties,
+                invalidated_properties: vec![],
+            }
+            .to_emit_message(&path.into())
+        };
+        assert_eq!(invalidated_device(&message("Notifying")), None);
+        assert_eq!(
+            invalidated_device(&message("MTU")),
+            Some(DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66"))
+        );
+    }
+
+    #[test]
+    fn stale_insert_ignored() {
+        let cache = GattCache::default();
+        let device = DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66");
+        let database = Arc::new(GattDatabase {
+            device: device.clone(),
+            services: vec![],
+        });
+
+        let generation = cache.generation();
+        cache.generation.fetch_add(1, Ordering::SeqCst);
+        cache.insert(generation, database.clone());
+        assert_eq!(cache.get(&device), None);
+
+        cache.insert(cache.generation(), database.clone());
+        assert_eq!(cache.get(&device), Some(database));
+    }
+
+    #[test]
+    fn database_json() {
+        let device = DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66");
+        let database = GattDatabase {
+            device,
+            services: vec![GattDatabaseService {
+                info: ServiceInfo {
+                    id: ServiceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66/service0001"),
+                    uuid: Uuid::from_u128(0x0000180f_0000_1000_8000_00805f9b34fb),
+                    primary: true,
+                },
+                characteristics: vec![GattDatabaseCharacteristic {
+                    info: CharacteristicInfo {
+                        id: CharacteristicId::new(
+                            "/org/bluez/hci0/dev_11_22_33_44_55_66/service0001/char0002",
+                        ),
+                        uuid: Uuid::from_u128(0x00002a19_0000_1000_8000_00805f9b34fb),
+                        flags: CharacteristicFlags::READ | CharacteristicFlags::NOTIFY,
+                        mtu: None,
+                    },
+                    descriptors: vec![],
+                }],
+            }],
+        };
+        let json: serde_json::Value = serde_json::from_str(&database.to_json()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "device": "/org/bluez/hci0/dev_11_22_33_44_55_66",
+                "services": [{
+                    "id": "/org/bluez/hci0/dev_11_22_33_44_55_66/service0001",
+                    "uuid": "0000180f-0000-1000-8000-00805f9b34fb",
+                    "primary": true,
+                    "characteristics": [{
+                        "id": "/org/bluez/hci0/dev_11_22_33_44_55_66/service0001/char0002",
+                        "uuid": "00002a19-0000-1000-8000-00805f9b34fb",
+                        "flags": ["read", "notify"],
+                        "mtu": null,
+                        "descriptors": [],
+                    }],
+                }],
+            })
+        );
+    }
+}
@@ -0,0 +1,670 @@
+//! Support for exporting local GATT applications (i.e. acting as a GATT server, or peripheral) via
+//! the BlueZ GattManager1 interface.
+
+use async_trait::async_trait;
+use bluez_generated::{
+    OrgBluezGattManager1, ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME, ORG_BLUEZ_GATT_DESCRIPTOR1_NAME,
+    ORG_BLUEZ_GATT_SERVICE1_NAME,
+};
+use dbus::arg::{prop_cast, PropMap, RefArg, Variant};
+use dbus::channel::Sender;
+use dbus::message::SignalArgs;
+use dbus::nonblock::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus::{MethodErr, Path};
+use dbus_crossroads::{Crossroads, IfaceBuilder, IfaceToken};
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{AdapterId, CharacteristicFlags, DescriptorFlags, DeviceId, WriteType};
+
+/// Prefix for the object paths of local GATT applications.
+const APPLICATION_PATH_PREFIX: &str = "/bluez_async/gatt";
+
+/// Counter used to give each local GATT application a unique object path.
+static NEXT_APPLICATION_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+/// An error which a local GATT characteristic or descriptor handler can return to a remote device.
+#[derive(Clone, Copy, Debug, Eq, Error, PartialEq)]
+pub enum GattError {
+    /// The operation failed for some unspecified reason.
+    #[error("Operation failed")]
+    Failed,
+    /// Another operation is already in progress.
+    #[error("Operation already in progress")]
+    InProgress,
+    /// The operation is not permitted.
+    #[error("Operation not permitted")]
+    NotPermitted,
+    /// The value written had an invalid length.
+    #[error("Invalid value length")]
+    InvalidValueLength,
+    /// The offset of a read or write was invalid.
+    #[error("Invalid offset")]
+    InvalidOffset,
+    /// The remote device is not authorized to carry out the operation.
+    #[error("Not authorized")]
+    NotAuthorized,
+    /// The operation is not supported.
+    #[error("Operation not supported")]
+    NotSupported,
+}
+
+impl GattError {
+    fn error_name(&self) -> &'static str {
+        match self {
+            Self::Failed => "org.bluez.Error.Failed",
+            Self::InProgress => "org.bluez.Error.InProgress",
+            Self::NotPermitted => "org.bluez.Error.NotPermitted",
+            Self::InvalidValueLength => "org.bluez.Error.InvalidValueLength",
+            Self::InvalidOffset => "org.bluez.Error.InvalidOffset",
+            Self::NotAuthorized => "org.bluez.Error.NotAuthorized",
+            Self::NotSupported => "org.bluez.Error.NotSupported",
+        }
+    }
+}
+
+impl From<GattError> for MethodErr {
+    fn from(error: GattError) -> Self {
+        (error.error_name(), error.to_string()).into()
+    }
+}
+
+/// Details of a request from a remote device to read a local characteristic or descriptor.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ReadRequest {
+    /// The offset from which to read.
+    pub offset: usize,
+    /// The exchanged MTU of the connection, if known.
+    pub mtu: Option<u16>,
+    /// The remote device which is making the request, if known.
+    pub device: Option<DeviceId>,
+}
+
+impl ReadRequest {
+    fn from_options(options: &PropMap) -> Self {
+        ReadRequest {
+            offset: get_offset(options),
+            mtu: get_mtu(options),
+            device: get_device(options),
+        }
+    }
+}
+
+/// Details of a request from a remote device to write a local characteristic or descriptor.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct WriteRequest {
+    /// The offset at which to write.
+    pub offset: usize,
+    /// The type of write operation, if known.
+    pub write_type: Option<WriteType>,
+    /// The exchanged MTU of the connection, if known.
+    pub mtu: Option<u16>,
+    /// The remote device which is making the request, if known.
+    pub device: Option<DeviceId>,
+    /// True if this is a request to authorize a prepared write, rather than the write itself.
+    pub prepare_authorize: bool,
+}
+
+impl WriteRequest {
+    fn from_options(options: &PropMap) -> Self {
+        WriteRequest {
+            offset: get_offset(options),
+            write_type: options
+                .get("type")
+                .and_then(|write_type| write_type.0.as_str())
+                .and_then(WriteType::from_bluez_str),
+            mtu: get_mtu(options),
+            device: get_device(options),
+            prepare_authorize: prop_cast::<bool>(options, "prepare-authorize")
+                .copied()
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Handler for operations from remote devices on a characteristic of a local GATT application.
+///
+/// All methods have default implementations which reject the operation, so you only need to
+/// implement those which the characteristic's flags allow.
+#[async_trait]
+pub trait CharacteristicHandler: Send + Sync {
+    /// Handle a request to read the value of the characteristic.
+    async fn read(&self, _request: ReadRequest) -> Result<Vec<u8>, GattError> {
+        Err(GattError::NotSupported)
+    }
+
+    /// Handle a request to write the value of the characteristic.
+    async fn write(&self, _value: Vec<u8>, _request: WriteRequest) -> Result<(), GattError> {
+        Err(GattError::NotSupported)
+    }
+
+    /// Called when a remote device subscribes to notifications or indications. The given notifier
+    /// may be used to send new values until `stop_notify` is called.
+    async fn start_notify(&self, _notifier: CharacteristicNotifier) -> Result<(), GattError> {
+        Err(GattError::NotSupported)
+    }
+
+    /// Called when there are no longer any remote devices subscribed to notifications or
+    /// indications.
+    async fn stop_notify(&self) {}
+}
+
+/// Handler for operations from remote devices on a descriptor of a local GATT application.
+///
+/// All methods have default implementations which reject the operation, so you only need to
+/// implement those which the descriptor's flags allow.
+#[async_trait]
+pub trait DescriptorHandler: Send + Sync {
+    /// Handle a request to read the value of the descriptor.
+    async fn read(&self, _request: ReadRequest) -> Result<Vec<u8>, GattError> {
+        Err(GattError::NotSupported)
+    }
+
+    /// Handle a request to write the value of the descriptor.
+    async fn write(&self, _value: Vec<u8>, _request: WriteRequest) -> Result<(), GattError> {
+        Err(GattError::NotSupported)
+    }
+}
+
+/// A handle which a [`CharacteristicHandler`](trait.CharacteristicHandler.html) can use to send
+/// notifications or indications of new values of its characteristic to subscribed remote devices.
+#[derive(Clone)]
+pub struct CharacteristicNotifier {
+    connection: Arc<SyncConnection>,
+    object_path: Path<'static>,
+}
+
+impl Debug for CharacteristicNotifier {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "CharacteristicNotifier {{ object_path: {} }}",
+            self.object_path
+        )
+    }
+}
+
+impl CharacteristicNotifier {
+    /// Send the given value to all subscribed remote devices.
+    pub fn notify(&self, value: impl Into<Vec<u8>>) {
+        let mut changed_properties: PropMap = HashMap::new();
+        changed_properties.insert("Value".to_owned(), Variant(Box::new(value.into())));
+        let message = PropertiesPropertiesChanged {
+            interface_name: ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME.to_owned(),
+            changed_properties,
+            invalidated_properties: vec![],
+        }
+        .to_emit_message(&self.object_path);
+        if self.connection.send(message).is_err() {
+            log::error!("Failed to send notification for {}", self.object_path);
+        }
+    }
+}
+
+/// A local GATT application to register with BlueZ, consisting of a set of services.
+#[derive(Clone, Debug, Default)]
+pub struct GattApplication {
+    /// The services which make up the application.
+    pub services: Vec<GattService>,
+}
+
+/// A GATT service which is part of a local GATT application.
+#[derive(Clone, Debug)]
+pub struct GattService {
+    /// The 128-bit UUID of the service.
+    pub uuid: Uuid,
+    /// Whether this is a primary service.
+    pub primary: bool,
+    /// The characteristics of the service.
+    pub characteristics: Vec<GattCharacteristic>,
+}
+
+/// A GATT characteristic which is part of a local GATT service.
+#[derive(Clone)]
+pub struct GattCharacteristic {
+    /// The 128-bit UUID of the characteristic.
+    pub uuid: Uuid,
+    /// The set of flags (a.k.a. properties) of the characteristic, defining how remote devices may
+    /// use it.
+    pub flags: CharacteristicFlags,
+    /// The handler for operations on the characteristic.
+    pub handler: Arc<dyn CharacteristicHandler>,
+    /// The descriptors of the characteristic.
+    pub descriptors: Vec<GattDescriptor>,
+}
+
+impl Debug for GattCharacteristic {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("GattCharacteristic")
+            .field("uuid", &self.uuid)
+            .field("flags", &self.flags)
+            .field("descriptors", &self.descriptors)
+            .finish()
+    }
+}
+
+/// A GATT descriptor which is part of a local GATT characteristic.
+#[derive(Clone)]
+pub struct GattDescriptor {
+    /// The 128-bit UUID of the descriptor.
+    pub uuid: Uuid,
+    /// The set of flags of the descriptor, defining how remote devices may use it.
+    pub flags: DescriptorFlags,
+    /// The handler for operations on the descriptor.
+    pub handler: Arc<dyn DescriptorHandler>,
+}
+
+impl Debug for GattDescriptor {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("GattDescriptor")
+            .field("uuid", &self.uuid)
+            .field("flags", &self.flags)
+            .finish()
+    }
+}
+
+/// A handle to a local GATT application which has been registered with BlueZ. The application will
+/// be unregistered when this is dropped.
+pub struct GattApplicationHandle {
+    pub(crate) connection: Arc<SyncConnection>,
+    pub(crate) crossroads: Arc<Mutex<Crossroads>>,
+    /// The timeout for D-Bus method calls, from the session which registered this.
+    pub(crate) method_call_timeout: Duration,
+    pub(crate) adapter: AdapterId,
+    pub(crate) object_paths: Vec<Path<'static>>,
+}
+
+impl Debug for GattApplicationHandle {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "GattApplicationHandle {{ adapter: {}, object_path: {} }}",
+            self.adapter,
+            self.object_path()
+        )
+    }
+}
+
+impl GattApplicationHandle {
+    /// Get the D-Bus object path of the root of the application.
+    pub fn object_path(&self) -> &Path<'static> {
+        &self.object_paths[0]
+    }
+}
+
+impl Drop for GattApplicationHandle {
+    fn drop(&mut self) {
+        remove_objects(&mut self.crossroads.lock().unwrap(), &self.object_paths);
+        let gatt_manager = Proxy::new(
+            "org.bluez",
+            self.adapter.object_path.clone(),
+            self.method_call_timeout,
+            self.connection.clone(),
+        );
+        let object_path = self.object_path().clone();
+        tokio::spawn(async move {
+            if let Err(e) = gatt_manager.unregister_application(object_path).await {
+                log::error!("Error unregistering GATT application: {}", e);
+            }
+        });
+    }
+}
+
+/// Remove the objects at the given paths from the given `Crossroads` instance.
+pub(crate) fn remove_objects(crossroads: &mut Crossroads, object_paths: &[Path<'static>]) {
+    for object_path in object_paths {
+        // The type parameter only matters for getting the data back, which we don't need.
+        crossroads.remove::<()>(object_path);
+    }
+}
+
+/// Tokens for the interfaces which local GATT applications implement.
+#[derive(Clone, Copy)]
+pub(crate) struct GattInterfaces {
+    object_manager: IfaceToken<()>,
+    service: IfaceToken<LocalService>,
+    characteristic: IfaceToken<LocalCharacteristic>,
+    descriptor: IfaceToken<LocalDescriptor>,
+}
+
+impl GattInterfaces {
+    /// Register the GATT interfaces with the given `Crossroads` instance.
+    pub(crate) fn register(crossroads: &mut Crossroads, connection: &Arc<SyncConnection>) -> Self {
+        GattInterfaces {
+            object_manager: crossroads.object_manager(),
+            service: register_service(crossroads),
+            characteristic: register_characteristic(crossroads, connection.clone()),
+            descriptor: register_descriptor(crossroads),
+        }
+    }
+
+    /// Insert objects for all the parts of the given application into the given `Crossroads`
+    /// instance. Returns the object paths which were inserted, starting with the application root.
+    pub(crate) fn insert_application(
+        &self,
+        crossroads: &mut Crossroads,
+        application: GattApplication,
+    ) -> Vec<Path<'static>> {
+        let application_path: Path<'static> = format!(
+            "{}{}",
+            APPLICATION_PATH_PREFIX,
+            NEXT_APPLICATION_INDEX.fetch_add(1, Ordering::Relaxed)
+        )
+        .into();
+        let mut object_paths = vec![application_path.clone()];
+        crossroads.insert(application_path.clone(), &[self.object_manager], ());
+
+        for (service_index, service) in application.services.into_iter().enumerate() {
+            let service_path: Path<'static> =
+                format!("{}/service{}", application_path, service_index).into();
+            for (characteristic_index, characteristic) in
+                service.characteristics.into_iter().enumerate()
+            {
+                let characteristic_path: Path<'static> =
+                    format!("{}/char{}", service_path, characteristic_index).into();
+                for (descriptor_index, descriptor) in
+                    characteristic.descriptors.into_iter().enumerate()
+                {
+                    let descriptor_path: Path<'static> =
+                        format!("{}/desc{}", characteristic_path, descriptor_index).into();
+                    crossroads.insert(
+                        descriptor_path.clone(),
+                        &[self.descriptor],
+                        LocalDescriptor {
+                            uuid: descriptor.uuid,
+                            flags: descriptor.flags,
+                            characteristic: characteristic_path.clone(),
+                            handler: descriptor.handler,
+                        },
+                    );
+                    object_paths.push(descriptor_path);
+                }
+                crossroads.insert(
+                    characteristic_path.clone(),
+                    &[self.characteristic],
+                    LocalCharacteristic {
+                        uuid: characteristic.uuid,
+                        flags: characteristic.flags,
+                        service: service_path.clone(),
+                        handler: characteristic.handler,
+                    },
+                );
+                object_paths.push(characteristic_path);
+            }
+            crossroads.insert(
+                service_path.clone(),
+                &[self.service],
+                LocalService {
+                    uuid: service.uuid,
+                    primary: service.primary,
+                },
+            );
+            object_paths.push(service_path);
+        }
+
+        object_paths
+    }
+}
+
+struct LocalService {
+    uuid: Uuid,
+    primary: bool,
+}
+
+struct LocalCharacteristic {
+    uuid: Uuid,
+    flags: CharacteristicFlags,
+    service: Path<'static>,
+    handler: Arc<dyn CharacteristicHandler>,
+}
+
+struct LocalDescriptor {
+    uuid: Uuid,
+    flags: DescriptorFlags,
+    characteristic: Path<'static>,
+    handler: Arc<dyn DescriptorHandler>,
+}
+
+fn register_service(crossroads: &mut Crossroads) -> IfaceToken<LocalService> {
+    crossroads.register(
+        ORG_BLUEZ_GATT_SERVICE1_NAME,
+        |b: &mut IfaceBuilder<LocalService>| {
+            b.property("UUID")
+                .get(|_, service| Ok(service.uuid.to_string()));
+            b.property("Primary").get(|_, service| Ok(service.primary));
+        },
+    )
+}
+
+fn register_characteristic(
+    crossroads: &mut Crossroads,
+    connection: Arc<SyncConnection>,
+) -> IfaceToken<LocalCharacteristic> {
+    crossroads.register(
+        ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+        move |b: &mut IfaceBuilder<LocalCharacteristic>| {
+            b.property("UUID")
+                .get(|_, characteristic| Ok(characteristic.uuid.to_string()));
+            b.property("Service")
+                .get(|_, characteristic| Ok(characteristic.service.clone()));
+            b.property("Flags")
+                .get(|_, characteristic| Ok(characteristic.flags.to_strings()));
+            b.method_with_cr_async(
+                "ReadValue",
+                ("options",),
+                ("value",),
+                |mut ctx, crossroads, (options,): (PropMap,)| {
+                    let handler = characteristic_handler(crossroads, ctx.path());
+                    async move {
+                        let result = match handler {
+                            Ok(handler) => handler
+                                .read(ReadRequest::from_options(&options))
+                                .await
+                                .map(|value| (value,))
+                                .map_err(Into::into),
+                            Err(e) => Err(e),
+                        };
+                        ctx.reply(result)
+                    }
+                },
+            );
+            b.method_with_cr_async(
+                "WriteValue",
+                ("value", "options"),
+                (),
+                |mut ctx, crossroads, (value, options): (Vec<u8>, PropMap)| {
+                    let handler = characteristic_handler(crossroads, ctx.path());
+                    async move {
+                        let result = match handler {
+                            Ok(handler) => handler
+                                .write(value, WriteRequest::from_options(&options))
+                                .await
+                                .map_err(Into::into),
+                            Err(e) => Err(e),
+                        };
+                        ctx.reply(result)
+                    }
+                },
+            );
+            b.method_with_cr_async("StartNotify", (), (), move |mut ctx, crossroads, ()| {
+                let handler = characteristic_handler(crossroads, ctx.path());
+                let notifier = CharacteristicNotifier {
+                    connection: connection.clone(),
+                    object_path: ctx.path().clone(),
+                };
+                async move {
+                    let result = match handler {
+                        Ok(handler) => handler.start_notify(notifier).await.map_err(Into::into),
+                        Err(e) => Err(e),
+                    };
+                    ctx.reply(result)
+                }
+            });
+            b.method_with_cr_async("StopNotify", (), (), |mut ctx, crossroads, ()| {
+                let handler = characteristic_handler(crossroads, ctx.path());
+                async move {
+                    let result = match handler {
+                        Ok(handler) => {
+                            handler.stop_notify().await;
+                            Ok(())
+                        }
+                        Err(e) => Err(e),
+                    };
+                    ctx.reply(result)
+                }
+            });
+        },
+    )
+}
+
+/// Get the handler for the characteristic at the given path.
+fn characteristic_handler(
+    crossroads: &mut Crossroads,
+    path: &Path<'static>,
+) -> Result<Arc<dyn CharacteristicHandler>, MethodErr> {
+    let characteristic = crossroads
+        .data_mut::<LocalCharacteristic>(path)
+        .ok_or_else(|| MethodErr::no_path(path))?;
+    Ok(characteristic.handler.clone())
+}
+
+fn register_descriptor(crossroads: &mut Crossroads) -> IfaceToken<LocalDescriptor> {
+    crossroads.register(
+        ORG_BLUEZ_GATT_DESCRIPTOR1_NAME,
+        |b: &mut IfaceBuilder<LocalDescriptor>| {
+            b.property("UUID")
+                .get(|_, descriptor| Ok(descriptor.uuid.to_string()));
+            b.property("Characteristic")
+                .get(|_, descriptor| Ok(descriptor.characteristic.clone()));
+            b.property("Flags")
+                .get(|_, descriptor| Ok(descriptor.flags.to_strings()));
+            b.method_with_cr_async(
+                "ReadValue",
+                ("options",),
+                ("value",),
+                |mut ctx, crossroads, (options,): (PropMap,)| {
+                    let handler = descriptor_handler(crossroads, ctx.path());
+                    async move {
+                        let result = match handler {
+                            Ok(handler) => handler
+                                .read(ReadRequest::from_options(&options))
+                                .await
+                                .map(|value| (value,))
+                                .map_err(Into::into),
+                            Err(e) => Err(e),
+                        };
+                        ctx.reply(result)
+                    }
+                },
+            );
+            b.method_with_cr_async(
+                "WriteValue",
+                ("value", "options"),
+                (),
+                |mut ctx, crossroads, (value, options): (Vec<u8>, PropMap)| {
+                    let handler = descriptor_handler(crossroads, ctx.path());
+                    async move {
+                        let result = match handler {
+                            Ok(handler) => handler
+                                .write(value, WriteRequest::from_options(&options))
+                                .await
+                                .map_err(Into::into),
+                            Err(e) => Err(e),
+                        };
+                        ctx.reply(result)
+                    }
+                },
+            );
+        },
+    )
+}
+
+/// Get the handler for the descriptor at the given path.
+fn descriptor_handler(
+    crossroads: &mut Crossroads,
+    path: &Path<'static>,
+) -> Result<Arc<dyn DescriptorHandler>, MethodErr> {
+    let descriptor = crossroads
+        .data_mut::<LocalDescriptor>(path)
+        .ok_or_else(|| MethodErr::no_path(path))?;
+    Ok(descriptor.handler.clone())
+}
+
+fn get_offset(options: &PropMap) -> usize {
+    options
+        .get("offset")
+        .and_then(|offset| offset.0.as_u64())
+        .unwrap_or(0) as usize
+}
+
+fn get_mtu(options: &PropMap) -> Option<u16> {
+    options
+        .get("mtu")
+        .and_then(|mtu| mtu.0.as_u64())
+        .map(|mtu| mtu as u16)
+}
+
+fn get_device(options: &PropMap) -> Option<DeviceId> {
+    prop_cast::<Path>(options, "device").map(|path| DeviceId {
+        object_path: path.clone().into_static(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_request_from_options() {
+        let mut options: PropMap = HashMap::new();
+        options.insert("offset".to_string(), Variant(Box::new(3u16)));
+        options.insert("mtu".to_string(), Variant(Box::new(23u16)));
+        options.insert(
+            "device".to_string(),
+            Variant(Box::new(Path::from(
+                "/org/bluez/hci0/dev_11_22_33_44_55_66",
+            ))),
+        );
+        assert_eq!(
+            ReadRequest::from_options(&options),
+            ReadRequest {
+                offset: 3,
+                mtu: Some(23),
+                device: Some(DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66")),
+            }
+        );
+    }
+
+    #[test]
+    fn write_request_from_options() {
+        let mut options: PropMap = HashMap::new();
+        options.insert("type".to_string(), Variant(Box::new("command".to_string())));
+        options.insert("prepare-authorize".to_string(), Variant(Box::new(true)));
+        assert_eq!(
+            WriteRequest::from_options(&options),
+            WriteRequest {
+                offset: 0,
+                write_type: Some(WriteType::WithoutResponse),
+                mtu: None,
+                device: None,
+                prepare_authorize: true,
+            }
+        );
+    }
+
+    #[test]
+    fn request_from_empty_options() {
+        let options: PropMap = HashMap::new();
+        assert_eq!(ReadRequest::from_options(&options), ReadRequest::default());
+        assert_eq!(
+            WriteRequest::from_options(&options),
+            WriteRequest::default()
+        );
+    }
+}
@@ -7,51 +7,118 @@
 //!
 //! [`BluetoothSession']: struct.BluetoothSession.html
 
+mod acquire;
 mod adapter;
+mod advertisement;
+mod advertisement_stream;
+mod agent;
+mod assigned_numbers;
 mod bleuuid;
 mod characteristic;
+mod connection_manager;
 mod descriptor;
 mod device;
 mod events;
-mod introspect;
+#[cfg(feature = "fake")]
+pub mod fake;
+mod gatt_cache;
+mod gatt_server;
 mod messagestream;
+mod profile;
+mod recording;
+mod scheduler;
 mod service;
+mod standard_services;
 
+pub use self::acquire::{CharacteristicWriter, NotificationStream};
 pub use self::adapter::{AdapterId, AdapterInfo};
+use self::advertisement::{insert_advertisement, register_advertisement};
+pub use self::advertisement::{Advertisement, AdvertisementHandle, AdvertisementType};
+pub use self::advertisement_stream::{
+    AdvertisementDecoder, AdvertisementDecoders, AdvertisementRecord, AdvertisementStream,
+    AdvertisementStreamOptions,
+};
+use self::agent::{insert_agent, register_agent, LocalAgent};
+pub use self::agent::{Agent, AgentCapability, AgentError, AgentHandle};
+pub use self::assigned_numbers::{characteristic_name, descriptor_name, service_name};
 pub use self::bleuuid::{uuid_from_u16, uuid_from_u32, BleUuid};
-pub use self::characteristic::{CharacteristicFlags, CharacteristicId, CharacteristicInfo};
-pub use self::descriptor::{DescriptorId, DescriptorInfo};
-pub use self::device::{AddressType, DeviceId, DeviceInfo};
+pub use self::characteristic::{
+    CharacteristicFlags, CharacteristicId, CharacteristicInfo, CharacteristicMetadata,
+};
+pub use self::connection_manager::{
+    ConnectionHandler, ConnectionManager, ConnectionManagerOptions, ConnectionState,
+    ConnectionStateChange,
+};
+pub use self::descriptor::{
+    parse_user_description, ClientCharacteristicConfiguration, DescriptorFlags, DescriptorId,
+    DescriptorInfo, ParseDescriptorError, PresentationFormat, ValueFormat,
+};
+use self::device::convert_services;
+pub use self::device::{AddressType, ConnectionInfo, DeviceId, DeviceInfo};
 pub use self::events::{AdapterEvent, BluetoothEvent, CharacteristicEvent, DeviceEvent};
-use self::introspect::IntrospectParse;
+use self::gatt_cache::{services_resolved, GattCache};
+pub use self::gatt_cache::{GattDatabase, GattDatabaseCharacteristic, GattDatabaseService};
+use self::gatt_server::{remove_objects, GattInterfaces};
+pub use self::gatt_server::{
+    CharacteristicHandler, CharacteristicNotifier, DescriptorHandler, GattApplication,
+    GattApplicationHandle, GattCharacteristic, GattDescriptor, GattError, GattService, ReadRequest,
+    WriteRequest,
+};
 use self::messagestream::MessageStream;
+use self::profile::{insert_profile, register_profile, LocalProfile};
+pub use self::profile::{
+    ProfileConnection, ProfileEvent, ProfileHandle, ProfileOptions, ProfileRole,
+};
+pub use self::recording::{EventRecorder, EventReplay, RecordedEvent, RecordingError};
+use self::scheduler::{Permit, Scheduler};
+pub use self::scheduler::{QueueDepth, SchedulerOptions};
 pub use self::service::{ServiceId, ServiceInfo};
+pub use self::standard_services::{
+    parse_humidity, parse_pressure, parse_temperature, DeviceInformation, EnvironmentalReadings,
+    ParseCharacteristicError,
+};
+use self::standard_services::{
+    parse_string, DEVICE_INFORMATION_SERVICE_UUID, ENVIRONMENTAL_SENSING_SERVICE_UUID,
+};
 use bluez_generated::{
-    OrgBluezAdapter1, OrgBluezAdapter1Properties, OrgBluezDevice1, OrgBluezDevice1Properties,
-    OrgBluezGattCharacteristic1, OrgBluezGattDescriptor1, OrgBluezGattService1,
-    ORG_BLUEZ_ADAPTER1_NAME, ORG_BLUEZ_DEVICE1_NAME,
+    OrgBluezAdapter1, OrgBluezAdapter1Properties, OrgBluezAgentManager1,
+    OrgBluezBattery1Properties, OrgBluezDevice1, OrgBluezDevice1Properties,
+    OrgBluezGattCharacteristic1, OrgBluezGattCharacteristic1Properties, OrgBluezGattDescriptor1,
+    OrgBluezGattManager1, OrgBluezGattService1, OrgBluezLEAdvertisingManager1,
+    OrgBluezProfileManager1, ORG_BLUEZ_ADAPTER1_NAME, ORG_BLUEZ_BATTERY1_NAME,
+    ORG_BLUEZ_DEVICE1_NAME, ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
 };
 use dbus::arg::{PropMap, Variant};
+use dbus::channel::{Channel, MatchingReceiver, Sender};
+use dbus::message::MatchRule;
 use dbus::nonblock::stdintf::org_freedesktop_dbus::{Introspectable, ObjectManager, Properties};
 use dbus::nonblock::{Proxy, SyncConnection};
 use dbus::Path;
-use dbus_tokio::connection::IOResourceError;
+use dbus_crossroads::{Crossroads, IfaceToken};
+use dbus_tokio::connection::{IOResource, IOResourceError};
 use futures::stream::{self, select_all, StreamExt};
 use futures::{FutureExt, Stream};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
-use std::convert::TryInto;
+use std::convert::TryFrom;
 use std::fmt::{self, Debug, Display, Formatter};
 use std::future::Future;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use thiserror::Error;
+use tokio::io::AsyncWrite;
 use tokio::task::JoinError;
 use tokio::time::timeout;
 use uuid::Uuid;
 
 const DBUS_METHOD_CALL_TIMEOUT: Duration = Duration::from_secs(30);
 const SERVICE_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(5);
+/// The UUID of the standard GATT Battery Service.
+const BATTERY_SERVICE_UUID: Uuid = uuid_from_u16(0x180f);
+/// The UUID of the standard GATT Battery Level characteristic.
+const BATTERY_LEVEL_UUID: Uuid = uuid_from_u16(0x2a19);
 
 /// An error carrying out a Bluetooth operation.
 #[derive(Debug, Error)]
@@ -61,10 +128,7 @@ pub enum BluetoothError {
     NoBluetoothAdapters,
     /// There was an error talking to the BlueZ daemon over D-Bus.
     #[error(transparent)]
-    DbusError(#[from] dbus::Error),
-    /// Error parsing XML for introspection.
-    #[error("Error parsing XML for introspection: {0}")]
-    XmlParseError(#[from] serde_xml_rs::Error),
+    DbusError(dbus::Error),
     /// No service or characteristic was found for some UUID.
     #[error("Service or characteristic UUID {uuid} not found.")]
     UUIDNotFound { uuid: Uuid },
@@ -80,9 +144,182 @@ pub enum BluetoothError {
     /// A required property of some device or other object was not found.
     #[error("Required property {0} missing.")]
     RequiredPropertyMissing(&'static str),
-    /// Service discovery didn't happen within the time limit.
-    #[error("Service discovery timed out")]
-    ServiceDiscoveryTimedOut,
+    /// Error parsing the value of a standard GATT descriptor.
+    #[error(transparent)]
+    DescriptorParseError(#[from] ParseDescriptorError),
+    /// Error parsing the value of a standard GATT characteristic.
+    #[error(transparent)]
+    CharacteristicParseError(#[from] ParseCharacteristicError),
+    /// Some operation didn't finish within the time limit set in the
+    /// [`BluetoothSessionConfig`](struct.BluetoothSessionConfig.html).
+    #[error("{operation} timed out")]
+    Timeout { operation: Operation },
+    /// There was an error reading from or writing to a file descriptor acquired from BlueZ.
+    #[error("Error on acquired file descriptor: {0}")]
+    IoError(#[from] std::io::Error),
+    /// A value was too long to write without response in a single packet.
+    #[error("Value of length {length} is longer than the maximum of {max_length}")]
+    ValueTooLong { length: usize, max_length: usize },
+    /// BlueZ or the adapter wasn't ready for the operation, e.g. because the adapter is powered
+    /// off.
+    #[error("Not ready: {0}")]
+    NotReady(#[source] dbus::Error),
+    /// The same operation or a conflicting one was already in progress.
+    #[error("Operation already in progress: {0}")]
+    InProgress(#[source] dbus::Error),
+    /// The device was already connected.
+    #[error("Already connected: {0}")]
+    AlreadyConnected(#[source] dbus::Error),
+    /// BlueZ or the device didn't permit the operation.
+    #[error("Operation not permitted: {0}")]
+    NotPermitted(#[source] dbus::Error),
+    /// The operation isn't supported by BlueZ, the adapter or the device.
+    #[error("Operation not supported: {0}")]
+    NotSupported(#[source] dbus::Error),
+    /// Authentication with the device failed, was rejected or was cancelled, e.g. while pairing.
+    #[error("Authentication failed: {0}")]
+    AuthenticationFailed(#[source] dbus::Error),
+    /// The local adapter gave up on a connection, usually because the device is out of range or
+    /// didn't respond in time.
+    #[error("Connection aborted by local adapter: {0}")]
+    ConnectionAbortedByLocal(#[source] dbus::Error),
+    /// The device (or other object) doesn't exist, e.g. because BlueZ has removed it since it was
+    /// last seen.
+    #[error("Device not found: {0}")]
+    DeviceNotFound(#[source] dbus::Error),
+    /// The discoverable timeout of an advertisement was too long for BlueZ to represent.
+    #[error(
+        "Discoverable timeout {0:?} is longer than the maximum of {} seconds",
+        u16::MAX
+    )]
+    DiscoverableTimeoutTooLong(Duration),
+    /// The discoverable timeout of an adapter was too long for BlueZ to represent.
+    #[error(
+        "Adapter discoverable timeout {0:?} is longer than the maximum of {} seconds",
+        u32::MAX
+    )]
+    AdapterDiscoverableTimeoutTooLong(Duration),
+}
+
+impl BluetoothError {
+    /// Returns whether the operation which failed with this error might succeed if it is retried
+    /// without changing anything else.
+    ///
+    /// This is true for errors which are likely to be transient, such as timeouts, connections
+    /// aborted because the device was briefly out of range, or BlueZ being busy with another
+    /// operation. It is false for errors which will keep happening until something else changes,
+    /// such as the operation not being supported, or an invalid value being passed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            BluetoothError::Timeout { .. }
+                | BluetoothError::NotReady(_)
+                | BluetoothError::InProgress(_)
+                | BluetoothError::ConnectionAbortedByLocal(_)
+        )
+    }
+
+    /// Returns whether BlueZ has said that the operation which failed with this error will keep
+    /// failing if it is retried, e.g. because it isn't supported or permitted, or the device no
+    /// longer exists.
+    ///
+    /// Errors which haven't been classified, such as generic D-Bus errors, are neither retryable
+    /// nor permanent, so callers must decide how to treat them. If in doubt it is usually best to
+    /// retry them with a backoff.
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self,
+            BluetoothError::NotPermitted(_)
+                | BluetoothError::NotSupported(_)
+                | BluetoothError::AuthenticationFailed(_)
+                | BluetoothError::DeviceNotFound(_)
+        )
+    }
+}
+
+impl From<dbus::Error> for BluetoothError {
+    fn from(error: dbus::Error) -> Self {
+        match error.name() {
+            // The former is returned by the dbus crate when it gives up waiting for a reply, the
+            // latter by the bus if BlueZ doesn't reply in time.
+            Some("org.freedesktop.DBus.Error.Timeout")
+            | Some("org.freedesktop.DBus.Error.NoReply") => BluetoothError::Timeout {
+                operation: Operation::MethodCall,
+            },
+            Some("org.bluez.Error.NotReady") => BluetoothError::NotReady(error),
+            Some("org.bluez.Error.InProgress") => BluetoothError::InProgress(error),
+            Some("org.bluez.Error.AlreadyConnected") => BluetoothError::AlreadyConnected(error),
+            Some("org.bluez.Error.NotPermitted") | Some("org.bluez.Error.NotAuthorized") => {
+                BluetoothError::NotPermitted(error)
+            }
+            Some("org.bluez.Error.NotSupported") => BluetoothError::NotSupported(error),
+            Some("org.bluez.Error.AuthenticationFailed")
+            | Some("org.bluez.Error.AuthenticationCanceled")
+            | Some("org.bluez.Error.AuthenticationRejected")
+            | Some("org.bluez.Error.AuthenticationTimeout") => {
+                BluetoothError::AuthenticationFailed(error)
+            }
+            Some("org.bluez.Error.DoesNotExist")
+            | Some("org.freedesktop.DBus.Error.UnknownObject") => {
+                BluetoothError::DeviceNotFound(error)
+            }
+            // BlueZ reports connection failures as a generic failure, with the reason in the
+            // message, e.g. "le-connection-abort-by-local".
+            Some("org.bluez.Error.Failed")
+                if error
+                    .message()
+                    .is_some_and(|message| message.ends_with("connection-abort-by-local")) =>
+            {
+                BluetoothError::ConnectionAbortedByLocal(error)
+            }
+            _ => BluetoothError::DbusError(error),
+        }
+    }
+}
+
+/// An operation which may time out, as reported by
+/// [`BluetoothError::Timeout`](enum.BluetoothError.html#variant.Timeout).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Operation {
+    /// A D-Bus method call to BlueZ, other than connecting.
+    MethodCall,
+    /// Connecting to a device.
+    Connect,
+    /// Waiting for the services of a device to be resolved after connecting.
+    ServiceDiscovery,
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Operation::MethodCall => "D-Bus method call",
+            Operation::Connect => "Connection",
+            Operation::ServiceDiscovery => "Service discovery",
+        })
+    }
+}
+
+/// Configuration for a [`BluetoothSession`](struct.BluetoothSession.html), as passed to
+/// [`BluetoothSession::with_config`](struct.BluetoothSession.html#method.with_config).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BluetoothSessionConfig {
+    /// How long to wait for BlueZ to reply to a D-Bus method call, other than connecting to a
+    /// device.
+    pub method_call_timeout: Duration,
+    /// How long to wait for BlueZ to connect to a device.
+    pub connect_timeout: Duration,
+    /// How long to wait after connecting to a device for its services to be resolved.
+    pub service_discovery_timeout: Duration,
+}
+
+impl Default for BluetoothSessionConfig {
+    fn default() -> Self {
+        Self {
+            method_call_timeout: DBUS_METHOD_CALL_TIMEOUT,
+            connect_timeout: DBUS_METHOD_CALL_TIMEOUT,
+            service_discovery_timeout: SERVICE_DISCOVERY_TIMEOUT,
+        }
+    }
 }
 
 /// Error type for futures representing tasks spawned by this crate.
@@ -104,6 +341,19 @@ impl Display for MacAddress {
     }
 }
 
+impl Serialize for MacAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for MacAddress {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mac_address = String::deserialize(deserializer)?;
+        mac_address.parse().map_err(D::Error::custom)
+    }
+}
+
 /// An error parsing a MAC address from a string.
 #[derive(Clone, Debug, Error, Eq, PartialEq)]
 #[error("Invalid MAC address")]
@@ -241,6 +491,15 @@ impl WriteType {
             Self::Reliable => "reliable",
         }
     }
+
+    fn from_bluez_str(s: &str) -> Option<Self> {
+        match s {
+            "request" => Some(Self::WithResponse),
+            "command" => Some(Self::WithoutResponse),
+            "reliable" => Some(Self::Reliable),
+            _ => None,
+        }
+    }
 }
 
 impl Display for WriteType {
@@ -285,6 +544,17 @@ impl Into<PropMap> for WriteOptions {
 #[derive(Clone)]
 pub struct BluetoothSession {
     connection: Arc<SyncConnection>,
+    /// Objects exported on the D-Bus connection for BlueZ to call, e.g. for GATT applications.
+    crossroads: Arc<Mutex<Crossroads>>,
+    gatt_interfaces: GattInterfaces,
+    advertisement_interface: IfaceToken<Advertisement>,
+    agent_interface: IfaceToken<LocalAgent>,
+    profile_interface: IfaceToken<LocalProfile>,
+    /// Limits on concurrent operations, if enabled with `with_scheduler`.
+    scheduler: Option<Arc<Scheduler>>,
+    config: BluetoothSessionConfig,
+    /// The GATT database of each device whose services have been resolved.
+    gatt_cache: Arc<GattCache>,
 }
 
 impl Debug for BluetoothSession {
@@ -303,16 +573,155 @@ impl BluetoothSession {
     ) -> Result<(impl Future<Output = Result<(), SpawnError>>, Self), BluetoothError> {
         // Connect to the D-Bus system bus (this is blocking, unfortunately).
         let (dbus_resource, connection) = dbus_tokio::connection::new_system_sync()?;
+        Ok(Self::spawn_with_resource(dbus_resource, connection))
+    }
+
+    /// Establish a new D-Bus connection to the bus at the given address, and use it to communicate
+    /// with BlueZ (or something pretending to be BlueZ) on that bus.
+    ///
+    /// This is mainly useful for testing, e.g. against a [`FakeBluez`](fake/struct.FakeBluez.html)
+    /// on a private bus.
+    ///
+    /// Returns a tuple of (join handle, Self), as for [`new`](#method.new).
+    pub async fn new_with_address(
+        address: &str,
+    ) -> Result<(impl Future<Output = Result<(), SpawnError>>, Self), BluetoothError> {
+        // Connect to the D-Bus bus at the given address (this is blocking, unfortunately).
+        let mut channel = Channel::open_private(address)?;
+        channel.register()?;
+        let (dbus_resource, connection) = dbus_tokio::connection::from_channel(channel)?;
+        Ok(Self::spawn_with_resource(dbus_resource, connection))
+    }
+
+    /// Create a new session using an existing D-Bus connection.
+    ///
+    /// The caller is responsible for making sure that the `IOResource` for the connection has been
+    /// spawned. The session will handle all incoming method calls on the connection, so that BlueZ
+    /// can call back into local GATT applications and the like; only one session should be created
+    /// for each connection.
+    ///
+    /// This also enables
+    /// [`set_signal_match_mode`](https://docs.rs/dbus/0.9/dbus/nonblock/struct.SyncConnection.html#method.set_signal_match_mode)
+    /// on the connection, so that each signal is delivered to every match for it rather than only
+    /// the first.
+    pub fn new_with_connection(connection: Arc<SyncConnection>) -> Self {
+        // Event streams and the GATT cache may all be interested in the same signals, and the cache
+        // would never be invalidated if they received them instead.
+        connection.set_signal_match_mode(true);
+
+        let mut crossroads = Crossroads::new();
+        crossroads.set_async_support(Some((
+            connection.clone() as Arc<dyn Sender + Send + Sync>,
+            Box::new(|future| {
+                tokio::spawn(future);
+            }),
+        )));
+        let gatt_interfaces = GattInterfaces::register(&mut crossroads, &connection);
+        let advertisement_interface = register_advertisement(&mut crossroads);
+        let agent_interface = register_agent(&mut crossroads);
+        let profile_interface = register_profile(&mut crossroads);
+        let crossroads = Arc::new(Mutex::new(crossroads));
+
+        let receiver = crossroads.clone();
+        connection.start_receive(
+            MatchRule::new_method_call(),
+            Box::new(move |message, connection| {
+                receiver
+                    .lock()
+                    .unwrap()
+                    .handle_message(message, connection)
+                    .unwrap();
+                true
+            }),
+        );
+
+        BluetoothSession {
+            connection,
+            crossroads,
+            gatt_interfaces,
+            advertisement_interface,
+            agent_interface,
+            profile_interface,
+            scheduler: None,
+            config: BluetoothSessionConfig::default(),
+            gatt_cache: Default::default(),
+        }
+    }
+
+    /// Get a copy of this session which uses the given configuration, e.g. to set different
+    /// timeouts.
+    ///
+    /// Timeouts apply to each call separately. Any call may also be cancelled early by dropping
+    /// the future it returns, e.g. by using `tokio::time::timeout`.
+    pub fn with_config(&self, config: BluetoothSessionConfig) -> Self {
+        Self {
+            config,
+            ..self.clone()
+        }
+    }
+
+    /// Get the configuration which this session uses.
+    pub fn config(&self) -> &BluetoothSessionConfig {
+        &self.config
+    }
+
+    /// Get a copy of this session which limits how many operations are in progress at once, as
+    /// configured by the given options.
+    ///
+    /// Connection attempts on each adapter are limited to `max_concurrent_connects` at once, and
+    /// GATT operations on each device may be serialized, with further operations waiting until
+    /// earlier ones finish. This avoids problems with BlueZ and adapters which misbehave when asked
+    /// to do several things at once, while still allowing different adapters to be used in
+    /// parallel. The limits are shared by clones of the returned session, but not with this
+    /// session.
+    pub fn with_scheduler(&self, options: SchedulerOptions) -> Self {
+        Self {
+            scheduler: Some(Arc::new(Scheduler::new(options))),
+            ..self.clone()
+        }
+    }
+
+    /// Get the number of operations in progress and waiting on the given adapter, if this session
+    /// was created with [`with_scheduler`](#method.with_scheduler). Sessions without a scheduler
+    /// don't keep track, so will always return zero.
+    pub fn queue_depth(&self, adapter: &AdapterId) -> QueueDepth {
+        self.scheduler
+            .as_ref()
+            .map(|scheduler| scheduler.queue_depth(adapter))
+            .unwrap_or_default()
+    }
+
+    /// Wait until the scheduler (if any) allows a connection to be attempted to the given device.
+    async fn schedule_connect(&self, id: &DeviceId) -> Option<Permit> {
+        match &self.scheduler {
+            Some(scheduler) => Some(scheduler.connect(id).await),
+            None => None,
+        }
+    }
+
+    /// Wait until the scheduler (if any) allows a GATT operation to be started on the given
+    /// device.
+    async fn schedule_gatt_operation(&self, id: &DeviceId) -> Option<Permit> {
+        match &self.scheduler {
+            Some(scheduler) => Some(scheduler.gatt_operation(id).await),
+            None => None,
+        }
+    }
+
+    fn spawn_with_resource(
+        dbus_resource: IOResource<SyncConnection>,
+        connection: Arc<SyncConnection>,
+    ) -> (impl Future<Output = Result<(), SpawnError>>, Self) {
         // The resource is a task that should be spawned onto a tokio compatible
         // reactor ASAP. If the resource ever finishes, you lost connection to D-Bus.
         let dbus_handle = tokio::spawn(async {
             let err = dbus_resource.await;
             Err(SpawnError::DbusConnectionLost(err))
         });
-        Ok((
+        (
             dbus_handle.map(|res| Ok(res??)),
-            BluetoothSession { connection },
-        ))
+            Self::new_with_connection(connection),
+        )
     }
 
     /// Power on all Bluetooth adapters, remove any discovery filter, and then start scanning for
@@ -409,12 +818,84 @@ impl BluetoothSession {
         Ok(())
     }
 
+    /// Power the given Bluetooth adapter on or off.
+    pub async fn set_powered(
+        &self,
+        adapter_id: &AdapterId,
+        powered: bool,
+    ) -> Result<(), BluetoothError> {
+        Ok(self.adapter(adapter_id).set_powered(powered).await?)
+    }
+
+    /// Set the friendly name of the given Bluetooth adapter, which other devices will see.
+    pub async fn set_alias(
+        &self,
+        adapter_id: &AdapterId,
+        alias: impl Into<String>,
+    ) -> Result<(), BluetoothError> {
+        Ok(self.adapter(adapter_id).set_alias(alias.into()).await?)
+    }
+
+    /// Set whether the given Bluetooth adapter is discoverable by other devices.
+    ///
+    /// The adapter will stop being discoverable again after its discoverable timeout, which may be
+    /// set with [`set_discoverable_timeout`](#method.set_discoverable_timeout).
+    pub async fn set_discoverable(
+        &self,
+        adapter_id: &AdapterId,
+        discoverable: bool,
+    ) -> Result<(), BluetoothError> {
+        Ok(self
+            .adapter(adapter_id)
+            .set_discoverable(discoverable)
+            .await?)
+    }
+
+    /// Set how long the given Bluetooth adapter will remain discoverable for after being made
+    /// discoverable. A timeout of zero means that it will remain discoverable indefinitely.
+    ///
+    /// The timeout is rounded down to a whole number of seconds, and may be at most `u32::MAX`
+    /// seconds.
+    pub async fn set_discoverable_timeout(
+        &self,
+        adapter_id: &AdapterId,
+        timeout: Duration,
+    ) -> Result<(), BluetoothError> {
+        let timeout_secs = u32::try_from(timeout.as_secs())
+            .map_err(|_| BluetoothError::AdapterDiscoverableTimeoutTooLong(timeout))?;
+        Ok(self
+            .adapter(adapter_id)
+            .set_discoverable_timeout(timeout_secs)
+            .await?)
+    }
+
+    /// Set whether the given Bluetooth adapter allows incoming pairing requests.
+    pub async fn set_pairable(
+        &self,
+        adapter_id: &AdapterId,
+        pairable: bool,
+    ) -> Result<(), BluetoothError> {
+        Ok(self.adapter(adapter_id).set_pairable(pairable).await?)
+    }
+
+    /// Remove the given device from BlueZ, along with any pairing information. It will be
+    /// rediscovered as a new device the next time it is seen while discovering.
+    ///
+    /// This may be used to clear out BlueZ's cached information about a device, such as its GATT
+    /// services.
+    pub async fn remove_device(&self, id: &DeviceId) -> Result<(), BluetoothError> {
+        Ok(self
+            .adapter(&id.adapter())
+            .remove_device(id.object_path.clone())
+            .await?)
+    }
+
     /// Get a list of all Bluetooth adapters on the system.
     pub async fn get_adapters(&self) -> Result<Vec<AdapterInfo>, BluetoothError> {
         let bluez_root = Proxy::new(
             "org.bluez",
             "/",
-            DBUS_METHOD_CALL_TIMEOUT,
+            self.config.method_call_timeout,
             self.connection.clone(),
         );
         // TODO: See whether there is a way to do this with introspection instead, rather than
@@ -434,7 +915,7 @@ impl BluetoothSession {
         let bluez_root = Proxy::new(
             "org.bluez",
             "/",
-            DBUS_METHOD_CALL_TIMEOUT,
+            self.config.method_call_timeout,
             self.connection.clone(),
         );
         let tree = bluez_root.get_managed_objects().await?;
@@ -443,7 +924,13 @@ impl BluetoothSession {
             .into_iter()
             .filter_map(|(object_path, interfaces)| {
                 let device_properties = OrgBluezDevice1Properties::from_interfaces(&interfaces)?;
-                DeviceInfo::from_properties(DeviceId { object_path }, device_properties).ok()
+                let battery_properties = OrgBluezBattery1Properties::from_interfaces(&interfaces);
+                DeviceInfo::from_properties(
+                    DeviceId { object_path },
+                    device_properties,
+                    battery_properties,
+                )
+                .ok()
             })
             .collect();
         Ok(devices)
@@ -461,6 +948,39 @@ impl BluetoothSession {
             .collect())
     }
 
+    /// Get all the GATT services, characteristics and descriptors of the given device.
+    ///
+    /// These are fetched from BlueZ in a single call, and cached once the device's services have
+    /// been resolved until it disconnects or its services change. The result can be serialized,
+    /// e.g. to JSON with [`GattDatabase::to_json`](struct.GattDatabase.html#method.to_json).
+    ///
+    /// Note that this won't be filled in until the device is connected.
+    pub async fn get_gatt_database(
+        &self,
+        device: &DeviceId,
+    ) -> Result<Arc<GattDatabase>, BluetoothError> {
+        if let Some(database) = self.gatt_cache.get(device) {
+            return Ok(database);
+        }
+
+        // Start watching for changes before reading anything, so nothing is missed.
+        self.gatt_cache.watch(&self.connection).await?;
+        let generation = self.gatt_cache.generation();
+        let bluez_root = Proxy::new(
+            "org.bluez",
+            "/",
+            self.config.method_call_timeout,
+            self.connection.clone(),
+        );
+        let tree = bluez_root.get_managed_objects().await?;
+        let database = Arc::new(GattDatabase::from_managed_objects(device, &tree)?);
+        // The set of services isn't complete until they have been resolved.
+        if services_resolved(device, &tree) {
+            self.gatt_cache.insert(generation, database.clone());
+        }
+        Ok(database)
+    }
+
     /// Get a list of all GATT services which the given Bluetooth device offers.
     ///
     /// Note that this won't be filled in until the device is connected.
@@ -468,83 +988,46 @@ impl BluetoothSession {
         &self,
         device: &DeviceId,
     ) -> Result<Vec<ServiceInfo>, BluetoothError> {
-        let device_node = self.device(device).introspect_parse().await?;
-        let mut services = vec![];
-        for subnode in device_node.nodes {
-            let subnode_name = subnode.name.as_ref().unwrap();
-            // Service paths are always of the form
-            // /org/bluez/{hci0,hci1,...}/dev_XX_XX_XX_XX_XX_XX/serviceXXXX
-            if subnode_name.starts_with("service") {
-                let service_id = ServiceId {
-                    object_path: format!("{}/{}", device.object_path, subnode_name).into(),
-                };
-                let service = self.service(&service_id);
-                let uuid = Uuid::parse_str(&service.uuid().await?)?;
-                let primary = service.primary().await?;
-                services.push(ServiceInfo {
-                    id: service_id,
-                    uuid,
-                    primary,
-                });
-            }
-        }
-        Ok(services)
+        let database = self.get_gatt_database(device).await?;
+        Ok(database
+            .services
+            .iter()
+            .map(|service| service.info.clone())
+            .collect())
     }
 
     /// Get a list of all characteristics on the given GATT service.
+    ///
+    /// Returns `BluetoothError::DeviceNotFound` if the service doesn't exist.
     pub async fn get_characteristics(
         &self,
         service: &ServiceId,
     ) -> Result<Vec<CharacteristicInfo>, BluetoothError> {
-        let service_node = self.service(service).introspect_parse().await?;
-        let mut characteristics = vec![];
-        for subnode in service_node.nodes {
-            let subnode_name = subnode.name.as_ref().unwrap();
-            // Characteristic paths are always of the form
-            // /org/bluez/{hci0,hci1,...}/dev_XX_XX_XX_XX_XX_XX/serviceXXXX/charYYYY
-            if subnode_name.starts_with("char") {
-                let characteristic_id = CharacteristicId {
-                    object_path: format!("{}/{}", service.object_path, subnode_name).into(),
-                };
-                let characteristic = self.characteristic(&characteristic_id);
-                let uuid = Uuid::parse_str(&characteristic.uuid().await?)?;
-                let flags = characteristic.flags().await?;
-                characteristics.push(CharacteristicInfo {
-                    id: characteristic_id,
-                    uuid,
-                    flags: flags.try_into()?,
-                });
-            }
-        }
-        Ok(characteristics)
+        let database = self.get_gatt_database(&service.device()).await?;
+        let service = database
+            .service(service)
+            .ok_or_else(|| unknown_object(&service.object_path))?;
+        Ok(service
+            .characteristics
+            .iter()
+            .map(|characteristic| characteristic.info.clone())
+            .collect())
     }
 
     /// Get a list of all descriptors on the given GATT characteristic.
+    ///
+    /// Returns `BluetoothError::DeviceNotFound` if the characteristic doesn't exist.
     pub async fn get_descriptors(
         &self,
         characteristic: &CharacteristicId,
     ) -> Result<Vec<DescriptorInfo>, BluetoothError> {
-        let characteristic_node = self
-            .characteristic(characteristic)
-            .introspect_parse()
+        let database = self
+            .get_gatt_database(&characteristic.service().device())
             .await?;
-        let mut descriptors = vec![];
-        for subnode in characteristic_node.nodes {
-            let subnode_name = subnode.name.as_ref().unwrap();
-            // Service paths are always of the form
-            // /org/bluez/{hci0,hci1,...}/dev_XX_XX_XX_XX_XX_XX/serviceXXXX/charYYYY/descZZZZ
-            if subnode_name.starts_with("desc") {
-                let descriptor_id = DescriptorId {
-                    object_path: format!("{}/{}", characteristic.object_path, subnode_name).into(),
-                };
-                let uuid = Uuid::parse_str(&self.descriptor(&descriptor_id).uuid().await?)?;
-                descriptors.push(DescriptorInfo {
-                    id: descriptor_id,
-                    uuid,
-                });
-            }
-        }
-        Ok(descriptors)
+        let characteristic = database
+            .characteristic(characteristic)
+            .ok_or_else(|| unknown_object(&characteristic.object_path))?;
+        Ok(characteristic.descriptors.clone())
     }
 
     /// Find a GATT service with the given UUID advertised by the given device, if any.
@@ -595,7 +1078,213 @@ impl BluetoothSession {
     pub async fn get_device_info(&self, id: &DeviceId) -> Result<DeviceInfo, BluetoothError> {
         let device = self.device(&id);
         let properties = device.get_all(ORG_BLUEZ_DEVICE1_NAME).await?;
-        DeviceInfo::from_properties(id.to_owned(), OrgBluezDevice1Properties(&properties))
+        let device_properties = OrgBluezDevice1Properties(&properties);
+        // BlueZ's battery plugin only adds the battery interface to devices with the standard
+        // Battery service, once they are connected, so don't waste a round trip asking for it
+        // otherwise.
+        let has_battery_service = device_properties
+            .uuids()
+            .is_some_and(|uuids| convert_services(uuids).contains(&BATTERY_SERVICE_UUID));
+        let battery_properties = if has_battery_service {
+            match device.get_all(ORG_BLUEZ_BATTERY1_NAME).await {
+                Ok(battery_properties) => Some(battery_properties),
+                Err(e)
+                    if e.name() == Some("org.freedesktop.DBus.Error.InvalidArgs")
+                        || e.name() == Some("org.freedesktop.DBus.Error.UnknownInterface") =>
+                {
+                    None
+                }
+                Err(e) => return Err(e.into()),
+            }
+        } else {
+            None
+        };
+        DeviceInfo::from_properties(
+            id.to_owned(),
+            device_properties,
+            battery_properties.as_ref().map(OrgBluezBattery1Properties),
+        )
+    }
+
+    /// Get the battery level of the given device as a percentage, if it reports one.
+    ///
+    /// This uses the level reported by BlueZ if there is one, as in
+    /// [`DeviceInfo::battery_percentage`](struct.DeviceInfo.html#structfield.battery_percentage).
+    /// Otherwise it falls back to reading the standard Battery Level characteristic, which requires
+    /// the device to be connected. Returns `None` if neither is available.
+    pub async fn get_battery_percentage(
+        &self,
+        id: &DeviceId,
+    ) -> Result<Option<u8>, BluetoothError> {
+        if let Some(battery_percentage) = self.get_device_info(id).await?.battery_percentage {
+            return Ok(Some(battery_percentage));
+        }
+        match self.get_battery_level_characteristic(id).await? {
+            Some(characteristic) => Ok(self
+                .read_characteristic_value(&characteristic.id)
+                .await?
+                .first()
+                .copied()),
+            None => Ok(None),
+        }
+    }
+
+    /// Get a stream of updates to the battery level of the given device, as a percentage.
+    ///
+    /// Like [`get_battery_percentage`](#method.get_battery_percentage), this uses the level
+    /// reported by BlueZ if there is one, and otherwise starts notifications on the standard Battery
+    /// Level characteristic. Returns `BluetoothError::UUIDNotFound` if neither is available.
+    pub async fn battery_percentage_stream(
+        &self,
+        id: &DeviceId,
+    ) -> Result<impl Stream<Item = u8>, BluetoothError> {
+        // Subscribe to events before checking the current state, so that no updates are missed.
+        let events = self.device_event_stream(id).await?;
+        let characteristic = if self.get_device_info(id).await?.battery_percentage.is_some() {
+            None
+        } else {
+            let characteristic = self.get_battery_level_characteristic(id).await?.ok_or(
+                BluetoothError::UUIDNotFound {
+                    uuid: BATTERY_LEVEL_UUID,
+                },
+            )?;
+            self.start_notify(&characteristic.id).await?;
+            Some(characteristic.id)
+        };
+        Ok(events.filter_map(move |event| {
+            let battery_percentage = match (event, &characteristic) {
+                (
+                    BluetoothEvent::Device {
+                        event: DeviceEvent::BatteryPercentage { battery_percentage },
+                        ..
+                    },
+                    None,
+                ) => Some(battery_percentage),
+                (
+                    BluetoothEvent::Characteristic {
+                        id,
+                        event: CharacteristicEvent::Value { value },
+                    },
+                    Some(characteristic),
+                ) if &id == characteristic => value.first().copied(),
+                _ => None,
+            };
+            async move { battery_percentage }
+        }))
+    }
+
+    /// Find the standard Battery Level characteristic of the given device, if it has one.
+    async fn get_battery_level_characteristic(
+        &self,
+        id: &DeviceId,
+    ) -> Result<Option<CharacteristicInfo>, BluetoothError> {
+        match self
+            .get_service_characteristic_by_uuid(id, BATTERY_SERVICE_UUID, BATTERY_LEVEL_UUID)
+            .await
+        {
+            Ok(characteristic) => Ok(Some(characteristic)),
+            Err(BluetoothError::UUIDNotFound { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read the standard Device Information service (0x180A) of the given device, which includes
+    /// its manufacturer, model and firmware and hardware revisions. Fields for characteristics
+    /// which the device doesn't have are left as `None`.
+    ///
+    /// Returns `BluetoothError::UUIDNotFound` if the device doesn't have the service. The device
+    /// must be connected.
+    pub async fn get_device_information(
+        &self,
+        id: &DeviceId,
+    ) -> Result<DeviceInformation, BluetoothError> {
+        let characteristics = self
+            .get_standard_service_characteristics(id, DEVICE_INFORMATION_SERVICE_UUID)
+            .await?;
+        let mut information = DeviceInformation::default();
+        for characteristic in characteristics {
+            if let Some(field @ None) = information.field(characteristic.uuid) {
+                let value = self.read_characteristic_value(&characteristic.id).await?;
+                *field = Some(parse_string(characteristic.uuid, &value)?);
+            }
+        }
+        Ok(information)
+    }
+
+    /// Read the temperature, humidity and pressure from the standard Environmental Sensing service
+    /// (0x181A) of the given device. Readings for characteristics which the device doesn't have, or
+    /// whose value the device reports as not known, are left as `None`.
+    ///
+    /// Returns `BluetoothError::UUIDNotFound` if the device doesn't have the service. The device
+    /// must be connected.
+    pub async fn get_environmental_readings(
+        &self,
+        id: &DeviceId,
+    ) -> Result<EnvironmentalReadings, BluetoothError> {
+        let characteristics = self
+            .get_standard_service_characteristics(id, ENVIRONMENTAL_SENSING_SERVICE_UUID)
+            .await?;
+        let mut readings = EnvironmentalReadings::default();
+        for characteristic in characteristics {
+            if let Some((field @ None, parse)) = readings.field(characteristic.uuid) {
+                let value = self.read_characteristic_value(&characteristic.id).await?;
+                *field = parse(&value)?;
+            }
+        }
+        Ok(readings)
+    }
+
+    /// Get the characteristics of the first service with the given UUID on the given device.
+    async fn get_standard_service_characteristics(
+        &self,
+        id: &DeviceId,
+        service_uuid: Uuid,
+    ) -> Result<Vec<CharacteristicInfo>, BluetoothError> {
+        let database = self.get_gatt_database(id).await?;
+        let service = database
+            .services
+            .iter()
+            .find(|service| service.info.uuid == service_uuid)
+            .ok_or(BluetoothError::UUIDNotFound { uuid: service_uuid })?;
+        Ok(service
+            .characteristics
+            .iter()
+            .map(|characteristic| characteristic.info.clone())
+            .collect())
+    }
+
+    /// Get information about the current connection to the given Bluetooth device, such as the
+    /// negotiated ATT MTU.
+    pub async fn get_connection_info(
+        &self,
+        id: &DeviceId,
+    ) -> Result<ConnectionInfo, BluetoothError> {
+        let device_info = self.get_device_info(id).await?;
+        let mtu = if device_info.connected {
+            let bluez_root = Proxy::new(
+                "org.bluez",
+                "/",
+                self.config.method_call_timeout,
+                self.connection.clone(),
+            );
+            let tree = bluez_root.get_managed_objects().await?;
+            let prefix = format!("{}/", id.object_path);
+            // All characteristics on the device share the same connection, so report the MTU from
+            // whichever has one.
+            tree.iter()
+                .filter(|(object_path, _)| object_path.starts_with(&prefix))
+                .find_map(|(_, interfaces)| {
+                    OrgBluezGattCharacteristic1Properties::from_interfaces(interfaces)?.mtu()
+                })
+        } else {
+            None
+        };
+        Ok(ConnectionInfo {
+            id: id.to_owned(),
+            connected: device_info.connected,
+            mtu,
+            rssi: device_info.rssi,
+        })
     }
 
     /// Get information about the given Bluetooth adapter.
@@ -623,13 +1312,13 @@ impl BluetoothSession {
         id: &CharacteristicId,
     ) -> Result<CharacteristicInfo, BluetoothError> {
         let characteristic = self.characteristic(&id);
-        let uuid = Uuid::parse_str(&characteristic.uuid().await?)?;
-        let flags = characteristic.flags().await?;
-        Ok(CharacteristicInfo {
-            id: id.to_owned(),
-            uuid,
-            flags: flags.try_into()?,
-        })
+        let properties = characteristic
+            .get_all(ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME)
+            .await?;
+        CharacteristicInfo::from_properties(
+            id.to_owned(),
+            OrgBluezGattCharacteristic1Properties(&properties),
+        )
     }
 
     /// Get information about the given GATT descriptor.
@@ -644,11 +1333,79 @@ impl BluetoothSession {
         })
     }
 
+    /// Get information about the given GATT characteristic, along with the decoded values of its
+    /// standard descriptors: Characteristic User Description (0x2901), Client Characteristic
+    /// Configuration (0x2902) and Characteristic Presentation Format (0x2904).
+    ///
+    /// This reads each of those descriptors which the characteristic has, so the device must be
+    /// connected. If reading or parsing any one descriptor fails then a warning is logged and the
+    /// corresponding field is left as `None`, rather than failing the whole call.
+    pub async fn get_characteristic_metadata(
+        &self,
+        id: &CharacteristicId,
+    ) -> Result<CharacteristicMetadata, BluetoothError> {
+        let mut metadata = CharacteristicMetadata {
+            info: self.get_characteristic_info(id).await?,
+            user_description: None,
+            client_configuration: None,
+            presentation_format: None,
+        };
+        for descriptor in self.get_descriptors(id).await? {
+            match descriptor.uuid.to_ble_u16() {
+                Some(0x2901) => {
+                    metadata.user_description = self
+                        .read_standard_descriptor(&descriptor, parse_user_description)
+                        .await;
+                }
+                Some(0x2902) => {
+                    metadata.client_configuration = self
+                        .read_standard_descriptor(
+                            &descriptor,
+                            ClientCharacteristicConfiguration::from_bytes,
+                        )
+                        .await;
+                }
+                Some(0x2904) if metadata.presentation_format.is_none() => {
+                    metadata.presentation_format = self
+                        .read_standard_descriptor(&descriptor, PresentationFormat::from_bytes)
+                        .await;
+                }
+                _ => {}
+            }
+        }
+        Ok(metadata)
+    }
+
+    /// Read the value of the given descriptor and parse it with the given function, logging a
+    /// warning and returning `None` if either step fails.
+    async fn read_standard_descriptor<T>(
+        &self,
+        descriptor: &DescriptorInfo,
+        parse: impl FnOnce(&[u8]) -> Result<T, ParseDescriptorError>,
+    ) -> Option<T> {
+        let result = match self.read_descriptor_value(&descriptor.id).await {
+            Ok(value) => parse(&value).map_err(BluetoothError::from),
+            Err(e) => Err(e),
+        };
+        result
+            .map_err(|e| log::warn!("Error reading descriptor {}: {}", descriptor.id, e))
+            .ok()
+    }
+
     fn adapter(&self, id: &AdapterId) -> impl OrgBluezAdapter1 + Introspectable + Properties {
         Proxy::new(
             "org.bluez",
             id.object_path.to_owned(),
-            DBUS_METHOD_CALL_TIMEOUT,
+            self.config.method_call_timeout,
+            self.connection.clone(),
+        )
+    }
+
+    fn gatt_manager(&self, id: &AdapterId) -> impl OrgBluezGattManager1 {
+        Proxy::new(
+            "org.bluez",
+            id.object_path.to_owned(),
+            self.config.method_call_timeout,
             self.connection.clone(),
         )
     }
@@ -657,7 +1414,7 @@ impl BluetoothSession {
         Proxy::new(
             "org.bluez",
             id.object_path.to_owned(),
-            DBUS_METHOD_CALL_TIMEOUT,
+            self.config.method_call_timeout,
             self.connection.clone(),
         )
     }
@@ -666,7 +1423,7 @@ impl BluetoothSession {
         Proxy::new(
             "org.bluez",
             id.object_path.to_owned(),
-            DBUS_METHOD_CALL_TIMEOUT,
+            self.config.method_call_timeout,
             self.connection.clone(),
         )
     }
@@ -678,7 +1435,7 @@ impl BluetoothSession {
         Proxy::new(
             "org.bluez",
             id.object_path.to_owned(),
-            DBUS_METHOD_CALL_TIMEOUT,
+            self.config.method_call_timeout,
             self.connection.clone(),
         )
     }
@@ -690,7 +1447,7 @@ impl BluetoothSession {
         Proxy::new(
             "org.bluez",
             id.object_path.to_owned(),
-            DBUS_METHOD_CALL_TIMEOUT,
+            self.config.method_call_timeout,
             self.connection.clone(),
         )
     }
@@ -703,7 +1460,7 @@ impl BluetoothSession {
             log::info!("Services already resolved.");
             return Ok(());
         }
-        timeout(SERVICE_DISCOVERY_TIMEOUT, async {
+        timeout(self.config.service_discovery_timeout, async {
             while let Some(event) = events.next().await {
                 if matches!(event, BluetoothEvent::Device {
                     id,
@@ -715,15 +1472,31 @@ impl BluetoothSession {
             }
 
             // Stream ended prematurely. This shouldn't happen, so something has gone wrong.
-            Err(BluetoothError::ServiceDiscoveryTimedOut)
+            Err(BluetoothError::Timeout {
+                operation: Operation::ServiceDiscovery,
+            })
         })
         .await
-        .unwrap_or(Err(BluetoothError::ServiceDiscoveryTimedOut))
+        .unwrap_or(Err(BluetoothError::Timeout {
+            operation: Operation::ServiceDiscovery,
+        }))
     }
 
     /// Connect to the given Bluetooth device.
     pub async fn connect(&self, id: &DeviceId) -> Result<(), BluetoothError> {
-        self.device(id).connect().await?;
+        let _permit = self.schedule_connect(id).await;
+        let device = Proxy::new(
+            "org.bluez",
+            id.object_path.to_owned(),
+            self.config.connect_timeout,
+            self.connection.clone(),
+        );
+        device.connect().await.map_err(|e| match e.into() {
+            BluetoothError::Timeout { .. } => BluetoothError::Timeout {
+                operation: Operation::Connect,
+            },
+            e => e,
+        })?;
         self.await_service_discovery(id).await
     }
 
@@ -732,6 +1505,32 @@ impl BluetoothSession {
         Ok(self.device(id).disconnect().await?)
     }
 
+    /// Pair with the given Bluetooth device.
+    ///
+    /// Depending on the device, BlueZ may ask the registered [`Agent`](trait.Agent.html) to
+    /// confirm a passkey or the like, so you will probably want to call
+    /// [`register_agent`](#method.register_agent) first.
+    pub async fn pair(&self, id: &DeviceId) -> Result<(), BluetoothError> {
+        Ok(self.device(id).pair().await?)
+    }
+
+    /// Cancel an in-progress attempt to pair with the given Bluetooth device.
+    pub async fn cancel_pairing(&self, id: &DeviceId) -> Result<(), BluetoothError> {
+        Ok(self.device(id).cancel_pairing().await?)
+    }
+
+    /// Set whether the given Bluetooth device is trusted. Trusted devices may connect without
+    /// their connections needing to be authorized by an agent.
+    pub async fn set_trusted(&self, id: &DeviceId, trusted: bool) -> Result<(), BluetoothError> {
+        Ok(self.device(id).set_trusted(trusted).await?)
+    }
+
+    /// Set whether the given Bluetooth device is blocked. Incoming connections from blocked devices
+    /// will be rejected, and any existing connections will be dropped.
+    pub async fn set_blocked(&self, id: &DeviceId, blocked: bool) -> Result<(), BluetoothError> {
+        Ok(self.device(id).set_blocked(blocked).await?)
+    }
+
     /// Read the value of the given GATT characteristic.
     ///
     /// This is equivalent to calling `read_characteristic_value_with_offset(0)`.
@@ -748,6 +1547,7 @@ impl BluetoothSession {
         id: &CharacteristicId,
         offset: usize,
     ) -> Result<Vec<u8>, BluetoothError> {
+        let _permit = self.schedule_gatt_operation(&id.service().device()).await;
         let characteristic = self.characteristic(id);
         Ok(characteristic.read_value(offset_to_propmap(offset)).await?)
     }
@@ -771,6 +1571,7 @@ impl BluetoothSession {
         value: impl Into<Vec<u8>>,
         options: WriteOptions,
     ) -> Result<(), BluetoothError> {
+        let _permit = self.schedule_gatt_operation(&id.service().device()).await;
         let characteristic = self.characteristic(id);
         Ok(characteristic
             .write_value(value.into(), options.into())
@@ -793,6 +1594,9 @@ impl BluetoothSession {
         id: &DescriptorId,
         offset: usize,
     ) -> Result<Vec<u8>, BluetoothError> {
+        let _permit = self
+            .schedule_gatt_operation(&id.characteristic().service().device())
+            .await;
         let descriptor = self.descriptor(id);
         Ok(descriptor.read_value(offset_to_propmap(offset)).await?)
     }
@@ -815,6 +1619,9 @@ impl BluetoothSession {
         value: impl Into<Vec<u8>>,
         offset: usize,
     ) -> Result<(), BluetoothError> {
+        let _permit = self
+            .schedule_gatt_operation(&id.characteristic().service().device())
+            .await;
         let descriptor = self.descriptor(id);
         Ok(descriptor
             .write_value(value.into(), offset_to_propmap(offset))
@@ -823,6 +1630,7 @@ impl BluetoothSession {
 
     /// Start notifications on the given GATT characteristic.
     pub async fn start_notify(&self, id: &CharacteristicId) -> Result<(), BluetoothError> {
+        let _permit = self.schedule_gatt_operation(&id.service().device()).await;
         let characteristic = self.characteristic(id);
         characteristic.start_notify().await?;
         Ok(())
@@ -830,16 +1638,253 @@ impl BluetoothSession {
 
     /// Stop notifications on the given GATT characteristic.
     pub async fn stop_notify(&self, id: &CharacteristicId) -> Result<(), BluetoothError> {
+        let _permit = self.schedule_gatt_operation(&id.service().device()).await;
         let characteristic = self.characteristic(id);
         characteristic.stop_notify().await?;
         Ok(())
     }
 
+    /// Start notifications on the given GATT characteristic, and get a stream of the notified
+    /// values over a file descriptor acquired from BlueZ.
+    ///
+    /// This avoids sending a D-Bus signal for each notification, so is more efficient than
+    /// [`start_notify`](#method.start_notify) for characteristics which notify frequently. The
+    /// notifications will stop when the stream is dropped. BlueZ only supports this for
+    /// characteristics which support notifications but not indications, and not while
+    /// notifications have already been started some other way.
+    pub async fn acquire_notify(
+        &self,
+        id: &CharacteristicId,
+    ) -> Result<NotificationStream, BluetoothError> {
+        let _permit = self.schedule_gatt_operation(&id.service().device()).await;
+        let (fd, mtu) = self
+            .characteristic(id)
+            .acquire_notify(HashMap::new())
+            .await?;
+        Ok(NotificationStream::new(fd, mtu)?)
+    }
+
+    /// Get a writer to send values to the given GATT characteristic without response, over a file
+    /// descriptor acquired from BlueZ.
+    ///
+    /// This avoids a D-Bus method call for each value, so is more efficient than
+    /// [`write_characteristic_value_with_options`](#method.write_characteristic_value_with_options)
+    /// for sending many values. The characteristic must support write without response.
+    pub async fn acquire_write(
+        &self,
+        id: &CharacteristicId,
+    ) -> Result<CharacteristicWriter, BluetoothError> {
+        let _permit = self.schedule_gatt_operation(&id.service().device()).await;
+        let (fd, mtu) = self
+            .characteristic(id)
+            .acquire_write(HashMap::new())
+            .await?;
+        Ok(CharacteristicWriter::new(fd, mtu)?)
+    }
+
+    /// Register a local GATT application with BlueZ on the given adapter, so that remote devices can
+    /// connect to it and use its services.
+    ///
+    /// The application will be unregistered when the returned handle is dropped.
+    pub async fn register_gatt_application(
+        &self,
+        adapter: &AdapterId,
+        application: GattApplication,
+    ) -> Result<GattApplicationHandle, BluetoothError> {
+        let object_paths = self
+            .gatt_interfaces
+            .insert_application(&mut self.crossroads.lock().unwrap(), application);
+        if let Err(e) = self
+            .gatt_manager(adapter)
+            .register_application(object_paths[0].clone(), HashMap::new())
+            .await
+        {
+            remove_objects(&mut self.crossroads.lock().unwrap(), &object_paths);
+            return Err(e.into());
+        }
+        Ok(GattApplicationHandle {
+            connection: self.connection.clone(),
+            crossroads: self.crossroads.clone(),
+            method_call_timeout: self.config.method_call_timeout,
+            adapter: adapter.to_owned(),
+            object_paths,
+        })
+    }
+
+    /// Register an advertisement with BlueZ, to be broadcast by the given adapter.
+    ///
+    /// The advertisement will be unregistered when the returned handle is dropped.
+    pub async fn register_advertisement(
+        &self,
+        adapter: &AdapterId,
+        advertisement: Advertisement,
+    ) -> Result<AdvertisementHandle, BluetoothError> {
+        advertisement.discoverable_timeout_secs()?;
+        let object_path = insert_advertisement(
+            &mut self.crossroads.lock().unwrap(),
+            self.advertisement_interface,
+            advertisement,
+        );
+        let mut handle = AdvertisementHandle {
+            connection: self.connection.clone(),
+            crossroads: self.crossroads.clone(),
+            method_call_timeout: self.config.method_call_timeout,
+            adapter: adapter.to_owned(),
+            object_path,
+            registered: true,
+        };
+        if let Err(e) = handle
+            .advertising_manager()
+            .register_advertisement(handle.object_path.clone(), HashMap::new())
+            .await
+        {
+            handle.remove();
+            return Err(e.into());
+        }
+        Ok(handle)
+    }
+
+    /// Unregister the given advertisement, so that it is no longer broadcast.
+    pub async fn unregister_advertisement(
+        &self,
+        mut advertisement: AdvertisementHandle,
+    ) -> Result<(), BluetoothError> {
+        advertisement.remove();
+        advertisement
+            .advertising_manager()
+            .unregister_advertisement(advertisement.object_path.clone())
+            .await?;
+        Ok(())
+    }
+
+    /// Register an agent with BlueZ, to handle pairing and authorization requests with the given
+    /// input and output capability.
+    ///
+    /// BlueZ will use the agent for pairing initiated by this session. To also use it for requests
+    /// initiated by remote devices, call
+    /// [`request_default_agent`](#method.request_default_agent) as well.
+    ///
+    /// The agent will be unregistered when the returned handle is dropped.
+    pub async fn register_agent(
+        &self,
+        agent: Arc<dyn Agent>,
+        capability: AgentCapability,
+    ) -> Result<AgentHandle, BluetoothError> {
+        let object_path = insert_agent(
+            &mut self.crossroads.lock().unwrap(),
+            self.agent_interface,
+            agent,
+        );
+        let mut handle = AgentHandle {
+            connection: self.connection.clone(),
+            crossroads: self.crossroads.clone(),
+            method_call_timeout: self.config.method_call_timeout,
+            object_path,
+            registered: true,
+        };
+        if let Err(e) = handle
+            .agent_manager()
+            .register_agent(handle.object_path.clone(), capability.as_str())
+            .await
+        {
+            handle.remove();
+            return Err(e.into());
+        }
+        Ok(handle)
+    }
+
+    /// Make the given agent the default agent, so that BlueZ uses it for requests which weren't
+    /// initiated by any particular client, such as incoming pairing requests.
+    pub async fn request_default_agent(&self, agent: &AgentHandle) -> Result<(), BluetoothError> {
+        agent
+            .agent_manager()
+            .request_default_agent(agent.object_path.clone())
+            .await?;
+        Ok(())
+    }
+
+    /// Unregister the given agent, so that it no longer receives requests from BlueZ.
+    pub async fn unregister_agent(&self, mut agent: AgentHandle) -> Result<(), BluetoothError> {
+        agent.remove();
+        agent
+            .agent_manager()
+            .unregister_agent(agent.object_path.clone())
+            .await?;
+        Ok(())
+    }
+
+    /// Register a Bluetooth Classic profile with BlueZ, such as the Serial Port Profile (SPP)
+    /// over RFCOMM, with the given UUID and options.
+    ///
+    /// Connections to the profile are received by polling the returned handle as a `Stream`, as
+    /// [`ProfileEvent::NewConnection`](enum.ProfileEvent.html#variant.NewConnection). The profile
+    /// will be unregistered when the handle is dropped.
+    pub async fn register_profile(
+        &self,
+        uuid: Uuid,
+        options: ProfileOptions,
+    ) -> Result<ProfileHandle, BluetoothError> {
+        let (object_path, events) =
+            insert_profile(&mut self.crossroads.lock().unwrap(), self.profile_interface);
+        let mut handle = ProfileHandle {
+            connection: self.connection.clone(),
+            crossroads: self.crossroads.clone(),
+            method_call_timeout: self.config.method_call_timeout,
+            object_path,
+            registered: true,
+            events,
+        };
+        if let Err(e) = handle
+            .profile_manager()
+            .register_profile(
+                handle.object_path.clone(),
+                &uuid.to_string(),
+                options.into(),
+            )
+            .await
+        {
+            handle.remove();
+            return Err(e.into());
+        }
+        Ok(handle)
+    }
+
+    /// Unregister the given profile, so that it no longer receives connections.
+    pub async fn unregister_profile(
+        &self,
+        mut profile: ProfileHandle,
+    ) -> Result<(), BluetoothError> {
+        profile.remove();
+        profile
+            .profile_manager()
+            .unregister_profile(profile.object_path.clone())
+            .await?;
+        Ok(())
+    }
+
     /// Get a stream of events for all devices.
     pub async fn event_stream(&self) -> Result<impl Stream<Item = BluetoothEvent>, BluetoothError> {
         self.filtered_event_stream(None::<&DeviceId>).await
     }
 
+    /// Start recording every event from this session to the given writer (e.g. a `tokio::fs::File`),
+    /// along with when it was received, until the returned `EventRecorder` is dropped.
+    ///
+    /// The UUIDs of characteristics and the details of newly discovered devices are looked up and
+    /// recorded along with the events for them, so that the recording can be interpreted without
+    /// BlueZ.
+    ///
+    /// The recording can be read back with [`EventReplay`](struct.EventReplay.html), to feed the
+    /// same events in the same order to code which would otherwise use
+    /// [`event_stream`](#method.event_stream).
+    pub async fn record_events(
+        &self,
+        writer: impl AsyncWrite + Send + Unpin + 'static,
+    ) -> Result<EventRecorder, BluetoothError> {
+        let events = self.event_stream().await?;
+        Ok(EventRecorder::start(self.clone(), events, writer))
+    }
+
     /// Get a stream of events for a particular device. This includes events for all its
     /// characteristics.
     pub async fn device_event_stream(
@@ -857,6 +1902,20 @@ impl BluetoothSession {
         self.filtered_event_stream(Some(characteristic)).await
     }
 
+    /// Get a stream of the advertisement data received from devices, with the data for each device
+    /// merged into a single record which is returned whenever any of it changes.
+    ///
+    /// This doesn't start discovery; call
+    /// [`start_discovery_with_filter`](#method.start_discovery_with_filter) for that. You will
+    /// probably want to set `duplicate_data: Some(true)` in the filter, so that BlueZ reports each
+    /// advertisement rather than only those whose data has changed.
+    pub async fn advertisement_stream(
+        &self,
+        options: AdvertisementStreamOptions,
+    ) -> Result<AdvertisementStream, BluetoothError> {
+        AdvertisementStream::new(self.clone(), options).await
+    }
+
     async fn filtered_event_stream(
         &self,
         object: Option<&(impl Into<Path<'static>> + Clone)>,
@@ -878,3 +1937,20 @@ fn offset_to_propmap(offset: usize) -> PropMap {
     }
     map
 }
+
+/// The error for a GATT object which isn't in its device's GATT database, the same as BlueZ
+/// returns for an object which doesn't exist.
+fn unknown_object(object_path: &Path) -> BluetoothError {
+    BluetoothError::DeviceNotFound(dbus::Error::new_custom(
+        "org.freedesktop.DBus.Error.UnknownObject",
+        &format!("No such object {}", object_path),
+    ))
+}
+
+/// Deserialize a D-Bus object path from a string, as used for the various ID types.
+fn deserialize_object_path<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Path<'static>, D::Error> {
+    let object_path = String::deserialize(deserializer)?;
+    Path::new(object_path).map_err(D::Error::custom)
+}
@@ -0,0 +1,467 @@
+//! Support for Bluetooth Classic profiles such as the Serial Port Profile, by exporting a profile
+//! via the BlueZ ProfileManager1 interface and receiving a socket for each connection to it.
+
+use bluez_generated::OrgBluezProfileManager1;
+use dbus::arg::{prop_cast, OwnedFd, PropMap, Variant};
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus::{MethodErr, Path};
+use dbus_crossroads::{Crossroads, IfaceBuilder, IfaceToken};
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::{ready, Stream};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use uuid::Uuid;
+
+use crate::DeviceId;
+
+/// The name of the D-Bus interface which profile objects implement.
+const ORG_BLUEZ_PROFILE1_NAME: &str = "org.bluez.Profile1";
+
+/// Prefix for the object paths of profiles.
+const PROFILE_PATH_PREFIX: &str = "/bluez_async/profile";
+
+/// Counter used to give each profile a unique object path.
+static NEXT_PROFILE_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether a profile connects to remote devices or accepts connections from them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ProfileRole {
+    /// The local side initiates connections.
+    Client,
+    /// The local side accepts connections.
+    Server,
+}
+
+impl ProfileRole {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Client => "client",
+            Self::Server => "server",
+        }
+    }
+}
+
+impl Display for ProfileRole {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Options for registering a profile with
+/// [`BluetoothSession::register_profile`](struct.BluetoothSession.html#method.register_profile).
+///
+/// Any options which are not set will use the BlueZ defaults for the profile UUID.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ProfileOptions {
+    /// A human-readable name for the profile.
+    pub name: Option<String>,
+    /// The primary service class UUID, if different from the profile UUID.
+    pub service: Option<Uuid>,
+    /// Whether the profile connects to remote devices or accepts connections from them.
+    pub role: Option<ProfileRole>,
+    /// The RFCOMM channel number to use.
+    pub channel: Option<u16>,
+    /// The L2CAP PSM number to use.
+    pub psm: Option<u16>,
+    /// Whether pairing is required before connecting.
+    pub require_authentication: Option<bool>,
+    /// Whether connections must be authorized by an agent.
+    pub require_authorization: Option<bool>,
+    /// Whether BlueZ should connect the profile automatically when a device connects.
+    pub auto_connect: Option<bool>,
+    /// A complete SDP record to use instead of the one BlueZ generates, in XML format.
+    pub service_record: Option<String>,
+    /// The profile version, to include in the SDP record.
+    pub version: Option<u16>,
+    /// The profile features, to include in the SDP record.
+    pub features: Option<u16>,
+}
+
+impl From<ProfileOptions> for PropMap {
+    fn from(options: ProfileOptions) -> Self {
+        let mut map: PropMap = PropMap::new();
+        if let Some(name) = options.name {
+            map.insert("Name".to_string(), Variant(Box::new(name)));
+        }
+        if let Some(service) = options.service {
+            map.insert(
+                "Service".to_string(),
+                Variant(Box::new(service.to_string())),
+            );
+        }
+        if let Some(role) = options.role {
+            map.insert(
+                "Role".to_string(),
+                Variant(Box::new(role.as_str().to_string())),
+            );
+        }
+        if let Some(channel) = options.channel {
+            map.insert("Channel".to_string(), Variant(Box::new(channel)));
+        }
+        if let Some(psm) = options.psm {
+            map.insert("PSM".to_string(), Variant(Box::new(psm)));
+        }
+        if let Some(require_authentication) = options.require_authentication {
+            map.insert(
+                "RequireAuthentication".to_string(),
+                Variant(Box::new(require_authentication)),
+            );
+        }
+        if let Some(require_authorization) = options.require_authorization {
+            map.insert(
+                "RequireAuthorization".to_string(),
+                Variant(Box::new(require_authorization)),
+            );
+        }
+        if let Some(auto_connect) = options.auto_connect {
+            map.insert("AutoConnect".to_string(), Variant(Box::new(auto_connect)));
+        }
+        if let Some(service_record) = options.service_record {
+            map.insert(
+                "ServiceRecord".to_string(),
+                Variant(Box::new(service_record)),
+            );
+        }
+        if let Some(version) = options.version {
+            map.insert("Version".to_string(), Variant(Box::new(version)));
+        }
+        if let Some(features) = options.features {
+            map.insert("Features".to_string(), Variant(Box::new(features)));
+        }
+        map
+    }
+}
+
+/// A connection to a registered profile, as received from a
+/// [`ProfileHandle`](struct.ProfileHandle.html).
+///
+/// This can be read from and written to like any other async byte stream, e.g. with
+/// `tokio::io::AsyncReadExt` and `AsyncWriteExt`. Dropping it closes the connection.
+pub struct ProfileConnection {
+    /// The remote device which is connected.
+    pub device: DeviceId,
+    /// The profile version reported by the remote device, if any.
+    pub version: Option<u16>,
+    /// The profile features reported by the remote device, if any.
+    pub features: Option<u16>,
+    socket: AsyncFd<OwnedFd>,
+}
+
+impl Debug for ProfileConnection {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("ProfileConnection")
+            .field("device", &self.device)
+            .field("version", &self.version)
+            .field("features", &self.features)
+            .finish()
+    }
+}
+
+impl ProfileConnection {
+    fn new(device: DeviceId, fd: OwnedFd, fd_properties: &PropMap) -> io::Result<Self> {
+        // The socket is an RFCOMM or L2CAP socket rather than a Unix domain socket, so it is kept as
+        // a raw file descriptor and only generic system calls are used on it.
+        set_nonblocking(&fd)?;
+        Ok(Self {
+            device,
+            version: prop_cast(fd_properties, "Version").copied(),
+            features: prop_cast(fd_properties, "Features").copied(),
+            socket: AsyncFd::new(fd)?,
+        })
+    }
+}
+
+/// Put the given file descriptor into non-blocking mode.
+fn set_nonblocking(fd: &OwnedFd) -> io::Result<()> {
+    // Safe because F_GETFL and F_SETFL only change the flags of a file descriptor which we own.
+    unsafe {
+        let flags = libc::fcntl(fd.as_raw_fd(), libc::F_GETFL);
+        if flags < 0 || libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Convert the return value of a system call which returns a length or -1 into an `io::Result`.
+fn check_length(result: isize) -> io::Result<usize> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result as usize)
+    }
+}
+
+impl AsyncRead for ProfileConnection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = ready!(self.socket.poll_read_ready(cx))?;
+            let unfilled = buf.initialize_unfilled();
+            // Safe because we pass a valid buffer along with its length.
+            let result = guard.try_io(|socket| {
+                check_length(unsafe {
+                    libc::read(
+                        socket.as_raw_fd(),
+                        unfilled.as_mut_ptr() as *mut libc::c_void,
+                        unfilled.len(),
+                    )
+                })
+            });
+            match result {
+                Ok(Ok(length)) => {
+                    buf.advance(length);
+                    return Poll::Ready(Ok(()));
+                }
+                Ok(Err(e)) => return Poll::Ready(Err(e)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl AsyncWrite for ProfileConnection {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.socket.poll_write_ready(cx))?;
+            // Safe because we pass a valid buffer along with its length. MSG_NOSIGNAL avoids a
+            // SIGPIPE if the remote device has closed the connection.
+            let result = guard.try_io(|socket| {
+                check_length(unsafe {
+                    libc::send(
+                        socket.as_raw_fd(),
+                        buf.as_ptr() as *const libc::c_void,
+                        buf.len(),
+                        libc::MSG_NOSIGNAL,
+                    )
+                })
+            });
+            match result {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        // Writes go straight to the socket, so there is nothing to flush.
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        // Safe because shutdown doesn't access any memory.
+        if unsafe { libc::shutdown(self.socket.as_raw_fd(), libc::SHUT_WR) } != 0 {
+            return Poll::Ready(Err(io::Error::last_os_error()));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// A request from BlueZ to a registered profile, as returned by the `Stream` implementation of
+/// [`ProfileHandle`](struct.ProfileHandle.html).
+#[derive(Debug)]
+pub enum ProfileEvent {
+    /// A remote device has connected to the profile.
+    NewConnection(ProfileConnection),
+    /// BlueZ has asked for the connection to the given device to be closed, e.g. because the device
+    /// is being disconnected. The corresponding `ProfileConnection` should be dropped.
+    RequestDisconnection {
+        /// The device whose connection should be closed.
+        device: DeviceId,
+    },
+    /// BlueZ has unregistered the profile, e.g. because it is shutting down. There will be no more
+    /// connections after this.
+    Released,
+}
+
+/// A handle to a profile which has been registered with BlueZ. The profile will be unregistered
+/// when this is dropped, or it may be explicitly unregistered with
+/// [`BluetoothSession::unregister_profile`](struct.BluetoothSession.html#method.unregister_profile)
+/// to find out whether that succeeded.
+///
+/// Connections to the profile and other requests from BlueZ are received by polling this as a
+/// `Stream`.
+pub struct ProfileHandle {
+    pub(crate) connection: Arc<SyncConnection>,
+    pub(crate) crossroads: Arc<Mutex<Crossroads>>,
+    /// The timeout for D-Bus method calls, from the session which registered this.
+    pub(crate) method_call_timeout: Duration,
+    pub(crate) object_path: Path<'static>,
+    /// Whether the profile still needs to be unregistered when the handle is dropped.
+    pub(crate) registered: bool,
+    pub(crate) events: UnboundedReceiver<ProfileEvent>,
+}
+
+impl Debug for ProfileHandle {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "ProfileHandle {{ object_path: {} }}", self.object_path)
+    }
+}
+
+impl ProfileHandle {
+    /// Get the D-Bus object path of the profile.
+    pub fn object_path(&self) -> &Path<'static> {
+        &self.object_path
+    }
+
+    /// Get a proxy for the BlueZ profile manager.
+    pub(crate) fn profile_manager(&self) -> impl OrgBluezProfileManager1 {
+        Proxy::new(
+            "org.bluez",
+            "/org/bluez",
+            self.method_call_timeout,
+            self.connection.clone(),
+        )
+    }
+
+    /// Stop exporting the profile object, and mark it as no longer needing to be unregistered.
+    pub(crate) fn remove(&mut self) {
+        self.crossroads
+            .lock()
+            .unwrap()
+            .remove::<LocalProfile>(&self.object_path);
+        self.registered = false;
+    }
+}
+
+impl Stream for ProfileHandle {
+    type Item = ProfileEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<ProfileEvent>> {
+        Pin::new(&mut self.events).poll_next(cx)
+    }
+}
+
+impl Drop for ProfileHandle {
+    fn drop(&mut self) {
+        if !self.registered {
+            return;
+        }
+        self.remove();
+        let profile_manager = self.profile_manager();
+        let object_path = self.object_path.clone();
+        tokio::spawn(async move {
+            if let Err(e) = profile_manager.unregister_profile(object_path).await {
+                log::error!("Error unregistering profile: {}", e);
+            }
+        });
+    }
+}
+
+pub(crate) struct LocalProfile {
+    events: UnboundedSender<ProfileEvent>,
+}
+
+impl LocalProfile {
+    fn send(&self, event: ProfileEvent) -> Result<(), MethodErr> {
+        self.events
+            .unbounded_send(event)
+            .map_err(|_| MethodErr::failed("Profile handle has been dropped"))
+    }
+}
+
+/// Register the `Profile1` interface with the given `Crossroads` instance.
+pub(crate) fn register_profile(crossroads: &mut Crossroads) -> IfaceToken<LocalProfile> {
+    crossroads.register(
+        ORG_BLUEZ_PROFILE1_NAME,
+        |b: &mut IfaceBuilder<LocalProfile>| {
+            b.method("Release", (), (), |_, profile, ()| {
+                profile.send(ProfileEvent::Released)
+            });
+            b.method(
+                "NewConnection",
+                ("device", "fd", "fd_properties"),
+                (),
+                |_, profile, (device, fd, fd_properties): (Path<'static>, OwnedFd, PropMap)| {
+                    let connection = ProfileConnection::new(
+                        DeviceId {
+                            object_path: device,
+                        },
+                        fd,
+                        &fd_properties,
+                    )
+                    .map_err(|e| MethodErr::failed(&e))?;
+                    profile.send(ProfileEvent::NewConnection(connection))
+                },
+            );
+            b.method(
+                "RequestDisconnection",
+                ("device",),
+                (),
+                |_, profile, (device,): (Path<'static>,)| {
+                    profile.send(ProfileEvent::RequestDisconnection {
+                        device: DeviceId {
+                            object_path: device,
+                        },
+                    })
+                },
+            );
+        },
+    )
+}
+
+/// Insert an object for a new profile into the given `Crossroads` instance, and return its path
+/// along with the receiver for events from BlueZ.
+pub(crate) fn insert_profile(
+    crossroads: &mut Crossroads,
+    interface: IfaceToken<LocalProfile>,
+) -> (Path<'static>, UnboundedReceiver<ProfileEvent>) {
+    let object_path: Path<'static> = format!(
+        "{}{}",
+        PROFILE_PATH_PREFIX,
+        NEXT_PROFILE_INDEX.fetch_add(1, Ordering::Relaxed)
+    )
+    .into();
+    let (events, receiver) = mpsc::unbounded();
+    crossroads.insert(object_path.clone(), &[interface], LocalProfile { events });
+    (object_path, receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_to_prop_map() {
+        assert_eq!(PropMap::from(ProfileOptions::default()).len(), 0);
+
+        let map: PropMap = ProfileOptions {
+            name: Some("Serial Port".to_string()),
+            role: Some(ProfileRole::Server),
+            channel: Some(1),
+            require_authentication: Some(false),
+            service: Some(crate::uuid_from_u16(0x1101)),
+            ..Default::default()
+        }
+        .into();
+        assert_eq!(map.len(), 5);
+        assert_eq!(
+            prop_cast::<String>(&map, "Name").map(String::as_str),
+            Some("Serial Port")
+        );
+        assert_eq!(
+            prop_cast::<String>(&map, "Role").map(String::as_str),
+            Some("server")
+        );
+        assert_eq!(prop_cast::<u16>(&map, "Channel"), Some(&1));
+        assert_eq!(
+            prop_cast::<String>(&map, "Service").map(String::as_str),
+            Some("00001101-0000-1000-8000-00805f9b34fb")
+        );
+        assert_eq!(
+            prop_cast::<bool>(&map, "RequireAuthentication"),
+            Some(&false)
+        );
+    }
+}
@@ -0,0 +1,304 @@
+//! Support for recording the events from a `BluetoothSession` along with when they were received,
+//! and replaying them later through the same `Stream` interface, so that problems which depend on
+//! the exact ordering of events can be reproduced offline.
+//!
+//! Recordings are stored as JSON, with one [`RecordedEvent`](struct.RecordedEvent.html) per line.
+
+use futures::stream::{self, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use uuid::Uuid;
+
+use crate::{BluetoothEvent, BluetoothSession, CharacteristicId, DeviceEvent, DeviceInfo};
+
+/// An event along with the time at which it was received, and any details of the object it is for
+/// which were looked up from BlueZ at the time.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RecordedEvent {
+    /// When the event was received.
+    pub timestamp: SystemTime,
+    /// The event itself.
+    pub event: BluetoothEvent,
+    /// For events about a characteristic, the UUID of the characteristic.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub characteristic_uuid: Option<Uuid>,
+    /// For `DeviceEvent::Discovered` events, information about the device when it was discovered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_info: Option<DeviceInfo>,
+}
+
+impl RecordedEvent {
+    /// Create a record of the given event with no details.
+    fn new(timestamp: SystemTime, event: BluetoothEvent) -> Self {
+        Self {
+            timestamp,
+            event,
+            characteristic_uuid: None,
+            device_info: None,
+        }
+    }
+}
+
+/// An error reading an event recording.
+#[derive(Debug, Error)]
+pub enum RecordingError {
+    /// There was an error reading the recording.
+    #[error("Error reading recording: {0}")]
+    Io(#[from] io::Error),
+    /// A line of the recording couldn't be parsed as an event.
+    #[error("Invalid event on line {line} of recording: {source}")]
+    Parse {
+        /// The line number, starting from 1.
+        line: usize,
+        /// The underlying parse error.
+        source: serde_json::Error,
+    },
+}
+
+/// A handle to an ongoing recording of events, as returned by
+/// [`BluetoothSession::record_events`](struct.BluetoothSession.html#method.record_events).
+///
+/// Recording will stop when this is dropped. Each event is written out in full before the next is
+/// received, so the recording will never end with a partial event.
+pub struct EventRecorder {
+    task: JoinHandle<()>,
+}
+
+impl Debug for EventRecorder {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "EventRecorder")
+    }
+}
+
+impl EventRecorder {
+    pub(crate) fn start(
+        session: BluetoothSession,
+        events: impl Stream<Item = BluetoothEvent> + Send + 'static,
+        mut writer: impl AsyncWrite + Send + Unpin + 'static,
+    ) -> Self {
+        let task = tokio::spawn(async move {
+            futures::pin_mut!(events);
+            // The UUID of a characteristic never changes, so only look it up once.
+            let mut characteristic_uuids = HashMap::new();
+            while let Some(event) = events.next().await {
+                let mut recorded = RecordedEvent::new(SystemTime::now(), event);
+                add_details(&session, &mut characteristic_uuids, &mut recorded).await;
+                if let Err(e) = write_event(&mut writer, &recorded).await {
+                    log::error!("Error recording event, stopping recording: {}", e);
+                    return;
+                }
+            }
+        });
+        Self { task }
+    }
+}
+
+/// Look up the details of the object which the given event is for, if it is a kind of event for
+/// which they are recorded. The object may already have gone by the time this happens, in which
+/// case the details are left out.
+async fn add_details(
+    session: &BluetoothSession,
+    characteristic_uuids: &mut HashMap<CharacteristicId, Uuid>,
+    recorded: &mut RecordedEvent,
+) {
+    match &recorded.event {
+        BluetoothEvent::Characteristic { id, .. } => {
+            if let Some(uuid) = characteristic_uuids.get(id) {
+                recorded.characteristic_uuid = Some(*uuid);
+            } else {
+                match session.get_characteristic_info(id).await {
+                    Ok(info) => {
+                        characteristic_uuids.insert(id.to_owned(), info.uuid);
+                        recorded.characteristic_uuid = Some(info.uuid);
+                    }
+                    Err(e) => log::warn!("Error getting characteristic {} to record: {}", id, e),
+                }
+            }
+        }
+        BluetoothEvent::Device {
+            id,
+            event: DeviceEvent::Discovered,
+        } => match session.get_device_info(id).await {
+            Ok(info) => recorded.device_info = Some(info),
+            Err(e) => log::warn!("Error getting device {} to record: {}", id, e),
+        },
+        _ => {}
+    }
+}
+
+impl Drop for EventRecorder {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Write the given event as a single line of JSON, and flush it so that nothing is lost if the
+/// process crashes.
+async fn write_event(
+    writer: &mut (impl AsyncWrite + Unpin),
+    event: &RecordedEvent,
+) -> io::Result<()> {
+    let mut line = serde_json::to_vec(event)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    writer.flush().await
+}
+
+/// A set of events read from a recording, which can be replayed as a stream.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EventReplay {
+    events: Vec<RecordedEvent>,
+}
+
+impl EventReplay {
+    /// Read a recording made by an [`EventRecorder`](struct.EventRecorder.html) from the given
+    /// reader. Blank lines are ignored.
+    pub fn read(reader: impl BufRead) -> Result<Self, RecordingError> {
+        let mut events = vec![];
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event = serde_json::from_str(&line).map_err(|source| RecordingError::Parse {
+                line: index + 1,
+                source,
+            })?;
+            events.push(event);
+        }
+        Ok(Self { events })
+    }
+
+    /// Read a recording from the file at the given path.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Get the recorded events, in the order in which they were received.
+    pub fn events(&self) -> &[RecordedEvent] {
+        &self.events
+    }
+
+    /// Get a stream which returns all the recorded events in order, as fast as they are consumed.
+    pub fn into_stream(self) -> impl Stream<Item = BluetoothEvent> {
+        stream::iter(self.events.into_iter().map(|recorded| recorded.event))
+    }
+
+    /// Get a stream which returns all the recorded events in order, waiting between them for as
+    /// long as there was between them when they were recorded.
+    pub fn into_timed_stream(self) -> impl Stream<Item = BluetoothEvent> {
+        let mut previous = None;
+        let events = self.events.into_iter().map(move |recorded| {
+            let delay = previous
+                .and_then(|previous| recorded.timestamp.duration_since(previous).ok())
+                .unwrap_or(Duration::ZERO);
+            previous = Some(recorded.timestamp);
+            (delay, recorded.event)
+        });
+        stream::iter(events).then(|(delay, event)| async move {
+            sleep(delay).await;
+            event
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AdapterEvent, AdapterId, CharacteristicEvent, DeviceEvent, DeviceId};
+
+    fn recording() -> Vec<RecordedEvent> {
+        let mut manufacturer_data = HashMap::new();
+        manufacturer_data.insert(0x1234, vec![1, 2, 3]);
+        let mut value_event = RecordedEvent::new(
+            SystemTime::UNIX_EPOCH + Duration::from_millis(1_600_000_000_020),
+            BluetoothEvent::Characteristic {
+                id: CharacteristicId::new(
+                    "/org/bluez/hci0/dev_11_22_33_44_55_66/service0022/char0023",
+                ),
+                event: CharacteristicEvent::Value { value: vec![42] },
+            },
+        );
+        value_event.characteristic_uuid = Some(crate::uuid_from_u16(0x2a19));
+        vec![
+            RecordedEvent::new(
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+                BluetoothEvent::Adapter {
+                    id: AdapterId::new("/org/bluez/hci0"),
+                    event: AdapterEvent::Powered { powered: true },
+                },
+            ),
+            RecordedEvent::new(
+                SystemTime::UNIX_EPOCH + Duration::from_millis(1_600_000_000_010),
+                BluetoothEvent::Device {
+                    id: DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66"),
+                    event: DeviceEvent::ManufacturerData { manufacturer_data },
+                },
+            ),
+            value_event,
+        ]
+    }
+
+    async fn write_recording(events: &[RecordedEvent]) -> Vec<u8> {
+        let mut buffer = vec![];
+        for event in events {
+            write_event(&mut buffer, event).await.unwrap();
+        }
+        buffer
+    }
+
+    #[tokio::test]
+    async fn serialized_form() {
+        let buffer = write_recording(&recording()).await;
+        let lines: Vec<_> = std::str::from_utf8(&buffer).unwrap().lines().collect();
+        assert_eq!(
+            lines[0],
+            r#"{"timestamp":{"secs_since_epoch":1600000000,"nanos_since_epoch":0},"event":{"Adapter":{"id":"/org/bluez/hci0","event":{"Powered":{"powered":true}}}}}"#
+        );
+        assert!(
+            lines[2].ends_with(r#","characteristic_uuid":"00002a19-0000-1000-8000-00805f9b34fb"}"#)
+        );
+    }
+
+    #[tokio::test]
+    async fn write_and_read() {
+        let mut buffer = write_recording(&recording()).await;
+        buffer.extend_from_slice(b"\n");
+        let replay = EventReplay::read(buffer.as_slice()).unwrap();
+        assert_eq!(replay.events(), recording().as_slice());
+    }
+
+    #[tokio::test]
+    async fn read_invalid() {
+        let mut buffer = write_recording(&recording()[0..1]).await;
+        buffer.extend_from_slice(b"{\"timestamp\":\n");
+        assert!(matches!(
+            EventReplay::read(buffer.as_slice()),
+            Err(RecordingError::Parse { line: 2, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn replay_stream() {
+        let replay = EventReplay {
+            events: recording(),
+        };
+        let events: Vec<_> = replay.clone().into_stream().collect().await;
+        let expected: Vec<_> = recording()
+            .into_iter()
+            .map(|recorded| recorded.event)
+            .collect();
+        assert_eq!(events, expected);
+        let timed_events: Vec<_> = replay.into_timed_stream().collect().await;
+        assert_eq!(timed_events, expected);
+    }
+}
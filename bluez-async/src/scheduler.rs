@@ -0,0 +1,337 @@
+//! An optional scheduler to limit how many operations BlueZ is asked to do at once, as many
+//! adapters misbehave when several connections are attempted at the same time.
+
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard, OwnedSemaphorePermit, Semaphore};
+
+use crate::{AdapterId, DeviceId};
+
+/// Options for scheduling operations on a [`BluetoothSession`](struct.BluetoothSession.html), as
+/// passed to [`BluetoothSession::with_scheduler`](struct.BluetoothSession.html#method.with_scheduler).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SchedulerOptions {
+    /// The maximum number of connection attempts to have in progress at once on each adapter.
+    /// Further attempts will wait until one of these finishes.
+    pub max_concurrent_connects: NonZeroUsize,
+    /// Whether to wait for each GATT operation (reading or writing a characteristic or descriptor,
+    /// or starting or stopping notifications) to finish before starting the next on the same
+    /// device.
+    pub serialize_gatt_operations: bool,
+}
+
+impl Default for SchedulerOptions {
+    fn default() -> Self {
+        Self {
+            max_concurrent_connects: NonZeroUsize::new(1).unwrap(),
+            serialize_gatt_operations: true,
+        }
+    }
+}
+
+/// The number of operations in progress and waiting to start on a Bluetooth adapter, as returned
+/// by [`BluetoothSession::queue_depth`](struct.BluetoothSession.html#method.queue_depth).
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct QueueDepth {
+    /// The number of connection attempts currently in progress.
+    pub connects_in_progress: usize,
+    /// The number of connection attempts waiting for others to finish.
+    pub connects_waiting: usize,
+    /// The number of GATT operations currently in progress, across all devices on the adapter.
+    pub gatt_operations_in_progress: usize,
+    /// The number of GATT operations waiting for others on the same device to finish.
+    pub gatt_operations_waiting: usize,
+}
+
+/// Counts some operations while it is alive.
+#[derive(Debug)]
+struct Counted(Arc<AtomicUsize>);
+
+impl Counted {
+    fn new(counter: &Arc<AtomicUsize>) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Self(counter.clone())
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    in_progress: Arc<AtomicUsize>,
+    waiting: Arc<AtomicUsize>,
+}
+
+#[derive(Debug)]
+struct AdapterQueue {
+    connects: Arc<Semaphore>,
+    connect_counters: Counters,
+}
+
+#[derive(Debug, Default)]
+struct DeviceQueue {
+    lock: Arc<AsyncMutex<()>>,
+    counters: Counters,
+}
+
+type DeviceQueues = Arc<Mutex<HashMap<DeviceId, Arc<DeviceQueue>>>>;
+
+/// A reference to the queue for a device, which removes the queue from the scheduler when the last
+/// reference is dropped, so that queues for devices which have gone away don't build up.
+#[derive(Debug)]
+struct DeviceQueueRef {
+    device: DeviceId,
+    queue: Option<Arc<DeviceQueue>>,
+    devices: DeviceQueues,
+}
+
+impl DeviceQueueRef {
+    fn queue(&self) -> &DeviceQueue {
+        self.queue.as_ref().unwrap()
+    }
+}
+
+impl Drop for DeviceQueueRef {
+    fn drop(&mut self) {
+        // New references are only taken while holding the lock, so nobody else can start using the
+        // queue between checking the count and removing it.
+        let mut devices = self.devices.lock().unwrap();
+        self.queue = None;
+        if let Some(queue) = devices.get(&self.device) {
+            if Arc::strong_count(queue) == 1 {
+                devices.remove(&self.device);
+            }
+        }
+    }
+}
+
+/// Permission to do an operation, which lasts until it is dropped.
+#[derive(Debug)]
+pub(crate) struct Permit {
+    _connect: Option<OwnedSemaphorePermit>,
+    _gatt: Option<OwnedMutexGuard<()>>,
+    _in_progress: Counted,
+    /// This must come after the guard, so that the queue isn't removed while it is still locked.
+    _device_queue: Option<DeviceQueueRef>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Scheduler {
+    options: SchedulerOptions,
+    adapters: Mutex<HashMap<AdapterId, Arc<AdapterQueue>>>,
+    devices: DeviceQueues,
+}
+
+impl Scheduler {
+    pub fn new(options: SchedulerOptions) -> Self {
+        Self {
+            options,
+            adapters: Mutex::new(HashMap::new()),
+            devices: Default::default(),
+        }
+    }
+
+    fn adapter_queue(&self, adapter: &AdapterId) -> Arc<AdapterQueue> {
+        self.adapters
+            .lock()
+            .unwrap()
+            .entry(adapter.to_owned())
+            .or_insert_with(|| {
+                Arc::new(AdapterQueue {
+                    connects: Arc::new(Semaphore::new(self.options.max_concurrent_connects.get())),
+                    connect_counters: Counters::default(),
+                })
+            })
+            .clone()
+    }
+
+    fn device_queue(&self, device: &DeviceId) -> DeviceQueueRef {
+        let queue = self
+            .devices
+            .lock()
+            .unwrap()
+            .entry(device.to_owned())
+            .or_default()
+            .clone();
+        DeviceQueueRef {
+            device: device.to_owned(),
+            queue: Some(queue),
+            devices: self.devices.clone(),
+        }
+    }
+
+    /// Wait until a connection to the given device may be attempted.
+    pub async fn connect(&self, device: &DeviceId) -> Permit {
+        let queue = self.adapter_queue(&device.adapter());
+        let waiting = Counted::new(&queue.connect_counters.waiting);
+        let permit = queue
+            .connects
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("Connect semaphore closed");
+        drop(waiting);
+        Permit {
+            _connect: Some(permit),
+            _gatt: None,
+            _in_progress: Counted::new(&queue.connect_counters.in_progress),
+            _device_queue: None,
+        }
+    }
+
+    /// Wait until a GATT operation on the given device may be started.
+    pub async fn gatt_operation(&self, device: &DeviceId) -> Permit {
+        let device_queue = self.device_queue(device);
+        let queue = device_queue.queue();
+        let guard = if self.options.serialize_gatt_operations {
+            let _waiting = Counted::new(&queue.counters.waiting);
+            Some(queue.lock.clone().lock_owned().await)
+        } else {
+            None
+        };
+        let in_progress = Counted::new(&queue.counters.in_progress);
+        Permit {
+            _connect: None,
+            _gatt: guard,
+            _in_progress: in_progress,
+            _device_queue: Some(device_queue),
+        }
+    }
+
+    /// Get the number of operations in progress and waiting on the given adapter.
+    pub fn queue_depth(&self, adapter: &AdapterId) -> QueueDepth {
+        let mut depth = QueueDepth::default();
+        if let Some(queue) = self.adapters.lock().unwrap().get(adapter) {
+            depth.connects_in_progress = queue.connect_counters.in_progress.load(Ordering::SeqCst);
+            depth.connects_waiting = queue.connect_counters.waiting.load(Ordering::SeqCst);
+        }
+        for (device, queue) in self.devices.lock().unwrap().iter() {
+            if &device.adapter() == adapter {
+                depth.gatt_operations_in_progress +=
+                    queue.counters.in_progress.load(Ordering::SeqCst);
+                depth.gatt_operations_waiting += queue.counters.waiting.load(Ordering::SeqCst);
+            }
+        }
+        depth
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    #[tokio::test]
+    async fn connects_limited_per_adapter() {
+        let scheduler = Scheduler::new(SchedulerOptions::default());
+        let hci0 = AdapterId::new("/org/bluez/hci0");
+        let hci1 = AdapterId::new("/org/bluez/hci1");
+        let device0 = DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66");
+        let device1 = DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_77");
+        let device2 = DeviceId::new("/org/bluez/hci1/dev_11_22_33_44_55_66");
+
+        let permit0 = scheduler.connect(&device0).await;
+        let mut second = Box::pin(scheduler.connect(&device1));
+        assert!((&mut second).now_or_never().is_none());
+        assert_eq!(
+            scheduler.queue_depth(&hci0),
+            QueueDepth {
+                connects_in_progress: 1,
+                connects_waiting: 1,
+                ..Default::default()
+            }
+        );
+
+        // A different adapter has its own limit.
+        let permit2 = scheduler.connect(&device2).await;
+        assert_eq!(scheduler.queue_depth(&hci1).connects_in_progress, 1);
+
+        drop(permit0);
+        let permit1 = second.await;
+        assert_eq!(
+            scheduler.queue_depth(&hci0),
+            QueueDepth {
+                connects_in_progress: 1,
+                ..Default::default()
+            }
+        );
+
+        drop(permit1);
+        drop(permit2);
+        assert_eq!(scheduler.queue_depth(&hci0), QueueDepth::default());
+        assert_eq!(scheduler.queue_depth(&hci1), QueueDepth::default());
+    }
+
+    #[tokio::test]
+    async fn gatt_operations_serialized_per_device() {
+        let scheduler = Scheduler::new(SchedulerOptions::default());
+        let hci0 = AdapterId::new("/org/bluez/hci0");
+        let device0 = DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66");
+        let device1 = DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_77");
+
+        let permit0 = scheduler.gatt_operation(&device0).await;
+        let mut second = Box::pin(scheduler.gatt_operation(&device0));
+        assert!((&mut second).now_or_never().is_none());
+        // Another device isn't blocked.
+        let permit1 = scheduler.gatt_operation(&device1).await;
+        assert_eq!(
+            scheduler.queue_depth(&hci0),
+            QueueDepth {
+                gatt_operations_in_progress: 2,
+                gatt_operations_waiting: 1,
+                ..Default::default()
+            }
+        );
+
+        drop(permit0);
+        drop(second.await);
+        drop(permit1);
+        assert_eq!(scheduler.queue_depth(&hci0), QueueDepth::default());
+    }
+
+    #[tokio::test]
+    async fn idle_device_queues_removed() {
+        let scheduler = Scheduler::new(SchedulerOptions::default());
+        let device = DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66");
+
+        let permit0 = scheduler.gatt_operation(&device).await;
+        let mut second = Box::pin(scheduler.gatt_operation(&device));
+        assert!((&mut second).now_or_never().is_none());
+
+        // The queue is kept while any operation is using or waiting for it, so they are still
+        // serialized.
+        drop(permit0);
+        let permit1 = second.await;
+        assert!((&mut Box::pin(scheduler.gatt_operation(&device)))
+            .now_or_never()
+            .is_none());
+        assert_eq!(scheduler.devices.lock().unwrap().len(), 1);
+
+        drop(permit1);
+        assert!(scheduler.devices.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn gatt_operations_concurrent_if_not_serialized() {
+        let scheduler = Scheduler::new(SchedulerOptions {
+            serialize_gatt_operations: false,
+            ..Default::default()
+        });
+        let device = DeviceId::new("/org/bluez/hci0/dev_11_22_33_44_55_66");
+
+        let _permit0 = scheduler.gatt_operation(&device).await;
+        let _permit1 = scheduler.gatt_operation(&device).await;
+        assert_eq!(
+            scheduler
+                .queue_depth(&device.adapter())
+                .gatt_operations_in_progress,
+            2
+        );
+    }
+}
@@ -1,8 +1,9 @@
 use dbus::Path;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt::{self, Display, Formatter};
 use uuid::Uuid;
 
-use crate::DeviceId;
+use crate::{deserialize_object_path, DeviceId};
 
 /// Opaque identifier for a GATT service on a Bluetooth device.
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
@@ -46,8 +47,22 @@ impl Display for ServiceId {
     }
 }
 
+impl Serialize for ServiceId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.object_path)
+    }
+}
+
+impl<'de> Deserialize<'de> for ServiceId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self {
+            object_path: deserialize_object_path(deserializer)?,
+        })
+    }
+}
+
 /// Information about a GATT service on a Bluetooth device.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
 pub struct ServiceInfo {
     /// An opaque identifier for the service on the device, including a reference to which adapter
     /// it was discovered on.
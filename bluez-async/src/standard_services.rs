@@ -0,0 +1,220 @@
+//! Decoders for the characteristics of some standard GATT services, so that devices which follow
+//! the specifications can be read without any device-specific code.
+
+use std::convert::TryInto;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{uuid_from_u16, BleUuid};
+
+/// UUID of the Device Information service.
+pub(crate) const DEVICE_INFORMATION_SERVICE_UUID: Uuid = uuid_from_u16(0x180a);
+/// UUID of the Environmental Sensing service.
+pub(crate) const ENVIRONMENTAL_SENSING_SERVICE_UUID: Uuid = uuid_from_u16(0x181a);
+/// UUID of the Pressure characteristic.
+const PRESSURE_UUID: Uuid = uuid_from_u16(0x2a6d);
+/// UUID of the Temperature characteristic.
+const TEMPERATURE_UUID: Uuid = uuid_from_u16(0x2a6e);
+/// UUID of the Humidity characteristic.
+const HUMIDITY_UUID: Uuid = uuid_from_u16(0x2a6f);
+
+/// The raw value of a Temperature characteristic meaning that the value is not known.
+const TEMPERATURE_UNKNOWN: i16 = i16::MIN;
+/// The raw value of a Humidity characteristic meaning that the value is not known.
+const HUMIDITY_UNKNOWN: u16 = u16::MAX;
+
+/// A function to parse the value of some standard GATT characteristic.
+pub(crate) type ParseFn<T> = fn(&[u8]) -> Result<T, ParseCharacteristicError>;
+
+/// An error parsing the value of a standard GATT characteristic.
+#[derive(Clone, Debug, Error, Eq, PartialEq)]
+#[error("Invalid value {value:?} for characteristic {}", .uuid.succinctly())]
+pub struct ParseCharacteristicError {
+    /// The UUID of the characteristic whose value couldn't be parsed.
+    pub uuid: Uuid,
+    /// The invalid value.
+    pub value: Vec<u8>,
+}
+
+impl ParseCharacteristicError {
+    fn new(uuid: Uuid, value: &[u8]) -> Self {
+        Self {
+            uuid,
+            value: value.to_owned(),
+        }
+    }
+}
+
+/// Parse the value of a Temperature characteristic (0x2A6E), which is a signed 16-bit integer in
+/// units of 0.01 °C. Returns the temperature in °C, or `None` if the device reports that the value
+/// is not known.
+pub fn parse_temperature(value: &[u8]) -> Result<Option<f32>, ParseCharacteristicError> {
+    let bytes = value
+        .try_into()
+        .map_err(|_| ParseCharacteristicError::new(TEMPERATURE_UUID, value))?;
+    Ok(match i16::from_le_bytes(bytes) {
+        TEMPERATURE_UNKNOWN => None,
+        temperature => Some(temperature as f32 / 100.0),
+    })
+}
+
+/// Parse the value of a Humidity characteristic (0x2A6F), which is an unsigned 16-bit integer in
+/// units of 0.01 %. Returns the relative humidity in %, or `None` if the device reports that the
+/// value is not known.
+pub fn parse_humidity(value: &[u8]) -> Result<Option<f32>, ParseCharacteristicError> {
+    let bytes = value
+        .try_into()
+        .map_err(|_| ParseCharacteristicError::new(HUMIDITY_UUID, value))?;
+    Ok(match u16::from_le_bytes(bytes) {
+        HUMIDITY_UNKNOWN => None,
+        humidity => Some(humidity as f32 / 100.0),
+    })
+}
+
+/// Parse the value of a Pressure characteristic (0x2A6D), which is an unsigned 32-bit integer in
+/// units of 0.1 Pa. Returns the pressure in Pa. Unlike temperature and humidity, the specification
+/// doesn't reserve any value to mean that the pressure is not known.
+pub fn parse_pressure(value: &[u8]) -> Result<f32, ParseCharacteristicError> {
+    let bytes = value
+        .try_into()
+        .map_err(|_| ParseCharacteristicError::new(PRESSURE_UUID, value))?;
+    Ok(u32::from_le_bytes(bytes) as f32 / 10.0)
+}
+
+/// Parse the value of one of the string characteristics of the Device Information service. Some
+/// devices include a trailing NUL, which is removed.
+pub(crate) fn parse_string(uuid: Uuid, value: &[u8]) -> Result<String, ParseCharacteristicError> {
+    let string =
+        std::str::from_utf8(value).map_err(|_| ParseCharacteristicError::new(uuid, value))?;
+    Ok(string.trim_end_matches('\0').to_owned())
+}
+
+/// Readings from the Environmental Sensing service (0x181A) of a device, as returned by
+/// [`BluetoothSession::get_environmental_readings`](struct.BluetoothSession.html#method.get_environmental_readings).
+///
+/// If the service has several characteristics of the same type then only the first one with a known
+/// value is used.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EnvironmentalReadings {
+    /// The temperature in °C, if the device has a Temperature characteristic and knows its value.
+    pub temperature: Option<f32>,
+    /// The relative humidity in %, if the device has a Humidity characteristic and knows its value.
+    pub humidity: Option<f32>,
+    /// The pressure in Pa, if the device has a Pressure characteristic.
+    pub pressure: Option<f32>,
+}
+
+impl EnvironmentalReadings {
+    /// Get the field corresponding to the characteristic with the given UUID, along with the
+    /// function to parse its value, if it is one which is handled. The function returns `None` if
+    /// the value is not known.
+    pub(crate) fn field(&mut self, uuid: Uuid) -> Option<(&mut Option<f32>, ParseFn<Option<f32>>)> {
+        match uuid.to_ble_u16()? {
+            0x2a6d => Some((&mut self.pressure, |value| parse_pressure(value).map(Some))),
+            0x2a6e => Some((&mut self.temperature, parse_temperature)),
+            0x2a6f => Some((&mut self.humidity, parse_humidity)),
+            _ => None,
+        }
+    }
+}
+
+/// Information from the Device Information service (0x180A) of a device, as returned by
+/// [`BluetoothSession::get_device_information`](struct.BluetoothSession.html#method.get_device_information).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DeviceInformation {
+    /// The Manufacturer Name String (0x2A29), if the device has one.
+    pub manufacturer_name: Option<String>,
+    /// The Model Number String (0x2A24), if the device has one.
+    pub model_number: Option<String>,
+    /// The Serial Number String (0x2A25), if the device has one.
+    pub serial_number: Option<String>,
+    /// The Hardware Revision String (0x2A27), if the device has one.
+    pub hardware_revision: Option<String>,
+    /// The Firmware Revision String (0x2A26), if the device has one.
+    pub firmware_revision: Option<String>,
+    /// The Software Revision String (0x2A28), if the device has one.
+    pub software_revision: Option<String>,
+}
+
+impl DeviceInformation {
+    /// Get the field corresponding to the characteristic with the given UUID, if it is one which is
+    /// handled.
+    pub(crate) fn field(&mut self, uuid: Uuid) -> Option<&mut Option<String>> {
+        match uuid.to_ble_u16()? {
+            0x2a24 => Some(&mut self.model_number),
+            0x2a25 => Some(&mut self.serial_number),
+            0x2a26 => Some(&mut self.firmware_revision),
+            0x2a27 => Some(&mut self.hardware_revision),
+            0x2a28 => Some(&mut self.software_revision),
+            0x2a29 => Some(&mut self.manufacturer_name),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temperature() {
+        assert_eq!(parse_temperature(&[0x34, 0x08]), Ok(Some(21.0)));
+        assert_eq!(parse_temperature(&[0x0c, 0xfe]), Ok(Some(-5.0)));
+        assert_eq!(parse_temperature(&[0x01, 0x80]), Ok(Some(-327.67)));
+        assert_eq!(
+            parse_temperature(&[0x34]),
+            Err(ParseCharacteristicError {
+                uuid: TEMPERATURE_UUID,
+                value: vec![0x34],
+            })
+        );
+    }
+
+    #[test]
+    fn humidity_and_pressure() {
+        assert_eq!(parse_humidity(&[0x88, 0x13]), Ok(Some(50.0)));
+        assert_eq!(parse_pressure(&[0x02, 0x76, 0x0f, 0x00]), Ok(101325.0));
+        assert!(parse_pressure(&[0x6a, 0x76, 0x0f]).is_err());
+    }
+
+    #[test]
+    fn unknown_values() {
+        assert_eq!(parse_temperature(&[0x00, 0x80]), Ok(None));
+        assert_eq!(parse_humidity(&[0xff, 0xff]), Ok(None));
+        assert_eq!(parse_humidity(&[0xfe, 0xff]), Ok(Some(655.34)));
+
+        let mut readings = EnvironmentalReadings::default();
+        let (field, parse) = readings.field(TEMPERATURE_UUID).unwrap();
+        *field = parse(&[0x00, 0x80]).unwrap();
+        assert_eq!(readings, EnvironmentalReadings::default());
+    }
+
+    #[test]
+    fn error_message() {
+        assert_eq!(
+            ParseCharacteristicError::new(HUMIDITY_UUID, &[1]).to_string(),
+            "Invalid value [1] for characteristic Humidity (0x2A6F)"
+        );
+    }
+
+    #[test]
+    fn fields() {
+        let mut information = DeviceInformation::default();
+        *information.field(uuid_from_u16(0x2a29)).unwrap() = Some("Acme".to_string());
+        assert!(information.field(uuid_from_u16(0x2a00)).is_none());
+        assert_eq!(information.manufacturer_name.as_deref(), Some("Acme"));
+
+        let mut readings = EnvironmentalReadings::default();
+        let (field, parse) = readings.field(HUMIDITY_UUID).unwrap();
+        *field = parse(&[0x88, 0x13]).unwrap();
+        assert!(readings.field(uuid_from_u16(0x2a29)).is_none());
+        assert_eq!(readings.humidity, Some(50.0));
+    }
+
+    #[test]
+    fn strings() {
+        let uuid = uuid_from_u16(0x2a29);
+        assert_eq!(parse_string(uuid, b"Acme\0"), Ok("Acme".to_string()));
+        assert!(parse_string(uuid, &[0xff, 0xfe]).is_err());
+    }
+}
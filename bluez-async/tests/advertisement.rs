@@ -0,0 +1,99 @@
+//! Integration tests for registering LE advertisements with a fake BlueZ daemon.
+
+use bluez_async::fake::{FakeAdapter, FakeBluez};
+use bluez_async::{uuid_from_u16, Advertisement, AdvertisementType, BluetoothSession};
+use dbus::arg::{prop_cast, RefArg};
+use dbus::nonblock::stdintf::org_freedesktop_dbus::Properties;
+use dbus::nonblock::Proxy;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::{sleep, timeout};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[tokio::test]
+async fn register_and_unregister() {
+    let fake = FakeBluez::start().await.unwrap();
+    let (_, session) = BluetoothSession::new_with_address(fake.address())
+        .await
+        .unwrap();
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+
+    let mut manufacturer_data = HashMap::new();
+    manufacturer_data.insert(0x0499, vec![0x05, 0x12, 0xfc]);
+    let advertisement = Advertisement {
+        advertisement_type: AdvertisementType::Broadcast,
+        service_uuids: vec![uuid_from_u16(0x181a)],
+        manufacturer_data,
+        local_name: Some("Beacon".to_string()),
+        include_tx_power: true,
+        ..Default::default()
+    };
+    let handle = session
+        .register_advertisement(&adapter, advertisement)
+        .await
+        .unwrap();
+
+    let advertisements = fake.advertisements();
+    assert_eq!(advertisements.len(), 1);
+    assert_eq!(advertisements[0].adapter, adapter);
+    assert_eq!(&advertisements[0].object_path, handle.object_path());
+
+    // Check the properties of the advertisement, as BlueZ would.
+    let proxy = Proxy::new(
+        advertisements[0].owner.clone(),
+        handle.object_path().clone(),
+        TIMEOUT,
+        fake.connection(),
+    );
+    let properties = proxy.get_all("org.bluez.LEAdvertisement1").await.unwrap();
+    assert_eq!(
+        prop_cast::<String>(&properties, "Type").unwrap(),
+        "broadcast"
+    );
+    assert_eq!(
+        prop_cast::<Vec<String>>(&properties, "ServiceUUIDs").unwrap(),
+        &vec!["0000181a-0000-1000-8000-00805f9b34fb".to_string()]
+    );
+    assert_eq!(
+        prop_cast::<String>(&properties, "LocalName").unwrap(),
+        "Beacon"
+    );
+    assert_eq!(
+        prop_cast::<Vec<String>>(&properties, "Includes").unwrap(),
+        &vec!["tx-power".to_string()]
+    );
+    let manufacturer_data = properties.get("ManufacturerData").unwrap();
+    let mut entries = manufacturer_data.0.as_iter().unwrap();
+    assert_eq!(entries.next().unwrap().as_u64(), Some(0x0499));
+    // Optional properties which aren't set should be omitted.
+    assert!(!properties.contains_key("Appearance"));
+    assert!(!properties.contains_key("Discoverable"));
+
+    session.unregister_advertisement(handle).await.unwrap();
+    assert_eq!(fake.advertisements(), vec![]);
+}
+
+#[tokio::test]
+async fn unregister_on_drop() {
+    let fake = FakeBluez::start().await.unwrap();
+    let (_, session) = BluetoothSession::new_with_address(fake.address())
+        .await
+        .unwrap();
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+
+    let handle = session
+        .register_advertisement(&adapter, Advertisement::default())
+        .await
+        .unwrap();
+    assert_eq!(fake.advertisements().len(), 1);
+
+    drop(handle);
+    timeout(TIMEOUT, async {
+        while !fake.advertisements().is_empty() {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
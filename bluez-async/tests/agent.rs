@@ -0,0 +1,148 @@
+//! Integration tests for registering pairing agents with a fake BlueZ daemon, which then calls into
+//! them as BlueZ would while pairing.
+
+use async_trait::async_trait;
+use bluez_async::fake::FakeBluez;
+use bluez_async::{uuid_from_u16, Agent, AgentCapability, AgentError, BluetoothSession, DeviceId};
+use dbus::nonblock::Proxy;
+use dbus::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::{sleep, timeout};
+use uuid::Uuid;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const DEVICE_PATH: &str = "/org/bluez/hci0/dev_11_22_33_44_55_66";
+
+/// An agent which always uses the same passkey, and records which services it has authorized.
+#[derive(Debug, Default)]
+struct FixedPasskey {
+    authorized: Mutex<Vec<(DeviceId, Uuid)>>,
+}
+
+#[async_trait]
+impl Agent for FixedPasskey {
+    async fn request_passkey(&self, _device: DeviceId) -> Result<u32, AgentError> {
+        Ok(123456)
+    }
+
+    async fn request_confirmation(
+        &self,
+        _device: DeviceId,
+        passkey: u32,
+    ) -> Result<(), AgentError> {
+        if passkey == 123456 {
+            Ok(())
+        } else {
+            Err(AgentError::Rejected)
+        }
+    }
+
+    async fn authorize_service(&self, device: DeviceId, uuid: Uuid) -> Result<(), AgentError> {
+        self.authorized.lock().unwrap().push((device, uuid));
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn register_and_handle_requests() {
+    let fake = FakeBluez::start().await.unwrap();
+    let (_, session) = BluetoothSession::new_with_address(fake.address())
+        .await
+        .unwrap();
+
+    let agent = Arc::new(FixedPasskey::default());
+    let handle = session
+        .register_agent(agent.clone(), AgentCapability::KeyboardDisplay)
+        .await
+        .unwrap();
+    let agents = fake.agents();
+    assert_eq!(agents.len(), 1);
+    assert_eq!(&agents[0].object_path, handle.object_path());
+    assert_eq!(agents[0].capability, "KeyboardDisplay");
+    assert!(!agents[0].default);
+
+    session.request_default_agent(&handle).await.unwrap();
+    assert!(fake.agents()[0].default);
+
+    // Call the agent as BlueZ would while pairing.
+    let proxy = Proxy::new(
+        agents[0].owner.clone(),
+        handle.object_path().clone(),
+        TIMEOUT,
+        fake.connection(),
+    );
+    let device = Path::from(DEVICE_PATH);
+    let (passkey,): (u32,) = proxy
+        .method_call("org.bluez.Agent1", "RequestPasskey", (device.clone(),))
+        .await
+        .unwrap();
+    assert_eq!(passkey, 123456);
+    proxy
+        .method_call::<(), _, _, _>(
+            "org.bluez.Agent1",
+            "RequestConfirmation",
+            (device.clone(), 123456u32),
+        )
+        .await
+        .unwrap();
+    let error = proxy
+        .method_call::<(), _, _, _>(
+            "org.bluez.Agent1",
+            "RequestConfirmation",
+            (device.clone(), 654321u32),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(error.name(), Some("org.bluez.Error.Rejected"));
+    proxy
+        .method_call::<(), _, _, _>(
+            "org.bluez.Agent1",
+            "AuthorizeService",
+            (device.clone(), uuid_from_u16(0x180f).to_string()),
+        )
+        .await
+        .unwrap();
+    {
+        let authorized = agent.authorized.lock().unwrap();
+        assert_eq!(authorized.len(), 1);
+        assert_eq!(authorized[0].0.to_string(), "hci0/dev_11_22_33_44_55_66");
+        assert_eq!(authorized[0].1, uuid_from_u16(0x180f));
+    }
+
+    // Methods which the agent doesn't implement should reject the request.
+    let error = proxy
+        .method_call::<(String,), _, _, _>("org.bluez.Agent1", "RequestPinCode", (device,))
+        .await
+        .unwrap_err();
+    assert_eq!(error.name(), Some("org.bluez.Error.Rejected"));
+
+    session.unregister_agent(handle).await.unwrap();
+    assert_eq!(fake.agents(), vec![]);
+}
+
+#[tokio::test]
+async fn unregister_on_drop() {
+    let fake = FakeBluez::start().await.unwrap();
+    let (_, session) = BluetoothSession::new_with_address(fake.address())
+        .await
+        .unwrap();
+
+    let handle = session
+        .register_agent(
+            Arc::new(FixedPasskey::default()),
+            AgentCapability::NoInputNoOutput,
+        )
+        .await
+        .unwrap();
+    assert_eq!(fake.agents().len(), 1);
+
+    drop(handle);
+    timeout(TIMEOUT, async {
+        while !fake.agents().is_empty() {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
@@ -0,0 +1,260 @@
+//! Integration tests for keeping devices connected with a `ConnectionManager`, against a fake BlueZ
+//! daemon.
+
+use async_trait::async_trait;
+use bluez_async::fake::{FakeAdapter, FakeBluez, FakeDevice};
+use bluez_async::{
+    BluetoothError, BluetoothSession, ConnectionHandler, ConnectionManager,
+    ConnectionManagerOptions, ConnectionState, ConnectionStateChange, DeviceId, MacAddress,
+    Operation,
+};
+use futures::{Stream, StreamExt};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const MAC_ADDRESS: &str = "11:22:33:44:55:66";
+
+/// A handler which records each device it is called for, and fails while `fail` is set. If
+/// `disconnect_once` is set then the first time it is called it disconnects the device before
+/// succeeding.
+#[derive(Debug, Default)]
+struct RecordingHandler {
+    connected: Mutex<Vec<DeviceId>>,
+    fail: Mutex<bool>,
+    disconnect_once: Mutex<bool>,
+}
+
+#[async_trait]
+impl ConnectionHandler for RecordingHandler {
+    async fn on_connected(
+        &self,
+        session: &BluetoothSession,
+        id: &DeviceId,
+    ) -> Result<(), BluetoothError> {
+        self.connected.lock().unwrap().push(id.to_owned());
+        let disconnect = std::mem::take(&mut *self.disconnect_once.lock().unwrap());
+        if disconnect {
+            session.disconnect(id).await?;
+            // Give the manager time to see the disconnection before the attempt finishes.
+            sleep(Duration::from_millis(100)).await;
+        }
+        if *self.fail.lock().unwrap() {
+            Err(BluetoothError::Timeout {
+                operation: Operation::MethodCall,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn test_options() -> ConnectionManagerOptions {
+    ConnectionManagerOptions {
+        initial_backoff: Duration::from_millis(50),
+        poll_interval: Duration::from_millis(10),
+        ..Default::default()
+    }
+}
+
+async fn start(
+    options: ConnectionManagerOptions,
+) -> (
+    FakeBluez,
+    ConnectionManager,
+    Arc<RecordingHandler>,
+    JoinHandle<()>,
+) {
+    let fake = FakeBluez::start().await.unwrap();
+    let (_, session) = BluetoothSession::new_with_address(fake.address())
+        .await
+        .unwrap();
+    let handler = Arc::new(RecordingHandler::default());
+    let manager = ConnectionManager::new(
+        session,
+        vec![MAC_ADDRESS.parse().unwrap()],
+        handler.clone(),
+        options,
+    );
+    let run_manager = manager.clone();
+    let task = tokio::spawn(async move { run_manager.run().await.unwrap() });
+    (fake, manager, handler, task)
+}
+
+async fn next_state(
+    changes: &mut (impl Stream<Item = ConnectionStateChange> + Unpin),
+) -> ConnectionState {
+    let change = timeout(TIMEOUT, changes.next())
+        .await
+        .expect("Timed out waiting for state change")
+        .expect("State change stream ended");
+    assert_eq!(
+        change.mac_address,
+        MAC_ADDRESS.parse::<MacAddress>().unwrap()
+    );
+    change.state
+}
+
+#[tokio::test]
+async fn connects_and_reconnects() {
+    let (fake, manager, handler, task) = start(test_options()).await;
+    let mut changes = manager.state_changes();
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let device = fake.add_device(&adapter, FakeDevice::new(MAC_ADDRESS.parse().unwrap()));
+
+    assert_eq!(next_state(&mut changes).await, ConnectionState::Connecting);
+    assert_eq!(
+        next_state(&mut changes).await,
+        ConnectionState::Connected { id: device.clone() }
+    );
+    assert!(fake.device(&device).unwrap().connected);
+    assert_eq!(*handler.connected.lock().unwrap(), vec![device.clone()]);
+
+    // Simulate the device going away.
+    fake.update_device(&device, |device| device.connected = false);
+    assert_eq!(
+        next_state(&mut changes).await,
+        ConnectionState::Disconnected
+    );
+    assert_eq!(next_state(&mut changes).await, ConnectionState::Connecting);
+    assert_eq!(
+        next_state(&mut changes).await,
+        ConnectionState::Connected { id: device.clone() }
+    );
+    assert_eq!(
+        *handler.connected.lock().unwrap(),
+        vec![device.clone(), device]
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn retries_failed_connection() {
+    let (fake, manager, _handler, task) = start(test_options()).await;
+    let mut changes = manager.state_changes();
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let mut device = FakeDevice::new(MAC_ADDRESS.parse().unwrap());
+    device.connectable = false;
+    let device = fake.add_device(&adapter, device);
+
+    for _ in 0..2 {
+        assert_eq!(next_state(&mut changes).await, ConnectionState::Connecting);
+        assert_eq!(
+            next_state(&mut changes).await,
+            ConnectionState::Disconnected
+        );
+    }
+
+    fake.update_device(&device, |device| device.connectable = true);
+    assert_eq!(next_state(&mut changes).await, ConnectionState::Connecting);
+    assert_eq!(
+        next_state(&mut changes).await,
+        ConnectionState::Connected { id: device }
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn disconnects_when_handler_fails() {
+    let (fake, manager, handler, task) = start(test_options()).await;
+    *handler.fail.lock().unwrap() = true;
+    let mut changes = manager.state_changes();
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let device = fake.add_device(&adapter, FakeDevice::new(MAC_ADDRESS.parse().unwrap()));
+
+    assert_eq!(next_state(&mut changes).await, ConnectionState::Connecting);
+    assert_eq!(
+        next_state(&mut changes).await,
+        ConnectionState::Disconnected
+    );
+    assert!(!fake.device(&device).unwrap().connected);
+
+    *handler.fail.lock().unwrap() = false;
+    assert_eq!(next_state(&mut changes).await, ConnectionState::Connecting);
+    assert_eq!(
+        next_state(&mut changes).await,
+        ConnectionState::Connected { id: device }
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn disconnect_while_connecting() {
+    let (fake, manager, handler, task) = start(test_options()).await;
+    *handler.disconnect_once.lock().unwrap() = true;
+    let mut changes = manager.state_changes();
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let device = fake.add_device(&adapter, FakeDevice::new(MAC_ADDRESS.parse().unwrap()));
+
+    // The device disconnects before the attempt finishes, so it shouldn't be treated as connected.
+    assert_eq!(next_state(&mut changes).await, ConnectionState::Connecting);
+    assert_eq!(
+        next_state(&mut changes).await,
+        ConnectionState::Disconnected
+    );
+
+    assert_eq!(next_state(&mut changes).await, ConnectionState::Connecting);
+    assert_eq!(
+        next_state(&mut changes).await,
+        ConnectionState::Connected { id: device.clone() }
+    );
+    assert!(fake.device(&device).unwrap().connected);
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn uses_whichever_adapter_can_connect() {
+    let (fake, manager, _handler, task) = start(test_options()).await;
+    let mut changes = manager.state_changes();
+    let adapter0 = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let adapter1 = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:56".parse().unwrap()));
+    let mut out_of_range = FakeDevice::new(MAC_ADDRESS.parse().unwrap());
+    out_of_range.connectable = false;
+    fake.add_device(&adapter0, out_of_range);
+    let in_range = fake.add_device(&adapter1, FakeDevice::new(MAC_ADDRESS.parse().unwrap()));
+
+    assert_eq!(next_state(&mut changes).await, ConnectionState::Connecting);
+    assert_eq!(
+        next_state(&mut changes).await,
+        ConnectionState::Connected { id: in_range }
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn reconnects_stale_device() {
+    let (fake, manager, handler, task) = start(ConnectionManagerOptions {
+        stale_timeout: Some(Duration::from_millis(100)),
+        ..test_options()
+    })
+    .await;
+    let mut changes = manager.state_changes();
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let device = fake.add_device(&adapter, FakeDevice::new(MAC_ADDRESS.parse().unwrap()));
+
+    assert_eq!(next_state(&mut changes).await, ConnectionState::Connecting);
+    assert_eq!(
+        next_state(&mut changes).await,
+        ConnectionState::Connected { id: device.clone() }
+    );
+    // Nothing is sent, so the device should become stale and be reconnected.
+    assert_eq!(
+        next_state(&mut changes).await,
+        ConnectionState::Disconnected
+    );
+    assert_eq!(next_state(&mut changes).await, ConnectionState::Connecting);
+    assert_eq!(
+        next_state(&mut changes).await,
+        ConnectionState::Connected { id: device }
+    );
+    assert_eq!(handler.connected.lock().unwrap().len(), 2);
+
+    task.abort();
+}
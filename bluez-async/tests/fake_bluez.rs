@@ -0,0 +1,1063 @@
+//! Integration tests which run a `BluetoothSession` against a fake BlueZ daemon on a private D-Bus
+//! bus.
+
+use bluez_async::fake::{
+    FakeAdapter, FakeBluez, FakeCharacteristic, FakeDescriptor, FakeDevice, FakeService,
+};
+use bluez_async::{
+    uuid_from_u16, AdapterEvent, AdvertisementStreamOptions, BluetoothError, BluetoothEvent,
+    BluetoothSession, BluetoothSessionConfig, CharacteristicEvent, CharacteristicFlags,
+    CharacteristicId, ClientCharacteristicConfiguration, DeviceEvent, EventReplay, Operation,
+    QueueDepth, SchedulerOptions, ServiceId, ValueFormat,
+};
+use futures::future::{join, join_all};
+use futures::{SinkExt, Stream, StreamExt};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::time::{sleep, timeout};
+
+const EVENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn start() -> (FakeBluez, BluetoothSession) {
+    let fake = FakeBluez::start().await.unwrap();
+    let (_, session) = BluetoothSession::new_with_address(fake.address())
+        .await
+        .unwrap();
+    (fake, session)
+}
+
+async fn next_event(events: &mut (impl Stream<Item = BluetoothEvent> + Unpin)) -> BluetoothEvent {
+    timeout(EVENT_TIMEOUT, events.next())
+        .await
+        .expect("Timed out waiting for event")
+        .expect("Event stream ended")
+}
+
+#[tokio::test]
+async fn get_adapters_and_devices() {
+    let (fake, session) = start().await;
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let mut device = FakeDevice::new("11:22:33:44:55:66".parse().unwrap());
+    device.name = Some("Sensor".to_string());
+    device.rssi = Some(-42);
+    device.manufacturer_data.insert(0x1234, vec![1, 2, 3]);
+    let device_id = fake.add_device(&adapter, device);
+
+    let adapters = session.get_adapters().await.unwrap();
+    assert_eq!(adapters.len(), 1);
+    assert_eq!(adapters[0].id, adapter);
+    assert_eq!(adapters[0].mac_address.to_string(), "00:11:22:33:44:55");
+    assert!(adapters[0].powered);
+
+    let devices = session.get_devices().await.unwrap();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].id, device_id);
+    assert_eq!(devices[0].mac_address.to_string(), "11:22:33:44:55:66");
+    assert_eq!(devices[0].name.as_deref(), Some("Sensor"));
+    assert_eq!(devices[0].rssi, Some(-42));
+    assert_eq!(devices[0].tx_power, None);
+    assert_eq!(
+        devices[0].manufacturer_data.get(&0x1234),
+        Some(&vec![1, 2, 3])
+    );
+    assert!(!devices[0].connected);
+}
+
+#[tokio::test]
+async fn adapter_settings() {
+    let (fake, session) = start().await;
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+
+    session.set_powered(&adapter, false).await.unwrap();
+    session.set_alias(&adapter, "Gateway").await.unwrap();
+    session.set_discoverable(&adapter, true).await.unwrap();
+    session
+        .set_discoverable_timeout(&adapter, Duration::from_secs(60))
+        .await
+        .unwrap();
+    session.set_pairable(&adapter, false).await.unwrap();
+
+    let adapter_info = session.get_adapter_info(&adapter).await.unwrap();
+    assert!(!adapter_info.powered);
+    assert_eq!(adapter_info.alias, "Gateway");
+    assert!(adapter_info.discoverable);
+    assert_eq!(adapter_info.discoverable_timeout, Duration::from_secs(60));
+    assert!(!adapter_info.pairable);
+
+    assert!(matches!(
+        session
+            .set_discoverable_timeout(&adapter, Duration::from_secs(u64::from(u32::MAX) + 1))
+            .await,
+        Err(BluetoothError::AdapterDiscoverableTimeoutTooLong(_))
+    ));
+    let adapter_info = session.get_adapter_info(&adapter).await.unwrap();
+    assert_eq!(adapter_info.discoverable_timeout, Duration::from_secs(60));
+}
+
+#[tokio::test]
+async fn adapter_events() {
+    let (fake, session) = start().await;
+    let mut events = Box::pin(session.event_stream().await.unwrap());
+
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    assert_eq!(
+        next_event(&mut events).await,
+        BluetoothEvent::Adapter {
+            id: adapter.clone(),
+            event: AdapterEvent::Added,
+        }
+    );
+
+    session.set_alias(&adapter, "Gateway").await.unwrap();
+    assert_eq!(
+        next_event(&mut events).await,
+        BluetoothEvent::Adapter {
+            id: adapter.clone(),
+            event: AdapterEvent::Alias {
+                alias: "Gateway".to_string()
+            },
+        }
+    );
+
+    session.set_discoverable(&adapter, true).await.unwrap();
+    assert_eq!(
+        next_event(&mut events).await,
+        BluetoothEvent::Adapter {
+            id: adapter.clone(),
+            event: AdapterEvent::Discoverable { discoverable: true },
+        }
+    );
+
+    fake.add_device(
+        &adapter,
+        FakeDevice::new("11:22:33:44:55:66".parse().unwrap()),
+    );
+    assert!(matches!(
+        next_event(&mut events).await,
+        BluetoothEvent::Device {
+            event: DeviceEvent::Discovered,
+            ..
+        }
+    ));
+
+    // Removing the adapter also removes its devices, before the adapter itself.
+    assert!(fake.remove_adapter(&adapter));
+    assert!(matches!(
+        next_event(&mut events).await,
+        BluetoothEvent::Device {
+            event: DeviceEvent::Removed,
+            ..
+        }
+    ));
+    assert_eq!(
+        next_event(&mut events).await,
+        BluetoothEvent::Adapter {
+            id: adapter.clone(),
+            event: AdapterEvent::Removed,
+        }
+    );
+    assert!(session.get_adapters().await.unwrap().is_empty());
+    assert!(session.get_devices().await.unwrap().is_empty());
+    assert!(!fake.remove_adapter(&adapter));
+}
+
+#[tokio::test]
+async fn connect_and_read() {
+    let (fake, session) = start().await;
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let device = fake.add_device(
+        &adapter,
+        FakeDevice::new("11:22:33:44:55:66".parse().unwrap()),
+    );
+    let service = fake.add_service(
+        &device,
+        FakeService {
+            uuid: uuid_from_u16(0x180f),
+            primary: true,
+        },
+    );
+    let characteristic = fake.add_characteristic(
+        &service,
+        FakeCharacteristic {
+            uuid: uuid_from_u16(0x2a19),
+            flags: CharacteristicFlags::READ | CharacteristicFlags::NOTIFY,
+            value: vec![42, 43],
+        },
+    );
+    let descriptor = fake.add_descriptor(
+        &characteristic,
+        FakeDescriptor {
+            uuid: uuid_from_u16(0x2901),
+            value: b"Battery".to_vec(),
+        },
+    );
+
+    session.connect(&device).await.unwrap();
+    assert!(session.get_device_info(&device).await.unwrap().connected);
+    assert!(fake.device(&device).unwrap().services_resolved);
+
+    let services = session.get_services(&device).await.unwrap();
+    assert_eq!(services.len(), 1);
+    assert_eq!(services[0].id, service);
+    assert_eq!(services[0].uuid, uuid_from_u16(0x180f));
+    assert!(services[0].primary);
+
+    let characteristics = session.get_characteristics(&service).await.unwrap();
+    assert_eq!(characteristics.len(), 1);
+    assert_eq!(characteristics[0].id, characteristic);
+    assert_eq!(
+        characteristics[0].flags,
+        CharacteristicFlags::READ | CharacteristicFlags::NOTIFY
+    );
+    assert_eq!(characteristics[0].mtu, Some(23));
+
+    let connection_info = session.get_connection_info(&device).await.unwrap();
+    assert!(connection_info.connected);
+    assert_eq!(connection_info.mtu, Some(23));
+
+    let descriptors = session.get_descriptors(&characteristic).await.unwrap();
+    assert_eq!(descriptors.len(), 1);
+    assert_eq!(descriptors[0].id, descriptor);
+
+    assert_eq!(
+        session
+            .read_characteristic_value(&characteristic)
+            .await
+            .unwrap(),
+        vec![42, 43]
+    );
+    assert_eq!(
+        session
+            .read_characteristic_value_with_offset(&characteristic, 1)
+            .await
+            .unwrap(),
+        vec![43]
+    );
+    assert_eq!(
+        session.read_descriptor_value(&descriptor).await.unwrap(),
+        b"Battery".to_vec()
+    );
+    // The characteristic isn't writable.
+    assert!(session
+        .write_characteristic_value(&characteristic, vec![1])
+        .await
+        .is_err());
+
+    // IDs of objects which don't exist are errors, rather than having no children.
+    let device_path = dbus::Path::from(device.clone());
+    let unknown_service: ServiceId =
+        serde_json::from_value(format!("{}/service00ff", device_path).into()).unwrap();
+    assert!(matches!(
+        session.get_characteristics(&unknown_service).await,
+        Err(BluetoothError::DeviceNotFound(_))
+    ));
+    let service_path = dbus::Path::from(service.clone());
+    let unknown_characteristic: CharacteristicId =
+        serde_json::from_value(format!("{}/char00ff", service_path).into()).unwrap();
+    assert!(matches!(
+        session.get_descriptors(&unknown_characteristic).await,
+        Err(BluetoothError::DeviceNotFound(_))
+    ));
+
+    session.disconnect(&device).await.unwrap();
+    assert!(!session.get_device_info(&device).await.unwrap().connected);
+}
+
+#[tokio::test]
+async fn scheduled_operations() {
+    let (fake, session) = start().await;
+    let session = session.with_scheduler(SchedulerOptions {
+        max_concurrent_connects: NonZeroUsize::new(1).unwrap(),
+        serialize_gatt_operations: true,
+    });
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let mut characteristics = vec![];
+    for mac_address in &["11:22:33:44:55:66", "11:22:33:44:55:77"] {
+        let device = fake.add_device(&adapter, FakeDevice::new(mac_address.parse().unwrap()));
+        let service = fake.add_service(
+            &device,
+            FakeService {
+                uuid: uuid_from_u16(0x1234),
+                primary: true,
+            },
+        );
+        let characteristic = fake.add_characteristic(
+            &service,
+            FakeCharacteristic {
+                uuid: uuid_from_u16(0x5678),
+                flags: CharacteristicFlags::READ,
+                value: vec![42],
+            },
+        );
+        characteristics.push((device, characteristic));
+    }
+
+    // Connect to both devices and read twice from each at once; the scheduler should queue them
+    // rather than failing.
+    let results = join_all(characteristics.iter().map(|(device, characteristic)| {
+        let session = &session;
+        async move {
+            session.connect(device).await?;
+            let (first, second) = join(
+                session.read_characteristic_value(characteristic),
+                session.read_characteristic_value(characteristic),
+            )
+            .await;
+            Ok::<_, BluetoothError>((first?, second?))
+        }
+    }))
+    .await;
+    for result in results {
+        assert_eq!(result.unwrap(), (vec![42], vec![42]));
+    }
+    assert_eq!(session.queue_depth(&adapter), QueueDepth::default());
+}
+
+#[tokio::test]
+async fn gatt_database_cached() {
+    let (fake, session) = start().await;
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let device = fake.add_device(
+        &adapter,
+        FakeDevice::new("11:22:33:44:55:66".parse().unwrap()),
+    );
+    let service = fake.add_service(
+        &device,
+        FakeService {
+            uuid: uuid_from_u16(0x180f),
+            primary: true,
+        },
+    );
+    let characteristic = fake.add_characteristic(
+        &service,
+        FakeCharacteristic {
+            uuid: uuid_from_u16(0x2a19),
+            flags: CharacteristicFlags::READ | CharacteristicFlags::NOTIFY,
+            value: vec![42],
+        },
+    );
+    let descriptor = fake.add_descriptor(
+        &characteristic,
+        FakeDescriptor {
+            uuid: uuid_from_u16(0x2901),
+            value: b"Battery".to_vec(),
+        },
+    );
+
+    // Services haven't been resolved yet, so nothing should be cached.
+    let database = session.get_gatt_database(&device).await.unwrap();
+    assert!(!Arc::ptr_eq(
+        &database,
+        &session.get_gatt_database(&device).await.unwrap()
+    ));
+
+    session.connect(&device).await.unwrap();
+    let database = session.get_gatt_database(&device).await.unwrap();
+    assert!(Arc::ptr_eq(
+        &database,
+        &session.get_gatt_database(&device).await.unwrap()
+    ));
+    assert_eq!(database.device, device);
+    assert_eq!(database.services.len(), 1);
+    assert_eq!(database.services[0].info.id, service);
+    let characteristics = &database.services[0].characteristics;
+    assert_eq!(characteristics.len(), 1);
+    assert_eq!(characteristics[0].info.id, characteristic);
+    assert_eq!(characteristics[0].descriptors.len(), 1);
+    assert_eq!(characteristics[0].descriptors[0].id, descriptor);
+    let json = database.to_json();
+    assert!(json.contains("00002a19-0000-1000-8000-00805f9b34fb"));
+    assert!(json.contains("\"notify\""));
+
+    // Adding a service should invalidate the cache. Make a round trip first to be sure that the
+    // signal has been handled.
+    let other_service = fake.add_service(
+        &device,
+        FakeService {
+            uuid: uuid_from_u16(0x1234),
+            primary: true,
+        },
+    );
+    session.get_device_info(&device).await.unwrap();
+    let services = session.get_services(&device).await.unwrap();
+    assert_eq!(services.len(), 2);
+    assert_eq!(services[1].id, other_service);
+
+    // So should disconnecting.
+    let database = session.get_gatt_database(&device).await.unwrap();
+    session.disconnect(&device).await.unwrap();
+    session.get_device_info(&device).await.unwrap();
+    assert!(!Arc::ptr_eq(
+        &database,
+        &session.get_gatt_database(&device).await.unwrap()
+    ));
+}
+
+#[tokio::test]
+async fn characteristic_metadata() {
+    let (fake, session) = start().await;
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let device = fake.add_device(
+        &adapter,
+        FakeDevice::new("11:22:33:44:55:66".parse().unwrap()),
+    );
+    let service = fake.add_service(
+        &device,
+        FakeService {
+            uuid: uuid_from_u16(0x181a),
+            primary: true,
+        },
+    );
+    let characteristic = fake.add_characteristic(
+        &service,
+        FakeCharacteristic {
+            uuid: uuid_from_u16(0x2a6e),
+            flags: CharacteristicFlags::READ | CharacteristicFlags::NOTIFY,
+            value: vec![0x0a, 0x09],
+        },
+    );
+    for (uuid, value) in [
+        (0x2901, b"Temperature\0".to_vec()),
+        (0x2902, vec![0x01, 0x00]),
+        (0x2904, vec![0x0e, 0xfe, 0x2f, 0x27, 0x01, 0x00, 0x00]),
+    ] {
+        fake.add_descriptor(
+            &characteristic,
+            FakeDescriptor {
+                uuid: uuid_from_u16(uuid),
+                value,
+            },
+        );
+    }
+    session.connect(&device).await.unwrap();
+
+    let metadata = session
+        .get_characteristic_metadata(&characteristic)
+        .await
+        .unwrap();
+    assert_eq!(metadata.info.id, characteristic);
+    assert_eq!(metadata.info.uuid, uuid_from_u16(0x2a6e));
+    assert_eq!(metadata.user_description.as_deref(), Some("Temperature"));
+    assert_eq!(
+        metadata.client_configuration,
+        Some(ClientCharacteristicConfiguration::NOTIFY)
+    );
+    let presentation_format = metadata.presentation_format.unwrap();
+    assert_eq!(presentation_format.format, ValueFormat::Sint16);
+    assert_eq!(presentation_format.unit, 0x272f);
+    let value = session
+        .read_characteristic_value(&characteristic)
+        .await
+        .unwrap();
+    assert_eq!(presentation_format.decode(&value), Some(23.14));
+}
+
+#[tokio::test]
+async fn characteristic_metadata_invalid_descriptor() {
+    let (fake, session) = start().await;
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let device = fake.add_device(
+        &adapter,
+        FakeDevice::new("11:22:33:44:55:66".parse().unwrap()),
+    );
+    let service = fake.add_service(
+        &device,
+        FakeService {
+            uuid: uuid_from_u16(0x181a),
+            primary: true,
+        },
+    );
+    let characteristic = fake.add_characteristic(
+        &service,
+        FakeCharacteristic {
+            uuid: uuid_from_u16(0x2a6e),
+            flags: CharacteristicFlags::READ,
+            value: vec![],
+        },
+    );
+    for (uuid, value) in [
+        (0x2901, b"Temperature".to_vec()),
+        // Too short to be a valid Client Characteristic Configuration.
+        (0x2902, vec![0x01]),
+    ] {
+        fake.add_descriptor(
+            &characteristic,
+            FakeDescriptor {
+                uuid: uuid_from_u16(uuid),
+                value,
+            },
+        );
+    }
+    session.connect(&device).await.unwrap();
+
+    // The invalid descriptor is skipped, but the others are still returned.
+    let metadata = session
+        .get_characteristic_metadata(&characteristic)
+        .await
+        .unwrap();
+    assert_eq!(metadata.user_description.as_deref(), Some("Temperature"));
+    assert_eq!(metadata.client_configuration, None);
+    assert_eq!(metadata.presentation_format, None);
+}
+
+#[tokio::test]
+async fn connect_timeout() {
+    let (fake, session) = start().await;
+    let session = session.with_config(BluetoothSessionConfig {
+        connect_timeout: Duration::from_millis(100),
+        ..Default::default()
+    });
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let mut device = FakeDevice::new("11:22:33:44:55:66".parse().unwrap());
+    device.responsive = false;
+    let device = fake.add_device(&adapter, device);
+
+    assert!(matches!(
+        session.connect(&device).await,
+        Err(BluetoothError::Timeout {
+            operation: Operation::Connect
+        })
+    ));
+
+    // Other method calls still use the default timeout.
+    assert_eq!(
+        session.config().method_call_timeout,
+        BluetoothSessionConfig::default().method_call_timeout
+    );
+    assert!(!session.get_device_info(&device).await.unwrap().connected);
+}
+
+#[tokio::test]
+async fn typed_errors() {
+    let (fake, session) = start().await;
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let mut device = FakeDevice::new("11:22:33:44:55:66".parse().unwrap());
+    device.connectable = false;
+    let device = fake.add_device(&adapter, device);
+
+    let error = session.connect(&device).await.unwrap_err();
+    assert!(
+        matches!(error, BluetoothError::ConnectionAbortedByLocal(_)),
+        "Unexpected error {:?}",
+        error
+    );
+    assert!(error.is_retryable());
+    assert!(!error.is_permanent());
+
+    session.remove_device(&device).await.unwrap();
+    let error = session.remove_device(&device).await.unwrap_err();
+    assert!(
+        matches!(error, BluetoothError::DeviceNotFound(_)),
+        "Unexpected error {:?}",
+        error
+    );
+    assert!(!error.is_retryable());
+    assert!(error.is_permanent());
+}
+
+#[tokio::test]
+async fn pair_trust_and_block() {
+    let (fake, session) = start().await;
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let device = fake.add_device(
+        &adapter,
+        FakeDevice::new("11:22:33:44:55:66".parse().unwrap()),
+    );
+
+    session.pair(&device).await.unwrap();
+    // Pairing again should fail, as the device is already paired.
+    assert!(session.pair(&device).await.is_err());
+    session.set_trusted(&device, true).await.unwrap();
+    session.set_blocked(&device, true).await.unwrap();
+
+    let device_info = session.get_device_info(&device).await.unwrap();
+    assert!(device_info.paired);
+    assert!(device_info.trusted);
+    assert!(device_info.blocked);
+
+    session.set_blocked(&device, false).await.unwrap();
+    assert!(!fake.device(&device).unwrap().blocked);
+}
+
+#[tokio::test]
+async fn write() {
+    let (fake, session) = start().await;
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let device = fake.add_device(
+        &adapter,
+        FakeDevice::new("11:22:33:44:55:66".parse().unwrap()),
+    );
+    let service = fake.add_service(
+        &device,
+        FakeService {
+            uuid: uuid_from_u16(0x1234),
+            primary: true,
+        },
+    );
+    let characteristic = fake.add_characteristic(
+        &service,
+        FakeCharacteristic {
+            uuid: uuid_from_u16(0x5678),
+            flags: CharacteristicFlags::WRITE,
+            value: vec![],
+        },
+    );
+
+    session
+        .write_characteristic_value(&characteristic, vec![1, 2, 3])
+        .await
+        .unwrap();
+    assert_eq!(
+        fake.characteristic_value(&characteristic),
+        Some(vec![1, 2, 3])
+    );
+    // The characteristic isn't readable.
+    assert!(session
+        .read_characteristic_value(&characteristic)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn acquire_notify_and_write() {
+    let (fake, session) = start().await;
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let device = fake.add_device(
+        &adapter,
+        FakeDevice::new("11:22:33:44:55:66".parse().unwrap()),
+    );
+    let service = fake.add_service(
+        &device,
+        FakeService {
+            uuid: uuid_from_u16(0x1234),
+            primary: true,
+        },
+    );
+    let characteristic = fake.add_characteristic(
+        &service,
+        FakeCharacteristic {
+            uuid: uuid_from_u16(0x5678),
+            flags: CharacteristicFlags::NOTIFY | CharacteristicFlags::WRITE_WITHOUT_RESPONSE,
+            value: vec![],
+        },
+    );
+    session.connect(&device).await.unwrap();
+
+    let mut notifications = session.acquire_notify(&characteristic).await.unwrap();
+    assert_eq!(notifications.mtu(), 23);
+    // Notifications can't be acquired twice.
+    assert!(session.acquire_notify(&characteristic).await.is_err());
+    fake.set_characteristic_value(&characteristic, vec![1, 2, 3]);
+    fake.set_characteristic_value(&characteristic, vec![4, 5]);
+    for expected in [vec![1, 2, 3], vec![4, 5]] {
+        let value = timeout(EVENT_TIMEOUT, notifications.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(value, expected);
+    }
+    // Once the stream is dropped, notifications may be acquired again.
+    drop(notifications);
+    session.acquire_notify(&characteristic).await.unwrap();
+
+    let mut writer = session.acquire_write(&characteristic).await.unwrap();
+    assert_eq!(writer.max_value_length(), 20);
+    writer.write(&[6, 7, 8]).await.unwrap();
+    assert_eq!(
+        fake.characteristic_value(&characteristic).unwrap(),
+        vec![6, 7, 8]
+    );
+    writer.send(vec![9]).await.unwrap();
+    assert_eq!(fake.characteristic_value(&characteristic).unwrap(), vec![9]);
+    assert!(matches!(
+        writer.write(&[0; 21]).await,
+        Err(BluetoothError::ValueTooLong {
+            length: 21,
+            max_length: 20
+        })
+    ));
+}
+
+#[tokio::test]
+async fn device_events() {
+    let (fake, session) = start().await;
+    let mut events = Box::pin(session.event_stream().await.unwrap());
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    assert_eq!(
+        next_event(&mut events).await,
+        BluetoothEvent::Adapter {
+            id: adapter.clone(),
+            event: AdapterEvent::Added,
+        }
+    );
+
+    let device = fake.add_device(
+        &adapter,
+        FakeDevice::new("11:22:33:44:55:66".parse().unwrap()),
+    );
+    assert_eq!(
+        next_event(&mut events).await,
+        BluetoothEvent::Device {
+            id: device.clone(),
+            event: DeviceEvent::Discovered,
+        }
+    );
+
+    assert!(fake.update_device(&device, |device| device.rssi = Some(-50)));
+    assert_eq!(
+        next_event(&mut events).await,
+        BluetoothEvent::Device {
+            id: device,
+            event: DeviceEvent::RSSI { rssi: -50 },
+        }
+    );
+}
+
+#[tokio::test]
+async fn record_and_replay_events() {
+    let (fake, session) = start().await;
+    let path = std::env::temp_dir().join(format!("bluez-async-events-{}.json", std::process::id()));
+    let recorder = session
+        .record_events(File::create(&path).await.unwrap())
+        .await
+        .unwrap();
+    let mut events = Box::pin(session.event_stream().await.unwrap());
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let device = fake.add_device(
+        &adapter,
+        FakeDevice::new("11:22:33:44:55:66".parse().unwrap()),
+    );
+    assert!(fake.update_device(&device, |device| device.rssi = Some(-50)));
+    let mut expected = vec![];
+    for _ in 0..3 {
+        expected.push(next_event(&mut events).await);
+    }
+
+    // Wait until the recorder has written all the events too.
+    let replay = timeout(EVENT_TIMEOUT, async {
+        loop {
+            // The last line may be incomplete if the recorder is in the middle of writing it.
+            if let Ok(replay) = EventReplay::open(&path) {
+                if replay.events().len() >= expected.len() {
+                    return replay;
+                }
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    drop(recorder);
+    std::fs::remove_file(&path).unwrap();
+
+    // The details of the newly discovered device should have been recorded along with it.
+    let discovered = replay
+        .events()
+        .iter()
+        .find(|recorded| {
+            recorded.event
+                == BluetoothEvent::Device {
+                    id: device.clone(),
+                    event: DeviceEvent::Discovered,
+                }
+        })
+        .unwrap();
+    assert_eq!(
+        discovered
+            .device_info
+            .as_ref()
+            .map(|info| &info.mac_address),
+        Some(&"11:22:33:44:55:66".parse().unwrap())
+    );
+
+    let replayed: Vec<BluetoothEvent> = replay.into_stream().collect().await;
+    assert_eq!(replayed, expected);
+}
+
+#[tokio::test]
+async fn advertisement_stream() {
+    let (fake, session) = start().await;
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let mut device = FakeDevice::new("11:22:33:44:55:66".parse().unwrap());
+    device.name = Some("Sensor".to_string());
+    let device = fake.add_device(&adapter, device);
+    let other_device = fake.add_device(
+        &adapter,
+        FakeDevice::new("22:33:44:55:66:77".parse().unwrap()),
+    );
+    assert_eq!(session.get_devices().await.unwrap().len(), 2);
+    let mut advertisements = session
+        .advertisement_stream(AdvertisementStreamOptions {
+            min_interval: Some(Duration::from_secs(3600)),
+        })
+        .await
+        .unwrap();
+
+    // Updates are merged with the existing state of the device.
+    assert!(fake.update_device(&device, |device| device.rssi = Some(-50)));
+    let record = timeout(EVENT_TIMEOUT, advertisements.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.id, device);
+    assert_eq!(record.name.as_deref(), Some("Sensor"));
+    assert_eq!(record.rssi, Some(-50));
+    assert!(record.manufacturer_data.is_empty());
+
+    // Further updates to the same device are rate limited, but those for other devices are not.
+    assert!(fake.update_device(&device, |device| {
+        device.manufacturer_data.insert(0x1234, vec![1, 2, 3]);
+    }));
+    assert!(fake.update_device(&other_device, |device| device.rssi = Some(-60)));
+    let record = timeout(EVENT_TIMEOUT, advertisements.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.id, other_device);
+    assert_eq!(record.rssi, Some(-60));
+}
+
+#[tokio::test]
+async fn remove_device() {
+    let (fake, session) = start().await;
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let device = fake.add_device(
+        &adapter,
+        FakeDevice::new("11:22:33:44:55:66".parse().unwrap()),
+    );
+    let service = fake.add_service(
+        &device,
+        FakeService {
+            uuid: uuid_from_u16(0x1234),
+            primary: true,
+        },
+    );
+    let other_device = fake.add_device(
+        &adapter,
+        FakeDevice::new("22:33:44:55:66:77".parse().unwrap()),
+    );
+    // Make sure that the events for adding the devices are all delivered before we start listening
+    // for events.
+    assert_eq!(session.get_devices().await.unwrap().len(), 2);
+    let mut events = Box::pin(session.event_stream().await.unwrap());
+
+    session.remove_device(&device).await.unwrap();
+    assert_eq!(
+        next_event(&mut events).await,
+        BluetoothEvent::Device {
+            id: device.clone(),
+            event: DeviceEvent::Removed,
+        }
+    );
+    assert!(session.get_service_info(&service).await.is_err());
+    // Removing it again should fail, as it no longer exists.
+    assert!(session.remove_device(&device).await.is_err());
+
+    // BlueZ may also remove devices of its own accord.
+    assert!(fake.remove_device(&other_device));
+    assert_eq!(
+        next_event(&mut events).await,
+        BluetoothEvent::Device {
+            id: other_device,
+            event: DeviceEvent::Removed,
+        }
+    );
+    assert!(session.get_devices().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn characteristic_notifications() {
+    let (fake, session) = start().await;
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let device = fake.add_device(
+        &adapter,
+        FakeDevice::new("11:22:33:44:55:66".parse().unwrap()),
+    );
+    let service = fake.add_service(
+        &device,
+        FakeService {
+            uuid: uuid_from_u16(0x1234),
+            primary: true,
+        },
+    );
+    let characteristic = fake.add_characteristic(
+        &service,
+        FakeCharacteristic {
+            uuid: uuid_from_u16(0x5678),
+            flags: CharacteristicFlags::NOTIFY,
+            value: vec![],
+        },
+    );
+    session.connect(&device).await.unwrap();
+
+    let mut events = Box::pin(
+        session
+            .characteristic_event_stream(&characteristic)
+            .await
+            .unwrap(),
+    );
+    session.start_notify(&characteristic).await.unwrap();
+    assert!(fake.set_characteristic_value(&characteristic, vec![1, 2]));
+    assert_eq!(
+        next_event(&mut events).await,
+        BluetoothEvent::Characteristic {
+            id: characteristic.clone(),
+            event: CharacteristicEvent::Value { value: vec![1, 2] },
+        }
+    );
+    session.stop_notify(&characteristic).await.unwrap();
+}
+
+#[tokio::test]
+async fn battery_from_bluez() {
+    let (fake, session) = start().await;
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let mut device = FakeDevice::new("11:22:33:44:55:66".parse().unwrap());
+    device.services = vec![uuid_from_u16(0x180f)];
+    device.battery_percentage = Some(80);
+    let device = fake.add_device(&adapter, device);
+
+    assert_eq!(
+        session
+            .get_device_info(&device)
+            .await
+            .unwrap()
+            .battery_percentage,
+        Some(80)
+    );
+    assert_eq!(
+        session.get_devices().await.unwrap()[0].battery_percentage,
+        Some(80)
+    );
+    assert_eq!(
+        session.get_battery_percentage(&device).await.unwrap(),
+        Some(80)
+    );
+
+    let mut levels = Box::pin(session.battery_percentage_stream(&device).await.unwrap());
+    assert!(fake.update_device(&device, |device| device.battery_percentage = Some(75)));
+    assert_eq!(
+        timeout(EVENT_TIMEOUT, levels.next()).await.unwrap(),
+        Some(75)
+    );
+}
+
+#[tokio::test]
+async fn battery_from_characteristic() {
+    let (fake, session) = start().await;
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let device = fake.add_device(
+        &adapter,
+        FakeDevice::new("11:22:33:44:55:66".parse().unwrap()),
+    );
+    assert_eq!(
+        session
+            .get_device_info(&device)
+            .await
+            .unwrap()
+            .battery_percentage,
+        None
+    );
+    assert_eq!(session.get_battery_percentage(&device).await.unwrap(), None);
+    assert!(matches!(
+        session.battery_percentage_stream(&device).await,
+        Err(BluetoothError::UUIDNotFound { .. })
+    ));
+
+    let service = fake.add_service(
+        &device,
+        FakeService {
+            uuid: uuid_from_u16(0x180f),
+            primary: true,
+        },
+    );
+    let characteristic = fake.add_characteristic(
+        &service,
+        FakeCharacteristic {
+            uuid: uuid_from_u16(0x2a19),
+            flags: CharacteristicFlags::READ | CharacteristicFlags::NOTIFY,
+            value: vec![42],
+        },
+    );
+    session.connect(&device).await.unwrap();
+    assert_eq!(
+        session.get_battery_percentage(&device).await.unwrap(),
+        Some(42)
+    );
+
+    let mut levels = Box::pin(session.battery_percentage_stream(&device).await.unwrap());
+    assert!(fake.set_characteristic_value(&characteristic, vec![41]));
+    assert_eq!(
+        timeout(EVENT_TIMEOUT, levels.next()).await.unwrap(),
+        Some(41)
+    );
+}
+
+#[tokio::test]
+async fn standard_services() {
+    let (fake, session) = start().await;
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let device = fake.add_device(
+        &adapter,
+        FakeDevice::new("11:22:33:44:55:66".parse().unwrap()),
+    );
+    let device_information = fake.add_service(
+        &device,
+        FakeService {
+            uuid: uuid_from_u16(0x180a),
+            primary: true,
+        },
+    );
+    for (uuid, value) in &[(0x2a29, &b"Acme"[..]), (0x2a26, &b"1.2.3\0"[..])] {
+        fake.add_characteristic(
+            &device_information,
+            FakeCharacteristic {
+                uuid: uuid_from_u16(*uuid),
+                flags: CharacteristicFlags::READ,
+                value: value.to_vec(),
+            },
+        );
+    }
+    let environmental_sensing = fake.add_service(
+        &device,
+        FakeService {
+            uuid: uuid_from_u16(0x181a),
+            primary: true,
+        },
+    );
+    fake.add_characteristic(
+        &environmental_sensing,
+        FakeCharacteristic {
+            uuid: uuid_from_u16(0x2a6e),
+            flags: CharacteristicFlags::READ,
+            value: vec![0x34, 0x08],
+        },
+    );
+    fake.add_characteristic(
+        &environmental_sensing,
+        FakeCharacteristic {
+            uuid: uuid_from_u16(0x2a6f),
+            flags: CharacteristicFlags::READ,
+            value: vec![0x88],
+        },
+    );
+    session.connect(&device).await.unwrap();
+
+    let information = session.get_device_information(&device).await.unwrap();
+    assert_eq!(information.manufacturer_name.as_deref(), Some("Acme"));
+    assert_eq!(information.firmware_revision.as_deref(), Some("1.2.3"));
+    assert_eq!(information.model_number, None);
+
+    match session.get_environmental_readings(&device).await {
+        Err(BluetoothError::CharacteristicParseError(e)) => {
+            assert_eq!(e.uuid, uuid_from_u16(0x2a6f));
+            assert_eq!(
+                e.to_string(),
+                "Invalid value [136] for characteristic Humidity (0x2A6F)"
+            );
+        }
+        result => panic!("Unexpected result {:?}", result),
+    }
+}
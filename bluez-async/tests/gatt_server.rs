@@ -0,0 +1,185 @@
+//! Integration tests for local GATT applications, registered with a fake BlueZ daemon which then
+//! calls into them as BlueZ would on behalf of remote devices.
+
+use async_trait::async_trait;
+use bluez_async::fake::{FakeAdapter, FakeBluez};
+use bluez_async::{
+    uuid_from_u16, BluetoothSession, CharacteristicFlags, CharacteristicHandler,
+    CharacteristicNotifier, DescriptorFlags, DescriptorHandler, GattApplication,
+    GattCharacteristic, GattDescriptor, GattError, GattService, ReadRequest, WriteRequest,
+};
+use bluez_generated::{OrgBluezGattCharacteristic1, OrgBluezGattDescriptor1};
+use dbus::message::SignalArgs;
+use dbus::nonblock::stdintf::org_freedesktop_dbus::{ObjectManager, PropertiesPropertiesChanged};
+use dbus::nonblock::Proxy;
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::{sleep, timeout};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+struct Counter {
+    value: Mutex<Vec<u8>>,
+}
+
+#[async_trait]
+impl CharacteristicHandler for Counter {
+    async fn read(&self, request: ReadRequest) -> Result<Vec<u8>, GattError> {
+        let value = self.value.lock().unwrap();
+        value
+            .get(request.offset..)
+            .map(|value| value.to_owned())
+            .ok_or(GattError::InvalidOffset)
+    }
+
+    async fn write(&self, value: Vec<u8>, request: WriteRequest) -> Result<(), GattError> {
+        if request.offset != 0 {
+            return Err(GattError::InvalidOffset);
+        }
+        *self.value.lock().unwrap() = value;
+        Ok(())
+    }
+
+    async fn start_notify(&self, notifier: CharacteristicNotifier) -> Result<(), GattError> {
+        notifier.notify(self.value.lock().unwrap().clone());
+        Ok(())
+    }
+}
+
+struct Description;
+
+#[async_trait]
+impl DescriptorHandler for Description {
+    async fn read(&self, _request: ReadRequest) -> Result<Vec<u8>, GattError> {
+        Ok(b"Counter".to_vec())
+    }
+}
+
+#[tokio::test]
+async fn register_and_use_application() {
+    let fake = FakeBluez::start().await.unwrap();
+    let (_, session) = BluetoothSession::new_with_address(fake.address())
+        .await
+        .unwrap();
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+
+    let counter = Arc::new(Counter::default());
+    let application = GattApplication {
+        services: vec![GattService {
+            uuid: uuid_from_u16(0x1234),
+            primary: true,
+            characteristics: vec![GattCharacteristic {
+                uuid: uuid_from_u16(0x5678),
+                flags: CharacteristicFlags::READ
+                    | CharacteristicFlags::WRITE
+                    | CharacteristicFlags::NOTIFY,
+                handler: counter.clone(),
+                descriptors: vec![GattDescriptor {
+                    uuid: uuid_from_u16(0x2901),
+                    flags: DescriptorFlags::READ,
+                    handler: Arc::new(Description),
+                }],
+            }],
+        }],
+    };
+    let handle = session
+        .register_gatt_application(&adapter, application)
+        .await
+        .unwrap();
+
+    let applications = fake.gatt_applications();
+    assert_eq!(applications.len(), 1);
+    assert_eq!(applications[0].adapter, adapter);
+    assert_eq!(&applications[0].object_path, handle.object_path());
+    let owner = applications[0].owner.clone();
+
+    // Check the objects which the application exports, as BlueZ would.
+    let root = Proxy::new(
+        owner.clone(),
+        handle.object_path().clone(),
+        TIMEOUT,
+        fake.connection(),
+    );
+    let objects = root.get_managed_objects().await.unwrap();
+    assert_eq!(objects.len(), 3);
+    let (characteristic_path, _) = objects
+        .iter()
+        .find(|(_, interfaces)| interfaces.contains_key("org.bluez.GattCharacteristic1"))
+        .unwrap();
+    let (descriptor_path, _) = objects
+        .iter()
+        .find(|(_, interfaces)| interfaces.contains_key("org.bluez.GattDescriptor1"))
+        .unwrap();
+
+    // Write, read and get notifications from the characteristic.
+    let characteristic = Proxy::new(
+        owner.clone(),
+        characteristic_path.clone(),
+        TIMEOUT,
+        fake.connection(),
+    );
+    assert_eq!(
+        characteristic.flags().await.unwrap(),
+        vec!["read", "write", "notify"]
+    );
+    OrgBluezGattCharacteristic1::write_value(&characteristic, vec![1, 2, 3], HashMap::new())
+        .await
+        .unwrap();
+    assert_eq!(*counter.value.lock().unwrap(), vec![1, 2, 3]);
+    assert_eq!(
+        OrgBluezGattCharacteristic1::read_value(&characteristic, HashMap::new())
+            .await
+            .unwrap(),
+        vec![1, 2, 3]
+    );
+
+    let match_rule =
+        PropertiesPropertiesChanged::match_rule(None, Some(characteristic_path)).static_clone();
+    let (_match, mut notifications) = fake
+        .connection()
+        .add_match(match_rule)
+        .await
+        .unwrap()
+        .msg_stream();
+    characteristic.start_notify().await.unwrap();
+    let notification = timeout(TIMEOUT, notifications.next())
+        .await
+        .unwrap()
+        .unwrap();
+    let properties_changed = PropertiesPropertiesChanged::from_message(&notification).unwrap();
+    assert_eq!(
+        properties_changed.interface_name,
+        "org.bluez.GattCharacteristic1"
+    );
+    assert_eq!(
+        dbus::arg::prop_cast::<Vec<u8>>(&properties_changed.changed_properties, "Value"),
+        Some(&vec![1, 2, 3])
+    );
+    characteristic.stop_notify().await.unwrap();
+
+    // The descriptor doesn't support writes.
+    let descriptor = Proxy::new(owner, descriptor_path.clone(), TIMEOUT, fake.connection());
+    assert_eq!(
+        OrgBluezGattDescriptor1::read_value(&descriptor, HashMap::new())
+            .await
+            .unwrap(),
+        b"Counter".to_vec()
+    );
+    let error = OrgBluezGattDescriptor1::write_value(&descriptor, vec![1], HashMap::new())
+        .await
+        .unwrap_err();
+    assert_eq!(error.name(), Some("org.bluez.Error.NotSupported"));
+
+    // Dropping the handle should unregister the application.
+    drop(handle);
+    timeout(TIMEOUT, async {
+        while !fake.gatt_applications().is_empty() {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
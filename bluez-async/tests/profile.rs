@@ -0,0 +1,93 @@
+//! Integration tests for registering Bluetooth Classic profiles with a fake BlueZ daemon, which
+//! then connects devices to them as BlueZ would.
+
+use bluez_async::fake::{FakeAdapter, FakeBluez, FakeDevice};
+use bluez_async::{uuid_from_u16, BluetoothSession, ProfileEvent, ProfileOptions, ProfileRole};
+use futures::StreamExt;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::{sleep, timeout};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The UUID of the Serial Port Profile.
+const SERIAL_PORT_UUID: u16 = 0x1101;
+
+#[tokio::test]
+async fn register_and_connect() {
+    let fake = FakeBluez::start().await.unwrap();
+    let (_, session) = BluetoothSession::new_with_address(fake.address())
+        .await
+        .unwrap();
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let device = fake.add_device(
+        &adapter,
+        FakeDevice::new("11:22:33:44:55:66".parse().unwrap()),
+    );
+
+    let mut handle = session
+        .register_profile(
+            uuid_from_u16(SERIAL_PORT_UUID),
+            ProfileOptions {
+                name: Some("Serial Port".to_string()),
+                role: Some(ProfileRole::Server),
+                channel: Some(1),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    let profiles = fake.profiles();
+    assert_eq!(profiles.len(), 1);
+    assert_eq!(&profiles[0].object_path, handle.object_path());
+    assert_eq!(profiles[0].uuid, uuid_from_u16(SERIAL_PORT_UUID));
+    assert_eq!(profiles[0].name.as_deref(), Some("Serial Port"));
+    assert_eq!(profiles[0].role.as_deref(), Some("server"));
+    assert_eq!(profiles[0].channel, Some(1));
+
+    let mut remote = fake.connect_profile(&profiles[0], &device).await.unwrap();
+    let mut connection = match timeout(TIMEOUT, handle.next()).await.unwrap() {
+        Some(ProfileEvent::NewConnection(connection)) => connection,
+        event => panic!("Unexpected event {:?}", event),
+    };
+    assert_eq!(connection.device, device);
+
+    // Data should flow both ways over the connection.
+    remote.write_all(b"hello").await.unwrap();
+    let mut buffer = [0; 5];
+    connection.read_exact(&mut buffer).await.unwrap();
+    assert_eq!(&buffer, b"hello");
+    connection.write_all(b"world").await.unwrap();
+    remote.read_exact(&mut buffer).await.unwrap();
+    assert_eq!(&buffer, b"world");
+
+    // Dropping the connection should close it.
+    drop(connection);
+    assert_eq!(remote.read(&mut buffer).await.unwrap(), 0);
+
+    session.unregister_profile(handle).await.unwrap();
+    assert_eq!(fake.profiles(), vec![]);
+}
+
+#[tokio::test]
+async fn unregister_on_drop() {
+    let fake = FakeBluez::start().await.unwrap();
+    let (_, session) = BluetoothSession::new_with_address(fake.address())
+        .await
+        .unwrap();
+
+    let handle = session
+        .register_profile(uuid_from_u16(SERIAL_PORT_UUID), ProfileOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(fake.profiles().len(), 1);
+
+    drop(handle);
+    timeout(TIMEOUT, async {
+        while !fake.profiles().is_empty() {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
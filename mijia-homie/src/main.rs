@@ -81,19 +81,23 @@ enum ConnectionStatus {
     MarkedDisconnected,
     /// Connected and subscribed to updates
     Connected { id: DeviceId },
+    /// The sensor can't be connected to, so readings only come from its advertisements.
+    Passive,
 }
 
 #[derive(Debug, Clone)]
 struct Sensor {
     mac_address: MacAddress,
     name: String,
-    /// The last time an update was received from the sensor.
+    /// The last time readings were received from the sensor by notification.
     last_update_timestamp: Instant,
     /// The last time an update from the sensor was sent to the server. This may be earlier than
     /// `last_update_timestamp` if the `min_update_time` config parameter is set.
     last_sent_timestamp: Instant,
     connection_status: ConnectionStatus,
     ids: Vec<DeviceId>,
+    /// Whether the sensor's node has been added to the Homie device.
+    has_node: bool,
 }
 
 impl Sensor {
@@ -106,6 +110,11 @@ impl Sensor {
             .get(&props.mac_address)
             .cloned()
             .unwrap_or_else(|| props.mac_address.to_string());
+        let connection_status = if props.supports_gatt() {
+            ConnectionStatus::Unknown
+        } else {
+            ConnectionStatus::Passive
+        };
         Self {
             mac_address: props.mac_address,
             name,
@@ -113,8 +122,9 @@ impl Sensor {
             // This should really be something like Instant::MIN, but there is no such constant so
             // one hour in the past should be more than enough.
             last_sent_timestamp: Instant::now() - Duration::from_secs(3600),
-            connection_status: ConnectionStatus::Unknown,
+            connection_status,
             ids: vec![props.id],
+            has_node: false,
         }
     }
 
@@ -161,8 +171,6 @@ impl Sensor {
     ) -> Result<(), eyre::Report> {
         println!("{} {} ({})", self.mac_address, readings, self.name);
         let now = Instant::now();
-        self.last_update_timestamp = now;
-
         if now > self.last_sent_timestamp + min_update_period {
             let node_id = self.node_id();
             homie
@@ -198,11 +206,38 @@ impl Sensor {
         homie: &mut HomieDevice,
         id: DeviceId,
     ) -> Result<(), eyre::Report> {
-        assert!(self.ids.contains(&id));
-        homie.add_node(self.as_node()).await?;
+        // BlueZ may have removed the device while we were connecting to it, in which case the
+        // connection is no use.
+        if !self.ids.contains(&id) {
+            println!(
+                "{} connected as {}, but it has since been removed. Will reconnect.",
+                self.name, id
+            );
+            self.connection_status = ConnectionStatus::Disconnected;
+            return Ok(());
+        }
+        self.add_node(homie).await?;
         self.connection_status = ConnectionStatus::Connected { id };
         Ok(())
     }
+
+    /// Add the sensor's node to the Homie device, if it hasn't been already.
+    async fn add_node(&mut self, homie: &mut HomieDevice) -> Result<(), eyre::Report> {
+        if !self.has_node {
+            homie.add_node(self.as_node()).await?;
+            self.has_node = true;
+        }
+        Ok(())
+    }
+
+    /// Remove the sensor's node from the Homie device, if it has been added.
+    async fn remove_node(&mut self, homie: &mut HomieDevice) -> Result<(), eyre::Report> {
+        if self.has_node {
+            homie.remove_node(&self.node_id()).await?;
+            self.has_node = false;
+        }
+        Ok(())
+    }
 }
 
 async fn run_sensor_system(
@@ -244,9 +279,15 @@ async fn bluetooth_connection_loop(
             }
         }
 
-        // Look for more sensors if enough time has elapsed since last time we tried.
+        // Look for more sensors if enough time has elapsed since last time we tried, and we are
+        // missing some or BlueZ has removed all the ids we had for some.
         let now = Instant::now();
-        if now > next_scan_due && state.lock().await.sensors.len() < sensor_names.len() {
+        let missing_sensors = {
+            let sensors = &state.lock().await.sensors;
+            sensors.len() < sensor_names.len()
+                || sensors.values().any(|sensor| sensor.ids.is_empty())
+        };
+        if now > next_scan_due && missing_sensors {
             next_scan_due = now + SCAN_INTERVAL;
             check_for_sensors(state.clone(), session, &sensor_names).await?;
         }
@@ -309,6 +350,7 @@ async fn action_sensor(
             check_for_stale_sensor(state, session, mac_address, &id).await?;
             Ok(())
         }
+        ConnectionStatus::Passive => Ok(()),
     }
 }
 
@@ -317,11 +359,12 @@ async fn check_for_sensors(
     session: &MijiaSession,
     sensor_names: &HashMap<MacAddress, String>,
 ) -> Result<(), eyre::Report> {
-    session.bt_session.start_discovery().await?;
+    session.start_advertisement_scan().await?;
 
     let sensors = session.get_sensors().await?;
     let state = &mut *state.lock().await;
     for props in sensors {
+        // Sensors which don't support GATT are added too, but only read from their advertisements.
         if sensor_names.contains_key(&props.mac_address) {
             if let Some(sensor) = state.sensors.get_mut(&props.mac_address) {
                 if !sensor.ids.contains(&props.id) {
@@ -408,7 +451,16 @@ async fn connect_and_subscribe_sensor_or_disconnect(
             max_elapsed_time: Some(SENSOR_CONNECT_RETRY_TIMEOUT),
             ..Default::default()
         },
-        || session.start_notify_sensor(&id).map_err(Into::into),
+        || {
+            session.start_notify_sensor(&id).map_err(|e| {
+                // Errors which aren't known to be permanent may well be transient BlueZ problems.
+                if e.is_permanent() {
+                    backoff::Error::Permanent(e)
+                } else {
+                    backoff::Error::Transient(e)
+                }
+            })
+        },
     )
     .or_else(|e| async {
         session
@@ -440,7 +492,7 @@ async fn check_for_stale_sensor(
             now - sensor.last_update_timestamp
         );
         sensor.connection_status = ConnectionStatus::Disconnected;
-        state.homie.remove_node(&sensor.node_id()).await?;
+        sensor.remove_node(&mut state.homie).await?;
         // We could drop our state lock at this point, if it ends up taking
         // too long. As it is, it's quite nice that we can't attempt to connect
         // while we're in the middle of disconnecting.
@@ -478,8 +530,13 @@ async fn handle_bluetooth_event(
     let homie = &mut state.homie;
     let sensors = &mut state.sensors;
     match event {
-        MijiaEvent::Readings { id, readings } => {
+        MijiaEvent::Readings {
+            id,
+            readings,
+            advertised: false,
+        } => {
             if let Some(sensor) = get_mut_sensor_by_id(sensors, &id) {
+                sensor.last_update_timestamp = Instant::now();
                 sensor
                     .publish_readings(homie, &readings, state.min_update_period)
                     .await?;
@@ -504,6 +561,22 @@ async fn handle_bluetooth_event(
                 println!("Got update from unknown device {}.", id);
             }
         }
+        MijiaEvent::Readings {
+            id,
+            readings,
+            advertised: true,
+        } => {
+            // Advertisements don't mean that the sensor is connected, so publish them without
+            // changing its connection status or treating it as a sign that the connection is alive.
+            if let Some(sensor) = get_mut_sensor_by_id(sensors, &id) {
+                sensor.add_node(homie).await?;
+                sensor
+                    .publish_readings(homie, &readings, state.min_update_period)
+                    .await?;
+            } else {
+                log::trace!("Ignoring advertised readings from unknown device {}.", id);
+            }
+        }
         MijiaEvent::Disconnected { id } => {
             if let Some(sensor) = get_mut_sensor_by_id(sensors, &id) {
                 if let ConnectionStatus::Connected { id: connected_id } = &sensor.connection_status
@@ -511,7 +584,7 @@ async fn handle_bluetooth_event(
                     if id == *connected_id {
                         println!("{} disconnected", sensor.name);
                         sensor.connection_status = ConnectionStatus::MarkedDisconnected;
-                        homie.remove_node(&sensor.node_id()).await?;
+                        sensor.remove_node(homie).await?;
                     } else {
                         println!(
                             "{} ({}) disconnected but was connected as {}.",
@@ -528,6 +601,25 @@ async fn handle_bluetooth_event(
                 println!("Unknown device {} disconnected.", id);
             }
         }
+        MijiaEvent::Removed { id } => {
+            if let Some(sensor) = get_mut_sensor_by_id(sensors, &id) {
+                println!("{} ({}) removed by BlueZ", sensor.name, id);
+                sensor.ids.retain(|sensor_id| *sensor_id != id);
+                // It will be added back the next time we scan, if BlueZ rediscovers it.
+                match &sensor.connection_status {
+                    ConnectionStatus::Connected { id: connected_id } if id == *connected_id => {
+                        sensor.connection_status = ConnectionStatus::Disconnected;
+                        sensor.remove_node(homie).await?;
+                    }
+                    ConnectionStatus::Connecting { .. } => {
+                        // The connection attempt in progress may be using this id, in which case
+                        // `mark_connected` will notice that it is gone once it finishes.
+                        log::info!("{} removed while connecting", sensor.name);
+                    }
+                    _ => {}
+                }
+            }
+        }
         _ => {}
     };
 
@@ -7,12 +7,18 @@ mod mijia_history_config;
 use crate::config::read_sensor_names;
 use crate::mijia_history_config::{get_influxdb_client, Config};
 use eyre::Report;
+use futures::TryStreamExt;
 use influx_db_client::{Client, Point, Precision};
-use mijia::{bluetooth::MacAddress, HistoryRecord, MijiaSession, SignedDuration};
+use mijia::{
+    bluetooth::MacAddress, HistoryCheckpoints, HistoryOptions, HistoryRecord, MijiaSession,
+    SignedDuration,
+};
 use std::time::{Duration, SystemTime};
 use tokio::time;
 
 const SCAN_DURATION: Duration = Duration::from_secs(5);
+/// How often to print progress while reading history, in records.
+const PROGRESS_INTERVAL: u32 = 100;
 const INFLUXDB_PRECISION: Option<Precision> = Some(Precision::Milliseconds);
 
 #[tokio::main]
@@ -23,6 +29,7 @@ async fn main() -> Result<(), Report> {
 
     let config = Config::from_file()?;
     let names = read_sensor_names(&config.sensor_names_filename)?;
+    let mut checkpoints = HistoryCheckpoints::load(&config.checkpoints_filename)?;
 
     let influxdb_client = get_influxdb_client(&config.influxdb)?;
     let (_, session) = MijiaSession::new().await?;
@@ -33,9 +40,9 @@ async fn main() -> Result<(), Report> {
     time::sleep(SCAN_DURATION).await;
 
     // Get the list of sensors which are currently visible and connect those for which we have
-    // names.
+    // names. History can only be read from sensors which support GATT.
     let sensors = session.get_sensors().await?;
-    for sensor in sensors.iter() {
+    for sensor in sensors.iter().filter(|sensor| sensor.supports_gatt()) {
         if let Some(name) = names.get(&sensor.mac_address) {
             println!("Connecting to {} ({})...", name, sensor.mac_address);
             if let Err(e) = session.bt_session.connect(&sensor.id).await {
@@ -53,17 +60,56 @@ async fn main() -> Result<(), Report> {
                     offset, config.max_clock_offset
                 );
             } else {
-                println!("Sensor time offset {:?}, reading history...", offset);
-                let history = session.get_all_history(&sensor.id).await?;
-                write_history(
-                    &influxdb_client,
-                    &config.influxdb.measurement,
-                    &sensor.mac_address,
-                    name,
-                    history,
-                )
-                .await?;
-                println!("Written to InfluxDB.");
+                let history_range = session.get_history_range(&sensor.id).await?;
+                let start_index = match checkpoints.get(&sensor.mac_address) {
+                    // If the checkpoint is beyond the end of the history then the sensor's history
+                    // must have been reset, so start again.
+                    Some(next_index) if next_index <= history_range.end => next_index,
+                    _ => history_range.start,
+                };
+                println!(
+                    "Sensor time offset {:?}, reading history from {} to {}...",
+                    offset, start_index, history_range.end
+                );
+                let options = HistoryOptions {
+                    start_index,
+                    ..Default::default()
+                };
+                let mut updates = session.get_history_stream(&sensor.id, &options).await?;
+                let mut history: Vec<HistoryRecord> = Vec::new();
+                while let Some(update) = updates.try_next().await? {
+                    if update.received % PROGRESS_INTERVAL == 0 {
+                        println!(
+                            "Received {}/{} records...",
+                            update.received, update.expected
+                        );
+                    }
+                    history.push(update.record);
+                }
+                if !history.is_empty() {
+                    write_history(
+                        &influxdb_client,
+                        &config.influxdb.measurement,
+                        &sensor.mac_address,
+                        name,
+                        &history,
+                    )
+                    .await?;
+                    println!("Written {} records to InfluxDB.", history.len());
+                    // Records which are still missing after retries will be fetched again next
+                    // time, so only move the checkpoint up to the first of them.
+                    let next_index = checkpoints.set_received(
+                        sensor.mac_address.clone(),
+                        start_index.max(history_range.start),
+                        history.iter().map(|record| record.index),
+                    );
+                    checkpoints.save()?;
+                    if next_index < history_range.end {
+                        println!("Records from {} are missing.", next_index);
+                    }
+                } else {
+                    println!("No new records.");
+                }
             }
 
             if let Err(e) = session.bt_session.disconnect(&sensor.id).await {
@@ -80,12 +126,11 @@ async fn write_history(
     measurement: &str,
     mac_address: &MacAddress,
     name: &str,
-    history: Vec<Option<HistoryRecord>>,
+    history: &[HistoryRecord],
 ) -> Result<(), Report> {
     let points = history
-        .into_iter()
-        .flatten()
-        .map(|record| point_for_record(measurement, mac_address, name, &record));
+        .iter()
+        .map(|record| point_for_record(measurement, mac_address, name, record));
     influxdb_client
         .write_points(points, INFLUXDB_PRECISION, None)
         .await?;
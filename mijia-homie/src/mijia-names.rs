@@ -97,5 +97,6 @@ fn write_name(sensor_names_filename: &str, mac: &MacAddress, name: &str) -> Resu
 }
 
 fn should_include_sensor(sensor: &SensorProps, names: &HashMap<MacAddress, String>) -> bool {
-    !names.contains_key(&sensor.mac_address)
+    // Other sensors can't be connected to, so there's no point naming them.
+    sensor.supports_gatt() && !names.contains_key(&sensor.mac_address)
 }
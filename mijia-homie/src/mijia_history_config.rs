@@ -10,6 +10,7 @@ const DEFAULT_DATABASE: &str = "mijia_history";
 const DEFAULT_MEASUREMENT: &str = "mijia_history";
 const DEFAULT_INFLUXDB_URL: &str = "http://localhost:8086";
 const DEFAULT_SENSOR_NAMES_FILENAME: &str = "sensor-names.toml";
+const DEFAULT_CHECKPOINTS_FILENAME: &str = "mijia-history-checkpoints.txt";
 const DEFAULT_MAX_CLOCK_OFFSET: Duration = Duration::from_secs(20 * 60);
 const CONFIG_FILENAME: &str = "mijia-history-influx.toml";
 
@@ -17,6 +18,7 @@ const CONFIG_FILENAME: &str = "mijia-history-influx.toml";
 #[serde(default, deny_unknown_fields)]
 pub struct Config {
     pub sensor_names_filename: String,
+    pub checkpoints_filename: String,
     #[serde(
         deserialize_with = "de_duration_seconds",
         rename = "max_clock_offset_seconds"
@@ -41,6 +43,7 @@ impl Default for Config {
     fn default() -> Config {
         Config {
             sensor_names_filename: DEFAULT_SENSOR_NAMES_FILENAME.to_owned(),
+            checkpoints_filename: DEFAULT_CHECKPOINTS_FILENAME.to_owned(),
             max_clock_offset: DEFAULT_MAX_CLOCK_OFFSET,
             influxdb: Default::default(),
         }
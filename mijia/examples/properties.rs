@@ -71,6 +71,10 @@ fn parse_args() -> Result<Vec<String>, Report> {
 }
 
 fn should_include_sensor(sensor: &SensorProps, filters: &Vec<String>) -> bool {
+    // Other sensors can only be used via their advertisements.
+    if !sensor.supports_gatt() {
+        return false;
+    }
     let mac = sensor.mac_address.to_string();
     filters.is_empty() || filters.iter().any(|filter| mac.contains(filter))
 }
@@ -41,9 +41,20 @@ async fn main() -> Result<(), Report> {
     println!("Readings:");
     while let Some(event) = events.next().await {
         match event {
-            MijiaEvent::Readings { id, readings } => {
+            MijiaEvent::Readings {
+                id,
+                readings,
+                advertised: false,
+            } => {
                 println!("{}: {}", id, readings);
             }
+            MijiaEvent::Readings {
+                id,
+                readings,
+                advertised: true,
+            } => {
+                println!("{} (advertised): {}", id, readings);
+            }
             _ => println!("Event: {:?}", event),
         }
     }
@@ -74,6 +85,10 @@ fn parse_args() -> Result<Vec<String>, Report> {
 }
 
 fn should_include_sensor(sensor: &SensorProps, filters: &Vec<String>) -> bool {
+    // Other sensors can only be used via their advertisements.
+    if !sensor.supports_gatt() {
+        return false;
+    }
     let mac = sensor.mac_address.to_string();
     filters.is_empty() || filters.iter().any(|filter| mac.contains(filter))
 }
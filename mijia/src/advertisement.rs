@@ -0,0 +1,261 @@
+//! Decoding of the readings which sensors broadcast in their advertisements, so that they can be
+//! read without connecting to them.
+
+use bluez_async::{AdvertisementDecoder, AdvertisementRecord, MacAddress};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use crate::decode::custom_firmware::decode_custom_firmware;
+use crate::decode::mibeacon::{BindKey, Measurement, MiBeacon};
+use crate::sensor_model::{CUSTOM_FIRMWARE_SERVICE_DATA_UUID, MIBEACON_SERVICE_DATA_UUID};
+use crate::Readings;
+
+/// The most recent measurements received from a sensor which sends them in separate
+/// advertisements, and the counters of the most recent frames in each format.
+#[derive(Clone, Debug, Default)]
+struct PartialReadings {
+    custom_firmware_counter: Option<u8>,
+    frame_counter: Option<u8>,
+    temperature: Option<f32>,
+    humidity: Option<f32>,
+    battery_percent: Option<u8>,
+}
+
+impl PartialReadings {
+    /// Update with the given MiBeacon frame, and return a complete set of readings if there is one
+    /// and it has changed.
+    fn update(&mut self, frame: &MiBeacon) -> Option<Readings> {
+        // Sensors repeat each frame several times, and BlueZ may report them all.
+        if self.frame_counter == Some(frame.frame_counter) || frame.measurements.is_empty() {
+            return None;
+        }
+        self.frame_counter = Some(frame.frame_counter);
+        for measurement in &frame.measurements {
+            match *measurement {
+                Measurement::Temperature(temperature) => self.temperature = Some(temperature),
+                Measurement::Humidity(humidity) => self.humidity = Some(humidity),
+                Measurement::Battery(battery_percent) => {
+                    self.battery_percent = Some(battery_percent)
+                }
+            }
+        }
+        Some(Readings {
+            temperature: self.temperature?,
+            humidity: self.humidity?.round() as u8,
+            battery_voltage: None,
+            battery_percent: self.battery_percent?.into(),
+            counter: None,
+            flags: None,
+        })
+    }
+
+    /// Check whether the given readings from custom firmware are new, as each frame is repeated
+    /// several times.
+    fn update_custom_firmware(&mut self, readings: Readings) -> Option<Readings> {
+        if readings.counter == self.custom_firmware_counter {
+            return None;
+        }
+        self.custom_firmware_counter = readings.counter;
+        Some(readings)
+    }
+}
+
+/// A decoder for the readings which Mijia sensors broadcast in their advertisements.
+///
+/// Both the MiBeacon format used by the stock firmware and the formats used by the atc1441 and
+/// pvvx custom firmware are supported.
+///
+/// With the stock firmware most sensors send temperature, humidity and battery level in separate
+/// advertisements, so readings are only returned once all three have been received from a sensor,
+/// and after that whenever any of them changes. Sensors which encrypt their advertisements need a
+/// bind key to be set with [`set_bind_key`](#method.set_bind_key).
+///
+/// This is used by [`MijiaSession::event_stream`](struct.MijiaSession.html#method.event_stream),
+/// but can also be used with `BluetoothSession::advertisement_stream`.
+#[derive(Debug, Default)]
+pub struct ReadingsDecoder {
+    bind_keys: Mutex<HashMap<MacAddress, BindKey>>,
+    readings: Mutex<HashMap<MacAddress, PartialReadings>>,
+}
+
+impl ReadingsDecoder {
+    /// Create a new decoder with no bind keys.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the key to use to decrypt advertisements from the sensor with the given MAC address.
+    pub fn set_bind_key(&self, mac_address: MacAddress, bind_key: BindKey) {
+        self.bind_keys.lock().unwrap().insert(mac_address, bind_key);
+    }
+
+    /// Forget the key for the sensor with the given MAC address.
+    pub fn remove_bind_key(&self, mac_address: &MacAddress) {
+        self.bind_keys.lock().unwrap().remove(mac_address);
+    }
+
+    /// Whether the given service data includes anything which this decoder might understand.
+    pub(crate) fn is_relevant(service_data: &HashMap<Uuid, Vec<u8>>) -> bool {
+        service_data.contains_key(&MIBEACON_SERVICE_DATA_UUID)
+            || service_data.contains_key(&CUSTOM_FIRMWARE_SERVICE_DATA_UUID)
+    }
+
+    /// Decode the service data from an advertisement by the sensor with the given MAC address,
+    /// returning a new set of readings if it completes one.
+    pub(crate) fn decode_service_data(
+        &self,
+        mac_address: &MacAddress,
+        service_data: &HashMap<Uuid, Vec<u8>>,
+    ) -> Option<Readings> {
+        let transmitted_mac_address = transmitted_order(mac_address);
+        if let Some(data) = service_data.get(&CUSTOM_FIRMWARE_SERVICE_DATA_UUID) {
+            match decode_custom_firmware(data, transmitted_mac_address) {
+                Ok(readings) => {
+                    return self
+                        .readings
+                        .lock()
+                        .unwrap()
+                        .entry(mac_address.to_owned())
+                        .or_default()
+                        .update_custom_firmware(readings);
+                }
+                // Other devices may use the same UUID for something else.
+                Err(e) => log::trace!("Not custom firmware data from {}: {}", mac_address, e),
+            }
+        }
+
+        let data = service_data.get(&MIBEACON_SERVICE_DATA_UUID)?;
+        let bind_key = self.bind_keys.lock().unwrap().get(mac_address).cloned();
+        let frame = MiBeacon::decode(data, transmitted_mac_address, bind_key.as_ref())
+            .map_err(|e| log::warn!("Error decoding MiBeacon from {}: {}", mac_address, e))
+            .ok()?;
+        self.readings
+            .lock()
+            .unwrap()
+            .entry(mac_address.to_owned())
+            .or_default()
+            .update(&frame)
+    }
+}
+
+impl AdvertisementDecoder for ReadingsDecoder {
+    type Output = Readings;
+
+    fn decode(&self, record: &AdvertisementRecord) -> Option<Readings> {
+        self.decode_service_data(&record.mac_address, &record.service_data)
+    }
+}
+
+/// Convert the given MAC address to the bytes in the order in which it is transmitted, i.e.
+/// least-significant byte first.
+fn transmitted_order(mac_address: &MacAddress) -> [u8; 6] {
+    let mut bytes = [0; 6];
+    // A `MacAddress` is always 6 pairs of hex digits separated by colons.
+    for (byte, octet) in bytes
+        .iter_mut()
+        .rev()
+        .zip(mac_address.to_string().split(':'))
+    {
+        *byte = u8::from_str_radix(octet, 16).unwrap();
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mac_address() -> MacAddress {
+        "A4:C1:38:01:02:03".parse().unwrap()
+    }
+
+    /// A plaintext MiBeacon frame with the given counter and object.
+    fn service_data(frame_counter: u8, object: &[u8]) -> HashMap<Uuid, Vec<u8>> {
+        let mut data = vec![0x40, 0x20, 0xaa, 0x01, frame_counter];
+        data.extend_from_slice(object);
+        let mut service_data = HashMap::new();
+        service_data.insert(MIBEACON_SERVICE_DATA_UUID, data);
+        service_data
+    }
+
+    #[test]
+    fn mac_address_order() {
+        assert_eq!(
+            transmitted_order(&mac_address()),
+            [0x03, 0x02, 0x01, 0x38, 0xc1, 0xa4]
+        );
+    }
+
+    #[test]
+    fn combine_measurements() {
+        let decoder = ReadingsDecoder::new();
+        let mac_address = mac_address();
+        // Temperature 22.0ºC and humidity 43.6%, but no battery level yet.
+        let temperature_humidity = service_data(1, &[0x0d, 0x10, 0x04, 0xdc, 0x00, 0xb4, 0x01]);
+        assert_eq!(
+            decoder.decode_service_data(&mac_address, &temperature_humidity),
+            None
+        );
+        // Battery 93%.
+        let battery = service_data(2, &[0x0a, 0x10, 0x01, 0x5d]);
+        assert_eq!(
+            decoder.decode_service_data(&mac_address, &battery),
+            Some(Readings {
+                temperature: 22.0,
+                humidity: 44,
+                battery_voltage: None,
+                battery_percent: 93,
+                counter: None,
+                flags: None,
+            })
+        );
+        // A repeat of the same frame is ignored.
+        assert_eq!(decoder.decode_service_data(&mac_address, &battery), None);
+        // Temperature -1.5ºC.
+        let temperature = service_data(3, &[0x04, 0x10, 0x02, 0xf1, 0xff]);
+        assert_eq!(
+            decoder
+                .decode_service_data(&mac_address, &temperature)
+                .unwrap()
+                .temperature,
+            -1.5
+        );
+    }
+
+    #[test]
+    fn custom_firmware() {
+        let decoder = ReadingsDecoder::new();
+        let mac_address = mac_address();
+        let mut service_data = HashMap::new();
+        service_data.insert(
+            CUSTOM_FIRMWARE_SERVICE_DATA_UUID,
+            vec![
+                0xa4, 0xc1, 0x38, 0x01, 0x02, 0x03, 0x00, 0xe6, 0x2d, 0x55, 0x0b, 0x7c, 0x12,
+            ],
+        );
+        assert!(ReadingsDecoder::is_relevant(&service_data));
+        let readings = decoder
+            .decode_service_data(&mac_address, &service_data)
+            .unwrap();
+        assert_eq!(readings.temperature, 23.0);
+        assert_eq!(readings.counter, Some(0x12));
+        // A repeat of the same frame is ignored.
+        assert_eq!(
+            decoder.decode_service_data(&mac_address, &service_data),
+            None
+        );
+    }
+
+    #[test]
+    fn other_service_data() {
+        let decoder = ReadingsDecoder::new();
+        let mut service_data = HashMap::new();
+        service_data.insert(Uuid::nil(), vec![1, 2, 3]);
+        assert!(!ReadingsDecoder::is_relevant(&service_data));
+        assert_eq!(
+            decoder.decode_service_data(&mac_address(), &service_data),
+            None
+        );
+    }
+}
@@ -0,0 +1,188 @@
+//! A persistent record of how much history has been fetched from each sensor.
+
+use bluez_async::MacAddress;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// The index of the next historical record to fetch from each sensor, keyed by MAC address and
+/// stored in a file, so that a process which runs periodically only needs to fetch new records.
+///
+/// The file has one line per sensor, with the MAC address and next index separated by a space.
+///
+/// ```no_run
+/// # use mijia::{HistoryCheckpoints, MijiaSession};
+/// # use futures::TryStreamExt;
+/// # async fn example(session: MijiaSession, sensor: mijia::SensorProps) -> Result<(), Box<dyn std::error::Error>> {
+/// let mut checkpoints = HistoryCheckpoints::load("checkpoints.txt")?;
+/// let start_index = checkpoints.get(&sensor.mac_address).unwrap_or(0);
+/// let records: Vec<_> = session
+///     .get_history_since(&sensor.id, start_index)
+///     .await?
+///     .try_collect()
+///     .await?;
+/// checkpoints.set_received(
+///     sensor.mac_address,
+///     start_index,
+///     records.iter().map(|record| record.index),
+/// );
+/// checkpoints.save()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HistoryCheckpoints {
+    path: PathBuf,
+    next_indices: HashMap<MacAddress, u32>,
+}
+
+impl HistoryCheckpoints {
+    /// Load checkpoints from the file at the given path. If the file doesn't exist yet then there
+    /// will be no checkpoints, and it will be created when they are saved.
+    pub fn load(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let next_indices = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| parse_line(line).ok_or_else(|| invalid_line(&path, line)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { path, next_indices })
+    }
+
+    /// Get the index of the next record to fetch from the sensor with the given MAC address, if
+    /// any have been fetched before.
+    pub fn get(&self, mac_address: &MacAddress) -> Option<u32> {
+        self.next_indices.get(mac_address).copied()
+    }
+
+    /// Set the index of the next record to fetch from the sensor with the given MAC address. This
+    /// isn't persisted until [`save`](#method.save) is called.
+    pub fn set(&mut self, mac_address: MacAddress, next_index: u32) {
+        self.next_indices.insert(mac_address, next_index);
+    }
+
+    /// Set the checkpoint for the sensor with the given MAC address after fetching the records with
+    /// the given indices from `start_index` onwards. The checkpoint only moves past records which
+    /// were all received, so any which were missed will be fetched again next time. Returns the new
+    /// next index.
+    ///
+    /// This isn't persisted until [`save`](#method.save) is called.
+    pub fn set_received(
+        &mut self,
+        mac_address: MacAddress,
+        start_index: u32,
+        indices: impl IntoIterator<Item = u32>,
+    ) -> u32 {
+        let received: HashSet<u32> = indices.into_iter().collect();
+        let mut next_index = start_index;
+        while received.contains(&next_index) {
+            next_index += 1;
+        }
+        self.set(mac_address, next_index);
+        next_index
+    }
+
+    /// Write the checkpoints back to the file they were loaded from.
+    ///
+    /// The new contents are written to a temporary file which is then renamed over the original,
+    /// so the checkpoints won't be lost if the process is interrupted.
+    pub fn save(&self) -> io::Result<()> {
+        let mut entries: Vec<_> = self.next_indices.iter().collect();
+        entries.sort();
+        let mut temporary_path = self.path.clone().into_os_string();
+        temporary_path.push(".tmp");
+        let mut file = File::create(&temporary_path)?;
+        for (mac_address, next_index) in entries {
+            writeln!(file, "{} {}", mac_address, next_index)?;
+        }
+        file.sync_all()?;
+        fs::rename(&temporary_path, &self.path)
+    }
+}
+
+fn parse_line(line: &str) -> Option<(MacAddress, u32)> {
+    let mut parts = line.split_whitespace();
+    let mac_address = parts.next()?.parse().ok()?;
+    let next_index = parts.next()?.parse().ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((mac_address, next_index))
+}
+
+fn invalid_line(path: &Path, line: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("Invalid checkpoint {:?} in {}", line, path.display()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+
+    fn temporary_path(name: &str) -> PathBuf {
+        temp_dir().join(format!("mijia-checkpoints-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn missing_file() {
+        let checkpoints = HistoryCheckpoints::load(temporary_path("missing")).unwrap();
+        assert_eq!(checkpoints.get(&"A4:C1:38:01:02:03".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = temporary_path("save");
+        let mac_address: MacAddress = "A4:C1:38:01:02:03".parse().unwrap();
+        let mut checkpoints = HistoryCheckpoints::load(&path).unwrap();
+        checkpoints.set(mac_address.clone(), 42);
+        checkpoints.set("A4:C1:38:04:05:06".parse().unwrap(), 7);
+        checkpoints.save().unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "A4:C1:38:01:02:03 42\nA4:C1:38:04:05:06 7\n"
+        );
+
+        let loaded = HistoryCheckpoints::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, checkpoints);
+        assert_eq!(loaded.get(&mac_address), Some(42));
+    }
+
+    #[test]
+    fn set_received_stops_at_gap() {
+        let mac_address: MacAddress = "A4:C1:38:01:02:03".parse().unwrap();
+        let mut checkpoints = HistoryCheckpoints::load(temporary_path("received")).unwrap();
+        assert_eq!(
+            checkpoints.set_received(mac_address.clone(), 5, vec![5, 8, 9, 6]),
+            7
+        );
+        assert_eq!(checkpoints.get(&mac_address), Some(7));
+        assert_eq!(
+            checkpoints.set_received(mac_address.clone(), 7, vec![9, 7, 8]),
+            10
+        );
+        assert_eq!(
+            checkpoints.set_received(mac_address.clone(), 10, vec![]),
+            10
+        );
+        assert_eq!(checkpoints.get(&mac_address), Some(10));
+    }
+
+    #[test]
+    fn invalid_file() {
+        let path = temporary_path("invalid");
+        fs::write(&path, "A4:C1:38:01:02:03 42\nnonsense\n").unwrap();
+        let result = HistoryCheckpoints::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::decode::readings::Readings;
+use crate::decode::{check_length, DecodeError};
+use std::convert::TryInto;
+
+/// The length of the atc1441 custom firmware advertisement format.
+const ATC_LENGTH: usize = 13;
+/// The length of the pvvx custom firmware advertisement format.
+const PVVX_LENGTH: usize = 15;
+
+/// Decode the readings from the service data which the atc1441 or pvvx custom firmware advertises
+/// for the Environmental Sensing service UUID (0x181A). `mac_address` is the address of the sensor
+/// in the order in which it is transmitted, i.e. least-significant byte first; the data is only
+/// accepted if it includes the same address, as other devices may use the same UUID.
+///
+/// The atc1441 format is 13 bytes, all big-endian:
+/// - MAC address (6 bytes)
+/// - temperature in 0.1 ºC (i16)
+/// - humidity in % (u8)
+/// - battery level in % (u8)
+/// - battery voltage in mV (u16)
+/// - frame counter (u8)
+///
+/// The pvvx format is 15 bytes, all little-endian:
+/// - MAC address (6 bytes)
+/// - temperature in 0.01 ºC (i16)
+/// - humidity in 0.01 % (u16)
+/// - battery voltage in mV (u16)
+/// - battery level in % (u8)
+/// - frame counter (u8)
+/// - flags (u8)
+pub(crate) fn decode_custom_firmware(
+    data: &[u8],
+    mac_address: [u8; 6],
+) -> Result<Readings, DecodeError> {
+    match data.len() {
+        ATC_LENGTH => {
+            let mut reversed_mac_address = mac_address;
+            reversed_mac_address.reverse();
+            check_mac_address(&data[0..6], reversed_mac_address)?;
+            Ok(Readings {
+                temperature: i16::from_be_bytes(data[6..8].try_into().unwrap()) as f32 / 10.0,
+                humidity: data[8],
+                battery_voltage: Some(u16::from_be_bytes(data[10..12].try_into().unwrap())),
+                battery_percent: data[9].into(),
+                counter: Some(data[12]),
+                flags: None,
+            })
+        }
+        _ => {
+            check_length(data.len(), PVVX_LENGTH)?;
+            check_mac_address(&data[0..6], mac_address)?;
+            let humidity = u16::from_le_bytes(data[8..10].try_into().unwrap()) as f32 / 100.0;
+            Ok(Readings {
+                temperature: i16::from_le_bytes(data[6..8].try_into().unwrap()) as f32 / 100.0,
+                humidity: humidity.round() as u8,
+                battery_voltage: Some(u16::from_le_bytes(data[10..12].try_into().unwrap())),
+                battery_percent: data[12].into(),
+                counter: Some(data[13]),
+                flags: Some(data[14]),
+            })
+        }
+    }
+}
+
+fn check_mac_address(actual: &[u8], expected: [u8; 6]) -> Result<(), DecodeError> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(DecodeError::InvalidValue(format!(
+            "Advertisement is for MAC address {:02X?}, not {:02X?}",
+            actual, expected
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC_ADDRESS: [u8; 6] = [0x03, 0x02, 0x01, 0x38, 0xc1, 0xa4];
+
+    #[test]
+    fn decode_atc() {
+        let data = [
+            0xa4, 0xc1, 0x38, 0x01, 0x02, 0x03, 0x00, 0xe6, 0x2d, 0x55, 0x0b, 0x7c, 0x12,
+        ];
+        assert_eq!(
+            decode_custom_firmware(&data, MAC_ADDRESS),
+            Ok(Readings {
+                temperature: 23.0,
+                humidity: 45,
+                battery_voltage: Some(2940),
+                battery_percent: 85,
+                counter: Some(0x12),
+                flags: None,
+            })
+        );
+    }
+
+    #[test]
+    fn decode_pvvx() {
+        let data = [
+            0x03, 0x02, 0x01, 0x38, 0xc1, 0xa4, 0x26, 0xf8, 0xb6, 0x11, 0x7c, 0x0b, 0x55, 0x34,
+            0x05,
+        ];
+        assert_eq!(
+            decode_custom_firmware(&data, MAC_ADDRESS),
+            Ok(Readings {
+                temperature: -20.1,
+                humidity: 45,
+                battery_voltage: Some(2940),
+                battery_percent: 85,
+                counter: Some(0x34),
+                flags: Some(0x05),
+            })
+        );
+    }
+
+    #[test]
+    fn decode_wrong_mac_address() {
+        let data = [
+            0xa4, 0xc1, 0x38, 0x01, 0x02, 0x04, 0x00, 0xe6, 0x2d, 0x55, 0x0b, 0x7c, 0x12,
+        ];
+        assert!(decode_custom_firmware(&data, MAC_ADDRESS).is_err());
+    }
+
+    #[test]
+    fn decode_wrong_length() {
+        assert_eq!(
+            decode_custom_firmware(&[1, 2, 3], MAC_ADDRESS),
+            Err(DecodeError::WrongLength {
+                length: 3,
+                expected_length: PVVX_LENGTH
+            })
+        );
+    }
+}
@@ -0,0 +1,366 @@
+use crate::decode::DecodeError;
+use aes::Aes128;
+use ccm::aead::{Aead, KeyInit, Payload};
+use ccm::consts::{U12, U4};
+use ccm::Ccm;
+use std::convert::TryInto;
+use std::fmt::{self, Debug, Formatter};
+use std::str::FromStr;
+
+/// AES-CCM with the 4 byte tag and 12 byte nonce used by MiBeacon v4 and v5.
+type MiBeaconCcm = Ccm<Aes128, U4, U12>;
+
+/// Additional authenticated data used for MiBeacon v4 and v5 encryption.
+const ASSOCIATED_DATA: [u8; 1] = [0x11];
+
+const FRAME_CONTROL_ENCRYPTED: u16 = 0x0008;
+const FRAME_CONTROL_MAC_INCLUDED: u16 = 0x0010;
+const FRAME_CONTROL_CAPABILITY_INCLUDED: u16 = 0x0020;
+const FRAME_CONTROL_OBJECT_INCLUDED: u16 = 0x0040;
+const CAPABILITY_IO: u8 = 0x20;
+
+/// The length of the extended frame counter and message integrity check at the end of an
+/// encrypted frame.
+const EXTENDED_COUNTER_LENGTH: usize = 3;
+const MIC_LENGTH: usize = 4;
+
+/// A 128-bit key used to decrypt the advertisements of a sensor which encrypts them.
+///
+/// The key is generated when the sensor is paired with the Mi Home app, and can be parsed from a
+/// string of 32 hex digits.
+#[derive(Clone, Eq, PartialEq)]
+pub struct BindKey([u8; 16]);
+
+impl BindKey {
+    /// Create a bind key from its raw bytes.
+    pub fn new(key: [u8; 16]) -> Self {
+        Self(key)
+    }
+}
+
+impl Debug for BindKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        // Don't leak keys into logs.
+        write!(f, "BindKey(..)")
+    }
+}
+
+impl FromStr for BindKey {
+    type Err = DecodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DecodeError::InvalidValue(format!("Invalid bind key {:?}", s));
+        if s.len() != 32 || !s.is_ascii() {
+            return Err(invalid());
+        }
+        let mut key = [0; 16];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(Self(key))
+    }
+}
+
+/// A measurement from a MiBeacon object.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Measurement {
+    /// Temperature in ºC.
+    Temperature(f32),
+    /// Percent humidity.
+    Humidity(f32),
+    /// Battery level in percent.
+    Battery(u8),
+}
+
+/// A MiBeacon frame, as advertised in the service data for UUID 0xFE95.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct MiBeacon {
+    pub product_id: u16,
+    pub frame_counter: u8,
+    /// The measurements in the frame, which may be empty if it doesn't include any objects or it
+    /// is encrypted and no bind key was given.
+    pub measurements: Vec<Measurement>,
+}
+
+impl MiBeacon {
+    /// Decode the given MiBeacon service data, decrypting it with the given bind key if it is
+    /// encrypted. `mac_address` is the address of the sensor in the order in which it is
+    /// transmitted (i.e. reversed from the usual string form), which is needed for decryption if
+    /// the frame doesn't include it.
+    pub(crate) fn decode(
+        data: &[u8],
+        mac_address: [u8; 6],
+        bind_key: Option<&BindKey>,
+    ) -> Result<MiBeacon, DecodeError> {
+        if data.len() < 5 {
+            return Err(DecodeError::InvalidValue(format!(
+                "MiBeacon frame too short: {:?}",
+                data
+            )));
+        }
+        let frame_control = u16::from_le_bytes(data[0..2].try_into().unwrap());
+        let version = frame_control >> 12;
+        let product_id = u16::from_le_bytes(data[2..4].try_into().unwrap());
+        let frame_counter = data[4];
+
+        let mut mac_address = mac_address;
+        let mut offset = 5;
+        if frame_control & FRAME_CONTROL_MAC_INCLUDED != 0 {
+            mac_address = data
+                .get(offset..offset + 6)
+                .ok_or_else(|| truncated(data))?
+                .try_into()
+                .unwrap();
+            offset += 6;
+        }
+        if frame_control & FRAME_CONTROL_CAPABILITY_INCLUDED != 0 {
+            let capability = *data.get(offset).ok_or_else(|| truncated(data))?;
+            offset += 1;
+            if capability & CAPABILITY_IO != 0 {
+                offset += 2;
+            }
+        }
+
+        let mut measurements = vec![];
+        if frame_control & FRAME_CONTROL_OBJECT_INCLUDED != 0 {
+            let payload = data.get(offset..).ok_or_else(|| truncated(data))?;
+            if frame_control & FRAME_CONTROL_ENCRYPTED == 0 {
+                measurements = decode_objects(payload)?;
+            } else if version < 4 {
+                return Err(DecodeError::InvalidValue(format!(
+                    "Unsupported MiBeacon encryption version {}",
+                    version
+                )));
+            } else if let Some(bind_key) = bind_key {
+                let plaintext = decrypt(&data[2..5], mac_address, payload, bind_key)?;
+                measurements = decode_objects(&plaintext)?;
+            }
+        }
+
+        Ok(MiBeacon {
+            product_id,
+            frame_counter,
+            measurements,
+        })
+    }
+}
+
+fn truncated(data: &[u8]) -> DecodeError {
+    DecodeError::InvalidValue(format!("MiBeacon frame truncated: {:?}", data))
+}
+
+/// Decrypt the payload of a MiBeacon v4 or v5 frame. `header` is the product ID and frame
+/// counter.
+fn decrypt(
+    header: &[u8],
+    mac_address: [u8; 6],
+    payload: &[u8],
+    bind_key: &BindKey,
+) -> Result<Vec<u8>, DecodeError> {
+    if payload.len() < EXTENDED_COUNTER_LENGTH + MIC_LENGTH {
+        return Err(DecodeError::InvalidValue(format!(
+            "Encrypted MiBeacon payload too short: {:?}",
+            payload
+        )));
+    }
+    let ciphertext_length = payload.len() - EXTENDED_COUNTER_LENGTH - MIC_LENGTH;
+    let ciphertext = &payload[..ciphertext_length];
+    let extended_counter = &payload[ciphertext_length..ciphertext_length + EXTENDED_COUNTER_LENGTH];
+    let mic = &payload[ciphertext_length + EXTENDED_COUNTER_LENGTH..];
+
+    let mut nonce = Vec::with_capacity(12);
+    nonce.extend_from_slice(&mac_address);
+    nonce.extend_from_slice(header);
+    nonce.extend_from_slice(extended_counter);
+    let mut message = ciphertext.to_vec();
+    message.extend_from_slice(mic);
+
+    MiBeaconCcm::new(&bind_key.0.into())
+        .decrypt(
+            nonce.as_slice().into(),
+            Payload {
+                msg: &message,
+                aad: &ASSOCIATED_DATA,
+            },
+        )
+        .map_err(|_| DecodeError::InvalidValue("Failed to decrypt MiBeacon payload".to_owned()))
+}
+
+/// Decode the objects in a (decrypted) MiBeacon payload, ignoring any types which we don't
+/// understand.
+fn decode_objects(payload: &[u8]) -> Result<Vec<Measurement>, DecodeError> {
+    let mut measurements = vec![];
+    let mut rest = payload;
+    while !rest.is_empty() {
+        if rest.len() < 3 {
+            return Err(truncated(payload));
+        }
+        let object_type = u16::from_le_bytes(rest[0..2].try_into().unwrap());
+        let length = rest[2] as usize;
+        let value = rest.get(3..3 + length).ok_or_else(|| truncated(payload))?;
+        rest = &rest[3 + length..];
+
+        match (object_type, value.len()) {
+            (0x1004, 2) => measurements.push(Measurement::Temperature(
+                i16::from_le_bytes(value.try_into().unwrap()) as f32 / 10.0,
+            )),
+            (0x1006, 2) => measurements.push(Measurement::Humidity(
+                u16::from_le_bytes(value.try_into().unwrap()) as f32 / 10.0,
+            )),
+            (0x100a, 1) | (0x4803, 1) => measurements.push(Measurement::Battery(value[0])),
+            (0x100d, 4) => {
+                measurements.push(Measurement::Temperature(
+                    i16::from_le_bytes(value[0..2].try_into().unwrap()) as f32 / 10.0,
+                ));
+                measurements.push(Measurement::Humidity(
+                    u16::from_le_bytes(value[2..4].try_into().unwrap()) as f32 / 10.0,
+                ));
+            }
+            (0x4c01, 4) => measurements.push(Measurement::Temperature(f32::from_le_bytes(
+                value.try_into().unwrap(),
+            ))),
+            (0x4c02, 1) => measurements.push(Measurement::Humidity(value[0] as f32)),
+            (0x4c08, 4) => measurements.push(Measurement::Humidity(f32::from_le_bytes(
+                value.try_into().unwrap(),
+            ))),
+            _ => log::trace!("Ignoring MiBeacon object {:#06x} {:?}", object_type, value),
+        }
+    }
+    Ok(measurements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC_ADDRESS: [u8; 6] = [0x66, 0x55, 0x44, 0x33, 0x22, 0x11];
+    const KEY: [u8; 16] = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee,
+        0xff,
+    ];
+
+    /// Encrypt the given objects into a v5 frame, as a sensor would.
+    fn encrypted_frame(objects: &[u8]) -> Vec<u8> {
+        let header = [0x48, 0x58, 0x5b, 0x05, 0x42];
+        let extended_counter = [0x01, 0x00, 0x00];
+        let mut nonce = MAC_ADDRESS.to_vec();
+        nonce.extend_from_slice(&header[2..5]);
+        nonce.extend_from_slice(&extended_counter);
+        let encrypted = MiBeaconCcm::new(&KEY.into())
+            .encrypt(
+                nonce.as_slice().into(),
+                Payload {
+                    msg: objects,
+                    aad: &ASSOCIATED_DATA,
+                },
+            )
+            .unwrap();
+        let (ciphertext, mic) = encrypted.split_at(objects.len());
+
+        let mut frame = header.to_vec();
+        frame.extend_from_slice(ciphertext);
+        frame.extend_from_slice(&extended_counter);
+        frame.extend_from_slice(mic);
+        frame
+    }
+
+    #[test]
+    fn parse_bind_key() {
+        assert_eq!(
+            "00112233445566778899AABBccddeeff".parse::<BindKey>(),
+            Ok(BindKey::new(KEY))
+        );
+        assert!("0011".parse::<BindKey>().is_err());
+        assert!("0011223344556677889900aabbccddeg"
+            .parse::<BindKey>()
+            .is_err());
+    }
+
+    #[test]
+    fn decode_plaintext() {
+        // Version 2, with MAC address, temperature 22.0ºC and humidity 43.6%.
+        let data = [
+            0x50, 0x20, 0xaa, 0x01, 0x07, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x0d, 0x10, 0x04,
+            0xdc, 0x00, 0xb4, 0x01,
+        ];
+        assert_eq!(
+            MiBeacon::decode(&data, [0; 6], None),
+            Ok(MiBeacon {
+                product_id: 0x01aa,
+                frame_counter: 7,
+                measurements: vec![Measurement::Temperature(22.0), Measurement::Humidity(43.6)],
+            })
+        );
+    }
+
+    #[test]
+    fn decode_without_objects() {
+        let data = [
+            0x30, 0x58, 0x5b, 0x05, 0x01, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x08,
+        ];
+        assert_eq!(
+            MiBeacon::decode(&data, [0; 6], None),
+            Ok(MiBeacon {
+                product_id: 0x055b,
+                frame_counter: 1,
+                measurements: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn decode_truncated() {
+        assert!(MiBeacon::decode(&[0x50, 0x20, 0xaa], [0; 6], None).is_err());
+        assert!(MiBeacon::decode(
+            &[0x50, 0x20, 0xaa, 0x01, 0x07, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x0d, 0x10, 0x04],
+            [0; 6],
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn decode_encrypted() {
+        // Battery 93%.
+        let data = encrypted_frame(&[0x0a, 0x10, 0x01, 0x5d]);
+        assert_eq!(
+            MiBeacon::decode(&data, MAC_ADDRESS, Some(&BindKey::new(KEY))),
+            Ok(MiBeacon {
+                product_id: 0x055b,
+                frame_counter: 0x42,
+                measurements: vec![Measurement::Battery(93)],
+            })
+        );
+
+        // Without the key the measurements can't be read.
+        assert_eq!(
+            MiBeacon::decode(&data, MAC_ADDRESS, None)
+                .unwrap()
+                .measurements,
+            vec![]
+        );
+
+        // With the wrong key or MAC address decryption fails.
+        assert!(MiBeacon::decode(&data, MAC_ADDRESS, Some(&BindKey::new([0; 16]))).is_err());
+        assert!(MiBeacon::decode(&data, [0; 6], Some(&BindKey::new(KEY))).is_err());
+    }
+
+    #[test]
+    fn decode_encrypted_capture() {
+        // A frame captured from a real LYWSD03MMC, from the tests of the xiaomi-ble project used by
+        // Home Assistant. It includes the MAC address A4:C1:38:02:83:F4, and humidity 46.7%.
+        let data = [
+            0x58, 0x58, 0x5b, 0x05, 0x50, 0xf4, 0x83, 0x02, 0x38, 0xc1, 0xa4, 0x95, 0xef, 0x58,
+            0x76, 0x3c, 0x26, 0x00, 0x00, 0x97, 0xe2, 0xab, 0xb5,
+        ];
+        let bind_key = "e9ea895fac7cca6d30532432a516f3a8".parse().unwrap();
+        assert_eq!(
+            MiBeacon::decode(&data, [0; 6], Some(&bind_key)),
+            Ok(MiBeacon {
+                product_id: 0x055b,
+                frame_counter: 0x50,
+                measurements: vec![Measurement::Humidity(46.7)],
+            })
+        );
+    }
+}
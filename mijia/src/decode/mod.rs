@@ -1,5 +1,7 @@
 pub mod comfort_level;
+pub mod custom_firmware;
 pub mod history;
+pub mod mibeacon;
 pub mod readings;
 pub mod temperature_unit;
 pub mod time;
@@ -10,19 +10,31 @@ pub struct Readings {
     pub temperature: f32,
     /// Percent humidity
     pub humidity: u8,
-    /// Voltage in millivolts
-    pub battery_voltage: u16,
-    /// Inferred from `battery_voltage` with a bit of hand-waving.
+    /// Voltage in millivolts, if the sensor reported it. Readings from advertisements may only
+    /// include `battery_percent`.
+    pub battery_voltage: Option<u16>,
+    /// Inferred from `battery_voltage` with a bit of hand-waving, or as reported by the sensor.
     pub battery_percent: u16,
+    /// The frame counter from the advertisement, if the readings came from the atc1441 or pvvx
+    /// custom firmware. This is incremented for each new set of readings.
+    pub counter: Option<u8>,
+    /// The flags from the advertisement, if the readings came from the pvvx custom firmware. These
+    /// report the state of the reed switch input and trigger outputs, if they are configured.
+    pub flags: Option<u8>,
 }
 
 impl Display for Readings {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(
             f,
-            "Temperature: {:.2}ºC Humidity: {:?}% Battery: {:?} mV ({:?}%)",
-            self.temperature, self.humidity, self.battery_voltage, self.battery_percent
-        )
+            "Temperature: {:.2}ºC Humidity: {:?}% Battery: ",
+            self.temperature, self.humidity
+        )?;
+        if let Some(battery_voltage) = self.battery_voltage {
+            write!(f, "{:?} mV ({:?}%)", battery_voltage, self.battery_percent)
+        } else {
+            write!(f, "{:?}%", self.battery_percent)
+        }
     }
 }
 
@@ -42,8 +54,10 @@ impl Readings {
         Ok(Readings {
             temperature,
             humidity,
-            battery_voltage,
+            battery_voltage: Some(battery_voltage),
             battery_percent,
+            counter: None,
+            flags: None,
         })
     }
 }
@@ -92,9 +106,25 @@ mod tests {
             Ok(Readings {
                 temperature: 5.13,
                 humidity: 3,
-                battery_voltage: 2564,
-                battery_percent: 46
+                battery_voltage: Some(2564),
+                battery_percent: 46,
+                counter: None,
+                flags: None,
             })
         );
     }
+
+    #[test]
+    fn display() {
+        let mut readings = Readings::decode(&[1, 2, 3, 4, 10]).unwrap();
+        assert_eq!(
+            readings.to_string(),
+            "Temperature: 5.13ºC Humidity: 3% Battery: 2564 mV (46%)"
+        );
+        readings.battery_voltage = None;
+        assert_eq!(
+            readings.to_string(),
+            "Temperature: 5.13ºC Humidity: 3% Battery: 46%"
+        );
+    }
 }
@@ -0,0 +1,352 @@
+//! Fetching a range of historical records from a sensor as a stream.
+
+use bluez_async::{
+    BluetoothEvent, BluetoothSession, CharacteristicEvent, CharacteristicId, DeviceId,
+};
+use futures::stream::{self, Stream};
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::pin::Pin;
+use tokio_stream::StreamExt;
+
+use crate::{
+    HistoryRecord, MijiaError, HISTORY_INDEX_CHARACTERISTIC_UUID,
+    HISTORY_RECORDS_CHARACTERISTIC_UUID, HISTORY_RECORD_TIMEOUT, SERVICE_UUID,
+};
+
+/// A stream of historical records, as returned by `MijiaSession::get_history_since`.
+pub(crate) type HistoryStream =
+    Pin<Box<dyn Stream<Item = Result<HistoryRecord, MijiaError>> + Send>>;
+
+/// Options for downloading history with
+/// [`MijiaSession::get_history_stream`](struct.MijiaSession.html#method.get_history_stream).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HistoryOptions {
+    /// The index of the first record to fetch. Records before the start of the sensor's history
+    /// range are not available, so if this is before it then records are fetched from the start of
+    /// the range.
+    pub start_index: u32,
+    /// How many more times to request any records which are still missing once the previous
+    /// requests have finished.
+    pub max_retries: u32,
+}
+
+impl Default for HistoryOptions {
+    fn default() -> Self {
+        Self {
+            start_index: 0,
+            max_retries: 3,
+        }
+    }
+}
+
+/// A historical record received from a sensor, along with the progress of the download.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryUpdate {
+    /// The record which was received.
+    pub record: HistoryRecord,
+    /// The number of distinct records received so far, including this one.
+    pub received: u32,
+    /// The total number of records being downloaded.
+    pub expected: u32,
+}
+
+/// A stream of history updates, as returned by `MijiaSession::get_history_stream`.
+pub(crate) type HistoryUpdateStream =
+    Pin<Box<dyn Stream<Item = Result<HistoryUpdate, MijiaError>> + Send>>;
+
+/// The state needed to receive the next historical record.
+struct HistoryState {
+    session: BluetoothSession,
+    id: DeviceId,
+    range: Range<u32>,
+    /// Notification values, or `None` if the timeout elapsed before the next one.
+    values: Pin<Box<dyn Stream<Item = Option<Vec<u8>>> + Send>>,
+    /// The characteristic to stop notifications on once finished, if they were started via D-Bus
+    /// rather than acquired.
+    notifying: Option<CharacteristicId>,
+}
+
+impl HistoryState {
+    /// Wait for the next record within the range. Returns `None` if no record arrives before the
+    /// timeout, or if the sensor sends a record after the end of the range, as it sends the rest of
+    /// its history after the requested records.
+    async fn next_record(&mut self) -> Option<Result<HistoryRecord, MijiaError>> {
+        while let Some(Some(value)) = self.values.next().await {
+            let record = match HistoryRecord::decode(&value) {
+                Ok(record) => record,
+                Err(e) => return Some(Err(e.into())),
+            };
+            log::trace!("{}: {}", self.id, record);
+            if self.range.contains(&record.index) {
+                return Some(Ok(record));
+            }
+            // The sensor sends records in order from the requested index, so one after the end of
+            // the range means it has moved past any in the range which were missed, even if none
+            // in the range arrived. Records before the start may be left over from an earlier
+            // request.
+            if record.index >= self.range.end {
+                log::debug!(
+                    "{}: got record {} after the end of {:?}, so the rest were missed",
+                    self.id,
+                    record.index,
+                    self.range
+                );
+                return None;
+            }
+            log::debug!(
+                "{}: ignoring record {} outside {:?}",
+                self.id,
+                record.index,
+                self.range
+            );
+        }
+        None
+    }
+
+    /// Stop notifications, if necessary.
+    async fn finish(mut self) {
+        if let Some(characteristic) = self.notifying.take() {
+            if let Err(e) = self.session.stop_notify(&characteristic).await {
+                log::warn!(
+                    "Failed to stop history notifications for {}: {}",
+                    self.id,
+                    e
+                );
+            }
+        }
+    }
+}
+
+impl Drop for HistoryState {
+    /// Stop notifications if the stream is dropped before it finishes. Acquired notifications stop
+    /// by themselves when the file descriptor is closed.
+    fn drop(&mut self) {
+        if let Some(characteristic) = self.notifying.take() {
+            let session = self.session.clone();
+            let id = self.id.clone();
+            tokio::spawn(async move {
+                if let Err(e) = session.stop_notify(&characteristic).await {
+                    log::warn!("Failed to stop history notifications for {}: {}", id, e);
+                }
+            });
+        }
+    }
+}
+
+/// Request the historical records in the given range from the sensor, and return a stream of them
+/// which completes as soon as the last record in the range is received, or if no record is received
+/// for `HISTORY_RECORD_TIMEOUT`.
+///
+/// Records are received over a notification file descriptor acquired from BlueZ if possible,
+/// falling back to D-Bus signals otherwise.
+pub(crate) async fn history_records(
+    session: &BluetoothSession,
+    id: &DeviceId,
+    range: Range<u32>,
+) -> Result<HistoryStream, MijiaError> {
+    if range.is_empty() {
+        return Ok(Box::pin(stream::empty()));
+    }
+
+    let history_record_characteristic = session
+        .get_service_characteristic_by_uuid(id, SERVICE_UUID, HISTORY_RECORDS_CHARACTERISTIC_UUID)
+        .await?;
+    let history_index_characteristic = session
+        .get_service_characteristic_by_uuid(id, SERVICE_UUID, HISTORY_INDEX_CHARACTERISTIC_UUID)
+        .await?;
+    let (values, notifying): (Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>, _) = match session
+        .acquire_notify(&history_record_characteristic.id)
+        .await
+    {
+        Ok(notifications) => (Box::pin(notifications), None),
+        Err(e) => {
+            log::warn!(
+                "Failed to acquire history notifications for {}, falling back to D-Bus: {}",
+                id,
+                e
+            );
+            let characteristic = history_record_characteristic.id;
+            let events = session.characteristic_event_stream(&characteristic).await?;
+            session.start_notify(&characteristic).await?;
+            let expected_characteristic = characteristic.clone();
+            let values = events.filter_map(move |event| {
+                if let BluetoothEvent::Characteristic {
+                    id: record_id,
+                    event: CharacteristicEvent::Value { value },
+                } = event
+                {
+                    if record_id == expected_characteristic {
+                        return Some(value);
+                    }
+                    log::warn!("Got record for wrong characteristic {:?}", record_id);
+                } else {
+                    log::warn!("Unexpected event: {:?}", event);
+                }
+                None
+            });
+            (Box::pin(values), Some(characteristic))
+        }
+    };
+
+    // Only request records once subscribed, so that none are missed.
+    session
+        .write_characteristic_value(&history_index_characteristic.id, range.start.to_le_bytes())
+        .await?;
+
+    let state = HistoryState {
+        session: session.clone(),
+        id: id.to_owned(),
+        range,
+        values: Box::pin(values.timeout(HISTORY_RECORD_TIMEOUT).map(Result::ok)),
+        notifying,
+    };
+    Ok(Box::pin(stream::unfold(Some(state), |state| async move {
+        let mut state = state?;
+        match state.next_record().await {
+            Some(Ok(record)) if record.index + 1 >= state.range.end => {
+                state.finish().await;
+                Some((Ok(record), None))
+            }
+            Some(result) => Some((result, Some(state))),
+            None => {
+                state.finish().await;
+                None
+            }
+        }
+    })))
+}
+
+/// The state of a download of a range of history, which may be made up of several requests.
+struct HistoryDownload {
+    session: BluetoothSession,
+    id: DeviceId,
+    range: Range<u32>,
+    /// Which records in the range have been received so far.
+    received: Vec<bool>,
+    received_count: u32,
+    retries_remaining: u32,
+    /// The records for the request in progress, if any.
+    current: Option<HistoryStream>,
+    /// Ranges still to be requested in the current pass.
+    pending: VecDeque<Range<u32>>,
+}
+
+impl HistoryDownload {
+    /// Wait for the next new record, making further requests as needed. Returns `None` once all
+    /// records have been received or the retries have been used up.
+    async fn next_update(&mut self) -> Option<Result<HistoryUpdate, MijiaError>> {
+        loop {
+            if let Some(current) = &mut self.current {
+                match current.next().await {
+                    Some(Ok(record)) => {
+                        let offset = (record.index - self.range.start) as usize;
+                        // Records may be sent again if a request overlaps what was received.
+                        if !self.received[offset] {
+                            self.received[offset] = true;
+                            self.received_count += 1;
+                            return Some(Ok(HistoryUpdate {
+                                record,
+                                received: self.received_count,
+                                expected: self.range.len() as u32,
+                            }));
+                        }
+                    }
+                    Some(Err(e)) => return Some(Err(e)),
+                    None => self.current = None,
+                }
+            } else if let Some(range) = self.pending.pop_front() {
+                match history_records(&self.session, &self.id, range).await {
+                    Ok(records) => self.current = Some(records),
+                    Err(e) => return Some(Err(e)),
+                }
+            } else {
+                let missing = missing_ranges(&self.received, self.range.start);
+                if missing.is_empty() || self.retries_remaining == 0 {
+                    return None;
+                }
+                self.retries_remaining -= 1;
+                log::info!(
+                    "{}: {} of {} history records missing, requesting {:?} again",
+                    self.id,
+                    self.range.len() as u32 - self.received_count,
+                    self.range.len(),
+                    missing
+                );
+                self.pending = missing.into();
+            }
+        }
+    }
+}
+
+/// Download the historical records in the given range from the sensor, returning a stream of the
+/// records along with the progress so far. Once all the requests for a pass have finished, any
+/// records which are still missing are requested again, up to `max_retries` times.
+///
+/// The stream ends after an error starting a request, as that probably means the sensor has
+/// disconnected.
+pub(crate) fn history_updates(
+    session: &BluetoothSession,
+    id: &DeviceId,
+    range: Range<u32>,
+    max_retries: u32,
+) -> HistoryUpdateStream {
+    let download = HistoryDownload {
+        session: session.clone(),
+        id: id.to_owned(),
+        received: vec![false; range.len()],
+        received_count: 0,
+        retries_remaining: max_retries,
+        current: None,
+        pending: if range.is_empty() {
+            VecDeque::new()
+        } else {
+            vec![range.clone()].into()
+        },
+        range,
+    };
+    Box::pin(stream::unfold(Some(download), |download| async move {
+        let mut download = download?;
+        match download.next_update().await? {
+            Ok(update) => Some((Ok(update), Some(download))),
+            Err(e) if download.current.is_some() => Some((Err(e), Some(download))),
+            Err(e) => Some((Err(e), None)),
+        }
+    }))
+}
+
+/// Find the contiguous ranges of records which haven't been received, given which of the records
+/// from `start` onwards have been.
+fn missing_ranges(received: &[bool], start: u32) -> Vec<Range<u32>> {
+    let mut ranges: Vec<Range<u32>> = Vec::new();
+    for (offset, _) in received
+        .iter()
+        .enumerate()
+        .filter(|(_, received)| !**received)
+    {
+        let index = start + offset as u32;
+        match ranges.last_mut() {
+            Some(range) if range.end == index => range.end += 1,
+            _ => ranges.push(index..index + 1),
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_ranges_none() {
+        assert_eq!(missing_ranges(&[true, true, true], 5), vec![]);
+    }
+
+    #[test]
+    fn missing_ranges_gaps() {
+        assert_eq!(
+            missing_ranges(&[false, true, false, false, true, false], 10),
+            vec![10..11, 12..14, 15..16]
+        );
+    }
+}
@@ -1,37 +1,49 @@
-//! A library for connecting to Xiaomi Mijia 2 Bluetooth temperature/humidity sensors.
+//! A library for connecting to Xiaomi Mijia Bluetooth temperature/humidity sensors, such as the
+//! LYWSD03MMC and MHO-C401. See [`SensorModel`] for the models which are recognised.
 //!
 //! Currently only supports running on Linux, as it depends on BlueZ for Bluetooth.
 //!
 //! Start by creating a [`MijiaSession`].
 //!
 //! [`MijiaSession']: struct.MijiaSession.html
+//! [`SensorModel`]: enum.SensorModel.html
 
 pub use bluez_async as bluetooth;
 use bluez_async::{
     BluetoothError, BluetoothEvent, BluetoothSession, CharacteristicEvent, DeviceEvent, DeviceId,
-    DeviceInfo, MacAddress, SpawnError,
+    DeviceInfo, DiscoveryFilter, MacAddress, RecordedEvent, SpawnError, Transport,
 };
 use core::future::Future;
 use futures::Stream;
 use std::ops::Range;
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 use thiserror::Error;
 use tokio::pin;
 use tokio_stream::StreamExt;
 use uuid::Uuid;
 
+mod advertisement;
+mod checkpoint;
 mod decode;
+mod history_sync;
+mod sensor_model;
 mod signed_duration;
+pub use advertisement::ReadingsDecoder;
+pub use checkpoint::HistoryCheckpoints;
 pub use decode::comfort_level::ComfortLevel;
 use decode::history::decode_range;
 pub use decode::history::HistoryRecord;
+pub use decode::mibeacon::BindKey;
 pub use decode::readings::Readings;
 pub use decode::temperature_unit::TemperatureUnit;
 use decode::time::{decode_time, encode_time};
 pub use decode::{DecodeError, EncodeError};
+use history_sync::{history_records, history_updates};
+pub use history_sync::{HistoryOptions, HistoryUpdate};
+pub use sensor_model::{Firmware, SensorModel};
 pub use signed_duration::SignedDuration;
 
-const MIJIA_NAME: &str = "LYWSD03MMC";
 const SERVICE_UUID: Uuid = Uuid::from_u128(0xebe0ccb0_7a0a_4b0c_8a1a_6ff2997da3a6);
 const CLOCK_CHARACTERISTIC_UUID: Uuid = Uuid::from_u128(0xebe0ccb7_7a0a_4b0c_8a1a_6ff2997da3a6);
 const HISTORY_RANGE_CHARACTERISTIC_UUID: Uuid =
@@ -71,7 +83,7 @@ pub enum MijiaError {
     Encoding(#[from] EncodeError),
 }
 
-/// The MAC address and opaque connection ID of a Mijia sensor which was discovered.
+/// The MAC address, opaque connection ID and model of a Mijia sensor which was discovered.
 #[derive(Clone, Debug)]
 pub struct SensorProps {
     /// An opaque identifier for the sensor, including a reference to which Bluetooth adapter it was
@@ -79,6 +91,19 @@ pub struct SensorProps {
     pub id: DeviceId,
     /// The MAC address of the sensor.
     pub mac_address: MacAddress,
+    /// The model of the sensor.
+    pub model: SensorModel,
+    /// The firmware which the sensor is running.
+    pub firmware: Firmware,
+}
+
+impl SensorProps {
+    /// Whether the sensor can be connected to and used with the methods on `MijiaSession`, i.e. it
+    /// is a model which supports GATT and is running its stock firmware. Other sensors can only be
+    /// used via their advertisements.
+    pub fn supports_gatt(&self) -> bool {
+        self.model.supports_gatt() && self.firmware == Firmware::Stock
+    }
 }
 
 /// An event from a Mijia sensor.
@@ -87,76 +112,155 @@ pub struct SensorProps {
 pub enum MijiaEvent {
     /// A new sensor has been discovered.
     Discovered { id: DeviceId },
-    /// A sensor has sent a new set of readings.
-    Readings { id: DeviceId, readings: Readings },
+    /// A sensor has sent a new set of readings, either by notification or in its advertisements.
+    Readings {
+        id: DeviceId,
+        readings: Readings,
+        /// Whether the readings were broadcast in the sensor's advertisements rather than sent by
+        /// notification, in which case it doesn't mean that the sensor is connected.
+        advertised: bool,
+    },
     /// A sensor has sent a new historical record.
     HistoryRecord { id: DeviceId, record: HistoryRecord },
     /// The Bluetooth connection to a sensor has been lost.
     Disconnected { id: DeviceId },
+    /// BlueZ has removed a device which may have been a sensor, so its `DeviceId` is no longer
+    /// valid. The sensor may be discovered again later with a new `DeviceId`.
+    Removed { id: DeviceId },
 }
 
 impl MijiaEvent {
-    async fn from(event: BluetoothEvent, session: BluetoothSession) -> Option<Self> {
-        match event {
+    async fn from(
+        event: BluetoothEvent,
+        session: BluetoothSession,
+        readings_decoder: Arc<ReadingsDecoder>,
+    ) -> Option<Self> {
+        // Look up the same details as `BluetoothSession::record_events` records.
+        let mut characteristic_uuid = None;
+        let mut device_info = None;
+        match &event {
             BluetoothEvent::Characteristic {
                 id: characteristic,
-                event: CharacteristicEvent::Value { value },
+                event: CharacteristicEvent::Value { .. },
             } => {
                 let info = session
-                    .get_characteristic_info(&characteristic)
+                    .get_characteristic_info(characteristic)
                     .await
                     .map_err(|e| log::error!("Error getting characteristic UUID: {:?}", e))
                     .ok()?;
-                match info.uuid {
-                    SENSOR_READING_CHARACTERISTIC_UUID => match Readings::decode(&value) {
-                        Ok(readings) => Some(MijiaEvent::Readings {
-                            id: characteristic.service().device(),
-                            readings,
-                        }),
-                        Err(e) => {
-                            log::error!("Error decoding readings: {:?}", e);
-                            None
-                        }
-                    },
-                    HISTORY_RECORDS_CHARACTERISTIC_UUID => match HistoryRecord::decode(&value) {
-                        Ok(record) => Some(MijiaEvent::HistoryRecord {
-                            id: characteristic.service().device(),
-                            record,
-                        }),
-                        Err(e) => {
-                            log::error!("Error decoding historical record: {:?}", e);
-                            None
-                        }
-                    },
-                    _ => {
-                        log::trace!(
-                            "Got BluetoothEvent::Value for characteristic {:?} with value {:?}",
-                            characteristic,
-                            value
-                        );
+                characteristic_uuid = Some(info.uuid);
+            }
+            BluetoothEvent::Device {
+                id,
+                event: DeviceEvent::Discovered,
+            } => {
+                let device = session
+                    .get_device_info(id)
+                    .await
+                    .map_err(|e| log::error!("Error getting device info: {:?}", e))
+                    .ok()?;
+                device_info = Some(device);
+            }
+            _ => {}
+        }
+        Self::decode(
+            event,
+            characteristic_uuid,
+            device_info.as_ref(),
+            &readings_decoder,
+        )
+    }
+
+    /// Convert an event recorded by `BluetoothSession::record_events` into an event for a sensor,
+    /// if it is relevant. This uses the details of characteristics and devices in the recording
+    /// rather than looking them up, so it works without BlueZ, e.g. to reproduce a problem offline
+    /// with `EventReplay`.
+    ///
+    /// Readings from advertisements are decoded with the given decoder, which must have any bind
+    /// keys needed set.
+    pub fn from_recorded(
+        recorded: RecordedEvent,
+        readings_decoder: &ReadingsDecoder,
+    ) -> Option<Self> {
+        Self::decode(
+            recorded.event,
+            recorded.characteristic_uuid,
+            recorded.device_info.as_ref(),
+            readings_decoder,
+        )
+    }
+
+    /// Convert the given Bluetooth event into an event for a sensor, given the UUID of the
+    /// characteristic for characteristic events and information about the device for discovery
+    /// events.
+    fn decode(
+        event: BluetoothEvent,
+        characteristic_uuid: Option<Uuid>,
+        device_info: Option<&DeviceInfo>,
+        readings_decoder: &ReadingsDecoder,
+    ) -> Option<Self> {
+        match event {
+            BluetoothEvent::Characteristic {
+                id: characteristic,
+                event: CharacteristicEvent::Value { value },
+            } => match characteristic_uuid {
+                Some(SENSOR_READING_CHARACTERISTIC_UUID) => match Readings::decode(&value) {
+                    Ok(readings) => Some(MijiaEvent::Readings {
+                        id: characteristic.service().device(),
+                        readings,
+                        advertised: false,
+                    }),
+                    Err(e) => {
+                        log::error!("Error decoding readings: {:?}", e);
+                        None
+                    }
+                },
+                Some(HISTORY_RECORDS_CHARACTERISTIC_UUID) => match HistoryRecord::decode(&value) {
+                    Ok(record) => Some(MijiaEvent::HistoryRecord {
+                        id: characteristic.service().device(),
+                        record,
+                    }),
+                    Err(e) => {
+                        log::error!("Error decoding historical record: {:?}", e);
                         None
                     }
+                },
+                _ => {
+                    log::trace!(
+                        "Got BluetoothEvent::Value for characteristic {:?} with value {:?}",
+                        characteristic,
+                        value
+                    );
+                    None
                 }
-            }
+            },
             BluetoothEvent::Device {
                 id,
                 event: DeviceEvent::Connected { connected: false },
             } => Some(MijiaEvent::Disconnected { id }),
+            // The device no longer exists so we can't check whether it is a sensor.
+            BluetoothEvent::Device {
+                id,
+                event: DeviceEvent::Removed,
+            } => Some(MijiaEvent::Removed { id }),
+            BluetoothEvent::Device {
+                id,
+                event: DeviceEvent::ServiceData { service_data },
+            } if ReadingsDecoder::is_relevant(&service_data) => {
+                let mac_address = id.mac_address()?;
+                let readings = readings_decoder.decode_service_data(&mac_address, &service_data)?;
+                Some(MijiaEvent::Readings {
+                    id,
+                    readings,
+                    advertised: true,
+                })
+            }
             BluetoothEvent::Device {
                 id,
                 event: DeviceEvent::Discovered,
             } => {
                 // Only pass through discovery events for sensors we recognise.
-                let device = session
-                    .get_device_info(&id)
-                    .await
-                    .map_err(|e| log::error!("Error getting device info: {:?}", e))
-                    .ok()?;
-                if is_mijia_sensor(&device) {
-                    Some(MijiaEvent::Discovered { id })
-                } else {
-                    None
-                }
+                SensorModel::detect(device_info?).map(|_| MijiaEvent::Discovered { id })
             }
             _ => None,
         }
@@ -191,6 +295,16 @@ pub struct MijiaSession {
     /// The underlying `BluetoothSession`. You can use this for Bluetooth operations which are not
     /// specific to Mijia sensors, such as connecting and disconnecting.
     pub bt_session: BluetoothSession,
+    readings_decoder: Arc<ReadingsDecoder>,
+}
+
+impl From<BluetoothSession> for MijiaSession {
+    fn from(bt_session: BluetoothSession) -> Self {
+        MijiaSession {
+            bt_session,
+            readings_decoder: Default::default(),
+        }
+    }
 }
 
 impl MijiaSession {
@@ -200,10 +314,41 @@ impl MijiaSession {
     pub async fn new(
     ) -> Result<(impl Future<Output = Result<(), SpawnError>>, Self), BluetoothError> {
         let (handle, bt_session) = BluetoothSession::new().await?;
-        Ok((handle, MijiaSession { bt_session }))
+        Ok((handle, bt_session.into()))
+    }
+
+    /// Start scanning for advertisements, without connecting to any sensors. Sensors which include
+    /// their readings in their advertisements will then send `MijiaEvent::Readings` with `advertised`
+    /// set on the [`event_stream`](#method.event_stream), which uses much less of their battery
+    /// than connecting to them.
+    ///
+    /// Sensors which encrypt their advertisements need a bind key to be set with
+    /// [`set_bind_key`](#method.set_bind_key) first.
+    pub async fn start_advertisement_scan(&self) -> Result<(), BluetoothError> {
+        self.bt_session
+            .start_discovery_with_filter(&DiscoveryFilter {
+                transport: Some(Transport::Le),
+                duplicate_data: Some(true),
+                ..Default::default()
+            })
+            .await
+    }
+
+    /// Set the key to use to decrypt advertisements from the sensor with the given MAC address.
+    pub fn set_bind_key(&self, mac_address: MacAddress, bind_key: BindKey) {
+        self.readings_decoder.set_bind_key(mac_address, bind_key);
+    }
+
+    /// Forget the key for the sensor with the given MAC address.
+    pub fn remove_bind_key(&self, mac_address: &MacAddress) {
+        self.readings_decoder.remove_bind_key(mac_address);
     }
 
     /// Get a list of all Mijia sensors which have currently been discovered.
+    ///
+    /// This includes sensors which can only be used via their advertisements, so check
+    /// [`SensorProps::supports_gatt`](struct.SensorProps.html#method.supports_gatt) before
+    /// connecting to a sensor to use the other methods here.
     pub async fn get_sensors(&self) -> Result<Vec<SensorProps>, BluetoothError> {
         let devices = self.bt_session.get_devices().await?;
 
@@ -216,14 +361,12 @@ impl MijiaSession {
                     device.name,
                     device.service_data
                 );
-                if is_mijia_sensor(&device) {
-                    Some(SensorProps {
-                        id: device.id,
-                        mac_address: device.mac_address,
-                    })
-                } else {
-                    None
-                }
+                SensorModel::detect(&device).map(|model| SensorProps {
+                    firmware: Firmware::detect(&device),
+                    id: device.id,
+                    mac_address: device.mac_address,
+                    model,
+                })
             })
             .collect();
         Ok(sensors)
@@ -421,60 +564,71 @@ impl MijiaSession {
     }
 
     /// Try to get all historical records for the sensor.
+    ///
+    /// Records are received over a notification file descriptor acquired from BlueZ if possible,
+    /// falling back to D-Bus signals otherwise. Any which are missed are requested again a few
+    /// times, but if they still can't be fetched they are left as `None`.
     pub async fn get_all_history(
         &self,
         id: &DeviceId,
     ) -> Result<Vec<Option<HistoryRecord>>, MijiaError> {
-        let history_range = self.get_history_range(&id).await?;
-
-        let history_record_characteristic = self
-            .bt_session
-            .get_service_characteristic_by_uuid(
-                id,
-                SERVICE_UUID,
-                HISTORY_RECORDS_CHARACTERISTIC_UUID,
-            )
-            .await?;
-        let events = self
-            .bt_session
-            .characteristic_event_stream(&history_record_characteristic.id)
-            .await?;
-        let events = events.timeout(HISTORY_RECORD_TIMEOUT);
-        pin!(events);
-        self.start_notify_history(&id, Some(0)).await?;
-
+        let history_range = self.get_history_range(id).await?;
         let mut history = vec![None; history_range.len()];
-        while let Some(Ok(event)) = events.next().await {
-            if let BluetoothEvent::Characteristic {
-                id: record_id,
-                event: CharacteristicEvent::Value { value },
-            } = event
-            {
-                let record = HistoryRecord::decode(&value)?;
-                log::trace!("{:?}: {}", record_id, record);
-                if record_id == history_record_characteristic.id {
-                    if history_range.contains(&record.index) {
-                        let offset = record.index - history_range.start;
-                        history[offset as usize] = Some(record);
-                    } else {
-                        log::error!(
-                            "Got record {:?} for sensor {:?} out of bounds {:?}",
-                            record,
-                            id,
-                            history_range
-                        );
-                    }
-                } else {
-                    log::warn!("Got record for wrong characteristic {:?}", record_id);
-                }
-            } else {
-                log::warn!("Unexpected event: {:?}", event);
-            }
+        let updates = history_updates(
+            &self.bt_session,
+            id,
+            history_range.clone(),
+            HistoryOptions::default().max_retries,
+        );
+        pin!(updates);
+        while let Some(update) = updates.next().await {
+            let record = update?.record;
+            let offset = record.index - history_range.start;
+            history[offset as usize] = Some(record);
         }
+        Ok(history)
+    }
 
-        self.stop_notify_history(&id).await?;
+    /// Get a stream of the historical records stored on the sensor from
+    /// `options.start_index` onwards, each along with the number of records received so far and
+    /// the number expected, so that progress can be shown for large downloads.
+    ///
+    /// Once the records have been requested, any which were missed (e.g. because of a weak
+    /// connection) are requested again, up to `options.max_retries` times. Records are therefore
+    /// not necessarily in order of index, but each is only returned once. The stream completes
+    /// once all the records in the sensor's history range (as of when this is called) have been
+    /// received or the retries are used up, so `received` may be less than `expected` at the end.
+    pub async fn get_history_stream(
+        &self,
+        id: &DeviceId,
+        options: &HistoryOptions,
+    ) -> Result<impl Stream<Item = Result<HistoryUpdate, MijiaError>>, MijiaError> {
+        let history_range = self.get_history_range(id).await?;
+        let start_index = options.start_index.max(history_range.start);
+        Ok(history_updates(
+            &self.bt_session,
+            id,
+            start_index..history_range.end,
+            options.max_retries,
+        ))
+    }
 
-        Ok(history)
+    /// Get a stream of the historical records stored on the sensor, starting from the given index.
+    ///
+    /// The stream completes as soon as the last record in the sensor's history range (as of when
+    /// this is called) has been received, or if no record is received for a couple of seconds, in
+    /// case some are lost. If `start_index` is before the start of the range then records are
+    /// returned from the start of the range, and if it is at or after the end then the stream will
+    /// be empty. Pair this with [`HistoryCheckpoints`](struct.HistoryCheckpoints.html) to fetch
+    /// only the records which haven't been fetched before.
+    pub async fn get_history_since(
+        &self,
+        id: &DeviceId,
+        start_index: u32,
+    ) -> Result<impl Stream<Item = Result<HistoryRecord, MijiaError>>, MijiaError> {
+        let history_range = self.get_history_range(id).await?;
+        let start_index = start_index.max(history_range.start);
+        history_records(&self.bt_session, id, start_index..history_range.end).await
     }
 
     /// Assuming that the given device ID refers to a Mijia sensor device and that it has already
@@ -508,17 +662,29 @@ impl MijiaSession {
     }
 
     /// Get a stream of reading/history/disconnected events for all sensors.
+    ///
+    /// This includes readings from advertisements as well as from sensors which are connected and
+    /// have had [`start_notify_sensor`](#method.start_notify_sensor) called.
     pub async fn event_stream(&self) -> Result<impl Stream<Item = MijiaEvent>, BluetoothError> {
         let events = self.bt_session.event_stream().await?;
+        Ok(self.events_from(events))
+    }
+
+    /// Convert the given stream of Bluetooth events into events for sensors, as
+    /// [`event_stream`](#method.event_stream) does for the events from BlueZ.
+    ///
+    /// Details of devices and characteristics are looked up from the session, so to replay events
+    /// recorded with `BluetoothSession::record_events` without BlueZ use
+    /// [`MijiaEvent::from_recorded`](enum.MijiaEvent.html#method.from_recorded) instead.
+    pub fn events_from(
+        &self,
+        events: impl Stream<Item = BluetoothEvent>,
+    ) -> impl Stream<Item = MijiaEvent> {
         let session = self.bt_session.clone();
-        Ok(Box::pin(futures::stream::StreamExt::filter_map(
+        let readings_decoder = self.readings_decoder.clone();
+        Box::pin(futures::stream::StreamExt::filter_map(
             events,
-            move |event| MijiaEvent::from(event, session.clone()),
-        )))
+            move |event| MijiaEvent::from(event, session.clone(), readings_decoder.clone()),
+        ))
     }
 }
-
-/// Check whether the given Bluetooth device is a Mijia sensor which we support.
-fn is_mijia_sensor(device: &DeviceInfo) -> bool {
-    device.name.as_deref() == Some(MIJIA_NAME)
-}
@@ -0,0 +1,264 @@
+use bluez_async::{uuid_from_u16, DeviceInfo};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt::{self, Display, Formatter};
+use uuid::Uuid;
+
+use crate::SERVICE_UUID;
+
+/// The UUID used for service data in the Xiaomi MiBeacon format.
+pub(crate) const MIBEACON_SERVICE_DATA_UUID: Uuid = uuid_from_u16(0xfe95);
+/// The UUID used for service data by the atc1441 and pvvx custom firmware.
+pub(crate) const CUSTOM_FIRMWARE_SERVICE_DATA_UUID: Uuid = uuid_from_u16(0x181a);
+
+/// The Bluetooth names which each model of sensor advertises with its stock firmware.
+const NAMES: &[(&str, SensorModel)] = &[
+    ("LYWSD03MMC", SensorModel::Lywsd03mmc),
+    ("MHO-C401", SensorModel::MhoC401),
+    ("ClearGrass Temp & RH", SensorModel::Cgg1),
+    ("Qingping Temp & RH M", SensorModel::Cgg1),
+    ("MJ_HT_V1", SensorModel::Lywsdcgq),
+    ("MJWSD05MMC", SensorModel::Mjwsd05mmc),
+];
+
+/// The prefixes of the names which the atc1441 and pvvx custom firmware advertise by default on
+/// each model, followed by the end of the MAC address.
+const CUSTOM_FIRMWARE_NAME_PREFIXES: &[(&str, SensorModel)] = &[
+    ("ATC_", SensorModel::Lywsd03mmc),
+    ("MHO_", SensorModel::MhoC401),
+    ("CGG_", SensorModel::Cgg1),
+];
+
+/// The product IDs which each model of sensor includes in its MiBeacon advertisements.
+const PRODUCT_IDS: &[(u16, SensorModel)] = &[
+    (0x01aa, SensorModel::Lywsdcgq),
+    (0x0347, SensorModel::Cgg1),
+    (0x0387, SensorModel::MhoC401),
+    (0x055b, SensorModel::Lywsd03mmc),
+    (0x0b48, SensorModel::Cgg1),
+    (0x2832, SensorModel::Mjwsd05mmc),
+];
+
+/// A model of sensor in the Mijia family.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum SensorModel {
+    /// The square LYWSD03MMC sensor with an LCD, also known as the Mijia 2.
+    Lywsd03mmc,
+    /// The MHO-C401 sensor with an e-ink display.
+    MhoC401,
+    /// The Qingping (formerly ClearGrass) CGG1 sensor with an e-ink display.
+    Cgg1,
+    /// The original round LYWSDCGQ sensor with an LCD, which takes an AAA battery.
+    Lywsdcgq,
+    /// The MJWSD05MMC sensor with an e-ink display.
+    Mjwsd05mmc,
+}
+
+impl SensorModel {
+    /// Work out which model of sensor the given Bluetooth device is, from its MiBeacon service
+    /// data, its name or the services it provides, in that order.
+    ///
+    /// Returns `None` if the device is not recognised as a sensor.
+    pub fn detect(device: &DeviceInfo) -> Option<Self> {
+        Self::detect_from(
+            device.name.as_deref(),
+            &device.services,
+            &device.service_data,
+        )
+    }
+
+    fn detect_from(
+        name: Option<&str>,
+        services: &[Uuid],
+        service_data: &HashMap<Uuid, Vec<u8>>,
+    ) -> Option<Self> {
+        service_data
+            .get(&MIBEACON_SERVICE_DATA_UUID)
+            .and_then(|data| Self::from_mibeacon(data))
+            .or_else(|| name.and_then(Self::from_name))
+            .or_else(|| {
+                // The LYWSD03MMC was the first model to use this service, and other models which
+                // share it should have been recognised by name.
+                if services.contains(&SERVICE_UUID) {
+                    Some(SensorModel::Lywsd03mmc)
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Get the model from the name which the sensor advertises, if it is one we recognise.
+    fn from_name(name: &str) -> Option<Self> {
+        NAMES
+            .iter()
+            .find(|(model_name, _)| *model_name == name)
+            .or_else(|| {
+                CUSTOM_FIRMWARE_NAME_PREFIXES
+                    .iter()
+                    .find(|(prefix, _)| name.starts_with(prefix))
+            })
+            .map(|(_, model)| *model)
+    }
+
+    /// Get the model from the product ID in the given MiBeacon service data, if it is one we
+    /// recognise.
+    fn from_mibeacon(data: &[u8]) -> Option<Self> {
+        // The product ID comes after the 2 byte frame control field.
+        let product_id = u16::from_le_bytes(data.get(2..4)?.try_into().unwrap());
+        PRODUCT_IDS
+            .iter()
+            .find(|(id, _)| *id == product_id)
+            .map(|(_, model)| *model)
+    }
+
+    /// The model number of the sensor, as printed on its label.
+    pub fn model_number(self) -> &'static str {
+        match self {
+            SensorModel::Lywsd03mmc => "LYWSD03MMC",
+            SensorModel::MhoC401 => "MHO-C401",
+            SensorModel::Cgg1 => "CGG1",
+            SensorModel::Lywsdcgq => "LYWSDCGQ",
+            SensorModel::Mjwsd05mmc => "MJWSD05MMC",
+        }
+    }
+
+    /// Whether the sensor provides the same GATT service as the LYWSD03MMC with its stock firmware,
+    /// so that it can be connected to and used with the methods on `MijiaSession`. Other models can
+    /// only be used via their advertisements.
+    ///
+    /// Custom firmware doesn't provide this service, so use `SensorProps::supports_gatt` to check a
+    /// particular sensor.
+    ///
+    /// All the models which support GATT use the same characteristics and value formats as the
+    /// LYWSD03MMC, so they are all decoded the same way.
+    pub fn supports_gatt(self) -> bool {
+        match self {
+            SensorModel::Lywsd03mmc | SensorModel::MhoC401 | SensorModel::Cgg1 => true,
+            SensorModel::Lywsdcgq | SensorModel::Mjwsd05mmc => false,
+        }
+    }
+}
+
+impl Display for SensorModel {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(self.model_number())
+    }
+}
+
+/// The firmware which a sensor is running.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum Firmware {
+    /// The firmware which the sensor was sold with.
+    Stock,
+    /// The atc1441 or pvvx custom firmware. This doesn't provide the stock GATT service, so the
+    /// sensor can only be read from its advertisements.
+    Custom,
+}
+
+impl Firmware {
+    /// Work out which firmware the given Bluetooth device is running, from its name or whether it
+    /// sends service data in the custom firmware format.
+    pub fn detect(device: &DeviceInfo) -> Self {
+        Self::detect_from(device.name.as_deref(), &device.service_data)
+    }
+
+    fn detect_from(name: Option<&str>, service_data: &HashMap<Uuid, Vec<u8>>) -> Self {
+        let custom_name = name.is_some_and(|name| {
+            CUSTOM_FIRMWARE_NAME_PREFIXES
+                .iter()
+                .any(|(prefix, _)| name.starts_with(prefix))
+        });
+        if custom_name || service_data.contains_key(&CUSTOM_FIRMWARE_SERVICE_DATA_UUID) {
+            Firmware::Custom
+        } else {
+            Firmware::Stock
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(name: Option<&str>) -> Option<SensorModel> {
+        SensorModel::detect_from(name, &[], &HashMap::new())
+    }
+
+    #[test]
+    fn detect_by_name() {
+        assert_eq!(detect(Some("LYWSD03MMC")), Some(SensorModel::Lywsd03mmc));
+        assert_eq!(detect(Some("MHO-C401")), Some(SensorModel::MhoC401));
+        assert_eq!(detect(Some("MJ_HT_V1")), Some(SensorModel::Lywsdcgq));
+        assert_eq!(detect(Some("ATC_010203")), Some(SensorModel::Lywsd03mmc));
+        assert_eq!(detect(Some("MHO_010203")), Some(SensorModel::MhoC401));
+        assert_eq!(detect(Some("Something else")), None);
+        assert_eq!(detect(None), None);
+    }
+
+    #[test]
+    fn detect_by_service_data() {
+        let name = Some("LYWSD03MMC");
+        let mut service_data = HashMap::new();
+        service_data.insert(
+            MIBEACON_SERVICE_DATA_UUID,
+            vec![0x50, 0x20, 0x32, 0x28, 0x01],
+        );
+        // The service data takes precedence over the name.
+        assert_eq!(
+            SensorModel::detect_from(name, &[], &service_data),
+            Some(SensorModel::Mjwsd05mmc)
+        );
+
+        // Unknown or truncated service data falls back to the name.
+        service_data.insert(MIBEACON_SERVICE_DATA_UUID, vec![0x50, 0x20, 0x34, 0x12]);
+        assert_eq!(
+            SensorModel::detect_from(name, &[], &service_data),
+            Some(SensorModel::Lywsd03mmc)
+        );
+        service_data.insert(MIBEACON_SERVICE_DATA_UUID, vec![0x50, 0x20, 0x47]);
+        assert_eq!(
+            SensorModel::detect_from(name, &[], &service_data),
+            Some(SensorModel::Lywsd03mmc)
+        );
+    }
+
+    #[test]
+    fn detect_by_services() {
+        assert_eq!(
+            SensorModel::detect_from(None, &[SERVICE_UUID], &HashMap::new()),
+            Some(SensorModel::Lywsd03mmc)
+        );
+    }
+
+    #[test]
+    fn detect_firmware() {
+        let no_service_data = HashMap::new();
+        assert_eq!(
+            Firmware::detect_from(Some("LYWSD03MMC"), &no_service_data),
+            Firmware::Stock
+        );
+        assert_eq!(
+            Firmware::detect_from(Some("ATC_010203"), &no_service_data),
+            Firmware::Custom
+        );
+        assert_eq!(
+            Firmware::detect_from(None, &no_service_data),
+            Firmware::Stock
+        );
+
+        // The pvvx firmware may be configured with a different name.
+        let mut service_data = HashMap::new();
+        service_data.insert(CUSTOM_FIRMWARE_SERVICE_DATA_UUID, vec![0; 15]);
+        assert_eq!(
+            Firmware::detect_from(Some("Kitchen"), &service_data),
+            Firmware::Custom
+        );
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(SensorModel::MhoC401.to_string(), "MHO-C401");
+    }
+}
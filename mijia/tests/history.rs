@@ -0,0 +1,269 @@
+//! Integration tests for fetching history from a sensor, using a fake BlueZ daemon on a private
+//! D-Bus bus.
+
+use bluez_async::fake::{FakeAdapter, FakeBluez, FakeCharacteristic, FakeDevice, FakeService};
+use bluez_async::{BluetoothSession, CharacteristicFlags, CharacteristicId, DeviceId};
+use futures::TryStreamExt;
+use mijia::{HistoryOptions, MijiaSession};
+use std::time::Duration;
+use tokio::time::{sleep, timeout};
+use uuid::Uuid;
+
+const SERVICE_UUID: Uuid = Uuid::from_u128(0xebe0ccb0_7a0a_4b0c_8a1a_6ff2997da3a6);
+const HISTORY_RANGE_CHARACTERISTIC_UUID: Uuid =
+    Uuid::from_u128(0xebe0ccb9_7a0a_4b0c_8a1a_6ff2997da3a6);
+const HISTORY_INDEX_CHARACTERISTIC_UUID: Uuid =
+    Uuid::from_u128(0xebe0ccba_7a0a_4b0c_8a1a_6ff2997da3a6);
+const HISTORY_RECORDS_CHARACTERISTIC_UUID: Uuid =
+    Uuid::from_u128(0xebe0ccbc_7a0a_4b0c_8a1a_6ff2997da3a6);
+
+/// Less than the time the library waits for missing records, so that tests will fail if they
+/// wait for that rather than finishing as soon as the last record arrives.
+const COMPLETION_TIMEOUT: Duration = Duration::from_secs(1);
+
+struct FakeSensor {
+    device: DeviceId,
+    index: CharacteristicId,
+    records: CharacteristicId,
+}
+
+/// Start a fake BlueZ with a connected sensor whose history range is 5 to 9 inclusive.
+async fn start() -> (FakeBluez, MijiaSession, FakeSensor) {
+    let fake = FakeBluez::start().await.unwrap();
+    let (_, bt_session) = BluetoothSession::new_with_address(fake.address())
+        .await
+        .unwrap();
+    let session = MijiaSession::from(bt_session);
+
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let device = fake.add_device(
+        &adapter,
+        FakeDevice::new("A4:C1:38:01:02:03".parse().unwrap()),
+    );
+    let service = fake.add_service(
+        &device,
+        FakeService {
+            uuid: SERVICE_UUID,
+            primary: true,
+        },
+    );
+    fake.add_characteristic(
+        &service,
+        FakeCharacteristic {
+            uuid: HISTORY_RANGE_CHARACTERISTIC_UUID,
+            flags: CharacteristicFlags::READ,
+            value: vec![9, 0, 0, 0, 5, 0, 0, 0],
+        },
+    );
+    let index = fake.add_characteristic(
+        &service,
+        FakeCharacteristic {
+            uuid: HISTORY_INDEX_CHARACTERISTIC_UUID,
+            flags: CharacteristicFlags::READ | CharacteristicFlags::WRITE,
+            value: vec![],
+        },
+    );
+    let records = fake.add_characteristic(
+        &service,
+        FakeCharacteristic {
+            uuid: HISTORY_RECORDS_CHARACTERISTIC_UUID,
+            flags: CharacteristicFlags::NOTIFY,
+            value: vec![],
+        },
+    );
+    session.bt_session.connect(&device).await.unwrap();
+
+    (
+        fake,
+        session,
+        FakeSensor {
+            device,
+            index,
+            records,
+        },
+    )
+}
+
+/// Wait until the sensor has been asked for records from the given index.
+async fn requested(fake: &FakeBluez, sensor: &FakeSensor, index: u32) {
+    timeout(COMPLETION_TIMEOUT, async {
+        while fake.characteristic_value(&sensor.index) != Some(index.to_le_bytes().to_vec()) {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("Records weren't requested");
+}
+
+/// Encode a history record with the given index.
+fn record(index: u32) -> Vec<u8> {
+    let mut value = index.to_le_bytes().to_vec();
+    value.extend_from_slice(&(1_600_000_000 + index * 3600).to_le_bytes());
+    value.extend_from_slice(&[0xdc, 0x00, 50, 0xd2, 0x00, 40]);
+    value
+}
+
+#[tokio::test]
+async fn history_since() {
+    let (fake, session, sensor) = start().await;
+
+    let records = session.get_history_since(&sensor.device, 7).await.unwrap();
+    assert_eq!(
+        fake.characteristic_value(&sensor.index),
+        Some(vec![7, 0, 0, 0])
+    );
+    for index in 7..10 {
+        fake.set_characteristic_value(&sensor.records, record(index));
+    }
+
+    let records: Vec<_> = timeout(COMPLETION_TIMEOUT, records.try_collect())
+        .await
+        .expect("Stream didn't complete after last record")
+        .unwrap();
+    let indices: Vec<_> = records.iter().map(|record| record.index).collect();
+    assert_eq!(indices, vec![7, 8, 9]);
+    assert_eq!(records[0].temperature_max, 22.0);
+    assert_eq!(records[0].humidity_min, 40);
+}
+
+#[tokio::test]
+async fn history_since_before_range() {
+    let (fake, session, sensor) = start().await;
+
+    let records = session.get_history_since(&sensor.device, 0).await.unwrap();
+    // Records are requested from the start of the range.
+    assert_eq!(
+        fake.characteristic_value(&sensor.index),
+        Some(vec![5, 0, 0, 0])
+    );
+    for index in 5..10 {
+        fake.set_characteristic_value(&sensor.records, record(index));
+    }
+    let records: Vec<_> = timeout(COMPLETION_TIMEOUT, records.try_collect())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(records.len(), 5);
+}
+
+#[tokio::test]
+async fn history_since_up_to_date() {
+    let (fake, session, sensor) = start().await;
+
+    let records = session.get_history_since(&sensor.device, 10).await.unwrap();
+    let records: Vec<_> = timeout(COMPLETION_TIMEOUT, records.try_collect())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(records.is_empty());
+    // Nothing should have been requested.
+    assert_eq!(fake.characteristic_value(&sensor.index), Some(vec![]));
+}
+
+#[tokio::test]
+async fn history_stream_retries_missing() {
+    let (fake, session, sensor) = start().await;
+
+    let options = HistoryOptions {
+        start_index: 0,
+        max_retries: 1,
+    };
+    let updates = session
+        .get_history_stream(&sensor.device, &options)
+        .await
+        .unwrap();
+    let updates = tokio::spawn(timeout(COMPLETION_TIMEOUT, updates.try_collect::<Vec<_>>()));
+
+    requested(&fake, &sensor, 5).await;
+    for index in &[5, 6, 8, 9] {
+        fake.set_characteristic_value(&sensor.records, record(*index));
+    }
+    // The missing record should be requested again.
+    requested(&fake, &sensor, 7).await;
+    fake.set_characteristic_value(&sensor.records, record(7));
+
+    let updates = updates
+        .await
+        .unwrap()
+        .expect("Stream didn't complete after missing record")
+        .unwrap();
+    let indices: Vec<_> = updates.iter().map(|update| update.record.index).collect();
+    assert_eq!(indices, vec![5, 6, 8, 9, 7]);
+    let progress: Vec<_> = updates
+        .iter()
+        .map(|update| (update.received, update.expected))
+        .collect();
+    assert_eq!(progress, vec![(1, 5), (2, 5), (3, 5), (4, 5), (5, 5)]);
+}
+
+#[tokio::test]
+async fn history_stream_retry_stops_after_gap() {
+    let (fake, session, sensor) = start().await;
+
+    let options = HistoryOptions {
+        start_index: 0,
+        max_retries: 2,
+    };
+    let updates = session
+        .get_history_stream(&sensor.device, &options)
+        .await
+        .unwrap();
+    let updates = tokio::spawn(timeout(COMPLETION_TIMEOUT, updates.try_collect::<Vec<_>>()));
+
+    requested(&fake, &sensor, 5).await;
+    for index in &[5, 8, 9] {
+        fake.set_characteristic_value(&sensor.records, record(*index));
+    }
+    // The sensor sends the rest of its history after the gap, but misses record 7 again. The retry
+    // should stop as soon as it gets past the gap, rather than waiting for a timeout.
+    requested(&fake, &sensor, 6).await;
+    for index in &[6, 8, 9] {
+        fake.set_characteristic_value(&sensor.records, record(*index));
+    }
+    requested(&fake, &sensor, 7).await;
+    for index in &[7, 8, 9] {
+        fake.set_characteristic_value(&sensor.records, record(*index));
+    }
+
+    let updates = updates
+        .await
+        .unwrap()
+        .expect("Stream didn't complete after missing records")
+        .unwrap();
+    let indices: Vec<_> = updates.iter().map(|update| update.record.index).collect();
+    assert_eq!(indices, vec![5, 8, 9, 6, 7]);
+}
+
+#[tokio::test]
+async fn history_stream_retry_stops_when_gap_missed_again() {
+    let (fake, session, sensor) = start().await;
+
+    let options = HistoryOptions {
+        start_index: 0,
+        max_retries: 1,
+    };
+    let updates = session
+        .get_history_stream(&sensor.device, &options)
+        .await
+        .unwrap();
+    let updates = tokio::spawn(timeout(COMPLETION_TIMEOUT, updates.try_collect::<Vec<_>>()));
+
+    requested(&fake, &sensor, 5).await;
+    for index in &[5, 6, 8, 9] {
+        fake.set_characteristic_value(&sensor.records, record(*index));
+    }
+    // The retry misses the only record it asked for, but the later records show that the sensor
+    // has moved past it.
+    requested(&fake, &sensor, 7).await;
+    for index in &[8, 9] {
+        fake.set_characteristic_value(&sensor.records, record(*index));
+    }
+
+    let updates = updates
+        .await
+        .unwrap()
+        .expect("Stream didn't complete after missing record")
+        .unwrap();
+    let indices: Vec<_> = updates.iter().map(|update| update.record.index).collect();
+    assert_eq!(indices, vec![5, 6, 8, 9]);
+}
@@ -0,0 +1,105 @@
+//! Integration test for recording the events from a fake BlueZ daemon, and then replaying them as
+//! sensor events once BlueZ has gone.
+
+use bluez_async::fake::{FakeAdapter, FakeBluez, FakeCharacteristic, FakeDevice, FakeService};
+use bluez_async::{BluetoothEvent, BluetoothSession, CharacteristicFlags, EventReplay};
+use mijia::{MijiaEvent, Readings, ReadingsDecoder};
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::time::{sleep, timeout};
+use uuid::Uuid;
+
+const SERVICE_UUID: Uuid = Uuid::from_u128(0xebe0ccb0_7a0a_4b0c_8a1a_6ff2997da3a6);
+const SENSOR_READING_CHARACTERISTIC_UUID: Uuid =
+    Uuid::from_u128(0xebe0ccc1_7a0a_4b0c_8a1a_6ff2997da3a6);
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[tokio::test]
+async fn replay_without_bluez() {
+    let fake = FakeBluez::start().await.unwrap();
+    let (_, session) = BluetoothSession::new_with_address(fake.address())
+        .await
+        .unwrap();
+    let path = std::env::temp_dir().join(format!("mijia-events-{}.json", std::process::id()));
+    let recorder = session
+        .record_events(File::create(&path).await.unwrap())
+        .await
+        .unwrap();
+
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let mut sensor = FakeDevice::new("A4:C1:38:01:02:03".parse().unwrap());
+    sensor.name = Some("LYWSD03MMC".to_string());
+    let device = fake.add_device(&adapter, sensor);
+    let service = fake.add_service(
+        &device,
+        FakeService {
+            uuid: SERVICE_UUID,
+            primary: true,
+        },
+    );
+    let readings = fake.add_characteristic(
+        &service,
+        FakeCharacteristic {
+            uuid: SENSOR_READING_CHARACTERISTIC_UUID,
+            flags: CharacteristicFlags::READ | CharacteristicFlags::NOTIFY,
+            value: vec![],
+        },
+    );
+    session.connect(&device).await.unwrap();
+    session.start_notify(&readings).await.unwrap();
+    assert!(fake.set_characteristic_value(&readings, vec![1, 2, 3, 4, 10]));
+
+    // Wait until the recorder has written the notification.
+    let replay =
+        timeout(TIMEOUT, async {
+            loop {
+                if let Ok(replay) = EventReplay::open(&path) {
+                    if replay.events().iter().any(|recorded| {
+                        matches!(recorded.event, BluetoothEvent::Characteristic { .. })
+                    }) {
+                        return replay;
+                    }
+                }
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    drop(recorder);
+    drop(session);
+    drop(fake);
+    std::fs::remove_file(&path).unwrap();
+
+    let decoder = ReadingsDecoder::new();
+    let events: Vec<MijiaEvent> = replay
+        .events()
+        .iter()
+        .cloned()
+        .filter_map(|recorded| MijiaEvent::from_recorded(recorded, &decoder))
+        .collect();
+    assert_eq!(events.len(), 2, "Unexpected events {:?}", events);
+    assert!(matches!(&events[0], MijiaEvent::Discovered { id } if *id == device));
+    match &events[1] {
+        MijiaEvent::Readings {
+            id,
+            readings,
+            advertised,
+        } => {
+            assert_eq!(*id, device);
+            assert!(!advertised);
+            assert_eq!(
+                *readings,
+                Readings {
+                    temperature: 5.13,
+                    humidity: 3,
+                    battery_voltage: Some(2564),
+                    battery_percent: 46,
+                    counter: None,
+                    flags: None,
+                }
+            );
+        }
+        event => panic!("Unexpected event {:?}", event),
+    }
+}
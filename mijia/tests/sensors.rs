@@ -0,0 +1,97 @@
+//! Integration tests for recognising sensors and their advertisements, using a fake BlueZ daemon
+//! on a private D-Bus bus.
+
+use bluez_async::fake::{FakeAdapter, FakeBluez, FakeDevice};
+use bluez_async::{uuid_from_u16, BluetoothSession};
+use futures::StreamExt;
+use mijia::{Firmware, MijiaEvent, MijiaSession, Readings, SensorModel};
+use std::time::Duration;
+use tokio::time::timeout;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[tokio::test]
+async fn custom_firmware_does_not_support_gatt() {
+    let fake = FakeBluez::start().await.unwrap();
+    let (_, bt_session) = BluetoothSession::new_with_address(fake.address())
+        .await
+        .unwrap();
+    let session = MijiaSession::from(bt_session);
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let mut stock = FakeDevice::new("A4:C1:38:01:02:03".parse().unwrap());
+    stock.name = Some("LYWSD03MMC".to_string());
+    fake.add_device(&adapter, stock);
+    let mut custom = FakeDevice::new("A4:C1:38:04:05:06".parse().unwrap());
+    custom.name = Some("ATC_040506".to_string());
+    fake.add_device(&adapter, custom);
+
+    let mut sensors = session.get_sensors().await.unwrap();
+    sensors.sort_by(|a, b| a.mac_address.cmp(&b.mac_address));
+    assert_eq!(sensors.len(), 2);
+
+    assert_eq!(sensors[0].model, SensorModel::Lywsd03mmc);
+    assert_eq!(sensors[0].firmware, Firmware::Stock);
+    assert!(sensors[0].supports_gatt());
+
+    // The custom firmware runs on the same hardware, but doesn't provide the stock GATT service.
+    assert_eq!(sensors[1].model, SensorModel::Lywsd03mmc);
+    assert_eq!(sensors[1].firmware, Firmware::Custom);
+    assert!(!sensors[1].supports_gatt());
+}
+
+#[tokio::test]
+async fn advertised_readings() {
+    let fake = FakeBluez::start().await.unwrap();
+    let (_, bt_session) = BluetoothSession::new_with_address(fake.address())
+        .await
+        .unwrap();
+    let session = MijiaSession::from(bt_session);
+    let mut events = Box::pin(session.event_stream().await.unwrap());
+    let adapter = fake.add_adapter(FakeAdapter::new("00:11:22:33:44:55".parse().unwrap()));
+    let mut sensor = FakeDevice::new("A4:C1:38:01:02:03".parse().unwrap());
+    sensor.name = Some("ATC_010203".to_string());
+    let device = fake.add_device(&adapter, sensor);
+
+    // An advertisement in the atc1441 custom firmware format.
+    fake.update_device(&device, |sensor| {
+        sensor.service_data.insert(
+            uuid_from_u16(0x181a),
+            vec![
+                0xa4, 0xc1, 0x38, 0x01, 0x02, 0x03, 0x00, 0xe6, 0x2d, 0x55, 0x0b, 0x7c, 0x12,
+            ],
+        );
+    });
+
+    let event = timeout(TIMEOUT, async {
+        while let Some(event) = events.next().await {
+            if let MijiaEvent::Readings { .. } = event {
+                return event;
+            }
+        }
+        panic!("Event stream ended");
+    })
+    .await
+    .unwrap();
+    match event {
+        MijiaEvent::Readings {
+            id,
+            readings,
+            advertised,
+        } => {
+            assert_eq!(id, device);
+            assert!(advertised);
+            assert_eq!(
+                readings,
+                Readings {
+                    temperature: 23.0,
+                    humidity: 45,
+                    battery_voltage: Some(2940),
+                    battery_percent: 85,
+                    counter: Some(0x12),
+                    flags: None,
+                }
+            );
+        }
+        event => panic!("Unexpected event {:?}", event),
+    }
+}